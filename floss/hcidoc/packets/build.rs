@@ -19,13 +19,16 @@ use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
 fn main() {
-    generate_packets();
+    generate_packets("l2cap_packets");
+    generate_packets("smp_packets");
+    generate_packets("att_packets");
+    generate_packets("avdtp_packets");
 }
 
-fn generate_packets() {
+fn generate_packets(name: &str) {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
-    let out_file = File::create(out_dir.join("l2cap_packets.rs")).unwrap();
-    let in_file = PathBuf::from("l2cap_packets.pdl");
+    let out_file = File::create(out_dir.join(format!("{}.rs", name))).unwrap();
+    let in_file = PathBuf::from(format!("{}.pdl", name));
 
     // Expect pdlc to be in the PATH
     println!("cargo:rerun-if-changed={}", in_file.display());
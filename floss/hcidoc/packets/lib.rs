@@ -2,6 +2,18 @@
 #![allow(unused)]
 #![allow(missing_docs)]
 
+pub mod att {
+    include!(concat!(env!("OUT_DIR"), "/att_packets.rs"));
+}
+
+pub mod avdtp {
+    include!(concat!(env!("OUT_DIR"), "/avdtp_packets.rs"));
+}
+
 pub mod l2cap {
     include!(concat!(env!("OUT_DIR"), "/l2cap_packets.rs"));
 }
+
+pub mod smp {
+    include!(concat!(env!("OUT_DIR"), "/smp_packets.rs"));
+}
@@ -0,0 +1,402 @@
+//! Compares the analysis of two logs of the same scenario, usually one taken before a regression
+//! and one after, so that only what changed between them is reported.
+
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+
+use crate::engine::{OutputFormat, RuleConfig, RuleEngine};
+use crate::groups::controllers::{OutstandingCommands, DEFAULT_COMMAND_TIMEOUT_MS};
+use crate::parser::{Packet, PacketChild};
+use bt_packets::hci::{Address, ErrorCode, EventChild, LeMetaEventChild, OpCode};
+
+/// Median command latencies are only reported as changed when one is this many times the other.
+const LATENCY_CHANGE_RATIO: f64 = 1.5;
+
+/// Smallest change of median command latency that is reported, so that commands answered in a
+/// fraction of a millisecond don't show up because of scheduling noise.
+const MIN_LATENCY_CHANGE_US: i64 = 1000;
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+/// Number of commands sent with one opcode and how long they took to be answered.
+#[derive(Default)]
+struct CommandStats {
+    count: usize,
+
+    /// Time between each command and its first Command Status or Command Complete, in
+    /// microseconds.
+    samples: Vec<i64>,
+}
+
+impl CommandStats {
+    fn median_us(&self) -> Option<i64> {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        sorted.get(sorted.len() / 2).copied()
+    }
+}
+
+/// How connections to a device ended. Only which outcomes happened is kept, not how often, so
+/// that logs of different lengths still compare equal.
+#[derive(Default, PartialEq)]
+struct DeviceOutcome {
+    connected: bool,
+
+    /// Status of every failed connection attempt.
+    failures: BTreeSet<String>,
+
+    /// Reason of every disconnection.
+    disconnections: BTreeSet<String>,
+}
+
+impl DeviceOutcome {
+    fn describe(&self) -> String {
+        let mut parts = vec![];
+        if self.connected {
+            parts.push("connected".to_string());
+        }
+        if !self.failures.is_empty() {
+            let failures: Vec<&str> = self.failures.iter().map(|s| s.as_str()).collect();
+            parts.push(format!("failed with {}", failures.join(", ")));
+        }
+        if !self.disconnections.is_empty() {
+            let reasons: Vec<&str> = self.disconnections.iter().map(|s| s.as_str()).collect();
+            parts.push(format!("disconnected with {}", reasons.join(", ")));
+        }
+        match parts.is_empty() {
+            true => "not seen".to_string(),
+            false => parts.join("; "),
+        }
+    }
+}
+
+/// Everything about one log that is compared with the other.
+pub struct LogSummary {
+    /// Runs the selected rule groups, for the signals they raise.
+    engine: RuleEngine,
+
+    /// Keyed by opcode name, so the comparison is printed in a stable order.
+    commands: BTreeMap<String, CommandStats>,
+
+    /// Commands not answered yet, keyed by adapter index.
+    outstanding: HashMap<u16, OutstandingCommands>,
+
+    /// Same as the `command_timeout_ms` parameter of CommandLatency.
+    command_timeout_ms: i64,
+
+    /// Device behind every connection, keyed by adapter index and handle.
+    handles: HashMap<(u16, ConnectionHandle), Address>,
+
+    /// Keyed by address.
+    devices: BTreeMap<String, DeviceOutcome>,
+}
+
+impl LogSummary {
+    pub fn new(engine: RuleEngine, config: &RuleConfig) -> Self {
+        LogSummary {
+            engine,
+            commands: BTreeMap::new(),
+            outstanding: HashMap::new(),
+            command_timeout_ms: config.get_i64(
+                "CommandLatency",
+                "command_timeout_ms",
+                DEFAULT_COMMAND_TIMEOUT_MS,
+            ),
+            handles: HashMap::new(),
+            devices: BTreeMap::new(),
+        }
+    }
+
+    fn process_answer(&mut self, opcode: OpCode, packet: &Packet) {
+        let command = match self.outstanding.get_mut(&packet.adapter_index) {
+            Some(outstanding) => outstanding.answered(opcode),
+            None => None,
+        };
+        if let Some(command) = command {
+            let latency = packet.ts.signed_duration_since(command.ts);
+            self.commands
+                .entry(format!("{:?}", opcode))
+                .or_default()
+                .samples
+                .push(latency.num_microseconds().unwrap_or(i64::MAX));
+        }
+    }
+
+    fn process_connection(
+        &mut self,
+        packet: &Packet,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        address: Address,
+    ) {
+        let outcome = self.devices.entry(address.to_string()).or_default();
+        if status == ErrorCode::Success {
+            outcome.connected = true;
+            self.handles.insert((packet.adapter_index, handle), address);
+        } else {
+            outcome.failures.insert(format!("{:?}", status));
+        }
+    }
+
+    fn process_disconnection(
+        &mut self,
+        packet: &Packet,
+        handle: ConnectionHandle,
+        reason: ErrorCode,
+    ) {
+        if let Some(address) = self.handles.remove(&(packet.adapter_index, handle)) {
+            self.devices
+                .entry(address.to_string())
+                .or_default()
+                .disconnections
+                .insert(format!("{:?}", reason));
+        }
+    }
+
+    pub fn process(&mut self, packet: Packet) {
+        // Commands that were never answered are dropped, so that the answer of a later command
+        // with the same opcode isn't matched to them.
+        let command_timeout_ms = self.command_timeout_ms;
+        let outstanding = self
+            .outstanding
+            .entry(packet.adapter_index)
+            .or_insert_with(|| OutstandingCommands::new(command_timeout_ms));
+        outstanding.expire(packet.ts);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                let opcode = cmd.get_op_code();
+                // A reset supersedes anything the controller was still working on.
+                if opcode == OpCode::Reset {
+                    outstanding.clear();
+                }
+                outstanding.sent(&packet);
+                self.commands.entry(format!("{:?}", opcode)).or_default().count += 1;
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::CommandStatus(cs) => {
+                    self.process_answer(cs.get_command_op_code(), &packet);
+                }
+                EventChild::CommandComplete(cc) => {
+                    self.process_answer(cc.get_command_op_code(), &packet);
+                }
+                EventChild::ConnectionComplete(ev) => {
+                    self.process_connection(
+                        &packet,
+                        ev.get_status(),
+                        ev.get_connection_handle(),
+                        ev.get_bd_addr(),
+                    );
+                }
+                EventChild::DisconnectionComplete(ev) => {
+                    self.process_disconnection(
+                        &packet,
+                        ev.get_connection_handle(),
+                        ev.get_reason(),
+                    );
+                }
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeConnectionComplete(ev) => {
+                        self.process_connection(
+                            &packet,
+                            ev.get_status(),
+                            ev.get_connection_handle(),
+                            ev.get_peer_address(),
+                        );
+                    }
+                    LeMetaEventChild::LeEnhancedConnectionComplete(ev) => {
+                        self.process_connection(
+                            &packet,
+                            ev.get_status(),
+                            ev.get_connection_handle(),
+                            ev.get_peer_address(),
+                        );
+                    }
+                    _ => {}
+                },
+
+                // PacketChild::HciEvent(ev).specialize()
+                _ => {}
+            },
+
+            // packet.inner
+            _ => {}
+        }
+
+        self.engine.process(packet);
+    }
+}
+
+/// Signals raised in only one of the logs.
+fn diff_signals(good: &LogSummary, bad: &LogSummary) -> Vec<Value> {
+    let good_counts = good.engine.signal_counts();
+    let bad_counts = bad.engine.signal_counts();
+    let tags: BTreeSet<&&str> = good_counts.keys().chain(bad_counts.keys()).collect();
+
+    tags.into_iter()
+        .filter(|tag| !good_counts.contains_key(*tag) || !bad_counts.contains_key(*tag))
+        .map(|tag| {
+            json!({
+                "type": "signal",
+                "tag": tag,
+                "good": good_counts.get(*tag).copied().unwrap_or(0),
+                "bad": bad_counts.get(*tag).copied().unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+/// Opcodes that were only sent in one of the logs, or whose median latency changed. How often
+/// an opcode was sent depends on how long each log is, so the counts are only shown.
+fn diff_commands(good: &LogSummary, bad: &LogSummary) -> Vec<Value> {
+    let empty = CommandStats::default();
+    let opcodes: BTreeSet<&String> = good.commands.keys().chain(bad.commands.keys()).collect();
+
+    let mut records = vec![];
+    for opcode in opcodes {
+        let good_stats = good.commands.get(opcode).unwrap_or(&empty);
+        let bad_stats = bad.commands.get(opcode).unwrap_or(&empty);
+        let good_median = good_stats.median_us();
+        let bad_median = bad_stats.median_us();
+
+        let latency_changed = match (good_median, bad_median) {
+            (Some(g), Some(b)) => {
+                let (low, high) = (g.min(b), g.max(b));
+                high - low >= MIN_LATENCY_CHANGE_US
+                    && high as f64 >= low as f64 * LATENCY_CHANGE_RATIO
+            }
+            _ => false,
+        };
+        if (good_stats.count > 0) == (bad_stats.count > 0) && !latency_changed {
+            continue;
+        }
+
+        let ms = |us: Option<i64>| us.map(|us| us as f64 / 1000.0);
+        records.push(json!({
+            "type": "command",
+            "opcode": opcode,
+            "good_count": good_stats.count,
+            "bad_count": bad_stats.count,
+            "good_median_latency_ms": ms(good_median),
+            "bad_median_latency_ms": ms(bad_median),
+        }));
+    }
+    records
+}
+
+/// Devices whose connections ended differently.
+fn diff_devices(good: &LogSummary, bad: &LogSummary) -> Vec<Value> {
+    let empty = DeviceOutcome::default();
+    let addresses: BTreeSet<&String> = good.devices.keys().chain(bad.devices.keys()).collect();
+
+    addresses
+        .into_iter()
+        .filter_map(|address| {
+            let good_outcome = good.devices.get(address).unwrap_or(&empty);
+            let bad_outcome = bad.devices.get(address).unwrap_or(&empty);
+            if good_outcome == bad_outcome {
+                return None;
+            }
+            Some(json!({
+                "type": "device",
+                "address": address,
+                "good": good_outcome.describe(),
+                "bad": bad_outcome.describe(),
+            }))
+        })
+        .collect()
+}
+
+fn write_text(
+    writer: &mut dyn Write,
+    (good_name, bad_name): (&str, &str),
+    signals: &[Value],
+    commands: &[Value],
+    devices: &[Value],
+) {
+    let _ = writeln!(writer, "Comparing {} (good) with {} (bad)", good_name, bad_name);
+
+    let _ = writeln!(writer, "### Signals ###");
+    for (name, key) in [(good_name, "good"), (bad_name, "bad")] {
+        let only: Vec<&Value> = signals.iter().filter(|s| s[key] != 0).collect();
+        if only.is_empty() {
+            continue;
+        }
+        let _ = writeln!(writer, "Only in {}:", name);
+        for signal in only {
+            let _ = writeln!(
+                writer,
+                "  {} ({})",
+                signal["tag"].as_str().unwrap_or_default(),
+                signal[key]
+            );
+        }
+    }
+
+    let _ = writeln!(writer, "### Commands ###");
+    let latency = |v: &Value| match v.as_f64() {
+        Some(ms) => format!("{:.3} ms", ms),
+        None => "-".to_string(),
+    };
+    for command in commands {
+        let _ = writeln!(
+            writer,
+            "{}: {} -> {} commands, median latency {} -> {}",
+            command["opcode"].as_str().unwrap_or_default(),
+            command["good_count"],
+            command["bad_count"],
+            latency(&command["good_median_latency_ms"]),
+            latency(&command["bad_median_latency_ms"]),
+        );
+    }
+
+    let _ = writeln!(writer, "### Devices ###");
+    for device in devices {
+        let _ = writeln!(
+            writer,
+            "{}: {} -> {}",
+            device["address"].as_str().unwrap_or_default(),
+            device["good"].as_str().unwrap_or_default(),
+            device["bad"].as_str().unwrap_or_default(),
+        );
+    }
+}
+
+/// Write the differences between a good and a bad log. Each log is given with the name it is
+/// shown under.
+pub fn report_diff(
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    (good_name, good): (&str, &LogSummary),
+    (bad_name, bad): (&str, &LogSummary),
+) {
+    let signals = diff_signals(good, bad);
+    let commands = diff_commands(good, bad);
+    let devices = diff_devices(good, bad);
+
+    match format {
+        OutputFormat::Text => {
+            write_text(writer, (good_name, bad_name), &signals, &commands, &devices);
+        }
+
+        OutputFormat::Json => {
+            let output = json!({
+                "good": good_name,
+                "bad": bad_name,
+                "signals": signals,
+                "commands": commands,
+                "devices": devices,
+            });
+            let _ = writeln!(writer, "{:#}", output);
+        }
+
+        OutputFormat::JsonLines => {
+            for record in signals.iter().chain(commands.iter()).chain(devices.iter()) {
+                let _ = writeln!(writer, "{}", record);
+            }
+        }
+    }
+}
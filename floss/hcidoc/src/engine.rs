@@ -1,10 +1,18 @@
 //! Handles stream processing of commands and events.
 
 use chrono::NaiveDateTime;
-use std::collections::BTreeMap;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
 use std::io::Write;
 
-use crate::parser::Packet;
+use crate::parser::{Packet, PacketChild};
+use bt_packets::hci::Address;
+
+/// Format used when printing timestamps in machine-readable output.
+const JSON_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
 
 /// Signals are pre-defined indicators that are seen in a packet stream.
 pub struct Signal {
@@ -19,38 +27,346 @@ pub struct Signal {
     pub tag: &'static str,
 }
 
+/// Static description of a signal. Every signal raised with a tag shares its definition.
+#[derive(Clone, Copy, Debug)]
+pub struct SignalDefinition {
+    pub tag: &'static str,
+    pub severity: Severity,
+
+    /// What the signal means, in one sentence.
+    pub description: &'static str,
+
+    /// Where to look next when the signal shows up.
+    pub next_step: &'static str,
+}
+
+/// A signal definition along with the rule group it belongs to.
+pub struct RegisteredSignal {
+    pub group: String,
+    pub definition: SignalDefinition,
+}
+
+/// Catalog of the signals that the rules of an engine can raise, keyed by tag.
+#[derive(Default)]
+pub struct SignalRegistry {
+    /// Group whose rules are currently registering.
+    group: String,
+
+    signals: BTreeMap<&'static str, RegisteredSignal>,
+}
+
+impl SignalRegistry {
+    pub fn new() -> Self {
+        SignalRegistry::default()
+    }
+
+    /// Add a signal a rule can raise. Registering a tag again, from the same or another rule,
+    /// keeps the first definition.
+    pub fn register(&mut self, definition: SignalDefinition) {
+        self.signals
+            .entry(definition.tag)
+            .or_insert_with(|| RegisteredSignal { group: self.group.clone(), definition });
+    }
+
+    pub fn get(&self, tag: &str) -> Option<&RegisteredSignal> {
+        self.signals.get(tag)
+    }
+
+    /// All registered signals, ordered by tag.
+    pub fn iter(&self) -> impl Iterator<Item = &RegisteredSignal> {
+        self.signals.values()
+    }
+
+    /// Add the severity of a signal to its machine-readable record.
+    fn label_record(&self, mut record: Value) -> Value {
+        let tag = record["tag"].as_str().unwrap_or_default();
+        record["severity"] = match self.get(tag) {
+            Some(registered) => registered.definition.severity.to_string().into(),
+            None => Value::Null,
+        };
+        record
+    }
+}
+
+/// How bad a finding is. Ordered from least to most severe.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    /// Purely informational, nothing is wrong.
+    Info,
+    /// Unusual behavior that may or may not cause problems.
+    Warning,
+    /// Something definitely went wrong.
+    Error,
+}
+
+impl TryFrom<&str> for Severity {
+    type Error = String;
+
+    fn try_from(item: &str) -> Result<Self, String> {
+        match item {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(format!("Unknown severity: {}", item)),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// A single reportable occurrence surfaced by a rule.
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+
+    /// Index and timestamp of the first packet relevant to this finding.
+    pub start_index: usize,
+    pub start_ts: NaiveDateTime,
+
+    /// Index and timestamp of the last packet relevant to this finding.
+    pub end_index: usize,
+    pub end_ts: NaiveDateTime,
+
+    /// Devices involved in this finding.
+    pub addresses: Vec<Address>,
+
+    /// Connection handles involved in this finding.
+    pub handles: Vec<u16>,
+
+    /// Human readable description.
+    pub message: String,
+}
+
+impl Finding {
+    /// Create a finding that is about a single packet.
+    pub fn new(packet: &Packet, severity: Severity, message: String) -> Self {
+        Finding {
+            severity,
+            start_index: packet.index,
+            start_ts: packet.ts,
+            end_index: packet.index,
+            end_ts: packet.ts,
+            addresses: vec![],
+            handles: vec![],
+            message,
+        }
+    }
+
+    /// Extend the finding back to an earlier packet.
+    pub fn since(mut self, packet: &Packet) -> Self {
+        self.start_index = packet.index;
+        self.start_ts = packet.ts;
+        self
+    }
+
+    /// Extend the finding to a later packet.
+    pub fn until(mut self, index: usize, ts: NaiveDateTime) -> Self {
+        self.end_index = index;
+        self.end_ts = ts;
+        self
+    }
+
+    pub fn with_address(mut self, address: Address) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    pub fn with_handle(mut self, handle: u16) -> Self {
+        self.handles.push(handle);
+        self
+    }
+
+    fn to_json(&self, group: &str, rule: &str) -> Value {
+        json!({
+            "group": group,
+            "rule": rule,
+            "severity": self.severity.to_string(),
+            "start_index": self.start_index,
+            "end_index": self.end_index,
+            "start_ts": self.start_ts.format(JSON_TIMESTAMP_FORMAT).to_string(),
+            "end_ts": self.end_ts.format(JSON_TIMESTAMP_FORMAT).to_string(),
+            "addresses": self.addresses.iter().map(|a| a.to_string()).collect::<Vec<String>>(),
+            "handles": self.handles,
+            "message": self.message,
+        })
+    }
+}
+
+impl Signal {
+    fn to_json(&self, group: &str, rule: &str) -> Value {
+        json!({
+            "group": group,
+            "rule": rule,
+            "index": self.index,
+            "ts": self.ts.format(JSON_TIMESTAMP_FORMAT).to_string(),
+            "tag": self.tag,
+        })
+    }
+}
+
+/// Per-rule parameters loaded from a config file. The file is a JSON object keyed by rule name,
+/// where each value is an object of parameters for that rule. For example:
+///
+/// ```json
+/// { "OddDisconnectionsRule": { "timeout_tolerance_ms": 3000 } }
+/// ```
+///
+/// Parameters that aren't set fall back to the rule's defaults.
+#[derive(Clone, Default)]
+pub struct RuleConfig {
+    rules: serde_json::Map<String, Value>,
+
+    /// Rule and parameter names the rules looked up, to tell which ones in the file are unknown.
+    looked_up: RefCell<HashSet<(String, String)>>,
+
+    /// Devices the analysis is restricted to. Empty means all devices.
+    addresses: Vec<Address>,
+}
+
+impl RuleConfig {
+    pub fn new() -> Self {
+        RuleConfig::default()
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let rules = match serde_json::from_str::<Value>(&contents).map_err(|e| e.to_string())? {
+            Value::Object(rules) => rules,
+            _ => return Err("Config must be a JSON object keyed by rule name".into()),
+        };
+
+        if let Some((name, _)) = rules.iter().find(|(_, params)| !params.is_object()) {
+            return Err(format!("Parameters for {} must be a JSON object", name));
+        }
+
+        Ok(RuleConfig { rules, ..Default::default() })
+    }
+
+    pub fn set_addresses(&mut self, addresses: Vec<Address>) {
+        self.addresses = addresses;
+    }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    fn get(&self, rule: &str, param: &str) -> Option<&Value> {
+        self.looked_up.borrow_mut().insert((rule.to_string(), param.to_string()));
+        self.rules.get(rule).and_then(|params| params.get(param))
+    }
+
+    /// Warn about the rules and parameters in the file that don't exist, most likely typos. Every
+    /// rule must have been created with this config first, so it looked up its parameters.
+    pub fn warn_unknown(&self, rule_names: &[&str]) {
+        let looked_up = self.looked_up.borrow();
+        for (rule, params) in self.rules.iter() {
+            if !rule_names.contains(&rule.as_str()) {
+                eprintln!("Unknown rule {} in config, ignoring it", rule);
+                continue;
+            }
+            for param in params.as_object().into_iter().flat_map(|params| params.keys()) {
+                if !looked_up.contains(&(rule.clone(), param.clone())) {
+                    eprintln!("Unknown parameter {}.{} in config, ignoring it", rule, param);
+                }
+            }
+        }
+    }
+
+    pub fn get_i64(&self, rule: &str, param: &str, default: i64) -> i64 {
+        match self.get(rule, param) {
+            Some(v) => v.as_i64().unwrap_or_else(|| {
+                eprintln!("{}.{} must be an integer, using default {}", rule, param, default);
+                default
+            }),
+            None => default,
+        }
+    }
+
+    pub fn get_bool(&self, rule: &str, param: &str, default: bool) -> bool {
+        match self.get(rule, param) {
+            Some(v) => v.as_bool().unwrap_or_else(|| {
+                eprintln!("{}.{} must be a boolean, using default {}", rule, param, default);
+                default
+            }),
+            None => default,
+        }
+    }
+}
+
 /// Trait that describes a single rule processor. A rule should be used to represent a certain type
 /// of analysis (for example: ACL Connections rule may keep track of all ACL connections and report
 /// on failed connections).
 pub trait Rule {
+    /// Name of this rule as shown in reports.
+    fn name(&self) -> &'static str;
+
     /// Process a single packet.
     fn process(&mut self, packet: &Packet);
 
     /// Generate a report for this rule based on the input stream so far. Usually, this should
     /// report on the instances of this rule that were discovered or any error conditions that are
     /// relevant to this rule.
-    fn report(&self, writer: &mut dyn Write);
+    fn report(&self, writer: &mut dyn Write) {
+        let findings = self.report_findings();
+        if !findings.is_empty() {
+            let _ = writeln!(writer, "{} report:", self.name());
+            for finding in findings.iter() {
+                let _ = writeln!(writer, "[{:?}] {}", finding.start_ts, finding.message);
+            }
+        }
+    }
+
+    /// Structured version of |report|. Every reportable occurrence should be represented by a
+    /// finding so that it can be consumed by other tools.
+    fn report_findings(&self) -> Vec<Finding>;
 
     /// Report on any signals seen by this rule on the input stream so far. Signals are
     /// structured indicators that specify a specific type of condition that are pre-defined and
     /// used to bucket interesting behavior. Not all reportable events are signals but all signals
     /// are reportable events.
     fn report_signals(&self) -> &[Signal];
+
+    /// Register the definition of every signal this rule can raise. Rules that raise signals
+    /// must override this so the signals show up in the catalog and can fail a run.
+    fn register_signals(&self, _registry: &mut SignalRegistry) {}
 }
 
 /// Grouping of rules. This is used to make it easier to enable/disable certain rules for
 /// processing a file.
 pub struct RuleGroup {
     rules: Vec<Box<dyn Rule>>,
+
+    /// Number of signals of each rule already written by |RuleEngine::report_new_signals|.
+    reported_signals: Vec<usize>,
 }
 
 impl RuleGroup {
     pub fn new() -> Self {
-        RuleGroup { rules: vec![] }
+        RuleGroup { rules: vec![], reported_signals: vec![] }
     }
 
     pub fn add_rule(&mut self, rule: Box<dyn Rule>) {
         self.rules.push(rule);
+        self.reported_signals.push(0);
+    }
+
+    pub fn rule_names(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|rule| rule.name()).collect()
+    }
+
+    pub fn register_signals(&self, registry: &mut SignalRegistry) {
+        for rule in &self.rules {
+            rule.register_signals(registry);
+        }
     }
 
     pub fn process(&mut self, packet: &Packet) {
@@ -72,37 +388,339 @@ impl RuleGroup {
             }
         }
     }
+
+    /// Signals raised since the last call, along with the name of the rule that raised them.
+    fn new_signals(&mut self) -> Vec<(&'static str, &Signal)> {
+        let mut signals = vec![];
+        for (rule, reported) in self.rules.iter().zip(self.reported_signals.iter_mut()) {
+            let rule_signals = rule.report_signals();
+            signals.extend(rule_signals[*reported..].iter().map(|signal| (rule.name(), signal)));
+            *reported = rule_signals.len();
+        }
+        signals
+    }
+
+    fn findings_to_json(&self, group: &str) -> Vec<Value> {
+        self.rules
+            .iter()
+            .flat_map(|rule| {
+                rule.report_findings()
+                    .iter()
+                    .map(|finding| finding.to_json(group, rule.name()))
+                    .collect::<Vec<Value>>()
+            })
+            .collect()
+    }
+
+    fn signals_to_json(&self, group: &str) -> Vec<Value> {
+        self.rules
+            .iter()
+            .flat_map(|rule| {
+                rule.report_signals().iter().map(|signal| signal.to_json(group, rule.name()))
+            })
+            .collect()
+    }
+}
+
+/// Supported formats for reports and signals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Free-form text meant for humans.
+    Text,
+    /// A single JSON document with a list of findings and a list of signals.
+    Json,
+    /// One JSON object per line, for findings and signals alike.
+    JsonLines,
+}
+
+impl TryFrom<&str> for OutputFormat {
+    type Error = String;
+
+    fn try_from(item: &str) -> Result<Self, Self::Error> {
+        match item {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            _ => Err(format!("Unknown output format: {}", item)),
+        }
+    }
+}
+
+/// Creates a fresh instance of a rule group.
+pub type GetRuleGroup = fn(&RuleConfig) -> RuleGroup;
+
+/// Rule state for a single adapter. Handles and addresses are only meaningful within one adapter,
+/// so every adapter gets its own instance of each rule group.
+struct AdapterRules {
+    index: u16,
+    address: Option<Address>,
+    groups: BTreeMap<String, RuleGroup>,
+}
+
+impl AdapterRules {
+    fn label(&self) -> String {
+        match self.address {
+            Some(address) => format!("hci{} ({})", self.index, address),
+            None => format!("hci{}", self.index),
+        }
+    }
+
+    /// Add the adapter to a machine-readable record.
+    fn label_record(&self, mut record: Value) -> Value {
+        record["adapter"] = self.index.into();
+        record["adapter_address"] = match self.address {
+            Some(address) => address.to_string().into(),
+            None => Value::Null,
+        };
+        record
+    }
 }
+
 /// Main entry point to process input data and run rules on them.
 pub struct RuleEngine {
-    groups: BTreeMap<String, RuleGroup>,
+    config: RuleConfig,
+    group_getters: BTreeMap<String, GetRuleGroup>,
+
+    /// Signals the rules of the added groups can raise.
+    signal_registry: SignalRegistry,
+
+    /// Rule state of every adapter seen so far, in order of appearance. An adapter index that is
+    /// removed and added again gets a new entry.
+    adapters: Vec<AdapterRules>,
+
+    /// Position in |adapters| for each adapter index that is currently present.
+    active_adapters: HashMap<u16, usize>,
 }
 
 impl RuleEngine {
-    pub fn new() -> Self {
-        RuleEngine { groups: BTreeMap::new() }
+    pub fn new(config: RuleConfig) -> Self {
+        RuleEngine {
+            config,
+            group_getters: BTreeMap::new(),
+            signal_registry: SignalRegistry::new(),
+            adapters: vec![],
+            active_adapters: HashMap::new(),
+        }
     }
 
-    pub fn add_rule_group(&mut self, name: String, group: RuleGroup) {
-        self.groups.insert(name, group);
+    pub fn add_rule_group(&mut self, name: String, get_group: GetRuleGroup) {
+        self.signal_registry.group = name.clone();
+        get_group(&self.config).register_signals(&mut self.signal_registry);
+        self.group_getters.insert(name, get_group);
+    }
+
+    pub fn signal_registry(&self) -> &SignalRegistry {
+        &self.signal_registry
+    }
+
+    /// Severity of the worst signal raised so far, if any was raised.
+    pub fn worst_signal_severity(&self) -> Option<Severity> {
+        self.adapters
+            .iter()
+            .flat_map(|adapter| adapter.groups.values())
+            .flat_map(|group| group.rules.iter())
+            .flat_map(|rule| rule.report_signals())
+            .filter_map(|signal| self.signal_registry.get(signal.tag))
+            .map(|registered| registered.definition.severity)
+            .max()
+    }
+
+    fn add_adapter(&mut self, index: u16) -> usize {
+        let groups = self
+            .group_getters
+            .iter()
+            .map(|(name, get_group)| (name.clone(), get_group(&self.config)))
+            .collect();
+        self.adapters.push(AdapterRules { index, address: None, groups });
+        self.active_adapters.insert(index, self.adapters.len() - 1);
+        self.adapters.len() - 1
+    }
+
+    fn get_or_add_adapter(&mut self, index: u16) -> usize {
+        match self.active_adapters.get(&index) {
+            Some(pos) => *pos,
+            None => self.add_adapter(index),
+        }
     }
 
     /// Consume a packet and run it through the various rules processors.
     pub fn process(&mut self, packet: Packet) {
-        for group in self.groups.values_mut() {
-            group.process(&packet);
+        match &packet.inner {
+            // Always start from a clean state when an adapter is added, even if we didn't see it
+            // getting removed.
+            PacketChild::NewIndex(info) => {
+                let pos = self.add_adapter(packet.adapter_index);
+                if !info.address.is_empty() {
+                    self.adapters[pos].address = Some(info.address);
+                }
+            }
+
+            PacketChild::DeleteIndex => {
+                self.active_adapters.remove(&packet.adapter_index);
+            }
+
+            PacketChild::IndexInfo(info) => {
+                let pos = self.get_or_add_adapter(packet.adapter_index);
+                self.adapters[pos].address = Some(info.address);
+            }
+
+            _ => {
+                let pos = self.get_or_add_adapter(packet.adapter_index);
+                for group in self.adapters[pos].groups.values_mut() {
+                    group.process(&packet);
+                }
+            }
+        }
+    }
+
+    /// Number of times each signal was raised, over all adapters.
+    pub fn signal_counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for adapter in self.adapters.iter() {
+            for group in adapter.groups.values() {
+                for signal in group.rules.iter().flat_map(|rule| rule.report_signals()) {
+                    *counts.entry(signal.tag).or_default() += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Only label output by adapter if there is more than one, so the common case stays terse.
+    fn write_adapter_label(&self, writer: &mut dyn Write, adapter: &AdapterRules) {
+        if self.adapters.len() > 1 {
+            let _ = writeln!(writer, "=== Adapter {} ===", adapter.label());
         }
     }
 
     pub fn report(&self, writer: &mut dyn Write) {
-        for group in self.groups.values() {
-            group.report(writer);
+        for adapter in self.adapters.iter() {
+            self.write_adapter_label(writer, adapter);
+            for group in adapter.groups.values() {
+                group.report(writer);
+            }
         }
     }
 
     pub fn report_signals(&self, writer: &mut dyn Write) {
-        for group in self.groups.values() {
-            group.report_signals(writer);
+        for adapter in self.adapters.iter() {
+            self.write_adapter_label(writer, adapter);
+            for group in adapter.groups.values() {
+                group.report_signals(writer);
+            }
+        }
+    }
+
+    /// Write findings and/or signals in one of the machine-readable formats.
+    pub fn report_with_format(
+        &self,
+        writer: &mut dyn Write,
+        format: OutputFormat,
+        include_findings: bool,
+        include_signals: bool,
+    ) {
+        match format {
+            OutputFormat::Text => {
+                if include_findings {
+                    self.report(writer);
+                }
+                if include_signals {
+                    let _ = writeln!(writer, "### Signals ###");
+                    self.report_signals(writer);
+                }
+            }
+
+            OutputFormat::Json => {
+                let (findings, signals) = self.json_records(include_findings, include_signals);
+                let mut output = serde_json::Map::new();
+                if include_findings {
+                    output.insert("findings".into(), Value::Array(findings));
+                }
+                if include_signals {
+                    output.insert("signals".into(), Value::Array(signals));
+                }
+                let _ = writeln!(writer, "{:#}", Value::Object(output));
+            }
+
+            OutputFormat::JsonLines => {
+                let (findings, signals) = self.json_records(include_findings, include_signals);
+                for (kind, mut record) in findings
+                    .into_iter()
+                    .map(|f| ("finding", f))
+                    .chain(signals.into_iter().map(|s| ("signal", s)))
+                {
+                    record["type"] = kind.into();
+                    let _ = writeln!(writer, "{}", record);
+                }
+            }
+        }
+    }
+
+    /// Collect the findings and signals of all the adapters as JSON records.
+    fn json_records(
+        &self,
+        include_findings: bool,
+        include_signals: bool,
+    ) -> (Vec<Value>, Vec<Value>) {
+        let mut findings = vec![];
+        let mut signals = vec![];
+        for adapter in self.adapters.iter() {
+            for (name, group) in adapter.groups.iter() {
+                if include_findings {
+                    findings.extend(
+                        group.findings_to_json(name).into_iter().map(|r| adapter.label_record(r)),
+                    );
+                }
+                if include_signals {
+                    signals.extend(
+                        group
+                            .signals_to_json(name)
+                            .into_iter()
+                            .map(|r| adapter.label_record(self.signal_registry.label_record(r))),
+                    );
+                }
+            }
+        }
+        (findings, signals)
+    }
+
+    /// Write the signals raised since the last call, so they can be printed as they fire while a
+    /// live capture or a growing log is being followed. Machine-readable formats are always
+    /// written as JSON lines here since the stream has no end.
+    pub fn report_new_signals(&mut self, writer: &mut dyn Write, format: OutputFormat) {
+        let label_text = self.adapters.len() > 1;
+        for adapter in self.adapters.iter_mut() {
+            let label = adapter.label();
+            let mut records = vec![];
+            for (name, group) in adapter.groups.iter_mut() {
+                for (rule, signal) in group.new_signals() {
+                    match format {
+                        OutputFormat::Text if label_text => {
+                            let _ = writeln!(
+                                writer,
+                                "{}: ({}, {}, {})",
+                                label, signal.index, signal.ts, signal.tag
+                            );
+                        }
+                        OutputFormat::Text => {
+                            let _ = writeln!(
+                                writer,
+                                "({}, {}, {})",
+                                signal.index, signal.ts, signal.tag
+                            );
+                        }
+                        OutputFormat::Json | OutputFormat::JsonLines => {
+                            let mut record = signal.to_json(name, rule);
+                            record["type"] = "signal".into();
+                            records.push(self.signal_registry.label_record(record));
+                        }
+                    }
+                }
+            }
+
+            for record in records {
+                let _ = writeln!(writer, "{}", adapter.label_record(record));
+            }
         }
     }
 }
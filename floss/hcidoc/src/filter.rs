@@ -0,0 +1,477 @@
+//! Restricts which packets reach the rules, by time, position in the log or device.
+
+use chrono::{Duration, NaiveDateTime};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::parser::{Packet, PacketChild};
+use bt_packets::hci::{
+    AclCommandChild, Address, CommandChild, ConnectionManagementCommandChild,
+    DiscoveryCommandChild, EventChild, LeConnectionManagementCommandChild, LeIsoCommandChild,
+    LeMetaEventChild, LeSecurityCommandChild, OpCode, ScoConnectionCommandChild,
+    SecurityCommandChild,
+};
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+/// Timestamp formats accepted for absolute time bounds.
+const TIMESTAMP_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+/// Start or end of the time window to analyze.
+#[derive(Clone, Copy, Debug)]
+pub enum TimeBound {
+    Absolute(NaiveDateTime),
+    /// Offset from the first packet in the log.
+    Relative(Duration),
+}
+
+impl TryFrom<&str> for TimeBound {
+    type Error = String;
+
+    /// Parse either a timestamp like `2023-11-14T22:13:20.5` or an offset from the first packet
+    /// like `+90s`. Offsets take an `ms`, `s`, `m` or `h` suffix and default to seconds.
+    fn try_from(item: &str) -> Result<Self, Self::Error> {
+        if let Some(offset) = item.strip_prefix('+') {
+            let (value, unit_us) = if let Some(v) = offset.strip_suffix("ms") {
+                (v, 1_000f64)
+            } else if let Some(v) = offset.strip_suffix('s') {
+                (v, 1_000_000f64)
+            } else if let Some(v) = offset.strip_suffix('m') {
+                (v, 60_000_000f64)
+            } else if let Some(v) = offset.strip_suffix('h') {
+                (v, 3_600_000_000f64)
+            } else {
+                (offset, 1_000_000f64)
+            };
+
+            return match value.parse::<f64>() {
+                Ok(v) if v >= 0.0 => {
+                    Ok(TimeBound::Relative(Duration::microseconds((v * unit_us) as i64)))
+                }
+                _ => Err(format!("Invalid time offset: {}", item)),
+            };
+        }
+
+        TIMESTAMP_FORMATS
+            .iter()
+            .find_map(|f| NaiveDateTime::parse_from_str(item, f).ok())
+            .map(TimeBound::Absolute)
+            .ok_or_else(|| format!("Invalid timestamp: {}", item))
+    }
+}
+
+/// Parse a range of packet indices like `100-200`. Either end can be left out.
+pub fn parse_index_range(item: &str) -> Result<(usize, Option<usize>), String> {
+    let invalid = || format!("Invalid index range: {}", item);
+    let (start, end) = item.split_once('-').ok_or_else(invalid)?;
+    let start = match start.trim() {
+        "" => 0,
+        s => s.parse::<usize>().map_err(|_| invalid())?,
+    };
+    let end = match end.trim() {
+        "" => None,
+        e => Some(e.parse::<usize>().map_err(|_| invalid())?),
+    };
+
+    match end {
+        Some(end) if end < start => Err(invalid()),
+        _ => Ok((start, end)),
+    }
+}
+
+/// Parse an address written like `AA:BB:CC:DD:EE:FF`, the way hcidoc prints them.
+pub fn parse_address(item: &str) -> Result<Address, String> {
+    let bytes = item
+        .split(':')
+        .map(|b| u8::from_str_radix(b, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| format!("Invalid address: {}", item))?;
+    if bytes.len() != 6 {
+        return Err(format!("Invalid address: {}", item));
+    }
+
+    // Addresses are stored in little-endian.
+    Ok(Address::from(&[bytes[5], bytes[4], bytes[3], bytes[2], bytes[1], bytes[0]]))
+}
+
+/// What a packet is about, as far as the address filter is concerned.
+enum PacketTarget {
+    /// The packet names the device.
+    Device(Address),
+    /// The packet refers to a connection.
+    Handle(ConnectionHandle),
+    /// The packet isn't about any device in particular (controller setup, scanning, ...).
+    None,
+}
+
+fn get_target(packet: &Packet) -> PacketTarget {
+    match &packet.inner {
+        PacketChild::HciCommand(cmd) => match cmd.specialize() {
+            CommandChild::AclCommand(cmd) => match cmd.specialize() {
+                AclCommandChild::ConnectionManagementCommand(cmd) => match cmd.specialize() {
+                    ConnectionManagementCommandChild::CreateConnection(cmd) => {
+                        PacketTarget::Device(cmd.get_bd_addr())
+                    }
+                    ConnectionManagementCommandChild::AcceptConnectionRequest(cmd) => {
+                        PacketTarget::Device(cmd.get_bd_addr())
+                    }
+                    ConnectionManagementCommandChild::RejectConnectionRequest(cmd) => {
+                        PacketTarget::Device(cmd.get_bd_addr())
+                    }
+                    ConnectionManagementCommandChild::ReadRemoteSupportedFeatures(cmd) => {
+                        PacketTarget::Handle(cmd.get_connection_handle())
+                    }
+                    ConnectionManagementCommandChild::ReadRemoteExtendedFeatures(cmd) => {
+                        PacketTarget::Handle(cmd.get_connection_handle())
+                    }
+                    _ => PacketTarget::None,
+                },
+                AclCommandChild::ScoConnectionCommand(cmd) => match cmd.specialize() {
+                    ScoConnectionCommandChild::SetupSynchronousConnection(cmd) => {
+                        PacketTarget::Handle(cmd.get_connection_handle())
+                    }
+                    ScoConnectionCommandChild::EnhancedSetupSynchronousConnection(cmd) => {
+                        PacketTarget::Handle(cmd.get_connection_handle())
+                    }
+                    ScoConnectionCommandChild::AcceptSynchronousConnection(cmd) => {
+                        PacketTarget::Device(cmd.get_bd_addr())
+                    }
+                    ScoConnectionCommandChild::EnhancedAcceptSynchronousConnection(cmd) => {
+                        PacketTarget::Device(cmd.get_bd_addr())
+                    }
+                    _ => PacketTarget::None,
+                },
+                AclCommandChild::LeConnectionManagementCommand(cmd) => match cmd.specialize() {
+                    LeConnectionManagementCommandChild::LeCreateConnection(cmd) => {
+                        PacketTarget::Device(cmd.get_peer_address())
+                    }
+                    LeConnectionManagementCommandChild::LeExtendedCreateConnection(cmd) => {
+                        PacketTarget::Device(cmd.get_peer_address())
+                    }
+                    LeConnectionManagementCommandChild::LeAddDeviceToFilterAcceptList(cmd) => {
+                        PacketTarget::Device(cmd.get_address())
+                    }
+                    LeConnectionManagementCommandChild::LeRemoveDeviceFromFilterAcceptList(cmd) => {
+                        PacketTarget::Device(cmd.get_address())
+                    }
+                    LeConnectionManagementCommandChild::LeReadRemoteFeatures(cmd) => {
+                        PacketTarget::Handle(cmd.get_connection_handle())
+                    }
+                    _ => PacketTarget::None,
+                },
+                AclCommandChild::Disconnect(cmd) => {
+                    PacketTarget::Handle(cmd.get_connection_handle())
+                }
+                AclCommandChild::ReadRemoteVersionInformation(cmd) => {
+                    PacketTarget::Handle(cmd.get_connection_handle())
+                }
+                _ => PacketTarget::None,
+            },
+            CommandChild::DiscoveryCommand(cmd) => match cmd.specialize() {
+                DiscoveryCommandChild::RemoteNameRequest(cmd) => {
+                    PacketTarget::Device(cmd.get_bd_addr())
+                }
+                _ => PacketTarget::None,
+            },
+            CommandChild::SecurityCommand(cmd) => match cmd.specialize() {
+                SecurityCommandChild::LinkKeyRequestReply(cmd) => {
+                    PacketTarget::Device(cmd.get_bd_addr())
+                }
+                SecurityCommandChild::LinkKeyRequestNegativeReply(cmd) => {
+                    PacketTarget::Device(cmd.get_bd_addr())
+                }
+                _ => PacketTarget::None,
+            },
+            CommandChild::LeSecurityCommand(cmd) => match cmd.specialize() {
+                LeSecurityCommandChild::LeStartEncryption(cmd) => {
+                    PacketTarget::Handle(cmd.get_connection_handle())
+                }
+                _ => PacketTarget::None,
+            },
+            _ => PacketTarget::None,
+        },
+
+        PacketChild::HciEvent(ev) => match ev.specialize() {
+            EventChild::ConnectionComplete(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::ConnectionRequest(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::SynchronousConnectionComplete(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::RemoteNameRequestComplete(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::RoleChange(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::LinkKeyRequest(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::LinkKeyNotification(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::IoCapabilityRequest(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::IoCapabilityResponse(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::UserConfirmationRequest(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::UserPasskeyRequest(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::SimplePairingComplete(ev) => PacketTarget::Device(ev.get_bd_addr()),
+            EventChild::DisconnectionComplete(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::AuthenticationComplete(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::EncryptionChange(ev) => PacketTarget::Handle(ev.get_connection_handle()),
+            EventChild::EncryptionKeyRefreshComplete(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::ModeChange(ev) => PacketTarget::Handle(ev.get_connection_handle()),
+            EventChild::ReadRemoteSupportedFeaturesComplete(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::ReadRemoteExtendedFeaturesComplete(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::ReadRemoteVersionInformationComplete(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::AuthenticatedPayloadTimeoutExpired(ev) => {
+                PacketTarget::Handle(ev.get_connection_handle())
+            }
+            EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                LeMetaEventChild::LeConnectionComplete(ev) => {
+                    PacketTarget::Device(ev.get_peer_address())
+                }
+                LeMetaEventChild::LeEnhancedConnectionComplete(ev) => {
+                    PacketTarget::Device(ev.get_peer_address())
+                }
+                LeMetaEventChild::LeConnectionUpdateComplete(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                LeMetaEventChild::LeReadRemoteFeaturesComplete(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                LeMetaEventChild::LeLongTermKeyRequest(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                LeMetaEventChild::LeRemoteConnectionParameterRequest(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                LeMetaEventChild::LeDataLengthChange(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                LeMetaEventChild::LePhyUpdateComplete(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                LeMetaEventChild::LeCisRequest(ev) => {
+                    PacketTarget::Handle(ev.get_acl_connection_handle())
+                }
+                LeMetaEventChild::LeCisEstablished(ev) => {
+                    PacketTarget::Handle(ev.get_connection_handle())
+                }
+                _ => PacketTarget::None,
+            },
+            _ => PacketTarget::None,
+        },
+
+        PacketChild::AclTx(acl) | PacketChild::AclRx(acl) => PacketTarget::Handle(acl.get_handle()),
+        PacketChild::ScoTx(sco) | PacketChild::ScoRx(sco) => PacketTarget::Handle(sco.get_handle()),
+        PacketChild::IsoTx(iso) | PacketChild::IsoRx(iso) => {
+            PacketTarget::Handle(iso.get_connection_handle())
+        }
+
+        _ => PacketTarget::None,
+    }
+}
+
+/// Decides which packets are passed on to the rule engine.
+#[derive(Clone, Default)]
+pub struct PacketFilter {
+    since: Option<TimeBound>,
+    until: Option<TimeBound>,
+    index_range: Option<(usize, Option<usize>)>,
+
+    /// Devices to keep. Empty keeps all devices.
+    addresses: HashSet<Address>,
+
+    /// Timestamp of the first packet, which relative time bounds are based on.
+    first_ts: Option<NaiveDateTime>,
+
+    /// Device behind every open connection, including connections of other devices, so that
+    /// their traffic can be dropped. Keyed by adapter index and connection handle.
+    handles: HashMap<(u16, ConnectionHandle), Address>,
+
+    /// Whether each command still waiting for its Command Complete/Status was accepted, oldest
+    /// first. Keyed by adapter index and opcode, so that the answer gets the same decision.
+    commands: HashMap<(u16, OpCode), VecDeque<bool>>,
+}
+
+impl PacketFilter {
+    pub fn new() -> Self {
+        PacketFilter::default()
+    }
+
+    pub fn set_time_window(&mut self, since: Option<TimeBound>, until: Option<TimeBound>) {
+        self.since = since;
+        self.until = until;
+    }
+
+    pub fn set_index_range(&mut self, start: usize, end: Option<usize>) {
+        self.index_range = Some((start, end));
+    }
+
+    pub fn set_addresses(&mut self, addresses: Vec<Address>) {
+        self.addresses = addresses.into_iter().collect();
+    }
+
+    fn resolve(&self, bound: TimeBound) -> Option<NaiveDateTime> {
+        match bound {
+            TimeBound::Absolute(ts) => Some(ts),
+            TimeBound::Relative(offset) => self.first_ts.map(|ts| ts + offset),
+        }
+    }
+
+    /// Keep track of which device every connection belongs to.
+    fn track_handles(&mut self, packet: &Packet) {
+        let adapter = packet.adapter_index;
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                if let CommandChild::LeIsoCommand(cmd) = cmd.specialize() {
+                    if let LeIsoCommandChild::LeCreateCis(cmd) = cmd.specialize() {
+                        for config in cmd.get_cis_config() {
+                            let acl = (adapter, config.acl_connection_handle);
+                            if let Some(address) = self.handles.get(&acl) {
+                                let cis = (adapter, config.cis_connection_handle);
+                                self.handles.insert(cis, *address);
+                            }
+                        }
+                    }
+                }
+            }
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::ConnectionComplete(ev) => {
+                    self.handles.insert((adapter, ev.get_connection_handle()), ev.get_bd_addr());
+                }
+                EventChild::SynchronousConnectionComplete(ev) => {
+                    self.handles.insert((adapter, ev.get_connection_handle()), ev.get_bd_addr());
+                }
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeConnectionComplete(ev) => {
+                        let handle = (adapter, ev.get_connection_handle());
+                        self.handles.insert(handle, ev.get_peer_address());
+                    }
+                    LeMetaEventChild::LeEnhancedConnectionComplete(ev) => {
+                        let handle = (adapter, ev.get_connection_handle());
+                        self.handles.insert(handle, ev.get_peer_address());
+                    }
+                    LeMetaEventChild::LeCisRequest(ev) => {
+                        let acl = (adapter, ev.get_acl_connection_handle());
+                        if let Some(address) = self.handles.get(&acl) {
+                            let cis = (adapter, ev.get_cis_connection_handle());
+                            self.handles.insert(cis, *address);
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Forget connections that went away, so that a reused handle isn't attributed to the old
+    /// device.
+    fn release_handles(&mut self, packet: &Packet) {
+        if let PacketChild::HciEvent(ev) = &packet.inner {
+            if let EventChild::DisconnectionComplete(ev) = ev.specialize() {
+                self.handles.remove(&(packet.adapter_index, ev.get_connection_handle()));
+            }
+        }
+    }
+
+    /// Remember whether a command was accepted, for when its answer comes.
+    fn track_command(&mut self, packet: &Packet, accepted: bool) {
+        if let PacketChild::HciCommand(cmd) = &packet.inner {
+            let opcode = cmd.get_op_code();
+            // Commands that were never answered before the reset won't be anymore.
+            if opcode == OpCode::Reset {
+                self.commands.retain(|(adapter, _), _| *adapter != packet.adapter_index);
+            }
+            self.commands.entry((packet.adapter_index, opcode)).or_default().push_back(accepted);
+        }
+    }
+
+    /// Whether the command answered by |packet| was accepted. None if |packet| isn't a Command
+    /// Complete/Status, or the command isn't in the log.
+    fn take_command_decision(&mut self, packet: &Packet) -> Option<bool> {
+        let opcode = match &packet.inner {
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::CommandComplete(ev) => ev.get_command_op_code(),
+                EventChild::CommandStatus(ev) => ev.get_command_op_code(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        self.commands.get_mut(&(packet.adapter_index, opcode))?.pop_front()
+    }
+
+    fn is_selected_device(&self, packet: &Packet) -> bool {
+        if self.addresses.is_empty() {
+            return true;
+        }
+
+        match get_target(packet) {
+            PacketTarget::Device(address) => self.addresses.contains(&address),
+            // Connections that started before the log can't be attributed, so keep them.
+            PacketTarget::Handle(handle) => {
+                match self.handles.get(&(packet.adapter_index, handle)) {
+                    Some(address) => self.addresses.contains(address),
+                    None => true,
+                }
+            }
+            PacketTarget::None => true,
+        }
+    }
+
+    /// Whether |packet| should be processed by the rules.
+    pub fn accept(&mut self, packet: &Packet) -> bool {
+        if self.first_ts.is_none() {
+            self.first_ts = Some(packet.ts);
+        }
+
+        // Adapter lifetime is needed to keep the engine's view of adapters right.
+        if let PacketChild::NewIndex(_) | PacketChild::DeleteIndex | PacketChild::IndexInfo(_) =
+            &packet.inner
+        {
+            return true;
+        }
+
+        // Handles are tracked over the whole log so that traffic can be attributed even when the
+        // connection was created outside of the selected window.
+        self.track_handles(packet);
+        let accepted = match self.take_command_decision(packet) {
+            // Answers are about whatever their command is about, so rules don't see answers to
+            // commands that were filtered out.
+            Some(command_accepted) => command_accepted && self.is_in_window(packet),
+            None => self.is_in_window(packet) && self.is_selected_device(packet),
+        };
+        self.track_command(packet, accepted);
+
+        // The disconnection itself still belongs to the device the handle was used by.
+        self.release_handles(packet);
+
+        accepted
+    }
+
+    fn is_in_window(&self, packet: &Packet) -> bool {
+        if let Some((start, end)) = self.index_range {
+            if packet.index < start || end.is_some_and(|end| packet.index > end) {
+                return false;
+            }
+        }
+
+        if let Some(since) = self.since.and_then(|b| self.resolve(b)) {
+            if packet.ts < since {
+                return false;
+            }
+        }
+
+        if let Some(until) = self.until.and_then(|b| self.resolve(b)) {
+            if packet.ts > until {
+                return false;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,1596 @@
+//! Rule group for tracking audio profiles.
+use chrono::NaiveDateTime;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::Write;
+
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
+use crate::l2cap::{Channel, ChannelEvent, Cid, L2capChannels, Psm, Sender};
+use crate::parser::{get_acl_content, AclContent, Packet, PacketChild};
+use crate::rfcomm::{Dlci, RfcommFrame, RfcommFrameType, RFCOMM_PSM};
+use bt_packets::hci::{Acl, Address, CommandChild, ErrorCode, EventChild};
+use hcidoc_packets::avdtp::{
+    Avdtp, AvdtpChild, AvdtpMessageType, AvdtpPacketType, AvdtpSignalIdentifier,
+};
+
+enum AudioSignal {
+    ConfigurationRejected,  // The peer rejected the stream configuration.
+    StartSuspendStorm,      // A stream was started and suspended many times in a short period.
+    NeverStarted,           // A media channel was opened and closed again without streaming.
+    SlcIncomplete,          // The HFP service level connection was never established.
+    CodecNegotiationFailed, // HFP codec negotiation with +BCS or AT+BAC failed.
+    ScoFailedAfterCodec,    // The audio connection failed right after a codec was selected.
+}
+
+impl From<AudioSignal> for SignalDefinition {
+    fn from(signal: AudioSignal) -> Self {
+        match signal {
+            AudioSignal::ConfigurationRejected => SignalDefinition {
+                tag: "AvdtpConfigurationRejected",
+                severity: Severity::Warning,
+                description: "The peer rejected an AVDTP stream configuration.",
+                next_step: "Compare the configuration sent in Set Configuration with the \
+                    capabilities the peer reported for that stream endpoint.",
+            },
+            AudioSignal::StartSuspendStorm => SignalDefinition {
+                tag: "AvdtpStartSuspendStorm",
+                severity: Severity::Warning,
+                description: "An A2DP stream was started and suspended many times in a short \
+                    period.",
+                next_step: "Look for what toggles audio on the host at that time, like \
+                    notification sounds or media sessions fighting for focus.",
+            },
+            AudioSignal::NeverStarted => SignalDefinition {
+                tag: "AvdtpNeverStarted",
+                severity: Severity::Info,
+                description: "A media channel was opened and closed again without ever streaming.",
+                next_step: "Check whether audio was expected to play at that time or the profile \
+                    connected only to idle.",
+            },
+            AudioSignal::SlcIncomplete => SignalDefinition {
+                tag: "HfpSlcIncomplete",
+                severity: Severity::Error,
+                description: "The HFP service level connection was never established.",
+                next_step: "Find the last AT command the AG accepted in the transcript and what \
+                    the HF sent, or failed to send, after it.",
+            },
+            AudioSignal::CodecNegotiationFailed => SignalDefinition {
+                tag: "HfpCodecNegotiationFailed",
+                severity: Severity::Warning,
+                description: "HFP codec negotiation with AT+BAC or +BCS failed.",
+                next_step:
+                    "Compare the codecs listed in AT+BAC with the one proposed in +BCS, and \
+                    check wideband speech support on both sides.",
+            },
+            AudioSignal::ScoFailedAfterCodec => SignalDefinition {
+                tag: "HfpScoFailedAfterCodec",
+                severity: Severity::Error,
+                description: "The audio connection failed right after a codec was selected.",
+                next_step: "Check that the controller supports the eSCO parameters used for the \
+                    selected codec, mSBC and LC3 need transparent air mode.",
+            },
+        }
+    }
+}
+
+impl From<AudioSignal> for &'static str {
+    fn from(signal: AudioSignal) -> Self {
+        SignalDefinition::from(signal).tag
+    }
+}
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+/// Stream end point identifier, local to the device that owns the end point.
+type Seid = u8;
+
+const AVDTP_PSM: Psm = 0x0019;
+
+/// Service capability category carrying the codec and its parameters.
+const MEDIA_CODEC_CATEGORY: u8 = 0x07;
+
+/// How many starts and suspends within `storm_window_ms` make a storm. Can be overridden with the
+/// `storm_threshold` parameter of AvdtpStreamsRule.
+const DEFAULT_STORM_THRESHOLD: i64 = 10;
+
+/// Can be overridden with the `storm_window_ms` parameter of AvdtpStreamsRule.
+const DEFAULT_STORM_WINDOW_MS: i64 = 10000;
+
+/// Names of the AVDTP and A2DP error codes (AVDTP 1.3, 8.20.6.2 and A2DP 1.4, 5.1.3).
+fn get_error_name(code: u8) -> Option<&'static str> {
+    match code {
+        0x01 => Some("BAD_HEADER_FORMAT"),
+        0x11 => Some("BAD_LENGTH"),
+        0x12 => Some("BAD_ACP_SEID"),
+        0x13 => Some("SEP_IN_USE"),
+        0x14 => Some("SEP_NOT_IN_USE"),
+        0x17 => Some("BAD_SERV_CATEGORY"),
+        0x18 => Some("BAD_PAYLOAD_FORMAT"),
+        0x19 => Some("NOT_SUPPORTED_COMMAND"),
+        0x1A => Some("INVALID_CAPABILITIES"),
+        0x22 => Some("BAD_RECOVERY_TYPE"),
+        0x23 => Some("BAD_MEDIA_TRANSPORT_FORMAT"),
+        0x25 => Some("BAD_RECOVERY_FORMAT"),
+        0x26 => Some("BAD_ROHC_FORMAT"),
+        0x27 => Some("BAD_CP_FORMAT"),
+        0x28 => Some("BAD_MULTIPLEXING_FORMAT"),
+        0x29 => Some("UNSUPPORTED_CONFIGURATION"),
+        0x31 => Some("BAD_STATE"),
+        0xC1 => Some("INVALID_CODEC_TYPE"),
+        0xC2 => Some("NOT_SUPPORTED_CODEC_TYPE"),
+        0xC3 => Some("INVALID_SAMPLING_FREQUENCY"),
+        0xC4 => Some("NOT_SUPPORTED_SAMPLING_FREQUENCY"),
+        0xC5 => Some("INVALID_CHANNEL_MODE"),
+        0xC6 => Some("NOT_SUPPORTED_CHANNEL_MODE"),
+        0xD3 => Some("INVALID_CODEC_PARAMETER"),
+        0xD4 => Some("NOT_SUPPORTED_CODEC_PARAMETER"),
+        _ => None,
+    }
+}
+
+fn format_error(code: u8) -> String {
+    match get_error_name(code) {
+        Some(name) => format!("{} (0x{:02x})", name, code),
+        None => format!("0x{:02x}", code),
+    }
+}
+
+/// Join the names of all bits set in `value`.
+fn format_flags(value: u8, flags: &[(u8, &str)]) -> String {
+    let names: Vec<&str> =
+        flags.iter().filter(|(mask, _)| value & mask != 0).map(|(_, name)| *name).collect();
+    match names.is_empty() {
+        true => "none".to_string(),
+        false => names.join("/"),
+    }
+}
+
+fn format_sbc(info: &[u8]) -> String {
+    if info.len() < 4 {
+        return "SBC".to_string();
+    }
+    format!(
+        "SBC {} Hz, {}, {} blocks, {} subbands, {}, bitpool {}-{}",
+        format_flags(
+            info[0],
+            &[(0x80, "16000"), (0x40, "32000"), (0x20, "44100"), (0x10, "48000")]
+        ),
+        format_flags(
+            info[0],
+            &[(0x08, "Mono"), (0x04, "Dual Channel"), (0x02, "Stereo"), (0x01, "Joint Stereo")]
+        ),
+        format_flags(info[1], &[(0x80, "4"), (0x40, "8"), (0x20, "12"), (0x10, "16")]),
+        format_flags(info[1], &[(0x08, "4"), (0x04, "8")]),
+        format_flags(info[1], &[(0x02, "SNR"), (0x01, "Loudness")]),
+        info[2],
+        info[3]
+    )
+}
+
+fn format_aac(info: &[u8]) -> String {
+    if info.len() < 6 {
+        return "AAC".to_string();
+    }
+    let frequencies = [
+        (info[1], 0x80, "8000"),
+        (info[1], 0x40, "11025"),
+        (info[1], 0x20, "12000"),
+        (info[1], 0x10, "16000"),
+        (info[1], 0x08, "22050"),
+        (info[1], 0x04, "24000"),
+        (info[1], 0x02, "32000"),
+        (info[1], 0x01, "44100"),
+        (info[2], 0x80, "48000"),
+        (info[2], 0x40, "64000"),
+        (info[2], 0x20, "88200"),
+        (info[2], 0x10, "96000"),
+    ];
+    let frequencies: Vec<&str> = frequencies
+        .iter()
+        .filter(|(value, mask, _)| value & mask != 0)
+        .map(|(_, _, name)| *name)
+        .collect();
+    let bitrate =
+        (u32::from(info[3] & 0x7F) << 16) | (u32::from(info[4]) << 8) | u32::from(info[5]);
+    format!(
+        "AAC {}, {} Hz, {} channels, {} bps{}",
+        format_flags(
+            info[0],
+            &[
+                (0x80, "MPEG-2 LC"),
+                (0x40, "MPEG-4 LC"),
+                (0x20, "MPEG-4 LTP"),
+                (0x10, "MPEG-4 scalable")
+            ]
+        ),
+        frequencies.join("/"),
+        format_flags(info[2], &[(0x08, "1"), (0x04, "2")]),
+        bitrate,
+        if info[3] & 0x80 != 0 { ", VBR" } else { "" }
+    )
+}
+
+fn format_vendor_codec(info: &[u8]) -> String {
+    if info.len() < 6 {
+        return "Vendor codec".to_string();
+    }
+    let vendor_id = u32::from_le_bytes([info[0], info[1], info[2], info[3]]);
+    let codec_id = u16::from_le_bytes([info[4], info[5]]);
+    match (vendor_id, codec_id) {
+        (0x004F, 0x0001) => "aptX".to_string(),
+        (0x00D7, 0x0024) => "aptX HD".to_string(),
+        (0x012D, 0x00AA) => "LDAC".to_string(),
+        (0x00E0, 0x0001) => "Opus".to_string(),
+        _ => format!("Vendor codec 0x{:04x} from 0x{:08x}", codec_id, vendor_id),
+    }
+}
+
+/// Describe the codec in a list of service capabilities, if there is one.
+fn describe_codec(capabilities: &[u8]) -> Option<String> {
+    let mut rest = capabilities;
+    while rest.len() >= 2 {
+        let (category, length) = (rest[0], usize::from(rest[1]));
+        let value = rest.get(2..2 + length)?;
+        // The value starts with the media type and the codec type.
+        if category == MEDIA_CODEC_CATEGORY && value.len() >= 2 {
+            let info = &value[2..];
+            return Some(match value[1] {
+                0x00 => format_sbc(info),
+                0x01 => "MPEG-1,2 Audio".to_string(),
+                0x02 => format_aac(info),
+                0x04 => "ATRAC".to_string(),
+                0xFF => format_vendor_codec(info),
+                codec => format!("Codec 0x{:02x}", codec),
+            });
+        }
+        rest = &rest[2 + length..];
+    }
+    None
+}
+
+/// A command waiting for a response from the other side.
+struct PendingCommand {
+    packet: Packet,
+    signal: AvdtpSignalIdentifier,
+
+    /// SEIDs of the acceptor the command is about.
+    acp_seids: Vec<Seid>,
+
+    /// For SetConfiguration, the SEID of the initiator.
+    int_seid: Option<Seid>,
+
+    /// For SetConfiguration and Reconfigure, the codec asked for.
+    codec: Option<String>,
+}
+
+/// A configured stream, from SetConfiguration until it is closed.
+struct Stream {
+    host_seid: Seid,
+    peer_seid: Seid,
+    codec: String,
+    configured: Packet,
+
+    /// Set once the media channel is opened.
+    media: Option<(Cid, Packet)>,
+
+    /// Set while streaming.
+    started: Option<Packet>,
+
+    starts: usize,
+    streaming_ms: i64,
+
+    /// Recent starts and suspends, to find storms.
+    recent: VecDeque<Packet>,
+}
+
+/// AVDTP state of a single ACL link.
+#[derive(Default)]
+struct AvdtpLink {
+    /// Host CID of the signalling channel, the first AVDTP channel of the link.
+    signalling: Option<Cid>,
+
+    /// Commands waiting for a response, keyed by the side that sent them and the transaction label.
+    pending: HashMap<(Sender, u8), PendingCommand>,
+
+    streams: Vec<Stream>,
+
+    /// Host SEID of the stream whose media channel is expected next.
+    opening: Option<Seid>,
+}
+
+impl AvdtpLink {
+    /// Find a stream from the SEID an acceptor was given by `commander`.
+    fn find_stream(&mut self, commander: Sender, acp_seid: Seid) -> Option<&mut Stream> {
+        self.streams.iter_mut().find(|s| match commander {
+            Sender::Host => s.peer_seid == acp_seid,
+            Sender::Peer => s.host_seid == acp_seid,
+        })
+    }
+
+    fn remove_stream(&mut self, commander: Sender, acp_seid: Seid) -> Option<Stream> {
+        let position = self.streams.iter().position(|s| match commander {
+            Sender::Host => s.peer_seid == acp_seid,
+            Sender::Peer => s.host_seid == acp_seid,
+        })?;
+        Some(self.streams.remove(position))
+    }
+}
+
+/// Follows AVDTP signalling for every stream, from configuration to close, and reports the codec
+/// in use and streams that misbehave.
+struct AvdtpStreamsRule {
+    /// Addresses of active links.
+    handles: HashMap<ConnectionHandle, Address>,
+
+    channels: L2capChannels,
+
+    links: HashMap<ConnectionHandle, AvdtpLink>,
+
+    storm_threshold: i64,
+    storm_window_ms: i64,
+
+    /// Timestamp of the last packet seen, to report on streams still open at the end of the log.
+    last_ts: Option<NaiveDateTime>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl AvdtpStreamsRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        AvdtpStreamsRule {
+            handles: HashMap::new(),
+            channels: L2capChannels::new(),
+            links: HashMap::new(),
+            storm_threshold: config.get_i64(
+                "AvdtpStreamsRule",
+                "storm_threshold",
+                DEFAULT_STORM_THRESHOLD,
+            ),
+            storm_window_ms: config.get_i64(
+                "AvdtpStreamsRule",
+                "storm_window_ms",
+                DEFAULT_STORM_WINDOW_MS,
+            ),
+            last_ts: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: AudioSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn report(&mut self, handle: ConnectionHandle, finding: Finding) {
+        let finding = match self.handles.get(&handle) {
+            Some(address) => finding.with_address(*address),
+            None => finding,
+        };
+        self.reportable.push(finding.with_handle(handle));
+    }
+
+    fn process_acl(&mut self, acl: &Acl, sender: Sender, packet: &Packet) {
+        let handle = acl.get_handle();
+        match get_acl_content(acl) {
+            AclContent::Control(control) => {
+                match self.channels.process_control(handle, sender, &control) {
+                    Some(ChannelEvent::Opened(channel)) if channel.psm == AVDTP_PSM => {
+                        self.process_channel_opened(channel, packet)
+                    }
+                    Some(ChannelEvent::Closed(channel)) if channel.psm == AVDTP_PSM => {
+                        self.process_channel_closed(channel, packet)
+                    }
+                    _ => {}
+                }
+            }
+            AclContent::StandardData(cid, data) => {
+                let host_cid = match self.channels.get(handle, sender, cid) {
+                    Some(channel) if channel.psm == AVDTP_PSM => channel.host_cid,
+                    _ => return,
+                };
+                let is_signalling =
+                    self.links.get(&handle).is_some_and(|link| link.signalling == Some(host_cid));
+                if !is_signalling {
+                    return;
+                }
+                if let Ok(avdtp) = Avdtp::parse(data.as_slice()) {
+                    self.process_avdtp(handle, sender, &avdtp, packet);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_channel_opened(&mut self, channel: Channel, packet: &Packet) {
+        let link = self.links.entry(channel.handle).or_default();
+        if link.signalling.is_none() {
+            link.signalling = Some(channel.host_cid);
+            return;
+        }
+
+        // Media channels are opened after Open is accepted, in the same order.
+        let seid = match link.opening.take() {
+            Some(seid) => seid,
+            None => match link.streams.iter().find(|s| s.media.is_none()) {
+                Some(stream) => stream.host_seid,
+                None => return,
+            },
+        };
+        if let Some(stream) = link.streams.iter_mut().find(|s| s.host_seid == seid) {
+            stream.media = Some((channel.host_cid, packet.clone()));
+        }
+    }
+
+    fn process_channel_closed(&mut self, channel: Channel, packet: &Packet) {
+        let link = match self.links.get_mut(&channel.handle) {
+            Some(link) => link,
+            None => return,
+        };
+
+        if link.signalling == Some(channel.host_cid) {
+            if let Some(link) = self.links.remove(&channel.handle) {
+                for stream in link.streams {
+                    self.finish_stream(channel.handle, stream, "ended with signalling", packet);
+                }
+            }
+            return;
+        }
+
+        let position = link
+            .streams
+            .iter()
+            .position(|s| s.media.as_ref().map(|m| m.0) == Some(channel.host_cid));
+        if let Some(position) = position {
+            let stream = link.streams.remove(position);
+            self.finish_stream(channel.handle, stream, "closed", packet);
+        }
+    }
+
+    fn process_avdtp(
+        &mut self,
+        handle: ConnectionHandle,
+        sender: Sender,
+        avdtp: &Avdtp,
+        packet: &Packet,
+    ) {
+        if avdtp.get_packet_type() != AvdtpPacketType::Single {
+            return;
+        }
+
+        let label = avdtp.get_transaction_label();
+        match avdtp.get_message_type() {
+            AvdtpMessageType::Command => {
+                let mut command = PendingCommand {
+                    packet: packet.clone(),
+                    signal: avdtp.get_signal_identifier(),
+                    acp_seids: vec![],
+                    int_seid: None,
+                    codec: None,
+                };
+                match avdtp.specialize() {
+                    AvdtpChild::AvdtpGetCapabilitiesCommand(cmd) => {
+                        command.acp_seids.push(cmd.get_acp_seid());
+                    }
+                    AvdtpChild::AvdtpGetAllCapabilitiesCommand(cmd) => {
+                        command.acp_seids.push(cmd.get_acp_seid());
+                    }
+                    AvdtpChild::AvdtpSetConfigurationCommand(cmd) => {
+                        command.acp_seids.push(cmd.get_acp_seid());
+                        command.int_seid = Some(cmd.get_int_seid());
+                        command.codec = describe_codec(cmd.get_capabilities());
+                    }
+                    AvdtpChild::AvdtpReconfigureCommand(cmd) => {
+                        command.acp_seids.push(cmd.get_acp_seid());
+                        command.codec = describe_codec(cmd.get_capabilities());
+                    }
+                    AvdtpChild::AvdtpOpenCommand(cmd) => command.acp_seids.push(cmd.get_acp_seid()),
+                    AvdtpChild::AvdtpCloseCommand(cmd) => {
+                        command.acp_seids.push(cmd.get_acp_seid())
+                    }
+                    AvdtpChild::AvdtpAbortCommand(cmd) => {
+                        command.acp_seids.push(cmd.get_acp_seid())
+                    }
+                    AvdtpChild::AvdtpStartCommand(cmd) => {
+                        command.acp_seids.extend(cmd.get_acp_seids().iter().map(|s| s.seid));
+                    }
+                    AvdtpChild::AvdtpSuspendCommand(cmd) => {
+                        command.acp_seids.extend(cmd.get_acp_seids().iter().map(|s| s.seid));
+                    }
+                    _ => {}
+                }
+                self.links.entry(handle).or_default().pending.insert((sender, label), command);
+            }
+
+            message_type => {
+                // Responses go the other way, so the command was sent by the receiver.
+                let commander = sender.other();
+                let command = match self
+                    .links
+                    .get_mut(&handle)
+                    .and_then(|link| link.pending.remove(&(commander, label)))
+                {
+                    Some(command) => command,
+                    None => return,
+                };
+                match message_type {
+                    AvdtpMessageType::ResponseAccept => {
+                        self.process_accept(handle, commander, command, avdtp, packet)
+                    }
+                    _ => self.process_reject(handle, commander, command, avdtp, packet),
+                }
+            }
+        }
+    }
+
+    fn process_accept(
+        &mut self,
+        handle: ConnectionHandle,
+        commander: Sender,
+        command: PendingCommand,
+        avdtp: &Avdtp,
+        packet: &Packet,
+    ) {
+        match avdtp.specialize() {
+            AvdtpChild::AvdtpDiscoverResponse(rsp) => {
+                let seps: Vec<String> = rsp
+                    .get_seps()
+                    .iter()
+                    .map(|sep| {
+                        format!(
+                            "{} {:?} {:?}{}",
+                            sep.acp_seid,
+                            sep.media_type,
+                            sep.sep_type,
+                            if sep.in_use != 0 { " (in use)" } else { "" }
+                        )
+                    })
+                    .collect();
+                let message =
+                    format!("{:?} has stream end points {}", commander.other(), seps.join(", "));
+                self.report(
+                    handle,
+                    Finding::new(packet, Severity::Info, message).since(&command.packet),
+                );
+                return;
+            }
+            AvdtpChild::AvdtpGetCapabilitiesResponse(rsp) => {
+                self.report_capabilities(
+                    handle,
+                    commander,
+                    &command,
+                    rsp.get_capabilities(),
+                    packet,
+                );
+                return;
+            }
+            AvdtpChild::AvdtpGetAllCapabilitiesResponse(rsp) => {
+                self.report_capabilities(
+                    handle,
+                    commander,
+                    &command,
+                    rsp.get_capabilities(),
+                    packet,
+                );
+                return;
+            }
+            _ => {}
+        }
+
+        let link = self.links.entry(handle).or_default();
+        let acp_seid = match command.acp_seids.first() {
+            Some(seid) => *seid,
+            None => return,
+        };
+        match command.signal {
+            AvdtpSignalIdentifier::SetConfiguration => {
+                let int_seid = command.int_seid.unwrap_or_default();
+                let (host_seid, peer_seid) = match commander {
+                    Sender::Host => (int_seid, acp_seid),
+                    Sender::Peer => (acp_seid, int_seid),
+                };
+                let codec = command.codec.unwrap_or_else(|| "unknown codec".to_string());
+                link.streams.retain(|s| s.host_seid != host_seid);
+                link.streams.push(Stream {
+                    host_seid,
+                    peer_seid,
+                    codec: codec.clone(),
+                    configured: command.packet.clone(),
+                    media: None,
+                    started: None,
+                    starts: 0,
+                    streaming_ms: 0,
+                    recent: VecDeque::new(),
+                });
+                let message = format!(
+                    "Stream configured by {:?} (host SEID {}, peer SEID {}): {}",
+                    commander, host_seid, peer_seid, codec
+                );
+                self.report(
+                    handle,
+                    Finding::new(packet, Severity::Info, message).since(&command.packet),
+                );
+            }
+            AvdtpSignalIdentifier::Reconfigure => {
+                if let (Some(stream), Some(codec)) =
+                    (link.find_stream(commander, acp_seid), command.codec)
+                {
+                    stream.codec = codec.clone();
+                    let message = format!("Stream reconfigured by {:?}: {}", commander, codec);
+                    self.report(
+                        handle,
+                        Finding::new(packet, Severity::Info, message).since(&command.packet),
+                    );
+                }
+            }
+            AvdtpSignalIdentifier::Open => {
+                if let Some(stream) = link.find_stream(commander, acp_seid) {
+                    link.opening = Some(stream.host_seid);
+                }
+            }
+            AvdtpSignalIdentifier::Start | AvdtpSignalIdentifier::Suspend => {
+                let starting = command.signal == AvdtpSignalIdentifier::Start;
+                for seid in command.acp_seids.iter() {
+                    if let Some(stream) = link.find_stream(commander, *seid) {
+                        if starting && stream.started.is_none() {
+                            stream.started = Some(packet.clone());
+                            stream.starts += 1;
+                        } else if let (false, Some(started)) = (starting, stream.started.take()) {
+                            stream.streaming_ms +=
+                                packet.ts.signed_duration_since(started.ts).num_milliseconds();
+                        }
+                    }
+                }
+                for seid in command.acp_seids.iter() {
+                    self.check_storm(handle, commander, *seid, packet);
+                }
+            }
+            AvdtpSignalIdentifier::Close | AvdtpSignalIdentifier::Abort => {
+                // The stream is released once its media channel goes away, unless it never had one.
+                let how = match command.signal {
+                    AvdtpSignalIdentifier::Close => "closed",
+                    _ => "aborted",
+                };
+                let has_media =
+                    link.find_stream(commander, acp_seid).is_some_and(|s| s.media.is_some());
+                if !has_media || command.signal == AvdtpSignalIdentifier::Abort {
+                    if let Some(stream) = link.remove_stream(commander, acp_seid) {
+                        self.finish_stream(handle, stream, how, packet);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_reject(
+        &mut self,
+        handle: ConnectionHandle,
+        commander: Sender,
+        command: PendingCommand,
+        avdtp: &Avdtp,
+        packet: &Packet,
+    ) {
+        let message = match (avdtp.get_message_type(), avdtp.specialize()) {
+            (AvdtpMessageType::GeneralReject, _) => {
+                format!("{:?} sent by {:?} was not understood", command.signal, commander)
+            }
+            // The error code is always the last byte of a reject, after any SEID or category.
+            (_, AvdtpChild::Payload(payload)) if !payload.is_empty() => {
+                let error = format_error(payload[payload.len() - 1]);
+                match command.signal {
+                    AvdtpSignalIdentifier::SetConfiguration
+                    | AvdtpSignalIdentifier::Reconfigure => {
+                        let category = match payload.len() {
+                            2 => format!(" in category 0x{:02x}", payload[0]),
+                            _ => String::new(),
+                        };
+                        format!(
+                            "{:?} of {} sent by {:?} rejected{}: {}",
+                            command.signal,
+                            command.codec.as_deref().unwrap_or("unknown codec"),
+                            commander,
+                            category,
+                            error
+                        )
+                    }
+                    _ => {
+                        format!("{:?} sent by {:?} rejected: {}", command.signal, commander, error)
+                    }
+                }
+            }
+            _ => format!("{:?} sent by {:?} rejected", command.signal, commander),
+        };
+
+        if let AvdtpSignalIdentifier::SetConfiguration | AvdtpSignalIdentifier::Reconfigure =
+            command.signal
+        {
+            self.add_signal(packet, AudioSignal::ConfigurationRejected);
+        }
+        self.report(
+            handle,
+            Finding::new(packet, Severity::Warning, message).since(&command.packet),
+        );
+    }
+
+    fn report_capabilities(
+        &mut self,
+        handle: ConnectionHandle,
+        commander: Sender,
+        command: &PendingCommand,
+        capabilities: &[u8],
+        packet: &Packet,
+    ) {
+        let codec = match describe_codec(capabilities) {
+            Some(codec) => codec,
+            None => return,
+        };
+        let message = format!(
+            "{:?} SEID {} supports {}",
+            commander.other(),
+            command.acp_seids.first().copied().unwrap_or_default(),
+            codec
+        );
+        self.report(handle, Finding::new(packet, Severity::Info, message).since(&command.packet));
+    }
+
+    fn check_storm(
+        &mut self,
+        handle: ConnectionHandle,
+        commander: Sender,
+        seid: Seid,
+        packet: &Packet,
+    ) {
+        let (window_ms, threshold) = (self.storm_window_ms, self.storm_threshold);
+        let stream = match self.links.get_mut(&handle).and_then(|l| l.find_stream(commander, seid))
+        {
+            Some(stream) => stream,
+            None => return,
+        };
+        while let Some(oldest) = stream.recent.front() {
+            if packet.ts.signed_duration_since(oldest.ts).num_milliseconds() <= window_ms {
+                break;
+            }
+            stream.recent.pop_front();
+        }
+        stream.recent.push_back(packet.clone());
+        if (stream.recent.len() as i64) < threshold {
+            return;
+        }
+
+        let first = stream.recent.front().cloned().unwrap_or_else(|| packet.clone());
+        let message = format!(
+            "Stream of {} started and suspended {} times in {} ms",
+            stream.codec,
+            stream.recent.len(),
+            packet.ts.signed_duration_since(first.ts).num_milliseconds()
+        );
+        stream.recent.clear();
+        self.add_signal(packet, AudioSignal::StartSuspendStorm);
+        self.report(handle, Finding::new(packet, Severity::Warning, message).since(&first));
+    }
+
+    fn finish_stream(
+        &mut self,
+        handle: ConnectionHandle,
+        mut stream: Stream,
+        how: &str,
+        packet: &Packet,
+    ) {
+        if let Some(started) = stream.started.take() {
+            stream.streaming_ms += packet.ts.signed_duration_since(started.ts).num_milliseconds();
+        }
+
+        if let (Some((_, opened)), 0) = (&stream.media, stream.starts) {
+            let message = format!(
+                "Media channel for {} was opened but the stream {} without ever starting",
+                stream.codec, how
+            );
+            let finding = Finding::new(packet, Severity::Warning, message).since(opened);
+            self.add_signal(packet, AudioSignal::NeverStarted);
+            self.report(handle, finding);
+            return;
+        }
+
+        let message = format!(
+            "Stream of {} {}: started {} times, streamed for {} ms",
+            stream.codec, how, stream.starts, stream.streaming_ms
+        );
+        self.report(
+            handle,
+            Finding::new(packet, Severity::Info, message).since(&stream.configured),
+        );
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        self.channels.remove_link(handle);
+        if let Some(link) = self.links.remove(&handle) {
+            for stream in link.streams {
+                self.finish_stream(handle, stream, "disconnected", packet);
+            }
+        }
+        self.handles.remove(&handle);
+    }
+
+    fn process_reset(&mut self) {
+        self.handles.clear();
+        self.channels.clear();
+        self.links.clear();
+    }
+}
+
+impl Rule for AvdtpStreamsRule {
+    fn name(&self) -> &'static str {
+        "AvdtpStreamsRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        self.last_ts = Some(packet.ts);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                if let CommandChild::Reset(_) = cmd.specialize() {
+                    self.process_reset();
+                }
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::ConnectionComplete(ev) if ev.get_status() == ErrorCode::Success => {
+                    self.handles.insert(ev.get_connection_handle(), ev.get_bd_addr());
+                }
+                EventChild::DisconnectionComplete(ev) => {
+                    self.process_disconnection(ev.get_connection_handle(), packet);
+                }
+
+                // PacketChild::HciEvent(ev).specialize()
+                _ => {}
+            },
+
+            PacketChild::AclTx(tx) => self.process_acl(tx, Sender::Host, packet),
+            PacketChild::AclRx(rx) => self.process_acl(rx, Sender::Peer, packet),
+
+            // packet.inner
+            _ => {}
+        }
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        let last_ts = match self.last_ts {
+            Some(ts) => ts,
+            None => return findings,
+        };
+        for (handle, link) in self.links.iter() {
+            for stream in link.streams.iter() {
+                let finding = match (&stream.media, stream.starts) {
+                    (Some((_, opened)), 0) => Finding::new(
+                        opened,
+                        Severity::Warning,
+                        format!(
+                            "Media channel for {} opened but never started by the end of the log",
+                            stream.codec
+                        ),
+                    ),
+                    _ => {
+                        let mut streaming_ms = stream.streaming_ms;
+                        if let Some(started) = &stream.started {
+                            streaming_ms +=
+                                last_ts.signed_duration_since(started.ts).num_milliseconds();
+                        }
+                        Finding::new(
+                            &stream.configured,
+                            Severity::Info,
+                            format!(
+                                "Stream of {} still configured at the end of the log: started {} \
+                                 times, streamed for {} ms",
+                                stream.codec, stream.starts, streaming_ms
+                            ),
+                        )
+                    }
+                };
+                let finding = finding.with_handle(*handle);
+                findings.push(match self.handles.get(handle) {
+                    Some(address) => finding.with_address(*address),
+                    None => finding,
+                });
+            }
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            AudioSignal::ConfigurationRejected,
+            AudioSignal::StartSuspendStorm,
+            AudioSignal::NeverStarted,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// HF supported features sent in AT+BRSF (HFP 1.8, 4.34.2).
+const HF_FEATURE_THREE_WAY_CALLING: u32 = 1 << 1;
+const HF_FEATURE_CODEC_NEGOTIATION: u32 = 1 << 7;
+
+/// AG supported features sent in +BRSF.
+const AG_FEATURE_THREE_WAY_CALLING: u32 = 1 << 0;
+const AG_FEATURE_CODEC_NEGOTIATION: u32 = 1 << 9;
+
+fn get_hfp_codec_name(id: &str) -> String {
+    match id {
+        "1" => "CVSD".to_string(),
+        "2" => "mSBC".to_string(),
+        "3" => "LC3-SWB".to_string(),
+        _ => format!("codec {}", id),
+    }
+}
+
+/// Split an AT command into its name with the command type, like `CIND=?` or `BRSF=`, and its
+/// arguments. Returns None for an empty command.
+fn split_at_command(line: &str) -> Option<(String, Vec<String>)> {
+    let command = line.strip_prefix("AT+").unwrap_or(line);
+    if command.is_empty() {
+        return None;
+    }
+
+    if let Some(name) = command.strip_suffix("=?") {
+        Some((format!("{}=?", name), vec![]))
+    } else if let Some((name, args)) = command.split_once('=') {
+        let args = match args {
+            "" => vec![],
+            _ => args.split(',').map(str::to_string).collect(),
+        };
+        Some((format!("{}=", name), args))
+    } else {
+        // Query commands end with '?', anything else is executed as is.
+        Some((command.to_string(), vec![]))
+    }
+}
+
+/// A line of the AT dialogue.
+struct TranscriptLine {
+    index: usize,
+    ts: NaiveDateTime,
+    from_hf: bool,
+    line: String,
+}
+
+/// The AT dialogue on one RFCOMM channel.
+struct Transcript {
+    handle: ConnectionHandle,
+    dlci: Dlci,
+    lines: Vec<TranscriptLine>,
+}
+
+/// A command waiting for OK or ERROR.
+struct PendingAtCommand {
+    packet: Packet,
+
+    /// Command name with its type, like `CIND=?`.
+    name: String,
+
+    args: Vec<String>,
+}
+
+/// State of an RFCOMM channel used for AT commands, by HFP or HSP.
+struct AtChannel {
+    /// Side sending the AT commands.
+    hf: Sender,
+
+    /// Partial lines not terminated yet, for each side.
+    buffers: HashMap<Sender, String>,
+
+    transcript: Transcript,
+
+    pending: Option<PendingAtCommand>,
+
+    hf_features: u32,
+    ag_features: u32,
+
+    /// Set once the HF starts the service level connection with AT+BRSF.
+    slc_started: Option<Packet>,
+
+    /// SLC commands that were accepted, in order.
+    slc_steps: Vec<String>,
+
+    slc_complete: bool,
+
+    /// Codec proposed by the AG with +BCS and not confirmed yet.
+    proposed_codec: Option<(String, Packet)>,
+
+    /// Codec agreed on for the next audio connection.
+    selected_codec: Option<(String, Packet)>,
+}
+
+impl AtChannel {
+    fn new(hf: Sender, handle: ConnectionHandle, dlci: Dlci) -> Self {
+        AtChannel {
+            hf,
+            buffers: HashMap::new(),
+            transcript: Transcript { handle, dlci, lines: vec![] },
+            pending: None,
+            hf_features: 0,
+            ag_features: 0,
+            slc_started: None,
+            slc_steps: vec![],
+            slc_complete: false,
+            proposed_codec: None,
+            selected_codec: None,
+        }
+    }
+
+    /// Three-way calling needs AT+CHLD=? before the SLC is complete.
+    fn needs_chld(&self) -> bool {
+        self.hf_features & HF_FEATURE_THREE_WAY_CALLING != 0
+            && self.ag_features & AG_FEATURE_THREE_WAY_CALLING != 0
+    }
+
+    fn uses_codec_negotiation(&self) -> bool {
+        self.hf_features & HF_FEATURE_CODEC_NEGOTIATION != 0
+            && self.ag_features & AG_FEATURE_CODEC_NEGOTIATION != 0
+    }
+}
+
+/// Reconstructs the AT command dialogue on HFP and HSP channels and checks the service level
+/// connection setup, codec negotiation and the audio connections that follow it.
+struct HfpRule {
+    /// Addresses of active links.
+    handles: HashMap<ConnectionHandle, Address>,
+
+    channels: L2capChannels,
+
+    /// AT channels keyed by link, host CID of the RFCOMM channel and DLCI.
+    at_channels: HashMap<(ConnectionHandle, Cid, Dlci), AtChannel>,
+
+    /// Transcripts of AT channels that were closed.
+    transcripts: Vec<Transcript>,
+
+    /// Whether to include the AT dialogue in the reports.
+    print_transcript: bool,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl HfpRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        HfpRule {
+            handles: HashMap::new(),
+            channels: L2capChannels::new(),
+            at_channels: HashMap::new(),
+            transcripts: vec![],
+            print_transcript: config.get_bool("HfpRule", "transcript", true),
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: AudioSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn report(&mut self, handle: ConnectionHandle, finding: Finding) {
+        let finding = match self.handles.get(&handle) {
+            Some(address) => finding.with_address(*address),
+            None => finding,
+        };
+        self.reportable.push(finding.with_handle(handle));
+    }
+
+    fn process_acl(&mut self, acl: &Acl, sender: Sender, packet: &Packet) {
+        let handle = acl.get_handle();
+        match get_acl_content(acl) {
+            AclContent::Control(control) => {
+                if let Some(ChannelEvent::Closed(channel)) =
+                    self.channels.process_control(handle, sender, &control)
+                {
+                    if channel.psm == RFCOMM_PSM {
+                        self.close_at_channels(
+                            |key| key.0 == handle && key.1 == channel.host_cid,
+                            packet,
+                        );
+                    }
+                }
+            }
+            AclContent::StandardData(cid, data) => {
+                let host_cid = match self.channels.get(handle, sender, cid) {
+                    Some(channel) if channel.psm == RFCOMM_PSM => channel.host_cid,
+                    _ => return,
+                };
+                let frame = match RfcommFrame::try_from(data.as_slice()) {
+                    Ok(frame) => frame,
+                    Err(_) => return,
+                };
+                match frame.frame_type {
+                    // Closing DLCI 0 closes the whole multiplexer.
+                    RfcommFrameType::Disc | RfcommFrameType::Dm => {
+                        let dlci = frame.dlci;
+                        self.close_at_channels(
+                            |key| {
+                                key.0 == handle && key.1 == host_cid && (dlci == 0 || key.2 == dlci)
+                            },
+                            packet,
+                        );
+                    }
+                    RfcommFrameType::Uih if frame.dlci != 0 => {
+                        self.process_at_data(
+                            handle,
+                            host_cid,
+                            frame.dlci,
+                            sender,
+                            &frame.payload,
+                            packet,
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_at_data(
+        &mut self,
+        handle: ConnectionHandle,
+        host_cid: Cid,
+        dlci: Dlci,
+        sender: Sender,
+        payload: &[u8],
+        packet: &Packet,
+    ) {
+        let key = (handle, host_cid, dlci);
+        if let Entry::Vacant(entry) = self.at_channels.entry(key) {
+            // Other profiles use RFCOMM too, only follow channels where AT commands are seen.
+            if payload.len() < 2 || !payload[..2].eq_ignore_ascii_case(b"AT") {
+                return;
+            }
+            entry.insert(AtChannel::new(sender, handle, dlci));
+        }
+
+        let lines: Vec<String> = {
+            let channel = match self.at_channels.get_mut(&key) {
+                Some(channel) => channel,
+                None => return,
+            };
+            let buffer = channel.buffers.entry(sender).or_default();
+            buffer.push_str(&String::from_utf8_lossy(payload));
+
+            // Commands end with a carriage return, results are surrounded by CR LF.
+            let mut lines = vec![];
+            while let Some(end) = buffer.find(['\r', '\n']) {
+                let line = buffer[..end].trim().to_string();
+                buffer.drain(..=end);
+                if !line.is_empty() {
+                    lines.push(line);
+                }
+            }
+            lines
+        };
+
+        for line in lines {
+            let from_hf = match self.at_channels.get_mut(&key) {
+                Some(channel) => {
+                    let from_hf = channel.hf == sender;
+                    channel.transcript.lines.push(TranscriptLine {
+                        index: packet.index,
+                        ts: packet.ts,
+                        from_hf,
+                        line: line.clone(),
+                    });
+                    from_hf
+                }
+                None => return,
+            };
+            match from_hf {
+                true => self.process_at_command(key, line, packet),
+                false => self.process_at_result(key, line, packet),
+            }
+        }
+    }
+
+    fn process_at_command(
+        &mut self,
+        key: (ConnectionHandle, Cid, Dlci),
+        line: String,
+        packet: &Packet,
+    ) {
+        let (name, args) = match split_at_command(&line) {
+            Some(command) => command,
+            None => return,
+        };
+
+        let mut failure = None;
+        if let Some(channel) = self.at_channels.get_mut(&key) {
+            match name.as_str() {
+                "BRSF=" => {
+                    channel.hf_features =
+                        args.first().and_then(|f| f.parse().ok()).unwrap_or_default();
+                    channel.slc_started = Some(packet.clone());
+                    channel.slc_steps.clear();
+                    channel.slc_complete = false;
+                }
+                "BCS=" => {
+                    let codec = args.first().cloned().unwrap_or_default();
+                    match channel.proposed_codec.take() {
+                        Some((proposed, since)) if proposed != codec => {
+                            failure = Some((
+                                format!(
+                                    "AG proposed {} but HF confirmed {}",
+                                    get_hfp_codec_name(&proposed),
+                                    get_hfp_codec_name(&codec)
+                                ),
+                                since,
+                            ));
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+            channel.pending = Some(PendingAtCommand { packet: packet.clone(), name, args });
+        }
+
+        if let Some((message, since)) = failure {
+            self.add_signal(packet, AudioSignal::CodecNegotiationFailed);
+            self.report(key.0, Finding::new(packet, Severity::Warning, message).since(&since));
+        }
+    }
+
+    fn process_at_result(
+        &mut self,
+        key: (ConnectionHandle, Cid, Dlci),
+        line: String,
+        packet: &Packet,
+    ) {
+        let channel = match self.at_channels.get_mut(&key) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let mut findings: Vec<(Severity, String, Packet, Option<AudioSignal>)> = vec![];
+        if let Some(features) = line.strip_prefix("+BRSF:") {
+            channel.ag_features = features.trim().parse().unwrap_or_default();
+        } else if let Some(codec) = line.strip_prefix("+BCS:") {
+            if let Some((previous, since)) = channel.proposed_codec.take() {
+                findings.push((
+                    Severity::Warning,
+                    format!(
+                        "AG proposed {} again before HF confirmed {}",
+                        get_hfp_codec_name(codec.trim()),
+                        get_hfp_codec_name(&previous)
+                    ),
+                    since,
+                    Some(AudioSignal::CodecNegotiationFailed),
+                ));
+            }
+            channel.proposed_codec = Some((codec.trim().to_string(), packet.clone()));
+        } else if line == "OK" {
+            if let Some(pending) = channel.pending.take() {
+                if channel.slc_started.is_some() && !channel.slc_complete {
+                    channel.slc_steps.push(pending.name.clone());
+                }
+                match pending.name.as_str() {
+                    "BCS=" => {
+                        let codec = pending.args.first().cloned().unwrap_or_default();
+                        channel.selected_codec = Some((codec, packet.clone()));
+                    }
+                    "CMER=" | "CHLD=?" if !channel.slc_complete => {
+                        let done = channel.slc_steps.iter().any(|s| s == "CMER=")
+                            && (!channel.needs_chld()
+                                || channel.slc_steps.iter().any(|s| s == "CHLD=?"));
+                        if let (true, Some(started)) = (done, &channel.slc_started) {
+                            channel.slc_complete = true;
+                            findings.push((
+                                Severity::Info,
+                                format!(
+                                    "Service level connection established in {} ms, HF features \
+                                     0x{:x}, AG features 0x{:x}{}",
+                                    packet.ts.signed_duration_since(started.ts).num_milliseconds(),
+                                    channel.hf_features,
+                                    channel.ag_features,
+                                    match channel.uses_codec_negotiation() {
+                                        true => ", with codec negotiation",
+                                        false => "",
+                                    }
+                                ),
+                                started.clone(),
+                                None,
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        } else if line == "ERROR" || line.starts_with("+CME ERROR") {
+            if let Some(pending) = channel.pending.take() {
+                let codec_command = pending.name == "BAC=" || pending.name == "BCS=";
+                if codec_command || (channel.slc_started.is_some() && !channel.slc_complete) {
+                    findings.push((
+                        Severity::Warning,
+                        format!(
+                            "AT+{} failed with {}{}",
+                            pending.name,
+                            line,
+                            match codec_command {
+                                true => " during codec negotiation",
+                                false => " during service level connection setup",
+                            }
+                        ),
+                        pending.packet,
+                        match codec_command {
+                            true => Some(AudioSignal::CodecNegotiationFailed),
+                            false => None,
+                        },
+                    ));
+                }
+            }
+        }
+
+        for (severity, message, since, signal) in findings {
+            if let Some(signal) = signal {
+                self.add_signal(packet, signal);
+            }
+            self.report(key.0, Finding::new(packet, severity, message).since(&since));
+        }
+    }
+
+    fn process_sco_complete(&mut self, address: Address, status: ErrorCode, packet: &Packet) {
+        let handles: Vec<ConnectionHandle> = self
+            .handles
+            .iter()
+            .filter(|(_, a)| **a == address)
+            .map(|(handle, _)| *handle)
+            .collect();
+
+        let mut failures = vec![];
+        for (key, channel) in self.at_channels.iter_mut() {
+            if !handles.contains(&key.0) {
+                continue;
+            }
+            // Each audio connection is preceded by its own codec negotiation.
+            if let Some((codec, since)) = channel.selected_codec.take() {
+                if status != ErrorCode::Success {
+                    failures.push((key.0, codec, since));
+                }
+            }
+        }
+
+        for (handle, codec, since) in failures {
+            self.add_signal(packet, AudioSignal::ScoFailedAfterCodec);
+            let message = format!(
+                "Audio connection failed with {:?} after {} was selected",
+                status,
+                get_hfp_codec_name(&codec)
+            );
+            self.report(handle, Finding::new(packet, Severity::Warning, message).since(&since));
+        }
+    }
+
+    fn close_at_channels<F>(&mut self, matches: F, packet: &Packet)
+    where
+        F: Fn(&(ConnectionHandle, Cid, Dlci)) -> bool,
+    {
+        let mut keys: Vec<(ConnectionHandle, Cid, Dlci)> =
+            self.at_channels.keys().filter(|key| matches(key)).cloned().collect();
+        keys.sort();
+        for key in keys {
+            if let Some(channel) = self.at_channels.remove(&key) {
+                self.finish_at_channel(channel, packet);
+            }
+        }
+    }
+
+    fn finish_at_channel(&mut self, channel: AtChannel, packet: &Packet) {
+        let handle = channel.transcript.handle;
+        if let (Some(started), false) = (&channel.slc_started, channel.slc_complete) {
+            let message = format!(
+                "Service level connection setup never completed, last accepted step was {}",
+                match channel.slc_steps.last() {
+                    Some(step) => format!("AT+{}", step.trim_end_matches('=')),
+                    None => "none".to_string(),
+                }
+            );
+            self.add_signal(packet, AudioSignal::SlcIncomplete);
+            self.report(handle, Finding::new(packet, Severity::Warning, message).since(started));
+        }
+        if let Some((codec, since)) = &channel.proposed_codec {
+            let message =
+                format!("HF never confirmed {} proposed by the AG", get_hfp_codec_name(codec));
+            self.add_signal(packet, AudioSignal::CodecNegotiationFailed);
+            self.report(handle, Finding::new(packet, Severity::Warning, message).since(since));
+        }
+        self.transcripts.push(channel.transcript);
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        self.channels.remove_link(handle);
+        self.close_at_channels(|key| key.0 == handle, packet);
+        self.handles.remove(&handle);
+    }
+
+    fn process_reset(&mut self, packet: &Packet) {
+        self.close_at_channels(|_| true, packet);
+        self.handles.clear();
+        self.channels.clear();
+    }
+
+    fn describe_transcript(&self, transcript: &Transcript) -> String {
+        let device = match self.handles.get(&transcript.handle) {
+            Some(address) => format!("{}", address),
+            None => "unknown device".to_string(),
+        };
+        format!(
+            "AT transcript for {} (handle {}, DLCI {})",
+            device, transcript.handle, transcript.dlci
+        )
+    }
+
+    fn write_transcript(&self, writer: &mut dyn Write, transcript: &Transcript) {
+        let _ = writeln!(writer, "{}:", self.describe_transcript(transcript));
+        for line in transcript.lines.iter() {
+            let side = if line.from_hf { "HF" } else { "AG" };
+            let _ = writeln!(writer, "  [{:?}] {}: {}", line.ts, side, line.line);
+        }
+    }
+
+    /// The whole AT dialogue of a channel as one finding, for the structured reports.
+    fn transcript_finding(&self, transcript: &Transcript) -> Option<Finding> {
+        let (first, last) = (transcript.lines.first()?, transcript.lines.last()?);
+        let lines: Vec<String> = transcript
+            .lines
+            .iter()
+            .map(|line| format!("{}: {}", if line.from_hf { "HF" } else { "AG" }, line.line))
+            .collect();
+        Some(Finding {
+            severity: Severity::Info,
+            start_index: first.index,
+            start_ts: first.ts,
+            end_index: last.index,
+            end_ts: last.ts,
+            addresses: self.handles.get(&transcript.handle).into_iter().cloned().collect(),
+            handles: vec![transcript.handle],
+            message: format!("{}: {}", self.describe_transcript(transcript), lines.join("; ")),
+        })
+    }
+
+    /// Transcripts of closed channels, then those of the channels still open.
+    fn all_transcripts(&self) -> Vec<&Transcript> {
+        let mut transcripts: Vec<&Transcript> = self.transcripts.iter().collect();
+        let mut active: Vec<&AtChannel> = self.at_channels.values().collect();
+        active.sort_by_key(|c| (c.transcript.handle, c.transcript.dlci));
+        transcripts.extend(active.into_iter().map(|c| &c.transcript));
+        transcripts
+    }
+
+    /// Findings about the AT channels, without the transcripts.
+    fn channel_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        let mut channels: Vec<&AtChannel> = self.at_channels.values().collect();
+        channels.sort_by_key(|c| (c.transcript.handle, c.transcript.dlci));
+        for channel in channels {
+            if let (Some(started), false) = (&channel.slc_started, channel.slc_complete) {
+                let handle = channel.transcript.handle;
+                let finding = Finding::new(
+                    started,
+                    Severity::Warning,
+                    "Service level connection setup still not completed at the end of the log"
+                        .to_string(),
+                )
+                .with_handle(handle);
+                findings.push(match self.handles.get(&handle) {
+                    Some(address) => finding.with_address(*address),
+                    None => finding,
+                });
+            }
+        }
+
+        findings
+    }
+}
+
+impl Rule for HfpRule {
+    fn name(&self) -> &'static str {
+        "HfpRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                if let CommandChild::Reset(_) = cmd.specialize() {
+                    self.process_reset(packet);
+                }
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::ConnectionComplete(ev) if ev.get_status() == ErrorCode::Success => {
+                    self.handles.insert(ev.get_connection_handle(), ev.get_bd_addr());
+                }
+                EventChild::DisconnectionComplete(ev) => {
+                    self.process_disconnection(ev.get_connection_handle(), packet);
+                }
+                EventChild::SynchronousConnectionComplete(ev) => {
+                    self.process_sco_complete(ev.get_bd_addr(), ev.get_status(), packet);
+                }
+
+                // PacketChild::HciEvent(ev).specialize()
+                _ => {}
+            },
+
+            PacketChild::AclTx(tx) => self.process_acl(tx, Sender::Host, packet),
+            PacketChild::AclRx(rx) => self.process_acl(rx, Sender::Peer, packet),
+
+            // packet.inner
+            _ => {}
+        }
+    }
+
+    /// Findings are followed by the AT dialogue of every channel.
+    fn report(&self, writer: &mut dyn Write) {
+        let findings = self.channel_findings();
+        let transcripts = self.all_transcripts();
+        if findings.is_empty() && (!self.print_transcript || transcripts.is_empty()) {
+            return;
+        }
+
+        let _ = writeln!(writer, "{} report:", self.name());
+        for finding in findings.iter() {
+            let _ = writeln!(writer, "[{:?}] {}", finding.start_ts, finding.message);
+        }
+        if self.print_transcript {
+            for transcript in transcripts {
+                self.write_transcript(writer, transcript);
+            }
+        }
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.channel_findings();
+        if self.print_transcript {
+            findings.extend(
+                self.all_transcripts().into_iter().filter_map(|t| self.transcript_finding(t)),
+            );
+        }
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            AudioSignal::SlcIncomplete,
+            AudioSignal::CodecNegotiationFailed,
+            AudioSignal::ScoFailedAfterCodec,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// Get a rule group with audio rules.
+pub fn get_audio_group(config: &RuleConfig) -> RuleGroup {
+    let mut group = RuleGroup::new();
+    group.add_rule(Box::new(AvdtpStreamsRule::new(config)));
+    group.add_rule(Box::new(HfpRule::new(config)));
+
+    group
+}
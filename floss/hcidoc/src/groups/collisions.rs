@@ -1,9 +1,10 @@
 ///! Rule group for tracking command collision issues.
 use chrono::NaiveDateTime;
 use std::convert::Into;
-use std::io::Write;
 
-use crate::engine::{Rule, RuleGroup, Signal};
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
 use crate::parser::{Packet, PacketChild};
 use bt_packets::hci::{ErrorCode, EventChild, OpCode};
 
@@ -14,14 +15,47 @@ enum CollisionSignal {
     InquirySelf,
 }
 
+impl From<CollisionSignal> for SignalDefinition {
+    fn from(signal: CollisionSignal) -> Self {
+        match signal {
+            CollisionSignal::RnrAndInquiry => SignalDefinition {
+                tag: "RnR-Inquiry-Collision",
+                severity: Severity::Warning,
+                description: "A remote name request and an inquiry were disallowed because they \
+                    overlapped.",
+                next_step: "Check that the host waits for the inquiry or remote name request to \
+                    complete before starting the other.",
+            },
+            CollisionSignal::RnrAndConnection => SignalDefinition {
+                tag: "RnR-Connection-Collision",
+                severity: Severity::Warning,
+                description: "A remote name request and a connection attempt were disallowed \
+                    because they overlapped.",
+                next_step:
+                    "Check that the host waits for the connection or remote name request to \
+                    complete before starting the other.",
+            },
+            CollisionSignal::InquiryAndConnection => SignalDefinition {
+                tag: "Inquiry-Connection-Collision",
+                severity: Severity::Warning,
+                description: "An inquiry and a connection attempt were disallowed because they \
+                    overlapped.",
+                next_step: "Check whether the controller supports connecting during inquiry and \
+                    whether the host should cancel the inquiry first.",
+            },
+            CollisionSignal::InquirySelf => SignalDefinition {
+                tag: "Inquiry-Self-Collision",
+                severity: Severity::Warning,
+                description: "An inquiry was disallowed because another inquiry was still running.",
+                next_step: "Look for two host components starting discovery at the same time.",
+            },
+        }
+    }
+}
+
 impl Into<&'static str> for CollisionSignal {
     fn into(self) -> &'static str {
-        match self {
-            CollisionSignal::RnrAndInquiry => "RnR-Inquiry-Collision",
-            CollisionSignal::RnrAndConnection => "RnR-Connection-Collision",
-            CollisionSignal::InquiryAndConnection => "Inquiry-Connection-Collision",
-            CollisionSignal::InquirySelf => "Inquiry-Self-Collision",
-        }
+        SignalDefinition::from(self).tag
     }
 }
 
@@ -47,7 +81,7 @@ struct ConnectionSerializationRule {
     signals: Vec<Signal>,
 
     /// Interesting occurrences surfaced by this rule.
-    reportable: Vec<(NaiveDateTime, String)>,
+    reportable: Vec<Finding>,
 }
 
 impl ConnectionSerializationRule {
@@ -97,6 +131,10 @@ impl ConnectionSerializationRule {
 }
 
 impl Rule for ConnectionSerializationRule {
+    fn name(&self) -> &'static str {
+        "ConnectionSerializationRule"
+    }
+
     fn process(&mut self, packet: &Packet) {
         match &packet.inner {
             PacketChild::HciEvent(ev) => match ev.specialize() {
@@ -126,11 +164,13 @@ impl Rule for ConnectionSerializationRule {
                         // We've hit a disallowed status. Check if we're
                         // conflicting with something that should be serializable.
                         else if cs.get_status() == ErrorCode::CommandDisallowed {
-                            self.reportable.push((
-                                packet.ts,
+                            let finding = Finding::new(
+                                packet,
+                                Severity::Warning,
                                 format!("Command Status was 'Disallowed' on {:?}. Potential conflict with: {:?} at {:?}",
                                     cs.get_command_op_code(), self.state, self.state_set_at)
-                            ));
+                            );
+                            self.reportable.push(finding);
 
                             let signal: Option<CollisionSignal> =
                                 self.get_signal_type(&cs.get_command_op_code());
@@ -159,12 +199,14 @@ impl Rule for ConnectionSerializationRule {
                         self.state = CollisionState::Nothing;
                         self.state_set_at = None;
                     } else if rnr_ev.get_status() == ErrorCode::CommandDisallowed {
-                        self.reportable.push((
-                                packet.ts,
+                        let finding = Finding::new(
+                                packet,
+                                Severity::Warning,
                                 format!("Remote name req complete with disallowed. Potential conflict with: {:?} at {:?}",
                                     self.state, self.state_set_at)
 
-                        ));
+                        ).with_address(rnr_ev.get_bd_addr());
+                        self.reportable.push(finding);
 
                         // Insert signals based on current serializable state.
                         let signal = self.get_signal_type(&OpCode::RemoteNameRequest);
@@ -204,22 +246,28 @@ impl Rule for ConnectionSerializationRule {
         }
     }
 
-    fn report(&self, writer: &mut dyn Write) {
-        if self.reportable.len() > 0 {
-            let _ = writeln!(writer, "ConnectionSerializationRule report:");
-            for (ts, message) in self.reportable.iter() {
-                let _ = writeln!(writer, "[{:?}] {}", ts, message);
-            }
-        }
+    fn report_findings(&self) -> Vec<Finding> {
+        self.reportable.clone()
     }
 
     fn report_signals(&self) -> &[Signal] {
         self.signals.as_slice()
     }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            CollisionSignal::RnrAndInquiry,
+            CollisionSignal::RnrAndConnection,
+            CollisionSignal::InquiryAndConnection,
+            CollisionSignal::InquirySelf,
+        ] {
+            registry.register(signal.into());
+        }
+    }
 }
 
 /// Get a rule group with collision rules.
-pub fn get_collisions_group() -> RuleGroup {
+pub fn get_collisions_group(_config: &RuleConfig) -> RuleGroup {
     let mut group = RuleGroup::new();
     group.add_rule(Box::new(ConnectionSerializationRule::new()));
 
@@ -2,15 +2,16 @@
 use chrono::NaiveDateTime;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::Into;
-use std::io::Write;
 use std::slice::Iter;
 
-use crate::engine::{Rule, RuleGroup, Signal};
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
 use crate::parser::{Packet, PacketChild};
 use bt_packets::hci::{
-    Acl, AclCommandChild, Address, AuthenticatedPayloadTimeoutExpired, CommandChild,
-    ConnectionManagementCommandChild, DisconnectReason, Enable, ErrorCode, EventChild,
-    InitiatorFilterPolicy, LeConnectionManagementCommandChild, LeMetaEventChild,
+    Acl, AclChild, AclCommandChild, Address, AuthenticatedPayloadTimeoutExpired, CommandChild,
+    CommandCompleteChild, ConnectionManagementCommandChild, DisconnectReason, Enable, ErrorCode,
+    EventChild, InitiatorFilterPolicy, LeConnectionManagementCommandChild, LeMetaEventChild,
     LeSecurityCommandChild, NumberOfCompletedPackets, OpCode, ScoConnectionCommandChild,
     SecurityCommandChild,
 };
@@ -24,20 +25,93 @@ enum ConnectionSignal {
     RemoteFeatureNoReply, // Host doesn't receive a response for a remote feature request. (b/300851411)
     RemoteFeatureError,   // Controller replies error for remote feature request. (b/292116133)
     SecurityMode3,        // Peer uses the unsupported legacy security mode 3. (b/260625799)
+    AclStarvation,        // All controller ACL buffers stay in use for a long time.
+    AclCreditLeak,        // ACL packets are never completed before the link disconnects.
+}
+
+impl From<ConnectionSignal> for SignalDefinition {
+    fn from(signal: ConnectionSignal) -> Self {
+        match signal {
+            ConnectionSignal::LinkKeyMismatch => SignalDefinition {
+                tag: "LinkKeyMismatch",
+                severity: Severity::Error,
+                description: "Authentication failed because the peer forgot the link key or it \
+                    mismatches ours.",
+                next_step:
+                    "Check whether the peer was reset or paired with another host, the bond \
+                    needs to be removed and created again.",
+            },
+            ConnectionSignal::LongTermKeyMismatch => SignalDefinition {
+                tag: "LongTermKeyMismatch",
+                severity: Severity::Error,
+                description:
+                    "LE encryption failed because the peer forgot the long term key or it \
+                    mismatches ours.",
+                next_step:
+                    "Check whether the peer was reset or paired with another host, the bond \
+                    needs to be removed and created again.",
+            },
+            ConnectionSignal::NocpDisconnect | ConnectionSignal::NocpTimeout => SignalDefinition {
+                tag: "Nocp",
+                severity: Severity::Warning,
+                description: "ACL data sent to the controller was not completed in time or before \
+                    the link went down.",
+                next_step:
+                    "Look at controller firmware logs around the time of the last Number Of \
+                    Completed Packets event.",
+            },
+            ConnectionSignal::ApteDisconnect => SignalDefinition {
+                tag: "AuthenticatedPayloadTimeoutExpired",
+                severity: Severity::Error,
+                description:
+                    "No packet with a valid MIC was received on an encrypted link for too \
+                    long.",
+                next_step:
+                    "Check the RF environment and the controller firmware, the link usually \
+                    drops soon after.",
+            },
+            ConnectionSignal::RemoteFeatureNoReply => SignalDefinition {
+                tag: "RemoteFeatureNoReply",
+                severity: Severity::Warning,
+                description: "The controller never completed a remote feature request.",
+                next_step: "Look for a controller hang or a link that went down while the request \
+                    was outstanding.",
+            },
+            ConnectionSignal::RemoteFeatureError => SignalDefinition {
+                tag: "RemoteFeatureError",
+                severity: Severity::Warning,
+                description: "The controller completed a remote feature request with an error.",
+                next_step: "Check whether the peer answered the LMP or LL feature exchange, the \
+                    error code tells which side gave up.",
+            },
+            ConnectionSignal::SecurityMode3 => SignalDefinition {
+                tag: "UnsupportedSecurityMode3",
+                severity: Severity::Error,
+                description: "The peer uses the legacy security mode 3, which is not supported.",
+                next_step: "Pairing with this peer can only work if the host allows security mode \
+                    3.",
+            },
+            ConnectionSignal::AclStarvation => SignalDefinition {
+                tag: "AclStarvation",
+                severity: Severity::Warning,
+                description: "All controller ACL buffers stayed in use for a long time.",
+                next_step: "Find the link the buffers are stuck on in the flow control report and \
+                    check whether the peer is still acknowledging data.",
+            },
+            ConnectionSignal::AclCreditLeak => SignalDefinition {
+                tag: "AclCreditLeak",
+                severity: Severity::Warning,
+                description: "ACL packets were stuck in the controller when the link disconnected.",
+                next_step: "Check whether the controller reports completed packets for links it \
+                    disconnects, otherwise the host leaks credits.",
+            },
+        }
+    }
 }
 
 impl Into<&'static str> for ConnectionSignal {
     fn into(self) -> &'static str {
-        match self {
-            ConnectionSignal::LinkKeyMismatch => "LinkKeyMismatch",
-            ConnectionSignal::LongTermKeyMismatch => "LongTermKeyMismatch",
-            ConnectionSignal::NocpDisconnect => "Nocp",
-            ConnectionSignal::NocpTimeout => "Nocp",
-            ConnectionSignal::ApteDisconnect => "AuthenticatedPayloadTimeoutExpired",
-            ConnectionSignal::RemoteFeatureError => "RemoteFeatureError",
-            ConnectionSignal::RemoteFeatureNoReply => "RemoteFeatureNoReply",
-            ConnectionSignal::SecurityMode3 => "UnsupportedSecurityMode3",
-        }
+        SignalDefinition::from(self).tag
     }
 }
 
@@ -50,16 +124,63 @@ pub const UNKNOWN_SCO_ADDRESS: [u8; 6] = [0xdeu8, 0xad, 0xbe, 0xef, 0x00, 0x00];
 
 /// The tolerance duration of not receiving an expected reply. If 5s elapsed and timeout occurs,
 /// we blame the pending event for causing timeout. This is used to detect NOCP and others.
+/// Can be overridden with the `timeout_tolerance_ms` parameter of OddDisconnectionsRule.
 pub const TIMEOUT_TOLERANCE_TIME_MS: i64 = 5000;
 
-pub(crate) struct NocpData {
-    /// Number of in-flight packets without a corresponding NOCP.
-    pub inflight_acl_ts: VecDeque<NaiveDateTime>,
+/// ACL packets sent by the host on each connection which the controller hasn't reported in a
+/// |Number of Completed Packets| event yet.
+#[derive(Default)]
+struct NocpTracker {
+    /// Timestamps of the in-flight packets, oldest first.
+    inflight_acl_ts: HashMap<ConnectionHandle, VecDeque<NaiveDateTime>>,
 }
 
-impl NocpData {
-    fn new() -> Self {
-        Self { inflight_acl_ts: VecDeque::new() }
+impl NocpTracker {
+    fn sent(&mut self, handle: ConnectionHandle, ts: NaiveDateTime) {
+        self.inflight_acl_ts.entry(handle).or_default().push_back(ts);
+    }
+
+    /// Complete |count| packets of |handle|. Returns when the oldest of them was sent.
+    fn complete(&mut self, handle: ConnectionHandle, count: u16) -> Option<NaiveDateTime> {
+        let inflight = self.inflight_acl_ts.get_mut(&handle).filter(|_| count > 0)?;
+        let oldest = inflight.front().cloned();
+        inflight.drain(..inflight.len().min(count.into()));
+        oldest
+    }
+
+    /// When the oldest packet still in flight on |handle| was sent.
+    fn oldest(&self, handle: ConnectionHandle) -> Option<NaiveDateTime> {
+        self.inflight_acl_ts.get(&handle).and_then(|inflight| inflight.front().cloned())
+    }
+
+    fn inflight(&self, handle: ConnectionHandle) -> usize {
+        self.inflight_acl_ts.get(&handle).map_or(0, |inflight| inflight.len())
+    }
+
+    /// Number of packets of |handle| that were in flight for longer than |ms| at |ts|.
+    fn inflight_longer_than(&self, handle: ConnectionHandle, ts: NaiveDateTime, ms: i64) -> usize {
+        self.inflight_acl_ts.get(&handle).map_or(0, |inflight| {
+            inflight
+                .iter()
+                .filter(|sent| ts.signed_duration_since(**sent).num_milliseconds() > ms)
+                .count()
+        })
+    }
+
+    /// Handles with packets in flight.
+    fn busy_handles(&self) -> impl Iterator<Item = ConnectionHandle> + '_ {
+        self.inflight_acl_ts
+            .iter()
+            .filter(|(_, inflight)| !inflight.is_empty())
+            .map(|(handle, _)| *handle)
+    }
+
+    fn remove(&mut self, handle: ConnectionHandle) {
+        self.inflight_acl_ts.remove(&handle);
+    }
+
+    fn clear(&mut self) {
+        self.inflight_acl_ts.clear();
     }
 }
 
@@ -101,7 +222,7 @@ struct OddDisconnectionsRule {
 
     /// Keep track of some number of |Number of Completed Packets| and filter to
     /// identify bursts.
-    nocp_by_handle: HashMap<ConnectionHandle, NocpData>,
+    nocp: NocpTracker,
 
     /// Number of |Authenticated Payload Timeout Expired| events hapened.
     apte_by_handle: HashMap<ConnectionHandle, u32>,
@@ -116,15 +237,18 @@ struct OddDisconnectionsRule {
     /// make this a special case.
     pending_disconnect_due_to_host_power_off: HashSet<ConnectionHandle>,
 
+    /// How long we wait for an expected reply before flagging it.
+    timeout_tolerance_ms: i64,
+
     /// Pre-defined signals discovered in the logs.
     signals: Vec<Signal>,
 
     /// Interesting occurrences surfaced by this rule.
-    reportable: Vec<(NaiveDateTime, String)>,
+    reportable: Vec<Finding>,
 }
 
 impl OddDisconnectionsRule {
-    pub fn new() -> Self {
+    pub fn new(config: &RuleConfig) -> Self {
         OddDisconnectionsRule {
             active_handles: HashMap::new(),
             connection_attempt: HashMap::new(),
@@ -135,13 +259,18 @@ impl OddDisconnectionsRule {
             sco_connection_attempt: HashMap::new(),
             last_sco_connection_attempt: None,
             accept_list: HashSet::new(),
-            nocp_by_handle: HashMap::new(),
+            nocp: NocpTracker::default(),
             apte_by_handle: HashMap::new(),
             pending_supported_feat: HashMap::new(),
             pending_extended_feat: HashMap::new(),
             pending_le_feat: HashMap::new(),
             last_feat_handle: HashMap::new(),
             pending_disconnect_due_to_host_power_off: HashSet::new(),
+            timeout_tolerance_ms: config.get_i64(
+                "OddDisconnectionsRule",
+                "timeout_tolerance_ms",
+                TIMEOUT_TOLERANCE_TIME_MS,
+            ),
             signals: vec![],
             reportable: vec![],
         }
@@ -150,10 +279,15 @@ impl OddDisconnectionsRule {
     fn process_classic_connection(&mut self, address: Address, packet: &Packet) {
         self.last_connection_attempt = Some(address);
         if let Some(p) = self.connection_attempt.insert(address, packet.clone()) {
-            self.reportable.push((
-                p.ts,
-                format!("Dangling connection attempt at {:?} replaced with {:?}", p, packet),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("Dangling connection attempt at {:?} replaced with {:?}", p, packet),
+                )
+                .since(&p)
+                .with_address(address),
+            );
         }
     }
 
@@ -167,10 +301,18 @@ impl OddDisconnectionsRule {
     fn process_sync_connection(&mut self, address: Address, packet: &Packet) {
         self.last_sco_connection_attempt = Some(address);
         if let Some(p) = self.sco_connection_attempt.insert(address, packet.clone()) {
-            self.reportable.push((
-                p.ts,
-                format!("Dangling sco connection attempt at {:?} replaced with {:?}", p, packet),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!(
+                        "Dangling sco connection attempt at {:?} replaced with {:?}",
+                        p, packet
+                    ),
+                )
+                .since(&p)
+                .with_address(address),
+            );
         }
     }
 
@@ -183,10 +325,15 @@ impl OddDisconnectionsRule {
         self.last_le_connection_attempt = Some(address);
         self.last_le_connection_filter_policy = Some(policy);
         if let Some(p) = self.le_connection_attempt.insert(address, packet.clone()) {
-            self.reportable.push((
-                p.ts,
-                format!("Dangling LE connection attempt at {:?} replaced with {:?}", p, packet),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("Dangling LE connection attempt at {:?} replaced with {:?}", p, packet),
+                )
+                .since(&p)
+                .with_address(address),
+            );
         }
     }
 
@@ -282,10 +429,14 @@ impl OddDisconnectionsRule {
 
         if let Some(address) = last_address {
             if status != ErrorCode::Success {
-                self.reportable.push((
-                    packet.ts,
-                    format!("Failing command status on [{}]: {:?}", address, opcode),
-                ));
+                self.reportable.push(
+                    Finding::new(
+                        packet,
+                        Severity::Error,
+                        format!("Failing command status on [{}]: {:?}", address, opcode),
+                    )
+                    .with_address(address),
+                );
 
                 // Also remove the connection attempt.
                 match opcode {
@@ -310,8 +461,9 @@ impl OddDisconnectionsRule {
             }
         } else {
             if status != ErrorCode::Success {
-                self.reportable.push((
-                    packet.ts,
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Error,
                     format!("Failing command status on unknown address: {:?}", opcode),
                 ));
             }
@@ -351,22 +503,32 @@ impl OddDisconnectionsRule {
             if status == ErrorCode::Success {
                 self.active_handles.insert(handle, (packet.ts, address));
             } else {
-                self.reportable.push((
-                    packet.ts,
+                self.reportable.push(
+                    Finding::new(
+                        packet,
+                        Severity::Error,
+                        format!(
+                            "ConnectionComplete error {:?} for addr {} (handle={})",
+                            status, address, handle
+                        ),
+                    )
+                    .with_address(address)
+                    .with_handle(handle),
+                );
+            }
+        } else {
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
                     format!(
-                        "ConnectionComplete error {:?} for addr {} (handle={})",
+                        "ConnectionComplete with status {:?} for unknown addr {} (handle={})",
                         status, address, handle
                     ),
-                ));
-            }
-        } else {
-            self.reportable.push((
-                packet.ts,
-                format!(
-                    "ConnectionComplete with status {:?} for unknown addr {} (handle={})",
-                    status, address, handle
-                ),
-            ));
+                )
+                .with_address(address)
+                .with_handle(handle),
+            );
         }
     }
 
@@ -379,25 +541,24 @@ impl OddDisconnectionsRule {
         self.active_handles.remove(&handle);
 
         // Check if this is a NOCP type disconnection and flag it.
-        if let Some(nocp_data) = self.nocp_by_handle.get_mut(&handle) {
-            if let Some(acl_front_ts) = nocp_data.inflight_acl_ts.pop_front() {
-                let duration_since_acl = packet.ts.signed_duration_since(acl_front_ts);
-                if duration_since_acl.num_milliseconds() > TIMEOUT_TOLERANCE_TIME_MS {
-                    self.signals.push(Signal {
-                        index: packet.index,
-                        ts: packet.ts,
-                        tag: ConnectionSignal::NocpDisconnect.into(),
-                    });
+        if let Some(acl_front_ts) = self.nocp.oldest(handle) {
+            let duration_since_acl = packet.ts.signed_duration_since(acl_front_ts);
+            if duration_since_acl.num_milliseconds() > self.timeout_tolerance_ms {
+                self.signals.push(Signal {
+                    index: packet.index,
+                    ts: packet.ts,
+                    tag: ConnectionSignal::NocpDisconnect.into(),
+                });
 
-                    self.reportable.push((
-                        packet.ts,
-                        format!("DisconnectionComplete for handle({}) showed incomplete in-flight ACL at {}",
-                        handle, acl_front_ts)));
-                }
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("DisconnectionComplete for handle({}) showed incomplete in-flight ACL at {}",
+                    handle, acl_front_ts)).with_handle(handle));
             }
         }
         // Remove nocp information for handles that were removed.
-        self.nocp_by_handle.remove(&handle);
+        self.nocp.remove(handle);
 
         // Check if auth payload timeout happened.
         if let Some(apte_count) = self.apte_by_handle.remove(&handle) {
@@ -407,10 +568,11 @@ impl OddDisconnectionsRule {
                 tag: ConnectionSignal::ApteDisconnect.into(),
             });
 
-            self.reportable.push((
-                packet.ts,
+            self.reportable.push(Finding::new(
+                packet,
+                Severity::Warning,
                 format!("DisconnectionComplete with {} Authenticated Payload Timeout Expired (handle={})",
-                apte_count, handle))
+                apte_count, handle)).with_handle(handle)
             );
         }
 
@@ -418,22 +580,26 @@ impl OddDisconnectionsRule {
         for feat_type in PendingRemoteFeature::iterate_all() {
             if let Some(ts) = self.get_feature_pending_map(feat_type).remove(&handle) {
                 let elapsed_time_ms = packet.ts.signed_duration_since(ts).num_milliseconds();
-                if elapsed_time_ms > TIMEOUT_TOLERANCE_TIME_MS {
+                if elapsed_time_ms > self.timeout_tolerance_ms {
                     self.signals.push(Signal {
                         index: packet.index,
                         ts: packet.ts,
                         tag: ConnectionSignal::RemoteFeatureNoReply.into(),
                     });
 
-                    self.reportable.push((
-                        packet.ts,
-                        format!(
-                            "Handle {} doesn't respond to {:?} feature request at {}.",
-                            handle,
-                            feat_type,
-                            ts.time()
-                        ),
-                    ));
+                    self.reportable.push(
+                        Finding::new(
+                            packet,
+                            Severity::Warning,
+                            format!(
+                                "Handle {} doesn't respond to {:?} feature request at {}.",
+                                handle,
+                                feat_type,
+                                ts.time()
+                            ),
+                        )
+                        .with_handle(handle),
+                    );
                 }
             }
         }
@@ -450,24 +616,30 @@ impl OddDisconnectionsRule {
             if status == ErrorCode::Success {
                 self.active_handles.insert(handle, (packet.ts, address));
             } else {
-                self.reportable.push((
-                    packet.ts,
-                    format!(
-                        "SynchronousConnectionComplete error {:?} for addr {} (handle={})",
-                        status, address, handle
-                    ),
-                ));
+                self.reportable.push(
+                    Finding::new(
+                        packet,
+                        Severity::Error,
+                        format!(
+                            "SynchronousConnectionComplete error {:?} for addr {} (handle={})",
+                            status, address, handle
+                        ),
+                    )
+                    .with_address(address)
+                    .with_handle(handle),
+                );
             }
         } else {
-            self.reportable.push((
-                packet.ts,
+            self.reportable.push(Finding::new(
+                packet,
+                Severity::Warning,
                 format!(
                     "SynchronousConnectionComplete with status {:?} for unknown addr {} (handle={})",
                     status,
                     address,
                     handle
                 ),
-            ));
+            ).with_address(address).with_handle(handle));
         }
     }
 
@@ -495,58 +667,57 @@ impl OddDisconnectionsRule {
                         status, address, handle
                     )
                 };
-                self.reportable.push((packet.ts, message));
+                self.reportable.push(
+                    Finding::new(packet, Severity::Error, message)
+                        .with_address(address)
+                        .with_handle(handle),
+                );
             }
         } else {
-            self.reportable.push((
-                packet.ts,
-                format!(
-                    "LeConnectionComplete with status {:?} for unknown addr {} (handle={})",
-                    status, address, handle
-                ),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!(
+                        "LeConnectionComplete with status {:?} for unknown addr {} (handle={})",
+                        status, address, handle
+                    ),
+                )
+                .with_address(address)
+                .with_handle(handle),
+            );
         }
     }
 
     fn process_acl_tx(&mut self, acl_tx: &Acl, packet: &Packet) {
-        let handle = acl_tx.get_handle();
-
-        // Insert empty Nocp data for handle if it doesn't exist.
-        if !self.nocp_by_handle.contains_key(&handle) {
-            self.nocp_by_handle.insert(handle, NocpData::new());
-        }
-
-        if let Some(nocp_data) = self.nocp_by_handle.get_mut(&handle) {
-            nocp_data.inflight_acl_ts.push_back(packet.ts);
-        }
+        self.nocp.sent(acl_tx.get_handle(), packet.ts);
     }
 
     fn process_nocp(&mut self, nocp: &NumberOfCompletedPackets, packet: &Packet) {
         let ts = &packet.ts;
         for completed_packet in nocp.get_completed_packets() {
             let handle = completed_packet.connection_handle;
-            if !self.nocp_by_handle.contains_key(&handle) {
-                self.nocp_by_handle.insert(handle, NocpData::new());
-            }
-
-            if let Some(nocp_data) = self.nocp_by_handle.get_mut(&handle) {
-                if let Some(acl_front_ts) = nocp_data.inflight_acl_ts.pop_front() {
-                    let duration_since_acl = ts.signed_duration_since(acl_front_ts);
-                    if duration_since_acl.num_milliseconds() > TIMEOUT_TOLERANCE_TIME_MS {
-                        self.signals.push(Signal {
-                            index: packet.index,
-                            ts: packet.ts,
-                            tag: ConnectionSignal::NocpTimeout.into(),
-                        });
-                        self.reportable.push((
-                            packet.ts,
+            // Only the oldest packet is checked, however many were completed.
+            if let Some(acl_front_ts) = self.nocp.complete(handle, 1) {
+                let duration_since_acl = ts.signed_duration_since(acl_front_ts);
+                if duration_since_acl.num_milliseconds() > self.timeout_tolerance_ms {
+                    self.signals.push(Signal {
+                        index: packet.index,
+                        ts: packet.ts,
+                        tag: ConnectionSignal::NocpTimeout.into(),
+                    });
+                    self.reportable.push(
+                        Finding::new(
+                            packet,
+                            Severity::Warning,
                             format!(
                                 "Nocp sent {} ms after ACL on handle({}).",
                                 duration_since_acl.num_milliseconds(),
                                 handle
                             ),
-                        ));
-                    }
+                        )
+                        .with_handle(handle),
+                    );
                 }
             }
         }
@@ -567,10 +738,14 @@ impl OddDisconnectionsRule {
         let feat_map = self.get_feature_pending_map(&feat_type);
 
         if feat_map.remove(&handle) == None {
-            self.reportable.push((
-                packet.ts,
-                format!("Got remote {:?} for unknown handle {}", feat_type, handle),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("Got remote {:?} for unknown handle {}", feat_type, handle),
+                )
+                .with_handle(handle),
+            );
         }
 
         if status != ErrorCode::Success {
@@ -580,10 +755,17 @@ impl OddDisconnectionsRule {
                 tag: ConnectionSignal::RemoteFeatureError.into(),
             });
 
-            self.reportable.push((
-                packet.ts,
-                format!("Got {:?} for remote {:?} feature, handle {}", status, feat_type, handle),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!(
+                        "Got {:?} for remote {:?} feature, handle {}",
+                        status, feat_type, handle
+                    ),
+                )
+                .with_handle(handle),
+            );
         }
     }
 
@@ -597,7 +779,7 @@ impl OddDisconnectionsRule {
         self.sco_connection_attempt.clear();
         self.last_sco_connection_attempt = None;
         self.accept_list.clear();
-        self.nocp_by_handle.clear();
+        self.nocp.clear();
         self.apte_by_handle.clear();
         self.pending_supported_feat.clear();
         self.pending_extended_feat.clear();
@@ -608,6 +790,10 @@ impl OddDisconnectionsRule {
 }
 
 impl Rule for OddDisconnectionsRule {
+    fn name(&self) -> &'static str {
+        "OddDisconnectionsRule"
+    }
+
     fn process(&mut self, packet: &Packet) {
         match &packet.inner {
             PacketChild::HciCommand(cmd) => match cmd.specialize() {
@@ -798,21 +984,30 @@ impl Rule for OddDisconnectionsRule {
 
             // We don't do anything with RX packets yet.
             PacketChild::AclRx(_) => (),
+
+            // Adapter lifetime is handled by the engine.
+            _ => (),
         }
     }
 
-    fn report(&self, writer: &mut dyn Write) {
-        if self.reportable.len() > 0 {
-            let _ = writeln!(writer, "OddDisconnectionsRule report:");
-            for (ts, message) in self.reportable.iter() {
-                let _ = writeln!(writer, "[{:?}] {}", ts, message);
-            }
-        }
+    fn report_findings(&self) -> Vec<Finding> {
+        self.reportable.clone()
     }
 
     fn report_signals(&self) -> &[Signal] {
         self.signals.as_slice()
     }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            ConnectionSignal::NocpDisconnect,
+            ConnectionSignal::ApteDisconnect,
+            ConnectionSignal::RemoteFeatureNoReply,
+            ConnectionSignal::RemoteFeatureError,
+        ] {
+            registry.register(signal.into());
+        }
+    }
 }
 
 // What state are we in for the LinkKeyMismatchRule state?
@@ -843,7 +1038,7 @@ struct LinkKeyMismatchRule {
     signals: Vec<Signal>,
 
     /// Interesting occurrences surfaced by this rule.
-    reportable: Vec<(NaiveDateTime, String)>,
+    reportable: Vec<Finding>,
 }
 
 impl LinkKeyMismatchRule {
@@ -866,10 +1061,17 @@ impl LinkKeyMismatchRule {
                     tag: ConnectionSignal::LinkKeyMismatch.into(),
                 });
 
-                self.reportable.push((
-                    packet.ts,
-                    format!("Peer {} forgets the link key, or it mismatches with ours.", address),
-                ));
+                self.reportable.push(
+                    Finding::new(
+                        packet,
+                        Severity::Error,
+                        format!(
+                            "Peer {} forgets the link key, or it mismatches with ours.",
+                            address
+                        ),
+                    )
+                    .with_address(address),
+                );
             }
         }
         self.states.remove(&address);
@@ -912,10 +1114,14 @@ impl LinkKeyMismatchRule {
                 .handles
                 .get(&handle)
                 .map_or(format!("handle {}", handle), |addr| format!("{}", addr));
-            self.reportable.push((
-                packet.ts,
-                format!("Encryption failure with {:?} for {}", status, address_format),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!("Encryption failure with {:?} for {}", status, address_format),
+                )
+                .with_handle(handle),
+            );
 
             if self.pending_le_encrypt.contains(&handle) {
                 self.signals.push(Signal {
@@ -937,6 +1143,10 @@ impl LinkKeyMismatchRule {
 }
 
 impl Rule for LinkKeyMismatchRule {
+    fn name(&self) -> &'static str {
+        "LinkKeyMismatchRule"
+    }
+
     fn process(&mut self, packet: &Packet) {
         match &packet.inner {
             PacketChild::HciEvent(ev) => match ev.specialize() {
@@ -1037,18 +1247,19 @@ impl Rule for LinkKeyMismatchRule {
         }
     }
 
-    fn report(&self, writer: &mut dyn Write) {
-        if self.reportable.len() > 0 {
-            let _ = writeln!(writer, "LinkKeyMismatchRule report:");
-            for (ts, message) in self.reportable.iter() {
-                let _ = writeln!(writer, "[{:?}] {}", ts, message);
-            }
-        }
+    fn report_findings(&self) -> Vec<Finding> {
+        self.reportable.clone()
     }
 
     fn report_signals(&self) -> &[Signal] {
         self.signals.as_slice()
     }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [ConnectionSignal::LinkKeyMismatch, ConnectionSignal::LongTermKeyMismatch] {
+            registry.register(signal.into());
+        }
+    }
 }
 
 struct SecurityMode3Rule {
@@ -1056,7 +1267,7 @@ struct SecurityMode3Rule {
     signals: Vec<Signal>,
 
     /// Interesting occurrences surfaced by this rule.
-    reportable: Vec<(NaiveDateTime, String)>,
+    reportable: Vec<Finding>,
 }
 
 impl SecurityMode3Rule {
@@ -1081,15 +1292,26 @@ impl SecurityMode3Rule {
                 tag: ConnectionSignal::SecurityMode3.into(),
             });
 
-            self.reportable.push((
-                packet.ts,
-                format!("Device {} uses unsupported legacy security mode 3 (b/260625799)", address),
-            ));
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!(
+                        "Device {} uses unsupported legacy security mode 3 (b/260625799)",
+                        address
+                    ),
+                )
+                .with_address(address),
+            );
         }
     }
 }
 
 impl Rule for SecurityMode3Rule {
+    fn name(&self) -> &'static str {
+        "SecurityMode3Rule"
+    }
+
     fn process(&mut self, packet: &Packet) {
         match &packet.inner {
             PacketChild::HciEvent(ev) => match ev.specialize() {
@@ -1107,26 +1329,552 @@ impl Rule for SecurityMode3Rule {
         }
     }
 
-    fn report(&self, writer: &mut dyn Write) {
-        if self.reportable.len() > 0 {
-            let _ = writeln!(writer, "SecurityMode3Rule report:");
-            for (ts, message) in self.reportable.iter() {
-                let _ = writeln!(writer, "[{:?}] {}", ts, message);
+    fn report_findings(&self) -> Vec<Finding> {
+        self.reportable.clone()
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        registry.register(ConnectionSignal::SecurityMode3.into());
+    }
+}
+
+/// How long all controller ACL buffers may stay in flight before it is reported as starvation, and
+/// how long packets may stay in flight before a disconnection is reported as leaking them. Can be
+/// overridden with the `starvation_ms` parameter of AclFlowControlRule.
+const DEFAULT_STARVATION_MS: i64 = 500;
+
+/// Length of the traffic timeline windows of each connection. The timeline adds a finding per
+/// window, so it is off unless the `timeline_window_ms` parameter of AclFlowControlRule is set.
+const DEFAULT_TIMELINE_WINDOW_MS: i64 = 0;
+
+/// Controller buffer pool a connection sends its ACL data through.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum AclBufferPool {
+    BrEdr,
+    Le,
+}
+
+/// Traffic statistics for a single ACL connection.
+struct AclLinkStats {
+    address: Address,
+    le: bool,
+
+    /// Packet that established the connection.
+    connected: Packet,
+
+    tx_bytes: usize,
+    tx_packets: usize,
+    rx_bytes: usize,
+    rx_packets: usize,
+
+    /// Most packets this connection had in the controller at once.
+    max_inflight: usize,
+
+    /// Traffic seen since |window_start|, when a timeline is requested.
+    window_start: Option<Packet>,
+    window_tx_bytes: usize,
+    window_rx_bytes: usize,
+    window_max_inflight: usize,
+}
+
+/// Builds a per-connection picture of ACL traffic and controller buffer usage, and flags periods
+/// where the host could not send because every controller buffer was in use.
+struct AclFlowControlRule {
+    links: HashMap<ConnectionHandle, AclLinkStats>,
+
+    /// ACL packets sent on each handle which the controller hasn't completed yet.
+    nocp: NocpTracker,
+
+    /// Number of ACL buffers in the controller, from |Read Buffer Size| and
+    /// |LE Read Buffer Size|. LE connections share the BR/EDR buffers when the controller has no
+    /// dedicated LE buffers.
+    buffers: HashMap<AclBufferPool, usize>,
+
+    /// When each pool ran out of buffers and which handles were holding them.
+    starving_since: HashMap<AclBufferPool, (Packet, Vec<ConnectionHandle>)>,
+
+    starvation_ms: i64,
+
+    /// Length of the timeline windows in the report. Zero disables the timeline.
+    timeline_window_ms: i64,
+
+    /// Index and timestamp of the last packet seen, for the summary of connections still open
+    /// and buffers still starved at the end of the log.
+    last_packet: Option<(usize, NaiveDateTime)>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl AclFlowControlRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        AclFlowControlRule {
+            links: HashMap::new(),
+            nocp: NocpTracker::default(),
+            buffers: HashMap::new(),
+            starving_since: HashMap::new(),
+            starvation_ms: config.get_i64(
+                "AclFlowControlRule",
+                "starvation_ms",
+                DEFAULT_STARVATION_MS,
+            ),
+            timeline_window_ms: config.get_i64(
+                "AclFlowControlRule",
+                "timeline_window_ms",
+                DEFAULT_TIMELINE_WINDOW_MS,
+            ),
+            last_packet: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn pool(&self, handle: ConnectionHandle) -> AclBufferPool {
+        match self.links.get(&handle) {
+            Some(link) if link.le && self.buffers.contains_key(&AclBufferPool::Le) => {
+                AclBufferPool::Le
+            }
+            _ => AclBufferPool::BrEdr,
+        }
+    }
+
+    fn pool_inflight(&self, pool: AclBufferPool) -> usize {
+        self.nocp
+            .busy_handles()
+            .filter(|h| self.pool(*h) == pool)
+            .map(|h| self.nocp.inflight(h))
+            .sum()
+    }
+
+    fn process_connection(
+        &mut self,
+        handle: ConnectionHandle,
+        address: Address,
+        le: bool,
+        packet: &Packet,
+    ) {
+        self.links.insert(
+            handle,
+            AclLinkStats {
+                address,
+                le,
+                connected: packet.clone(),
+                tx_bytes: 0,
+                tx_packets: 0,
+                rx_bytes: 0,
+                rx_packets: 0,
+                max_inflight: 0,
+                window_start: None,
+                window_tx_bytes: 0,
+                window_rx_bytes: 0,
+                window_max_inflight: 0,
+            },
+        );
+    }
+
+    fn process_acl(&mut self, acl: &Acl, tx: bool, packet: &Packet) {
+        let handle = acl.get_handle();
+        let bytes = match acl.specialize() {
+            AclChild::Payload(payload) => payload.len(),
+            _ => 0,
+        };
+
+        if tx {
+            self.nocp.sent(handle, packet.ts);
+        }
+        let inflight = self.nocp.inflight(handle);
+
+        self.update_timeline(handle, packet);
+        if let Some(link) = self.links.get_mut(&handle) {
+            if tx {
+                link.tx_bytes += bytes;
+                link.tx_packets += 1;
+                link.window_tx_bytes += bytes;
+            } else {
+                link.rx_bytes += bytes;
+                link.rx_packets += 1;
+                link.window_rx_bytes += bytes;
+            }
+            link.max_inflight = link.max_inflight.max(inflight);
+            link.window_max_inflight = link.window_max_inflight.max(inflight);
+        }
+
+        if tx {
+            self.check_starvation(self.pool(handle), packet);
+        }
+    }
+
+    fn process_nocp(&mut self, nocp: &NumberOfCompletedPackets, packet: &Packet) {
+        let mut pools = HashSet::new();
+        for completed_packet in nocp.get_completed_packets() {
+            let handle = completed_packet.connection_handle;
+            self.nocp.complete(handle, completed_packet.host_num_of_completed_packets);
+            pools.insert(self.pool(handle));
+        }
+
+        for pool in pools {
+            self.check_starvation(pool, packet);
+        }
+    }
+
+    /// Start or end a starvation period depending on how many buffers of |pool| are in use.
+    fn check_starvation(&mut self, pool: AclBufferPool, packet: &Packet) {
+        let buffers = match self.buffers.get(&pool) {
+            Some(buffers) => *buffers,
+            None => return,
+        };
+
+        let inflight = self.pool_inflight(pool);
+        if inflight >= buffers {
+            if !self.starving_since.contains_key(&pool) {
+                let holders = self.nocp.busy_handles().filter(|h| self.pool(*h) == pool).collect();
+                self.starving_since.insert(pool, (packet.clone(), holders));
+            }
+            return;
+        }
+
+        if let Some((start, holders)) = self.starving_since.remove(&pool) {
+            let starved_ms = packet.ts.signed_duration_since(start.ts).num_milliseconds();
+            if starved_ms > self.starvation_ms {
+                self.signals.push(Signal {
+                    index: packet.index,
+                    ts: packet.ts,
+                    tag: ConnectionSignal::AclStarvation.into(),
+                });
+
+                let finding = self.starvation_finding(
+                    pool,
+                    &start,
+                    &holders,
+                    format!("were in use for {} ms", starved_ms),
+                );
+                self.reportable.push(finding.until(packet.index, packet.ts));
+            }
+        }
+    }
+
+    fn starvation_finding(
+        &self,
+        pool: AclBufferPool,
+        start: &Packet,
+        holders: &[ConnectionHandle],
+        duration: String,
+    ) -> Finding {
+        let mut holders = holders.to_vec();
+        holders.sort_unstable();
+        let mut finding = Finding::new(
+            start,
+            Severity::Warning,
+            format!(
+                "All {} {:?} ACL buffers {}, held by handles {:?}",
+                self.buffers.get(&pool).cloned().unwrap_or_default(),
+                pool,
+                duration,
+                holders
+            ),
+        );
+        for handle in holders {
+            finding = finding.with_handle(handle);
+            if let Some(link) = self.links.get(&handle) {
+                finding = finding.with_address(link.address);
+            }
+        }
+        finding
+    }
+
+    /// Close the current timeline window of |handle| if |packet| falls outside of it.
+    fn update_timeline(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        if self.timeline_window_ms <= 0 {
+            return;
+        }
+
+        let window_ms = self.timeline_window_ms;
+        let finding = match self.links.get_mut(&handle) {
+            Some(link) => match &link.window_start {
+                Some(start)
+                    if packet.ts.signed_duration_since(start.ts).num_milliseconds() < window_ms =>
+                {
+                    None
+                }
+                Some(_) => {
+                    let finding = Self::window_finding(handle, link, packet);
+                    link.window_start = Some(packet.clone());
+                    Some(finding)
+                }
+                None => {
+                    link.window_start = Some(packet.clone());
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if let Some(finding) = finding {
+            self.reportable.push(finding);
+        }
+    }
+
+    /// Summarize and reset the current timeline window of |link|.
+    fn window_finding(
+        handle: ConnectionHandle,
+        link: &mut AclLinkStats,
+        packet: &Packet,
+    ) -> Finding {
+        let mut finding = Finding::new(
+            packet,
+            Severity::Info,
+            format!(
+                "Handle {}: tx {} bytes, rx {} bytes, up to {} packets in flight",
+                handle, link.window_tx_bytes, link.window_rx_bytes, link.window_max_inflight
+            ),
+        )
+        .with_address(link.address)
+        .with_handle(handle);
+        if let Some(start) = &link.window_start {
+            finding = finding.since(start);
+        }
+
+        link.window_tx_bytes = 0;
+        link.window_rx_bytes = 0;
+        link.window_max_inflight = 0;
+
+        finding
+    }
+
+    fn summary_finding(
+        handle: ConnectionHandle,
+        link: &AclLinkStats,
+        end_index: usize,
+        end_ts: NaiveDateTime,
+    ) -> Finding {
+        let duration_ms = end_ts.signed_duration_since(link.connected.ts).num_milliseconds();
+        // Bits per millisecond are kilobits per second.
+        let rate = |bytes: usize| {
+            if duration_ms > 0 {
+                bytes as f64 * 8.0 / duration_ms as f64
+            } else {
+                0.0
+            }
+        };
+        Finding::new(
+            &link.connected,
+            Severity::Info,
+            format!(
+                "Handle {}: sent {} bytes in {} packets ({:.1} kbps), received {} bytes in {} packets ({:.1} kbps) over {} ms, up to {} packets in flight",
+                handle,
+                link.tx_bytes,
+                link.tx_packets,
+                rate(link.tx_bytes),
+                link.rx_bytes,
+                link.rx_packets,
+                rate(link.rx_bytes),
+                duration_ms,
+                link.max_inflight
+            ),
+        )
+        .until(end_index, end_ts)
+        .with_address(link.address)
+        .with_handle(handle)
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        let pool = self.pool(handle);
+        // The controller implicitly completes the packets of a link that goes away, only those
+        // that were already stuck before are a problem.
+        let leaked = self.nocp.inflight_longer_than(handle, packet.ts, self.starvation_ms);
+        let link = self.links.remove(&handle);
+
+        if leaked > 0 {
+            self.signals.push(Signal {
+                index: packet.index,
+                ts: packet.ts,
+                tag: ConnectionSignal::AclCreditLeak.into(),
+            });
+            let mut finding = Finding::new(
+                packet,
+                Severity::Warning,
+                format!(
+                    "Handle {} disconnected with {} ACL packets in flight for more than {} ms",
+                    handle, leaked, self.starvation_ms
+                ),
+            )
+            .with_handle(handle);
+            if let Some(link) = &link {
+                finding = finding.with_address(link.address);
+            }
+            self.reportable.push(finding);
+        }
+
+        if let Some(mut link) = link {
+            if link.window_start.is_some() {
+                let finding = Self::window_finding(handle, &mut link, packet);
+                self.reportable.push(finding);
+            }
+            self.reportable.push(Self::summary_finding(handle, &link, packet.index, packet.ts));
+        }
+
+        // Buffers held by this connection are returned to the host on disconnection.
+        self.nocp.remove(handle);
+        self.check_starvation(pool, packet);
+    }
+
+    fn process_reset(&mut self) {
+        self.links.clear();
+        self.nocp.clear();
+        self.buffers.clear();
+        self.starving_since.clear();
+    }
+}
+
+impl Rule for AclFlowControlRule {
+    fn name(&self) -> &'static str {
+        "AclFlowControlRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                if let CommandChild::Reset(_) = cmd.specialize() {
+                    self.process_reset();
+                }
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::ConnectionComplete(cc) if cc.get_status() == ErrorCode::Success => {
+                    self.process_connection(
+                        cc.get_connection_handle(),
+                        cc.get_bd_addr(),
+                        false,
+                        packet,
+                    );
+                }
+                EventChild::DisconnectionComplete(dsc) => {
+                    self.process_disconnection(dsc.get_connection_handle(), packet);
+                }
+                EventChild::NumberOfCompletedPackets(nocp) => {
+                    self.process_nocp(&nocp, packet);
+                }
+                EventChild::CommandComplete(cc) => match cc.specialize() {
+                    CommandCompleteChild::ReadBufferSizeComplete(rbs)
+                        if rbs.get_status() == ErrorCode::Success =>
+                    {
+                        self.buffers.insert(
+                            AclBufferPool::BrEdr,
+                            rbs.get_total_num_acl_data_packets().into(),
+                        );
+                    }
+                    CommandCompleteChild::LeReadBufferSizeV1Complete(rbs)
+                        if rbs.get_status() == ErrorCode::Success =>
+                    {
+                        let buffers = rbs.get_le_buffer_size().total_num_le_packets;
+                        if buffers > 0 {
+                            self.buffers.insert(AclBufferPool::Le, buffers.into());
+                        }
+                    }
+                    CommandCompleteChild::LeReadBufferSizeV2Complete(rbs)
+                        if rbs.get_status() == ErrorCode::Success =>
+                    {
+                        let buffers = rbs.get_le_buffer_size().total_num_le_packets;
+                        if buffers > 0 {
+                            self.buffers.insert(AclBufferPool::Le, buffers.into());
+                        }
+                    }
+                    _ => {}
+                },
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeConnectionComplete(lcc)
+                        if lcc.get_status() == ErrorCode::Success =>
+                    {
+                        self.process_connection(
+                            lcc.get_connection_handle(),
+                            lcc.get_peer_address(),
+                            true,
+                            packet,
+                        );
+                    }
+                    LeMetaEventChild::LeEnhancedConnectionComplete(lecc)
+                        if lecc.get_status() == ErrorCode::Success =>
+                    {
+                        self.process_connection(
+                            lecc.get_connection_handle(),
+                            lecc.get_peer_address(),
+                            true,
+                            packet,
+                        );
+                    }
+                    _ => {}
+                },
+                _ => {}
+            },
+
+            PacketChild::AclTx(tx) => self.process_acl(tx, true, packet),
+            PacketChild::AclRx(rx) => self.process_acl(rx, false, packet),
+
+            _ => {}
+        }
+
+        self.last_packet = Some((packet.index, packet.ts));
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        if let Some((last_index, last_ts)) = self.last_packet {
+            // Flag buffers that were still starved at the end of the log.
+            for (pool, (start, holders)) in self.starving_since.iter() {
+                let starved_ms = last_ts.signed_duration_since(start.ts).num_milliseconds();
+                if starved_ms > self.starvation_ms {
+                    let finding = self.starvation_finding(
+                        *pool,
+                        start,
+                        holders,
+                        format!("were still in use at the end of the log, after {} ms", starved_ms),
+                    );
+                    findings.push(finding.until(last_index, last_ts));
+                }
+            }
+
+            // Summarize connections that were still open at the end of the log.
+            let mut handles: Vec<&ConnectionHandle> = self.links.keys().collect();
+            handles.sort_unstable();
+            for handle in handles {
+                findings.push(Self::summary_finding(
+                    *handle,
+                    &self.links[handle],
+                    last_index,
+                    last_ts,
+                ));
             }
         }
+
+        findings
     }
 
     fn report_signals(&self) -> &[Signal] {
         self.signals.as_slice()
     }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [ConnectionSignal::AclStarvation, ConnectionSignal::AclCreditLeak] {
+            registry.register(signal.into());
+        }
+    }
 }
 
 /// Get a rule group with connection rules.
-pub fn get_connections_group() -> RuleGroup {
+pub fn get_connections_group(config: &RuleConfig) -> RuleGroup {
     let mut group = RuleGroup::new();
     group.add_rule(Box::new(LinkKeyMismatchRule::new()));
-    group.add_rule(Box::new(OddDisconnectionsRule::new()));
+    group.add_rule(Box::new(OddDisconnectionsRule::new(config)));
     group.add_rule(Box::new(SecurityMode3Rule::new()));
+    group.add_rule(Box::new(AclFlowControlRule::new(config)));
 
     group
 }
@@ -1,21 +1,94 @@
 ///! Rule group for tracking controller related issues.
 use chrono::NaiveDateTime;
+use std::collections::{HashMap, VecDeque};
 use std::convert::Into;
-use std::io::Write;
 
-use crate::engine::{Rule, RuleGroup, Signal};
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
 use crate::parser::{Packet, PacketChild};
-use bt_packets::hci::EventChild;
+use crate::vendor::{get_status_name, VendorDecoders, VendorReport, BQR_ID_MONITOR_MODE};
+use bt_packets::hci::{ErrorCode, EventChild, OpCode};
 
 enum ControllerSignal {
-    HardwareError, // Controller reports HCI event: Hardware Error
+    HardwareError,       // Controller reports HCI event: Hardware Error
+    SlowCommand,         // Controller took longer than expected to answer a command
+    CommandTimeout,      // Controller did not answer a command before the host timed it out
+    NoCommandCredit,     // Host sent a command while the controller had no credits left
+    FirmwareException,   // Vendor event reports a controller firmware exception or core dump
+    BqrLinkQuality,      // Quality report about a degraded link
+    BqrRootInflammation, // Quality report about a fatal controller issue
+    MsftMonitorLeak,     // Advertisement monitors are registered and never cancelled
+}
+
+impl From<ControllerSignal> for SignalDefinition {
+    fn from(signal: ControllerSignal) -> Self {
+        match signal {
+            ControllerSignal::HardwareError => SignalDefinition {
+                tag: "HardwareError",
+                severity: Severity::Error,
+                description: "The controller reported a hardware error.",
+                next_step: "Collect the controller firmware dump or vendor logs from around this \
+                    time.",
+            },
+            ControllerSignal::SlowCommand => SignalDefinition {
+                tag: "SlowCommand",
+                severity: Severity::Warning,
+                description: "The controller took longer than expected to answer a command.",
+                next_step:
+                    "Compare with the command latency summary to see whether the controller \
+                    is slow in general or only for this command.",
+            },
+            ControllerSignal::CommandTimeout => SignalDefinition {
+                tag: "CommandTimeout",
+                severity: Severity::Error,
+                description: "The controller did not answer a command before the host timed it \
+                    out.",
+                next_step: "Collect controller firmware logs, the host usually resets the \
+                    controller after this.",
+            },
+            ControllerSignal::NoCommandCredit => SignalDefinition {
+                tag: "NoCommandCredit",
+                severity: Severity::Warning,
+                description: "The host sent a command while the controller had no command credits \
+                    left.",
+                next_step: "Check the host command queue, it should wait for Command Status or \
+                    Command Complete before sending more.",
+            },
+            ControllerSignal::FirmwareException => SignalDefinition {
+                tag: "FirmwareException",
+                severity: Severity::Error,
+                description: "A vendor event reported a controller firmware exception or core \
+                    dump.",
+                next_step: "Collect the firmware dump and report it to the controller vendor.",
+            },
+            ControllerSignal::BqrLinkQuality => SignalDefinition {
+                tag: "BqrLinkQuality",
+                severity: Severity::Warning,
+                description: "The controller sent a quality report about a degraded link.",
+                next_step: "Check the RSSI and retransmissions in the report for interference or \
+                    range issues.",
+            },
+            ControllerSignal::BqrRootInflammation => SignalDefinition {
+                tag: "BqrRootInflammation",
+                severity: Severity::Error,
+                description: "The controller sent a quality report about a fatal issue.",
+                next_step: "Look up the vendor error code and collect controller firmware logs.",
+            },
+            ControllerSignal::MsftMonitorLeak => SignalDefinition {
+                tag: "MsftMonitorLeak",
+                severity: Severity::Warning,
+                description: "Advertisement monitors are registered with the controller and not \
+                    cancelled.",
+                next_step: "Check that the host cancels monitors when their clients go away.",
+            },
+        }
+    }
 }
 
 impl Into<&'static str> for ControllerSignal {
     fn into(self) -> &'static str {
-        match self {
-            ControllerSignal::HardwareError => "HardwareError",
-        }
+        SignalDefinition::from(self).tag
     }
 }
 
@@ -24,7 +97,7 @@ struct ControllerRule {
     signals: Vec<Signal>,
 
     /// Interesting occurrences surfaced by this rule.
-    reportable: Vec<(NaiveDateTime, String)>,
+    reportable: Vec<Finding>,
 }
 
 impl ControllerRule {
@@ -39,11 +112,19 @@ impl ControllerRule {
             tag: ControllerSignal::HardwareError.into(),
         });
 
-        self.reportable.push((packet.ts, format!("controller reported hardware error")));
+        self.reportable.push(Finding::new(
+            packet,
+            Severity::Error,
+            format!("controller reported hardware error"),
+        ));
     }
 }
 
 impl Rule for ControllerRule {
+    fn name(&self) -> &'static str {
+        "Controller"
+    }
+
     fn process(&mut self, packet: &Packet) {
         match &packet.inner {
             PacketChild::HciEvent(ev) => match ev.specialize() {
@@ -56,24 +137,720 @@ impl Rule for ControllerRule {
         }
     }
 
-    fn report(&self, writer: &mut dyn Write) {
-        if self.reportable.len() > 0 {
-            let _ = writeln!(writer, "Controller report:");
-            for (ts, message) in self.reportable.iter() {
-                let _ = writeln!(writer, "[{:?}] {}", ts, message);
+    fn report_findings(&self) -> Vec<Finding> {
+        self.reportable.clone()
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        registry.register(ControllerSignal::HardwareError.into());
+    }
+}
+
+/// Commands answered later than this are reported as slow. Can be overridden with the
+/// `slow_command_ms` parameter of CommandLatency.
+const DEFAULT_SLOW_COMMAND_MS: i64 = 500;
+
+/// The stack gives up on a command after this long. Can be overridden with the
+/// `command_timeout_ms` parameter of CommandLatency.
+pub(crate) const DEFAULT_COMMAND_TIMEOUT_MS: i64 = 2000;
+
+/// Latency samples collected for a single opcode.
+struct OpcodeLatency {
+    /// First command and last answer with this opcode.
+    first: Packet,
+    last: Packet,
+
+    /// Time between each command and its first Command Status or Command Complete, in
+    /// microseconds.
+    samples: Vec<i64>,
+}
+
+impl OpcodeLatency {
+    /// Nearest-rank percentile of |sorted|, which must not be empty.
+    fn percentile(sorted: &[i64], percent: usize) -> i64 {
+        let rank = (sorted.len() * percent).div_ceil(100);
+        sorted[rank.max(1) - 1]
+    }
+}
+
+/// Commands sent to a controller and not answered yet, oldest first. Answers are matched to the
+/// oldest command with the same opcode.
+pub(crate) struct OutstandingCommands {
+    commands: VecDeque<Packet>,
+
+    /// Commands not answered within this long are given up on, like the stack does.
+    timeout_ms: i64,
+}
+
+impl OutstandingCommands {
+    pub fn new(timeout_ms: i64) -> Self {
+        OutstandingCommands { commands: VecDeque::new(), timeout_ms }
+    }
+
+    pub fn sent(&mut self, packet: &Packet) {
+        self.commands.push_back(packet.clone());
+    }
+
+    /// Take the command answered by an event for |opcode|.
+    pub fn answered(&mut self, opcode: OpCode) -> Option<Packet> {
+        let position = self.commands.iter().position(|command| match &command.inner {
+            PacketChild::HciCommand(cmd) => cmd.get_op_code() == opcode,
+            _ => false,
+        });
+        position.and_then(|p| self.commands.remove(p))
+    }
+
+    /// Take the commands that were not answered within the timeout by |ts|. Without this, a later
+    /// command with the same opcode would be matched to the answer it never got.
+    pub fn expire(&mut self, ts: NaiveDateTime) -> Vec<Packet> {
+        let mut expired = vec![];
+        while let Some(oldest) = self.commands.front() {
+            if ts.signed_duration_since(oldest.ts).num_milliseconds() <= self.timeout_ms {
+                break;
+            }
+            expired.extend(self.commands.pop_front());
+        }
+        expired
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Packet> {
+        self.commands.iter()
+    }
+}
+
+/// Measures how long the controller takes to answer commands.
+struct CommandLatencyRule {
+    outstanding: OutstandingCommands,
+
+    /// Number of commands the controller can currently accept, as reported in the last
+    /// Command Status or Command Complete. Unknown until the first of those, since the log may
+    /// start in the middle of a session.
+    credits: Option<u8>,
+
+    latencies: HashMap<OpCode, OpcodeLatency>,
+
+    slow_command_ms: i64,
+    command_timeout_ms: i64,
+
+    /// Index and timestamp of the last packet seen, to report the commands still outstanding at
+    /// the end of the log.
+    last_packet: Option<(usize, NaiveDateTime)>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl CommandLatencyRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        let command_timeout_ms =
+            config.get_i64("CommandLatency", "command_timeout_ms", DEFAULT_COMMAND_TIMEOUT_MS);
+        CommandLatencyRule {
+            outstanding: OutstandingCommands::new(command_timeout_ms),
+            credits: None,
+            latencies: HashMap::new(),
+            slow_command_ms: config.get_i64(
+                "CommandLatency",
+                "slow_command_ms",
+                DEFAULT_SLOW_COMMAND_MS,
+            ),
+            command_timeout_ms,
+            last_packet: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: ControllerSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn process_command(&mut self, opcode: OpCode, packet: &Packet) {
+        // A reset supersedes anything the controller was still working on. The commands that
+        // timed out were already reported, the others could just have been sent before their
+        // answers.
+        if opcode == OpCode::Reset {
+            self.outstanding.clear();
+        }
+
+        match self.credits {
+            Some(0) => {
+                self.add_signal(packet, ControllerSignal::NoCommandCredit);
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("{:?} sent while the controller had no command credits", opcode),
+                ));
+            }
+            Some(credits) => self.credits = Some(credits - 1),
+            None => {}
+        }
+
+        self.outstanding.sent(packet);
+    }
+
+    fn process_answer(&mut self, opcode: OpCode, credits: u8, packet: &Packet) {
+        self.credits = Some(credits);
+
+        // Events with the NONE opcode only return credits.
+        if opcode == OpCode::None {
+            return;
+        }
+
+        let command = match self.outstanding.answered(opcode) {
+            Some(command) => command,
+            None => return,
+        };
+
+        let latency = packet.ts.signed_duration_since(command.ts);
+        let latency_ms = latency.num_milliseconds();
+        if latency_ms > self.slow_command_ms {
+            self.add_signal(packet, ControllerSignal::SlowCommand);
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("{:?} took {} ms to be answered", opcode, latency_ms),
+                )
+                .since(&command),
+            );
+        }
+
+        let latency_us = latency.num_microseconds().unwrap_or(i64::MAX);
+        self.latencies
+            .entry(opcode)
+            .and_modify(|l| {
+                l.last = packet.clone();
+                l.samples.push(latency_us);
+            })
+            .or_insert_with(|| OpcodeLatency {
+                first: command.clone(),
+                last: packet.clone(),
+                samples: vec![latency_us],
+            });
+    }
+
+    /// Report the commands that timed out before |packet|.
+    fn check_timeouts(&mut self, packet: &Packet) {
+        for command in self.outstanding.expire(packet.ts) {
+            self.add_signal(packet, ControllerSignal::CommandTimeout);
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!(
+                        "{} not answered within {} ms",
+                        Self::command_name(&command),
+                        self.command_timeout_ms
+                    ),
+                )
+                .since(&command),
+            );
+        }
+    }
+
+    fn command_name(command: &Packet) -> String {
+        match &command.inner {
+            PacketChild::HciCommand(cmd) => format!("{:?}", cmd.get_op_code()),
+            _ => String::from("command"),
+        }
+    }
+}
+
+impl Rule for CommandLatencyRule {
+    fn name(&self) -> &'static str {
+        "CommandLatency"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        // Checked on every packet so commands that never get an answer are reported even if the
+        // log goes quiet afterwards.
+        self.check_timeouts(packet);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                self.process_command(cmd.get_op_code(), packet);
+            }
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::CommandStatus(cs) => {
+                    self.process_answer(
+                        cs.get_command_op_code(),
+                        cs.get_num_hci_command_packets(),
+                        packet,
+                    );
+                }
+                EventChild::CommandComplete(cc) => {
+                    self.process_answer(
+                        cc.get_command_op_code(),
+                        cc.get_num_hci_command_packets(),
+                        packet,
+                    );
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        self.last_packet = Some((packet.index, packet.ts));
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        // Commands still outstanding at the end of the log, those that timed out were already
+        // reported.
+        if let Some((last_index, last_ts)) = self.last_packet {
+            for command in self.outstanding.iter() {
+                let elapsed_ms = last_ts.signed_duration_since(command.ts).num_milliseconds();
+                findings.push(
+                    Finding::new(
+                        command,
+                        Severity::Info,
+                        format!(
+                            "{} still unanswered after {} ms at the end of the log",
+                            Self::command_name(command),
+                            elapsed_ms
+                        ),
+                    )
+                    .until(last_index, last_ts),
+                );
+            }
+        }
+
+        // Latency summary per opcode, in the order the opcodes were first seen.
+        let mut latencies: Vec<(&OpCode, &OpcodeLatency)> = self.latencies.iter().collect();
+        latencies.sort_by_key(|(_, l)| l.first.index);
+        for (opcode, latency) in latencies {
+            let mut sorted = latency.samples.clone();
+            sorted.sort_unstable();
+            let ms = |us: i64| us as f64 / 1000.0;
+            findings.push(
+                Finding::new(
+                    &latency.last,
+                    Severity::Info,
+                    format!(
+                        "{:?}: {} commands, latency p50 {:.3} ms, p90 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+                        opcode,
+                        sorted.len(),
+                        ms(OpcodeLatency::percentile(&sorted, 50)),
+                        ms(OpcodeLatency::percentile(&sorted, 90)),
+                        ms(OpcodeLatency::percentile(&sorted, 99)),
+                        ms(sorted[sorted.len() - 1]),
+                    ),
+                )
+                .since(&latency.first),
+            );
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            ControllerSignal::SlowCommand,
+            ControllerSignal::CommandTimeout,
+            ControllerSignal::NoCommandCredit,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// More advertisement monitors than this registered at once are reported as a leak. Can be
+/// overridden with the `monitor_leak_threshold` parameter of VendorHealth.
+const DEFAULT_MONITOR_LEAK_THRESHOLD: i64 = 16;
+
+/// Link quality reports the controller sent periodically for one connection.
+struct LinkQualitySummary {
+    first: Packet,
+    last: Packet,
+    reports: usize,
+    min_rssi: i8,
+    min_snr: i8,
+    retransmissions: u32,
+}
+
+/// Vendor specific packets that are only counted, like telemetry and log dumps.
+struct VendorPacketCount {
+    first: Packet,
+    last: Packet,
+    count: usize,
+}
+
+/// Decodes vendor specific commands and events to surface controller health issues.
+struct VendorHealthRule {
+    decoders: VendorDecoders,
+
+    /// Advertisement monitors registered with the MSFT extension, keyed by monitor handle.
+    monitors: HashMap<u8, Packet>,
+
+    monitor_leak_threshold: i64,
+
+    /// Leaks are reported once, they only get worse from there.
+    monitor_leak_reported: bool,
+
+    /// Periodic link quality reports, keyed by connection handle.
+    link_quality: HashMap<u16, LinkQualitySummary>,
+
+    /// Keyed by a description of the packets.
+    counts: HashMap<String, VendorPacketCount>,
+
+    /// Index and timestamp of the last packet seen, to report the monitors still registered at
+    /// the end of the log.
+    last_packet: Option<(usize, NaiveDateTime)>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl VendorHealthRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        VendorHealthRule {
+            decoders: VendorDecoders::new(),
+            monitors: HashMap::new(),
+            monitor_leak_threshold: config.get_i64(
+                "VendorHealth",
+                "monitor_leak_threshold",
+                DEFAULT_MONITOR_LEAK_THRESHOLD,
+            ),
+            monitor_leak_reported: false,
+            link_quality: HashMap::new(),
+            counts: HashMap::new(),
+            last_packet: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: ControllerSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn count(&mut self, description: String, packet: &Packet) {
+        self.counts
+            .entry(description)
+            .and_modify(|c| {
+                c.last = packet.clone();
+                c.count += 1;
+            })
+            .or_insert_with(|| VendorPacketCount {
+                first: packet.clone(),
+                last: packet.clone(),
+                count: 1,
+            });
+    }
+
+    fn report_monitor_leak(&mut self, packet: &Packet, message: String) {
+        if self.monitor_leak_reported {
+            return;
+        }
+        self.monitor_leak_reported = true;
+
+        self.add_signal(packet, ControllerSignal::MsftMonitorLeak);
+        let oldest = self.monitors.values().min_by_key(|p| p.index).cloned();
+        let mut finding = Finding::new(packet, Severity::Warning, message);
+        if let Some(oldest) = oldest {
+            finding = finding.since(&oldest);
+        }
+        self.reportable.push(finding);
+    }
+
+    fn process_report(&mut self, report: VendorReport, packet: &Packet) {
+        match report {
+            VendorReport::MsftFeatures { opcode, features, event_prefix } => {
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Info,
+                    format!(
+                        "MSFT extension on opcode 0x{:04x}, features 0x{:016x}, event prefix {:02x?}",
+                        opcode, features, event_prefix
+                    ),
+                ));
+            }
+
+            VendorReport::MsftMonitorAdded { status, handle } => {
+                if let Some(handle) = handle {
+                    self.monitors.insert(handle, packet.clone());
+                    if self.monitors.len() as i64 > self.monitor_leak_threshold {
+                        let message = format!(
+                            "{} advertisement monitors registered, more than the {} expected",
+                            self.monitors.len(),
+                            self.monitor_leak_threshold
+                        );
+                        self.report_monitor_leak(packet, message);
+                    }
+                } else if status == u8::from(ErrorCode::MemoryCapacityExceeded)
+                    && !self.monitors.is_empty()
+                {
+                    let message = format!(
+                        "controller is out of advertisement monitors with {} registered",
+                        self.monitors.len()
+                    );
+                    self.report_monitor_leak(packet, message);
+                } else {
+                    self.reportable.push(Finding::new(
+                        packet,
+                        Severity::Warning,
+                        format!("MSFT monitor advertisement failed: {}", get_status_name(status)),
+                    ));
+                }
+            }
+
+            VendorReport::MsftMonitorCancelled { status, handle } => {
+                if status == 0 {
+                    self.monitors.remove(&handle);
+                } else {
+                    self.reportable.push(Finding::new(
+                        packet,
+                        Severity::Warning,
+                        format!(
+                            "cancelling advertisement monitor {} failed: {}",
+                            handle,
+                            get_status_name(status)
+                        ),
+                    ));
+                }
+            }
+
+            VendorReport::MsftMonitorDevice { handle, address, tracking } => {
+                // The controller should have dropped the monitor together with its devices.
+                if !self.monitors.contains_key(&handle) {
+                    self.reportable.push(
+                        Finding::new(
+                            packet,
+                            Severity::Warning,
+                            format!(
+                                "device {} by advertisement monitor {} that isn't registered",
+                                if tracking { "found" } else { "lost" },
+                                handle
+                            ),
+                        )
+                        .with_address(address),
+                    );
+                }
+            }
+
+            VendorReport::AospCapabilities {
+                version,
+                max_advertisers,
+                max_filters,
+                quality_reports,
+            } => {
+                let version = match version {
+                    Some(v) => format!("{:x}.{:02x}", v >> 8, v & 0xFF),
+                    None => "0.55".to_string(),
+                };
+                let quality_reports = match quality_reports {
+                    Some(true) => "supported",
+                    Some(false) => "not supported",
+                    None => "unknown",
+                };
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Info,
+                    format!(
+                        "Android vendor capabilities {}: {} advertisers, {} filters, quality reports {}",
+                        version, max_advertisers, max_filters, quality_reports
+                    ),
+                ));
+            }
+
+            VendorReport::BqrLinkQuality {
+                report_id,
+                reason,
+                handle,
+                rssi,
+                snr,
+                retransmissions,
+                no_rx,
+                naks,
+            } => {
+                // Monitor mode reports are periodic and only summarised.
+                if report_id == BQR_ID_MONITOR_MODE {
+                    self.link_quality
+                        .entry(handle)
+                        .and_modify(|s| {
+                            s.last = packet.clone();
+                            s.reports += 1;
+                            s.min_rssi = s.min_rssi.min(rssi);
+                            s.min_snr = s.min_snr.min(snr);
+                            s.retransmissions = s.retransmissions.saturating_add(retransmissions);
+                        })
+                        .or_insert_with(|| LinkQualitySummary {
+                            first: packet.clone(),
+                            last: packet.clone(),
+                            reports: 1,
+                            min_rssi: rssi,
+                            min_snr: snr,
+                            retransmissions,
+                        });
+                    return;
+                }
+
+                self.add_signal(packet, ControllerSignal::BqrLinkQuality);
+                self.reportable.push(
+                    Finding::new(
+                        packet,
+                        Severity::Warning,
+                        format!(
+                            "quality report ({}): RSSI {} dBm, SNR {} dB, {} retransmissions, {} \
+                            packets not received, {} NAKs",
+                            reason, rssi, snr, retransmissions, no_rx, naks
+                        ),
+                    )
+                    .with_handle(handle),
+                );
+            }
+
+            VendorReport::BqrRootInflammation { error_code, vendor_error_code } => {
+                self.add_signal(packet, ControllerSignal::BqrRootInflammation);
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!(
+                        "quality report of a fatal controller issue: {}, vendor error 0x{:02x}",
+                        get_status_name(error_code),
+                        vendor_error_code
+                    ),
+                ));
+            }
+
+            VendorReport::BqrLogDump { reason } => {
+                self.count(format!("quality report log dumps ({})", reason), packet);
+            }
+
+            VendorReport::FirmwareException { vendor, description } => {
+                self.add_signal(packet, ControllerSignal::FirmwareException);
+                self.reportable.push(Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!("{} controller reported a {}", vendor, description),
+                ));
+            }
+
+            VendorReport::Telemetry { vendor } => {
+                self.count(format!("{} telemetry events", vendor), packet);
+            }
+        }
+    }
+}
+
+impl Rule for VendorHealthRule {
+    fn name(&self) -> &'static str {
+        "VendorHealth"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        match &packet.inner {
+            // Monitors don't survive a reset.
+            PacketChild::HciCommand(cmd) if cmd.get_op_code() == OpCode::Reset => {
+                self.monitors.clear();
+                self.monitor_leak_reported = false;
+            }
+            _ => {}
+        }
+
+        if let Some(report) = self.decoders.decode(packet) {
+            self.process_report(report, packet);
+        }
+
+        self.last_packet = Some((packet.index, packet.ts));
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        let mut link_quality: Vec<(&u16, &LinkQualitySummary)> = self.link_quality.iter().collect();
+        link_quality.sort_by_key(|(_, s)| s.first.index);
+        for (handle, summary) in link_quality {
+            findings.push(
+                Finding::new(
+                    &summary.last,
+                    Severity::Info,
+                    format!(
+                        "{} link quality reports, lowest RSSI {} dBm, lowest SNR {} dB, {} \
+                        retransmissions",
+                        summary.reports, summary.min_rssi, summary.min_snr, summary.retransmissions
+                    ),
+                )
+                .since(&summary.first)
+                .with_handle(*handle),
+            );
+        }
+
+        let mut counts: Vec<(&String, &VendorPacketCount)> = self.counts.iter().collect();
+        counts.sort_by_key(|(_, c)| c.first.index);
+        for (description, count) in counts {
+            findings.push(
+                Finding::new(
+                    &count.last,
+                    Severity::Info,
+                    format!("{} {}", count.count, description),
+                )
+                .since(&count.first),
+            );
+        }
+
+        if let Some((last_index, last_ts)) = self.last_packet {
+            let mut monitors: Vec<(&u8, &Packet)> = self.monitors.iter().collect();
+            monitors.sort_by_key(|(_, p)| p.index);
+            for (handle, added) in monitors {
+                findings.push(
+                    Finding::new(
+                        added,
+                        Severity::Info,
+                        format!(
+                            "advertisement monitor {} still registered at the end of the log",
+                            handle
+                        ),
+                    )
+                    .until(last_index, last_ts),
+                );
             }
         }
+
+        findings
     }
 
     fn report_signals(&self) -> &[Signal] {
         self.signals.as_slice()
     }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            ControllerSignal::FirmwareException,
+            ControllerSignal::BqrLinkQuality,
+            ControllerSignal::BqrRootInflammation,
+            ControllerSignal::MsftMonitorLeak,
+        ] {
+            registry.register(signal.into());
+        }
+    }
 }
 
-/// Get a rule group with connection rules.
-pub fn get_controllers_group() -> RuleGroup {
+/// Get a rule group with controller rules.
+pub fn get_controllers_group(config: &RuleConfig) -> RuleGroup {
     let mut group = RuleGroup::new();
     group.add_rule(Box::new(ControllerRule::new()));
+    group.add_rule(Box::new(CommandLatencyRule::new(config)));
+    group.add_rule(Box::new(VendorHealthRule::new(config)));
 
     group
 }
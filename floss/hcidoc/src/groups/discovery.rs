@@ -0,0 +1,889 @@
+//! Rule group for tracking inquiry, LE scanning and advertising activity.
+use chrono::NaiveDateTime;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
+use crate::parser::{Packet, PacketChild};
+use bt_packets::hci::{
+    Address, CommandChild, CommandCompleteChild, DiscoveryCommandChild, Enable, ErrorCode,
+    EventChild, LeAdvertisingCommandChild, LeMetaEventChild, LeScanningCommandChild, OpCode,
+};
+
+enum DiscoverySignal {
+    ScanChurn,              // LE scan was turned on and off many times in a short period.
+    LongScan,               // LE scan kept running for longer than expected.
+    InquiryNeverCompleted,  // Inquiry didn't complete within its requested length.
+    AdvertiserNeverEnabled, // Advertising set was configured but removed without being enabled.
+}
+
+impl From<DiscoverySignal> for SignalDefinition {
+    fn from(signal: DiscoverySignal) -> Self {
+        match signal {
+            DiscoverySignal::ScanChurn => SignalDefinition {
+                tag: "ScanChurn",
+                severity: Severity::Warning,
+                description: "LE scan was turned on and off many times in a short period.",
+                next_step: "Look for scanning clients on the host that restart their scans, each \
+                    restart costs power and loses results.",
+            },
+            DiscoverySignal::LongScan => SignalDefinition {
+                tag: "LongScan",
+                severity: Severity::Warning,
+                description: "LE scan kept running for longer than expected.",
+                next_step: "Find which client asked for the scan and whether it forgot to stop it.",
+            },
+            DiscoverySignal::InquiryNeverCompleted => SignalDefinition {
+                tag: "InquiryNeverCompleted",
+                severity: Severity::Warning,
+                description: "Inquiry did not complete within its requested length.",
+                next_step: "Check whether the controller was busy with connections or the inquiry \
+                    was cancelled without an Inquiry Complete.",
+            },
+            DiscoverySignal::AdvertiserNeverEnabled => SignalDefinition {
+                tag: "AdvertiserNeverEnabled",
+                severity: Severity::Warning,
+                description: "An advertising set was configured but removed without ever being \
+                    enabled.",
+                next_step: "Check whether the advertiser failed to start on the host side, for \
+                    example because of bad parameters.",
+            },
+        }
+    }
+}
+
+impl From<DiscoverySignal> for &'static str {
+    fn from(signal: DiscoverySignal) -> Self {
+        SignalDefinition::from(signal).tag
+    }
+}
+
+/// Inquiry length is given in units of 1.28 seconds.
+const INQUIRY_LENGTH_UNIT_MS: i64 = 1280;
+
+/// Extra time given to the controller to send Inquiry Complete after the inquiry length.
+const INQUIRY_COMPLETE_SLACK_MS: i64 = 5000;
+
+/// How many times scanning has to be enabled within `churn_window_ms` to be reported as churn.
+/// Can be overridden with the `churn_threshold` parameter of ScanActivityRule.
+const DEFAULT_CHURN_THRESHOLD: i64 = 5;
+
+/// Can be overridden with the `churn_window_ms` parameter of ScanActivityRule.
+const DEFAULT_CHURN_WINDOW_MS: i64 = 10000;
+
+/// Scans running for longer than this are reported. Can be overridden with the `long_scan_ms`
+/// parameter of ScanActivityRule.
+const DEFAULT_LONG_SCAN_MS: i64 = 300000;
+
+/// How many of the most active advertisers to report. Can be overridden with the
+/// `top_advertisers` parameter of ScanActivityRule.
+const DEFAULT_TOP_ADVERTISERS: i64 = 10;
+
+/// Scan interval and window are given in units of 0.625 ms.
+fn scan_units_to_ms(units: u16) -> f64 {
+    f64::from(units) * 0.625
+}
+
+/// An inquiry that was started and hasn't completed yet.
+struct InquirySession {
+    start: Packet,
+
+    /// How long the controller was asked to run the inquiry for.
+    length_ms: i64,
+
+    devices: HashSet<Address>,
+    results: usize,
+}
+
+/// An LE scan that was enabled and hasn't been disabled yet.
+struct ScanSession {
+    start: Packet,
+    devices: HashSet<Address>,
+    reports: usize,
+
+    /// Whether the scan was already flagged as running for too long.
+    long: bool,
+}
+
+/// Advertising reports received from a single device.
+struct AdvertiserReports {
+    first: Packet,
+    count: usize,
+}
+
+/// Follows BR/EDR inquiry and LE scanning sessions, and reports how long they ran, what they found
+/// and whether they were turned on and off too often.
+struct ScanActivityRule {
+    inquiry: Option<InquirySession>,
+
+    /// Inquiry command waiting for its command status.
+    pending_inquiry: Option<Packet>,
+
+    scan: Option<ScanSession>,
+
+    /// Scan enable or disable waiting for its command complete.
+    pending_scan_enable: Option<Enable>,
+
+    /// Last scan parameters set, in a readable form.
+    scan_parameters: Option<String>,
+
+    /// Recent scan enables, to find scan churn.
+    scan_enables: VecDeque<Packet>,
+
+    /// Advertising reports per device during the whole log.
+    advertisers: HashMap<Address, AdvertiserReports>,
+
+    churn_threshold: i64,
+    churn_window_ms: i64,
+    long_scan_ms: i64,
+    top_advertisers: i64,
+
+    /// Timestamp of the last packet seen, to report sessions still open at the end of the log.
+    last_ts: Option<NaiveDateTime>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl ScanActivityRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        ScanActivityRule {
+            inquiry: None,
+            pending_inquiry: None,
+            scan: None,
+            pending_scan_enable: None,
+            scan_parameters: None,
+            scan_enables: VecDeque::new(),
+            advertisers: HashMap::new(),
+            churn_threshold: config.get_i64(
+                "ScanActivityRule",
+                "churn_threshold",
+                DEFAULT_CHURN_THRESHOLD,
+            ),
+            churn_window_ms: config.get_i64(
+                "ScanActivityRule",
+                "churn_window_ms",
+                DEFAULT_CHURN_WINDOW_MS,
+            ),
+            long_scan_ms: config.get_i64("ScanActivityRule", "long_scan_ms", DEFAULT_LONG_SCAN_MS),
+            top_advertisers: config.get_i64(
+                "ScanActivityRule",
+                "top_advertisers",
+                DEFAULT_TOP_ADVERTISERS,
+            ),
+            last_ts: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: DiscoverySignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn process_inquiry_start(&mut self, inquiry_length: u8, packet: &Packet) {
+        if let Some(inquiry) = self.inquiry.take() {
+            self.finish_inquiry(inquiry, "restarted before completing", packet);
+        }
+        self.inquiry = Some(InquirySession {
+            start: packet.clone(),
+            length_ms: i64::from(inquiry_length) * INQUIRY_LENGTH_UNIT_MS,
+            devices: HashSet::new(),
+            results: 0,
+        });
+    }
+
+    fn process_inquiry_result(&mut self, address: Address) {
+        if let Some(inquiry) = self.inquiry.as_mut() {
+            inquiry.devices.insert(address);
+            inquiry.results += 1;
+        }
+    }
+
+    fn finish_inquiry(&mut self, inquiry: InquirySession, how: &str, packet: &Packet) {
+        let elapsed_ms = packet.ts.signed_duration_since(inquiry.start.ts).num_milliseconds();
+        if elapsed_ms > inquiry.length_ms + INQUIRY_COMPLETE_SLACK_MS {
+            self.add_signal(packet, DiscoverySignal::InquiryNeverCompleted);
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!(
+                        "Inquiry {} after {} ms, but was only requested to run for {} ms",
+                        how, elapsed_ms, inquiry.length_ms
+                    ),
+                )
+                .since(&inquiry.start),
+            );
+            return;
+        }
+
+        self.reportable.push(
+            Finding::new(
+                packet,
+                Severity::Info,
+                format!(
+                    "Inquiry {} after {} ms: {} devices found, {} results",
+                    how,
+                    elapsed_ms,
+                    inquiry.devices.len(),
+                    inquiry.results
+                ),
+            )
+            .since(&inquiry.start),
+        );
+    }
+
+    fn process_scan_parameters(&mut self, parameters: String, packet: &Packet) {
+        if self.scan_parameters.as_ref() == Some(&parameters) {
+            return;
+        }
+        let message = match self.scan_parameters.replace(parameters.clone()) {
+            Some(previous) => {
+                format!("LE scan parameters changed from {} to {}", previous, parameters)
+            }
+            None => format!("LE scan parameters set to {}", parameters),
+        };
+        self.reportable.push(Finding::new(packet, Severity::Info, message));
+    }
+
+    fn process_scan_enable(&mut self, enable: Enable, packet: &Packet) {
+        match enable {
+            Enable::Enabled => {
+                // Enabling again only changes duplicate filtering, the scan keeps going.
+                if self.scan.is_some() {
+                    return;
+                }
+                self.scan = Some(ScanSession {
+                    start: packet.clone(),
+                    devices: HashSet::new(),
+                    reports: 0,
+                    long: false,
+                });
+                self.check_churn(packet);
+            }
+            Enable::Disabled => {
+                if let Some(scan) = self.scan.take() {
+                    self.finish_scan(scan, "stopped", packet);
+                }
+            }
+        }
+    }
+
+    fn check_churn(&mut self, packet: &Packet) {
+        while let Some(oldest) = self.scan_enables.front() {
+            if packet.ts.signed_duration_since(oldest.ts).num_milliseconds() <= self.churn_window_ms
+            {
+                break;
+            }
+            self.scan_enables.pop_front();
+        }
+        self.scan_enables.push_back(packet.clone());
+
+        if (self.scan_enables.len() as i64) < self.churn_threshold {
+            return;
+        }
+        if let Some(first) = self.scan_enables.front() {
+            let finding = Finding::new(
+                packet,
+                Severity::Warning,
+                format!(
+                    "LE scan enabled {} times in {} ms",
+                    self.scan_enables.len(),
+                    packet.ts.signed_duration_since(first.ts).num_milliseconds()
+                ),
+            )
+            .since(first);
+            self.reportable.push(finding);
+        }
+        self.add_signal(packet, DiscoverySignal::ScanChurn);
+        self.scan_enables.clear();
+    }
+
+    fn finish_scan(&mut self, scan: ScanSession, how: &str, packet: &Packet) {
+        let elapsed_ms = packet.ts.signed_duration_since(scan.start.ts).num_milliseconds();
+        let severity = if elapsed_ms > self.long_scan_ms {
+            if !scan.long {
+                self.add_signal(packet, DiscoverySignal::LongScan);
+            }
+            Severity::Warning
+        } else {
+            Severity::Info
+        };
+        self.reportable.push(
+            Finding::new(
+                packet,
+                severity,
+                format!(
+                    "LE scan {} after {} ms: {} advertising reports from {} devices",
+                    how,
+                    elapsed_ms,
+                    scan.reports,
+                    scan.devices.len()
+                ),
+            )
+            .since(&scan.start),
+        );
+    }
+
+    /// Flag the scan once it runs for longer than `long_scan_ms`, so that a scan that is never
+    /// stopped is caught by the end of the log.
+    fn check_long_scan(&mut self, packet: &Packet) {
+        let long = match self.scan.as_mut() {
+            Some(scan)
+                if !scan.long
+                    && packet.ts.signed_duration_since(scan.start.ts).num_milliseconds()
+                        > self.long_scan_ms =>
+            {
+                scan.long = true;
+                true
+            }
+            _ => false,
+        };
+        if long {
+            self.add_signal(packet, DiscoverySignal::LongScan);
+        }
+    }
+
+    fn process_scan_enable_complete(&mut self, status: ErrorCode, packet: &Packet) {
+        if let Some(enable) = self.pending_scan_enable.take() {
+            // A rejected command leaves the scan as it was.
+            if status == ErrorCode::Success {
+                self.process_scan_enable(enable, packet);
+            }
+        }
+    }
+
+    fn process_advertising_report(&mut self, address: Address, packet: &Packet) {
+        if let Some(scan) = self.scan.as_mut() {
+            scan.devices.insert(address);
+            scan.reports += 1;
+        }
+        self.advertisers
+            .entry(address)
+            .or_insert_with(|| AdvertiserReports { first: packet.clone(), count: 0 })
+            .count += 1;
+    }
+
+    fn process_command_status(&mut self, opcode: OpCode, status: ErrorCode, packet: &Packet) {
+        if opcode != OpCode::Inquiry {
+            return;
+        }
+        if let Some(start) = self.pending_inquiry.take() {
+            if status != ErrorCode::Success {
+                self.inquiry = None;
+                self.reportable.push(
+                    Finding::new(
+                        packet,
+                        Severity::Warning,
+                        format!("Inquiry failed: {:?}", status),
+                    )
+                    .since(&start),
+                );
+            }
+        }
+    }
+
+    fn process_reset(&mut self, packet: &Packet) {
+        if let Some(inquiry) = self.inquiry.take() {
+            self.finish_inquiry(inquiry, "interrupted by reset", packet);
+        }
+        if let Some(scan) = self.scan.take() {
+            self.finish_scan(scan, "interrupted by reset", packet);
+        }
+        self.pending_inquiry = None;
+        self.pending_scan_enable = None;
+        self.scan_parameters = None;
+        self.scan_enables.clear();
+    }
+}
+
+impl Rule for ScanActivityRule {
+    fn name(&self) -> &'static str {
+        "ScanActivityRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        self.last_ts = Some(packet.ts);
+        self.check_long_scan(packet);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => match cmd.specialize() {
+                CommandChild::Reset(_) => self.process_reset(packet),
+                CommandChild::DiscoveryCommand(cmd) => match cmd.specialize() {
+                    DiscoveryCommandChild::Inquiry(cmd) => {
+                        self.pending_inquiry = Some(packet.clone());
+                        self.process_inquiry_start(cmd.get_inquiry_length(), packet);
+                    }
+                    DiscoveryCommandChild::InquiryCancel(_) => {
+                        if let Some(inquiry) = self.inquiry.take() {
+                            self.finish_inquiry(inquiry, "cancelled", packet);
+                        }
+                    }
+
+                    // CommandChild::DiscoveryCommand(cmd).specialize()
+                    _ => {}
+                },
+                CommandChild::LeScanningCommand(cmd) => match cmd.specialize() {
+                    LeScanningCommandChild::LeSetScanParameters(cmd) => {
+                        let parameters = format!(
+                            "{:?} interval {} ms window {} ms",
+                            cmd.get_le_scan_type(),
+                            scan_units_to_ms(cmd.get_le_scan_interval()),
+                            scan_units_to_ms(cmd.get_le_scan_window())
+                        );
+                        self.process_scan_parameters(parameters, packet);
+                    }
+                    LeScanningCommandChild::LeSetExtendedScanParameters(cmd) => {
+                        let parameters = cmd
+                            .get_parameters()
+                            .iter()
+                            .map(|phy| {
+                                format!(
+                                    "{:?} interval {} ms window {} ms",
+                                    phy.le_scan_type,
+                                    scan_units_to_ms(phy.le_scan_interval),
+                                    scan_units_to_ms(phy.le_scan_window)
+                                )
+                            })
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                        self.process_scan_parameters(parameters, packet);
+                    }
+                    LeScanningCommandChild::LeSetScanEnable(cmd) => {
+                        self.pending_scan_enable = Some(cmd.get_le_scan_enable());
+                    }
+                    LeScanningCommandChild::LeSetExtendedScanEnable(cmd) => {
+                        self.pending_scan_enable = Some(cmd.get_enable());
+                    }
+
+                    // CommandChild::LeScanningCommand(cmd).specialize()
+                    _ => {}
+                },
+
+                // PacketChild::HciCommand(cmd).specialize()
+                _ => {}
+            },
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::CommandStatus(ev) => {
+                    self.process_command_status(ev.get_command_op_code(), ev.get_status(), packet);
+                }
+                EventChild::CommandComplete(ev) => match ev.specialize() {
+                    CommandCompleteChild::LeSetScanEnableComplete(ev) => {
+                        self.process_scan_enable_complete(ev.get_status(), packet);
+                    }
+                    CommandCompleteChild::LeSetExtendedScanEnableComplete(ev) => {
+                        self.process_scan_enable_complete(ev.get_status(), packet);
+                    }
+
+                    // EventChild::CommandComplete(ev).specialize()
+                    _ => {}
+                },
+                EventChild::InquiryComplete(ev) => {
+                    self.pending_inquiry = None;
+                    if let Some(inquiry) = self.inquiry.take() {
+                        let how = match ev.get_status() {
+                            ErrorCode::Success => "completed".to_string(),
+                            status => format!("completed with {:?}", status),
+                        };
+                        self.finish_inquiry(inquiry, &how, packet);
+                    }
+                }
+                EventChild::InquiryResult(ev) => {
+                    for response in ev.get_responses() {
+                        self.process_inquiry_result(response.bd_addr);
+                    }
+                }
+                EventChild::InquiryResultWithRssi(ev) => {
+                    for response in ev.get_responses() {
+                        self.process_inquiry_result(response.address);
+                    }
+                }
+                EventChild::ExtendedInquiryResult(ev) => {
+                    self.process_inquiry_result(ev.get_address());
+                }
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    // Reports parse as either version depending on the advertising data.
+                    LeMetaEventChild::LeAdvertisingReport(ev) => {
+                        for response in ev.get_responses() {
+                            self.process_advertising_report(response.address, packet);
+                        }
+                    }
+                    LeMetaEventChild::LeAdvertisingReportRaw(ev) => {
+                        for response in ev.get_responses() {
+                            self.process_advertising_report(response.address, packet);
+                        }
+                    }
+                    LeMetaEventChild::LeExtendedAdvertisingReportRaw(ev) => {
+                        for response in ev.get_responses() {
+                            self.process_advertising_report(response.address, packet);
+                        }
+                    }
+                    LeMetaEventChild::LeScanTimeout(_) => {
+                        if let Some(scan) = self.scan.take() {
+                            self.finish_scan(scan, "timed out", packet);
+                        }
+                    }
+
+                    // EventChild::LeMetaEvent(ev).specialize()
+                    _ => {}
+                },
+
+                // PacketChild::HciEvent(ev).specialize()
+                _ => {}
+            },
+
+            // packet.inner
+            _ => {}
+        }
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        let mut advertisers: Vec<(&Address, &AdvertiserReports)> =
+            self.advertisers.iter().collect();
+        advertisers
+            .sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.1.first.index.cmp(&b.1.first.index)));
+        for (address, reports) in
+            advertisers.into_iter().take(usize::try_from(self.top_advertisers).unwrap_or(0))
+        {
+            findings.push(
+                Finding::new(
+                    &reports.first,
+                    Severity::Info,
+                    format!("{} advertising reports received", reports.count),
+                )
+                .with_address(*address),
+            );
+        }
+
+        let last_ts = match self.last_ts {
+            Some(ts) => ts,
+            None => return findings,
+        };
+        if let Some(scan) = &self.scan {
+            let elapsed_ms = last_ts.signed_duration_since(scan.start.ts).num_milliseconds();
+            if elapsed_ms > self.long_scan_ms {
+                findings.push(Finding::new(
+                    &scan.start,
+                    Severity::Warning,
+                    format!("LE scan still running after {} ms at the end of the log", elapsed_ms),
+                ));
+            }
+        }
+        if let Some(inquiry) = &self.inquiry {
+            let elapsed_ms = last_ts.signed_duration_since(inquiry.start.ts).num_milliseconds();
+            if elapsed_ms > inquiry.length_ms + INQUIRY_COMPLETE_SLACK_MS {
+                findings.push(Finding::new(
+                    &inquiry.start,
+                    Severity::Warning,
+                    format!(
+                        "Inquiry never completed, still running after {} ms at the end of the log",
+                        elapsed_ms
+                    ),
+                ));
+            }
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            DiscoverySignal::ScanChurn,
+            DiscoverySignal::LongScan,
+            DiscoverySignal::InquiryNeverCompleted,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// Legacy advertising has a single implicit set, extended advertising uses handles.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum AdvertisingSetId {
+    Legacy,
+    Extended(u8),
+}
+
+impl fmt::Display for AdvertisingSetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvertisingSetId::Legacy => write!(f, "Legacy advertising"),
+            AdvertisingSetId::Extended(handle) => write!(f, "Advertising set {}", handle),
+        }
+    }
+}
+
+/// Lifecycle of a single advertising set.
+struct AdvertisingSet {
+    configured: Packet,
+
+    /// Set while the set is advertising.
+    enabled_since: Option<Packet>,
+
+    times_enabled: usize,
+    enabled_ms: i64,
+}
+
+/// Follows advertising sets from configuration to removal, and reports sets that were configured
+/// but never enabled.
+struct AdvertisingSetsRule {
+    sets: HashMap<AdvertisingSetId, AdvertisingSet>,
+
+    /// Timestamp of the last packet seen, to report sets still active at the end of the log.
+    last_ts: Option<NaiveDateTime>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl AdvertisingSetsRule {
+    pub fn new() -> Self {
+        AdvertisingSetsRule {
+            sets: HashMap::new(),
+            last_ts: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: DiscoverySignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn process_configure(&mut self, id: AdvertisingSetId, packet: &Packet) {
+        self.sets.entry(id).or_insert_with(|| AdvertisingSet {
+            configured: packet.clone(),
+            enabled_since: None,
+            times_enabled: 0,
+            enabled_ms: 0,
+        });
+    }
+
+    fn process_enable(&mut self, id: AdvertisingSetId, packet: &Packet) {
+        // Sets can be enabled with the parameters left from before the log started.
+        let set = self.sets.entry(id).or_insert_with(|| AdvertisingSet {
+            configured: packet.clone(),
+            enabled_since: None,
+            times_enabled: 0,
+            enabled_ms: 0,
+        });
+        if set.enabled_since.is_none() {
+            set.enabled_since = Some(packet.clone());
+            set.times_enabled += 1;
+        }
+    }
+
+    fn process_disable(&mut self, id: AdvertisingSetId, packet: &Packet) {
+        if let Some(set) = self.sets.get_mut(&id) {
+            if let Some(since) = set.enabled_since.take() {
+                set.enabled_ms += packet.ts.signed_duration_since(since.ts).num_milliseconds();
+            }
+        }
+    }
+
+    fn process_remove(&mut self, id: AdvertisingSetId, how: &str, packet: &Packet) {
+        self.process_disable(id, packet);
+        let set = match self.sets.remove(&id) {
+            Some(set) => set,
+            None => return,
+        };
+
+        if set.times_enabled == 0 {
+            self.add_signal(packet, DiscoverySignal::AdvertiserNeverEnabled);
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("{} was configured but {} without ever being enabled", id, how),
+                )
+                .since(&set.configured),
+            );
+            return;
+        }
+
+        self.reportable.push(
+            Finding::new(
+                packet,
+                Severity::Info,
+                format!(
+                    "{} {}: enabled {} times, advertised for {} ms",
+                    id, how, set.times_enabled, set.enabled_ms
+                ),
+            )
+            .since(&set.configured),
+        );
+    }
+
+    fn process_remove_all(&mut self, how: &str, packet: &Packet) {
+        let mut ids: Vec<AdvertisingSetId> = self.sets.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            self.process_remove(id, how, packet);
+        }
+    }
+}
+
+impl Rule for AdvertisingSetsRule {
+    fn name(&self) -> &'static str {
+        "AdvertisingSetsRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        self.last_ts = Some(packet.ts);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => match cmd.specialize() {
+                CommandChild::Reset(_) => self.process_remove_all("reset", packet),
+                CommandChild::LeAdvertisingCommand(cmd) => match cmd.specialize() {
+                    LeAdvertisingCommandChild::LeSetAdvertisingParameters(_) => {
+                        self.process_configure(AdvertisingSetId::Legacy, packet);
+                    }
+                    LeAdvertisingCommandChild::LeSetAdvertisingEnable(cmd) => {
+                        match cmd.get_advertising_enable() {
+                            Enable::Enabled => {
+                                self.process_enable(AdvertisingSetId::Legacy, packet)
+                            }
+                            Enable::Disabled => {
+                                self.process_disable(AdvertisingSetId::Legacy, packet)
+                            }
+                        }
+                    }
+                    LeAdvertisingCommandChild::LeSetExtendedAdvertisingParametersLegacy(cmd) => {
+                        let id = AdvertisingSetId::Extended(cmd.get_advertising_handle());
+                        self.process_configure(id, packet);
+                    }
+                    LeAdvertisingCommandChild::LeSetExtendedAdvertisingParameters(cmd) => {
+                        let id = AdvertisingSetId::Extended(cmd.get_advertising_handle());
+                        self.process_configure(id, packet);
+                    }
+                    LeAdvertisingCommandChild::LeSetExtendedAdvertisingEnableDisableAll(_) => {
+                        let ids: Vec<AdvertisingSetId> = self.sets.keys().cloned().collect();
+                        for id in ids {
+                            self.process_disable(id, packet);
+                        }
+                    }
+                    LeAdvertisingCommandChild::LeSetExtendedAdvertisingDisable(cmd) => {
+                        for set in cmd.get_disabled_sets() {
+                            let id = AdvertisingSetId::Extended(set.advertising_handle);
+                            self.process_disable(id, packet);
+                        }
+                    }
+                    // Disabling sets with a non-zero duration doesn't match the variant above
+                    // and is parsed as this packet instead.
+                    LeAdvertisingCommandChild::LeSetExtendedAdvertisingEnable(cmd) => {
+                        let ids: Vec<AdvertisingSetId> = match cmd.get_enabled_sets().len() {
+                            0 => self.sets.keys().cloned().collect(),
+                            _ => cmd
+                                .get_enabled_sets()
+                                .iter()
+                                .map(|set| AdvertisingSetId::Extended(set.advertising_handle))
+                                .collect(),
+                        };
+                        for id in ids {
+                            match cmd.get_enable() {
+                                Enable::Enabled => self.process_enable(id, packet),
+                                Enable::Disabled => self.process_disable(id, packet),
+                            }
+                        }
+                    }
+                    LeAdvertisingCommandChild::LeRemoveAdvertisingSet(cmd) => {
+                        let id = AdvertisingSetId::Extended(cmd.get_advertising_handle());
+                        self.process_remove(id, "removed", packet);
+                    }
+                    LeAdvertisingCommandChild::LeClearAdvertisingSets(_) => {
+                        self.process_remove_all("cleared", packet);
+                    }
+
+                    // CommandChild::LeAdvertisingCommand(cmd).specialize()
+                    _ => {}
+                },
+
+                // PacketChild::HciCommand(cmd).specialize()
+                _ => {}
+            },
+
+            PacketChild::HciEvent(ev) => {
+                if let EventChild::LeMetaEvent(ev) = ev.specialize() {
+                    if let LeMetaEventChild::LeAdvertisingSetTerminated(ev) = ev.specialize() {
+                        // Advertising stops when the set times out or a connection is created.
+                        let id = AdvertisingSetId::Extended(ev.get_advertising_handle());
+                        self.process_disable(id, packet);
+                    }
+                }
+            }
+
+            // packet.inner
+            _ => {}
+        }
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        let last_ts = match self.last_ts {
+            Some(ts) => ts,
+            None => return findings,
+        };
+        let mut ids: Vec<&AdvertisingSetId> = self.sets.keys().collect();
+        ids.sort();
+        for id in ids {
+            let set = &self.sets[id];
+            if set.times_enabled == 0 {
+                findings.push(Finding::new(
+                    &set.configured,
+                    Severity::Warning,
+                    format!("{} was configured but never enabled by the end of the log", id),
+                ));
+                continue;
+            }
+
+            let mut enabled_ms = set.enabled_ms;
+            if let Some(since) = &set.enabled_since {
+                enabled_ms += last_ts.signed_duration_since(since.ts).num_milliseconds();
+            }
+            findings.push(Finding::new(
+                &set.configured,
+                Severity::Info,
+                format!(
+                    "{} still present at the end of the log: enabled {} times, advertised for {} ms",
+                    id, set.times_enabled, enabled_ms
+                ),
+            ));
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        registry.register(DiscoverySignal::AdvertiserNeverEnabled.into());
+    }
+}
+
+/// Get a rule group with discovery rules.
+pub fn get_discovery_group(config: &RuleConfig) -> RuleGroup {
+    let mut group = RuleGroup::new();
+    group.add_rule(Box::new(ScanActivityRule::new(config)));
+    group.add_rule(Box::new(AdvertisingSetsRule::new()));
+
+    group
+}
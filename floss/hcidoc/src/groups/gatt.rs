@@ -0,0 +1,613 @@
+//! Rule group for tracking ATT/GATT transactions.
+use std::collections::HashMap;
+
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
+use crate::parser::{AclContent, AclReassembler, Packet, PacketChild};
+use bt_packets::hci::{Acl, Address, CommandChild, ErrorCode, EventChild, LeMetaEventChild};
+use hcidoc_packets::att::{Att, AttChild, AttOpcode};
+
+enum GattSignal {
+    Timeout,     // A request or indication went unanswered past the ATT transaction timeout.
+    NoResponse,  // Link disconnected with a request or indication still unanswered.
+    ErrorLoop,   // The same request fails with the same error over and over.
+    Overlapping, // A new request is sent while another is still outstanding.
+}
+
+impl From<GattSignal> for SignalDefinition {
+    fn from(signal: GattSignal) -> Self {
+        match signal {
+            GattSignal::Timeout => SignalDefinition {
+                tag: "AttTimeout",
+                severity: Severity::Error,
+                description: "An ATT request or indication went unanswered past the ATT \
+                    transaction timeout.",
+                next_step: "Check whether the peer was busy, the bearer is unusable after a \
+                    timeout until it reconnects.",
+            },
+            GattSignal::NoResponse => SignalDefinition {
+                tag: "AttNoResponse",
+                severity: Severity::Warning,
+                description: "The link disconnected with an ATT request or indication still \
+                    unanswered.",
+                next_step: "Check whether the disconnection was caused by the host giving up on \
+                    the transaction.",
+            },
+            GattSignal::ErrorLoop => SignalDefinition {
+                tag: "AttErrorLoop",
+                severity: Severity::Warning,
+                description: "The same ATT request failed with the same error over and over.",
+                next_step: "Find the client repeating the request, it should handle the error \
+                    instead of retrying.",
+            },
+            GattSignal::Overlapping => SignalDefinition {
+                tag: "AttOverlapping",
+                severity: Severity::Warning,
+                description: "A new ATT request was sent while another was still outstanding.",
+                next_step: "Check the GATT client queue, ATT only allows one outstanding request \
+                    per bearer.",
+            },
+        }
+    }
+}
+
+impl From<GattSignal> for &'static str {
+    fn from(signal: GattSignal) -> Self {
+        SignalDefinition::from(signal).tag
+    }
+}
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+/// ATT transactions time out after 30 seconds (Core spec Vol 3, Part F, 3.3.3).
+const ATT_TRANSACTION_TIMEOUT_MS: i64 = 30000;
+
+/// Error code used by servers to end a discovery procedure.
+const ATT_ERROR_ATTRIBUTE_NOT_FOUND: u8 = 0x0A;
+
+/// How many identical errors in a row make an error loop. Can be overridden with the
+/// `error_loop_threshold` parameter of AttTransactionsRule.
+const DEFAULT_ERROR_LOOP_THRESHOLD: i64 = 5;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Sender {
+    Host,
+    Peer,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum TransactionType {
+    Request,
+    Indication,
+}
+
+/// What kind of PDU an opcode is, from the point of view of transactions.
+enum PduType {
+    Request(TransactionType),
+    Response(TransactionType),
+    Other,
+}
+
+fn get_pdu_type(opcode: AttOpcode) -> PduType {
+    match opcode {
+        AttOpcode::ExchangeMtuRequest
+        | AttOpcode::FindInformationRequest
+        | AttOpcode::FindByTypeValueRequest
+        | AttOpcode::ReadByTypeRequest
+        | AttOpcode::ReadRequest
+        | AttOpcode::ReadBlobRequest
+        | AttOpcode::ReadMultipleRequest
+        | AttOpcode::ReadByGroupTypeRequest
+        | AttOpcode::WriteRequest
+        | AttOpcode::PrepareWriteRequest
+        | AttOpcode::ExecuteWriteRequest
+        | AttOpcode::ReadMultipleVariableRequest => PduType::Request(TransactionType::Request),
+
+        AttOpcode::ErrorResponse
+        | AttOpcode::ExchangeMtuResponse
+        | AttOpcode::FindInformationResponse
+        | AttOpcode::FindByTypeValueResponse
+        | AttOpcode::ReadByTypeResponse
+        | AttOpcode::ReadResponse
+        | AttOpcode::ReadBlobResponse
+        | AttOpcode::ReadMultipleResponse
+        | AttOpcode::ReadByGroupTypeResponse
+        | AttOpcode::WriteResponse
+        | AttOpcode::PrepareWriteResponse
+        | AttOpcode::ExecuteWriteResponse
+        | AttOpcode::ReadMultipleVariableResponse => PduType::Response(TransactionType::Request),
+
+        AttOpcode::HandleValueIndication => PduType::Request(TransactionType::Indication),
+        AttOpcode::HandleValueConfirmation => PduType::Response(TransactionType::Indication),
+
+        _ => PduType::Other,
+    }
+}
+
+/// GATT discovery procedure a request belongs to, if any.
+fn get_discovery_phase(opcode: AttOpcode) -> Option<&'static str> {
+    match opcode {
+        AttOpcode::ReadByGroupTypeRequest => Some("primary service discovery"),
+        AttOpcode::FindByTypeValueRequest => Some("service discovery by UUID"),
+        AttOpcode::ReadByTypeRequest => Some("characteristic discovery"),
+        AttOpcode::FindInformationRequest => Some("descriptor discovery"),
+        _ => None,
+    }
+}
+
+/// A request or indication waiting for the other side.
+struct PendingTransaction {
+    packet: Packet,
+    opcode: AttOpcode,
+    /// Whether the transaction timeout was already reported.
+    timed_out: bool,
+}
+
+/// A GATT discovery procedure run by one side of the link.
+struct DiscoveryPhase {
+    name: &'static str,
+    start: Packet,
+    requests: usize,
+}
+
+/// The last error response and how many times in a row it was seen.
+struct RepeatedError {
+    opcode: AttOpcode,
+    attribute: u16,
+    error: u8,
+    first: Packet,
+    count: i64,
+}
+
+/// ATT state of a single link.
+#[derive(Default)]
+struct AttConnection {
+    /// Outstanding transactions keyed by who started them.
+    pending: HashMap<(Sender, TransactionType), PendingTransaction>,
+
+    /// MTU proposed by each side.
+    mtu: HashMap<Sender, u16>,
+
+    /// Discovery procedure in progress, keyed by the client running it.
+    discovery: HashMap<Sender, DiscoveryPhase>,
+
+    /// Last error response, keyed by the server that sent it.
+    last_error: HashMap<Sender, RepeatedError>,
+}
+
+/// Pairs ATT requests with their responses on every link, and flags requests that are never
+/// answered, answered too late or keep failing the same way.
+struct AttTransactionsRule {
+    /// Addresses of active links.
+    handles: HashMap<ConnectionHandle, Address>,
+
+    connections: HashMap<ConnectionHandle, AttConnection>,
+
+    /// ATT PDUs can be longer than a single ACL packet.
+    reassembler: AclReassembler,
+
+    error_loop_threshold: i64,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl AttTransactionsRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        AttTransactionsRule {
+            handles: HashMap::new(),
+            connections: HashMap::new(),
+            reassembler: AclReassembler::new(),
+            error_loop_threshold: config.get_i64(
+                "AttTransactionsRule",
+                "error_loop_threshold",
+                DEFAULT_ERROR_LOOP_THRESHOLD,
+            ),
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: GattSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn report(&mut self, handle: ConnectionHandle, finding: Finding) {
+        let finding = match self.handles.get(&handle) {
+            Some(address) => finding.with_address(*address),
+            None => finding,
+        };
+        self.reportable.push(finding.with_handle(handle));
+    }
+
+    fn process_att(&mut self, acl: &Acl, sender: Sender, packet: &Packet) {
+        let att: Att = match self.reassembler.get_acl_content(packet) {
+            AclContent::Att(att) => att,
+            _ => return,
+        };
+        let handle = acl.get_handle();
+        let opcode = att.get_opcode();
+
+        match get_pdu_type(opcode) {
+            PduType::Request(transaction) => {
+                self.process_request(handle, sender, transaction, opcode, packet)
+            }
+            PduType::Response(transaction) => {
+                // Responses go the other way, so the transaction belongs to the receiver.
+                let requester = match sender {
+                    Sender::Host => Sender::Peer,
+                    Sender::Peer => Sender::Host,
+                };
+                self.process_response(handle, requester, transaction, &att, packet)
+            }
+            PduType::Other => {}
+        }
+
+        match att.specialize() {
+            AttChild::AttExchangeMtuRequest(req) => {
+                self.connections.entry(handle).or_default().mtu.insert(sender, req.get_mtu());
+            }
+            AttChild::AttExchangeMtuResponse(rsp) => {
+                let conn = self.connections.entry(handle).or_default();
+                conn.mtu.insert(sender, rsp.get_mtu());
+                if let (Some(host), Some(peer)) =
+                    (conn.mtu.get(&Sender::Host), conn.mtu.get(&Sender::Peer))
+                {
+                    let message = format!(
+                        "MTU exchanged: host {}, peer {}, using {}",
+                        host,
+                        peer,
+                        std::cmp::min(host, peer)
+                    );
+                    self.report(handle, Finding::new(packet, Severity::Info, message));
+                }
+            }
+            AttChild::AttErrorResponse(err) => {
+                self.process_error(
+                    handle,
+                    sender,
+                    err.get_opcode_in_error(),
+                    err.get_handle_in_error(),
+                    err.get_error_code(),
+                    packet,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn process_request(
+        &mut self,
+        handle: ConnectionHandle,
+        sender: Sender,
+        transaction: TransactionType,
+        opcode: AttOpcode,
+        packet: &Packet,
+    ) {
+        let conn = self.connections.entry(handle).or_default();
+        let previous = conn.pending.insert(
+            (sender, transaction),
+            PendingTransaction { packet: packet.clone(), opcode, timed_out: false },
+        );
+
+        // Keep track of which discovery procedure the client is in.
+        let mut finished_phase = None;
+        if transaction == TransactionType::Request {
+            match (get_discovery_phase(opcode), conn.discovery.get_mut(&sender)) {
+                (Some(name), Some(phase)) if phase.name == name => phase.requests += 1,
+                (Some(name), _) => {
+                    finished_phase = conn.discovery.insert(
+                        sender,
+                        DiscoveryPhase { name, start: packet.clone(), requests: 1 },
+                    );
+                }
+                (None, _) => {}
+            }
+        }
+
+        if let Some(phase) = finished_phase {
+            self.report_discovery(handle, sender, phase, "interrupted", packet);
+        }
+
+        if let Some(previous) = previous {
+            self.add_signal(packet, GattSignal::Overlapping);
+            self.report(
+                handle,
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!(
+                        "{:?} sent {:?} while {:?} was still outstanding",
+                        sender, opcode, previous.opcode
+                    ),
+                )
+                .since(&previous.packet),
+            );
+        }
+    }
+
+    fn process_response(
+        &mut self,
+        handle: ConnectionHandle,
+        requester: Sender,
+        transaction: TransactionType,
+        att: &Att,
+        packet: &Packet,
+    ) {
+        let pending = match self.connections.get_mut(&handle) {
+            Some(conn) => conn.pending.remove(&(requester, transaction)),
+            None => None,
+        };
+
+        let pending = match pending {
+            Some(pending) => pending,
+            None => {
+                self.report(
+                    handle,
+                    Finding::new(
+                        packet,
+                        Severity::Warning,
+                        format!("{:?} without a matching request or indication", att.get_opcode()),
+                    ),
+                );
+                return;
+            }
+        };
+
+        // The timeout itself was signaled when it expired, this is the late answer.
+        if pending.timed_out {
+            let elapsed_ms = packet.ts.signed_duration_since(pending.packet.ts).num_milliseconds();
+            self.report(
+                handle,
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!(
+                        "{:?} answered after {} ms, past the ATT transaction timeout",
+                        pending.opcode, elapsed_ms
+                    ),
+                )
+                .since(&pending.packet),
+            );
+        }
+    }
+
+    /// Flag the transactions that went unanswered for longer than the ATT transaction timeout
+    /// before |packet|.
+    fn check_timeouts(&mut self, packet: &Packet) {
+        let mut timed_out = vec![];
+        for (handle, conn) in self.connections.iter_mut() {
+            for ((sender, _), pending) in conn.pending.iter_mut() {
+                if !pending.timed_out
+                    && packet.ts.signed_duration_since(pending.packet.ts).num_milliseconds()
+                        > ATT_TRANSACTION_TIMEOUT_MS
+                {
+                    pending.timed_out = true;
+                    timed_out.push((*handle, *sender, pending.opcode, pending.packet.clone()));
+                }
+            }
+        }
+
+        for (handle, sender, opcode, start) in timed_out {
+            self.add_signal(packet, GattSignal::Timeout);
+            self.report(
+                handle,
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!(
+                        "{:?} sent by {:?} got no answer within the ATT transaction timeout",
+                        opcode, sender
+                    ),
+                )
+                .since(&start),
+            );
+        }
+    }
+
+    fn process_error(
+        &mut self,
+        handle: ConnectionHandle,
+        server: Sender,
+        opcode: AttOpcode,
+        attribute: u16,
+        error: u8,
+        packet: &Packet,
+    ) {
+        let client = match server {
+            Sender::Host => Sender::Peer,
+            Sender::Peer => Sender::Host,
+        };
+
+        // Attribute Not Found is how a server ends a discovery procedure.
+        if error == ATT_ERROR_ATTRIBUTE_NOT_FOUND && get_discovery_phase(opcode).is_some() {
+            let phase = self.connections.get_mut(&handle).and_then(|c| c.discovery.remove(&client));
+            if let Some(phase) = phase {
+                self.report_discovery(handle, client, phase, "completed", packet);
+            }
+            return;
+        }
+
+        self.report(
+            handle,
+            Finding::new(
+                packet,
+                Severity::Warning,
+                format!(
+                    "{:?} failed with ATT error {:#04x} on attribute {:#06x}",
+                    opcode, error, attribute
+                ),
+            ),
+        );
+
+        // Count identical errors in a row and flag a loop once when hitting the threshold.
+        let threshold = self.error_loop_threshold;
+        let conn = self.connections.entry(handle).or_default();
+        let repeated = match conn.last_error.get_mut(&server) {
+            Some(last)
+                if last.opcode == opcode && last.attribute == attribute && last.error == error =>
+            {
+                last.count += 1;
+                last
+            }
+            _ => {
+                conn.last_error.insert(
+                    server,
+                    RepeatedError { opcode, attribute, error, first: packet.clone(), count: 1 },
+                );
+                conn.last_error.get_mut(&server).unwrap()
+            }
+        };
+
+        if repeated.count == threshold {
+            let finding = Finding::new(
+                packet,
+                Severity::Error,
+                format!(
+                    "{:?} on attribute {:#06x} failed with ATT error {:#04x} {} times in a row",
+                    opcode, attribute, error, threshold
+                ),
+            )
+            .since(&repeated.first);
+            self.add_signal(packet, GattSignal::ErrorLoop);
+            self.report(handle, finding);
+        }
+    }
+
+    fn report_discovery(
+        &mut self,
+        handle: ConnectionHandle,
+        client: Sender,
+        phase: DiscoveryPhase,
+        outcome: &str,
+        packet: &Packet,
+    ) {
+        let elapsed_ms = packet.ts.signed_duration_since(phase.start.ts).num_milliseconds();
+        let severity = if outcome == "completed" { Severity::Info } else { Severity::Warning };
+        self.report(
+            handle,
+            Finding::new(
+                packet,
+                severity,
+                format!(
+                    "{:?} {} {} after {} requests in {} ms",
+                    client, outcome, phase.name, phase.requests, elapsed_ms
+                ),
+            )
+            .since(&phase.start),
+        );
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        if let Some(mut conn) = self.connections.remove(&handle) {
+            for ((sender, _), pending) in conn.pending.drain() {
+                self.add_signal(packet, GattSignal::NoResponse);
+                self.report(
+                    handle,
+                    Finding::new(
+                        packet,
+                        Severity::Error,
+                        format!("{:?} sent by {:?} was never answered", pending.opcode, sender),
+                    )
+                    .since(&pending.packet),
+                );
+            }
+            for (client, phase) in conn.discovery.drain() {
+                self.report_discovery(handle, client, phase, "disconnected during", packet);
+            }
+        }
+        self.handles.remove(&handle);
+        self.reassembler.remove_link(handle);
+    }
+
+    fn process_reset(&mut self) {
+        self.handles.clear();
+        self.connections.clear();
+        self.reassembler.clear();
+    }
+}
+
+impl Rule for AttTransactionsRule {
+    fn name(&self) -> &'static str {
+        "AttTransactionsRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        // Checked on every packet so a transaction that stalls is caught even if nothing else
+        // happens on its link until the end of the log.
+        self.check_timeouts(packet);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => {
+                if let CommandChild::Reset(_) = cmd.specialize() {
+                    self.process_reset();
+                }
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::ConnectionComplete(ev) if ev.get_status() == ErrorCode::Success => {
+                    self.handles.insert(ev.get_connection_handle(), ev.get_bd_addr());
+                }
+                EventChild::DisconnectionComplete(ev) => {
+                    self.process_disconnection(ev.get_connection_handle(), packet);
+                }
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeConnectionComplete(ev)
+                        if ev.get_status() == ErrorCode::Success =>
+                    {
+                        self.handles.insert(ev.get_connection_handle(), ev.get_peer_address());
+                    }
+                    LeMetaEventChild::LeEnhancedConnectionComplete(ev)
+                        if ev.get_status() == ErrorCode::Success =>
+                    {
+                        self.handles.insert(ev.get_connection_handle(), ev.get_peer_address());
+                    }
+
+                    // EventChild::LeMetaEvent(ev).specialize()
+                    _ => {}
+                },
+
+                // PacketChild::HciEvent(ev).specialize()
+                _ => {}
+            },
+
+            PacketChild::AclTx(tx) => self.process_att(tx, Sender::Host, packet),
+            PacketChild::AclRx(rx) => self.process_att(rx, Sender::Peer, packet),
+
+            // packet.inner
+            _ => {}
+        }
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        self.reportable.clone()
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            GattSignal::Timeout,
+            GattSignal::NoResponse,
+            GattSignal::ErrorLoop,
+            GattSignal::Overlapping,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// Get a rule group with GATT rules.
+pub fn get_gatt_group(config: &RuleConfig) -> RuleGroup {
+    let mut group = RuleGroup::new();
+    group.add_rule(Box::new(AttTransactionsRule::new(config)));
+
+    group
+}
@@ -7,7 +7,7 @@ use std::fmt;
 use std::hash::Hash;
 use std::io::Write;
 
-use crate::engine::{Rule, RuleGroup, Signal};
+use crate::engine::{Finding, Rule, RuleConfig, RuleGroup, Severity, Signal};
 use crate::parser::{get_acl_content, AclContent, Packet, PacketChild};
 use bt_packets::hci::{
     AclCommandChild, Address, CommandChild, ConnectionManagementCommandChild, DisconnectReason,
@@ -143,17 +143,17 @@ impl DeviceInformation {
         return self.acls.last_mut().unwrap();
     }
 
-    fn report_connection_start(&mut self, handle: ConnectionHandle, ts: NaiveDateTime) {
+    fn report_connection_start(&mut self, handle: ConnectionHandle, packet: &Packet) {
         let mut acl = AclInformation::new(handle);
         let initiator = self.acl_state.into();
-        acl.report_start(initiator, ts);
+        acl.report_start(initiator, packet);
         self.acls.push(acl);
         self.acl_state = AclState::Connected;
     }
 
-    fn report_connection_end(&mut self, handle: ConnectionHandle, ts: NaiveDateTime) {
+    fn report_connection_end(&mut self, handle: ConnectionHandle, packet: &Packet) {
         let acl = self.get_or_allocate_connection(&handle);
-        acl.report_end(ts);
+        acl.report_end(packet);
         self.acl_state = AclState::None;
     }
 
@@ -194,6 +194,9 @@ enum CidState {
 struct AclInformation {
     start_time: NaiveDateTime,
     end_time: NaiveDateTime,
+    /// Index of the packets that started and ended the connection, if they were seen.
+    start_index: Option<usize>,
+    end_index: Option<usize>,
     handle: ConnectionHandle,
     initiator: InitiatorType,
     active_profiles: HashMap<ProfileId, ProfileInformation>,
@@ -207,6 +210,8 @@ impl AclInformation {
         AclInformation {
             start_time: INVALID_TS,
             end_time: INVALID_TS,
+            start_index: None,
+            end_index: None,
             handle: handle,
             initiator: InitiatorType::Unknown,
             active_profiles: HashMap::new(),
@@ -216,18 +221,20 @@ impl AclInformation {
         }
     }
 
-    fn report_start(&mut self, initiator: InitiatorType, ts: NaiveDateTime) {
+    fn report_start(&mut self, initiator: InitiatorType, packet: &Packet) {
         self.initiator = initiator;
-        self.start_time = ts;
+        self.start_time = packet.ts;
+        self.start_index = Some(packet.index);
     }
 
-    fn report_end(&mut self, ts: NaiveDateTime) {
+    fn report_end(&mut self, packet: &Packet) {
         // disconnect the active profiles
         for (_, mut profile) in self.active_profiles.drain() {
-            profile.report_end(ts);
+            profile.report_end(packet.ts);
             self.inactive_profiles.push(profile);
         }
-        self.end_time = ts;
+        self.end_time = packet.ts;
+        self.end_index = Some(packet.index);
     }
 
     fn report_profile_start(
@@ -361,6 +368,30 @@ impl AclInformation {
             self.report_profile_end(profile, profile_id, ts)
         }
     }
+
+    /// Summarize the connection as a finding. Connections that started before the log or are
+    /// still open at its end span to the respective end of the log.
+    fn to_finding(&self, first: (usize, NaiveDateTime), last: (usize, NaiveDateTime)) -> Finding {
+        let (start_index, start_ts) = match self.start_index {
+            Some(index) => (index, self.start_time),
+            None => first,
+        };
+        let (end_index, end_ts) = match self.end_index {
+            Some(index) => (index, self.end_time),
+            None => last,
+        };
+        let summary = format!("{}", self);
+        Finding {
+            severity: Severity::Info,
+            start_index,
+            start_ts,
+            end_index,
+            end_ts,
+            addresses: vec![],
+            handles: vec![self.handle],
+            message: summary.lines().map(str::trim).collect::<Vec<&str>>().join("; "),
+        }
+    }
 }
 
 impl fmt::Display for AclInformation {
@@ -487,17 +518,86 @@ struct InformationalRule {
     /// When powering off, the controller might or might not reply disconnection request. Therefore
     /// make this a special case.
     pending_disconnect_due_to_host_power_off: HashSet<ConnectionHandle>,
+    /// Only report these devices if not empty.
+    selected_addresses: HashSet<Address>,
+    /// Index and timestamp of the first and last packets of the log.
+    first_packet: Option<(usize, NaiveDateTime)>,
+    last_packet: Option<(usize, NaiveDateTime)>,
 }
 
 impl InformationalRule {
-    pub fn new() -> Self {
+    pub fn new(config: &RuleConfig) -> Self {
         InformationalRule {
             devices: HashMap::new(),
             handles: HashMap::new(),
             sco_handles: HashMap::new(),
             unknown_connections: HashMap::new(),
             pending_disconnect_due_to_host_power_off: HashSet::new(),
+            selected_addresses: config.addresses().iter().cloned().collect(),
+            first_packet: None,
+            last_packet: None,
+        }
+    }
+
+    /// The selected devices, from the most to the least important.
+    fn sorted_addresses(&self) -> Vec<Address> {
+        /* Sort when displaying the addresses, from the most to the least important:
+         * (1) Device with connections > Device without connections
+         * (2) Device with known name > Device with unknown name
+         * (3) BREDR > LE > Dual
+         * (4) Name, lexicographically (case sensitive)
+         * (5) Address, alphabetically
+         */
+        fn sort_addresses(a: &DeviceInformation, b: &DeviceInformation) -> Ordering {
+            let connection_order = a.acls.is_empty().cmp(&b.acls.is_empty());
+            if connection_order != Ordering::Equal {
+                return connection_order;
+            }
+
+            let known_name_order = a.names.is_empty().cmp(&b.names.is_empty());
+            if known_name_order != Ordering::Equal {
+                return known_name_order;
+            }
+
+            let address_type_order = a.address_type.cmp(&b.address_type);
+            if address_type_order != Ordering::Equal {
+                return address_type_order;
+            }
+
+            let a_name = format!("{}", DeviceInformation::print_names(&a.names));
+            let b_name = format!("{}", DeviceInformation::print_names(&b.names));
+            let name_order = a_name.cmp(&b_name);
+            if name_order != Ordering::Equal {
+                return name_order;
+            }
+
+            let a_address = <[u8; 6]>::from(a.address);
+            let b_address = <[u8; 6]>::from(b.address);
+            for i in (0..6).rev() {
+                let address_order = a_address[i].cmp(&b_address[i]);
+                if address_order != Ordering::Equal {
+                    return address_order;
+                }
+            }
+            // This shouldn't be executed
+            return Ordering::Equal;
         }
+
+        let filtered = !self.selected_addresses.is_empty();
+        let mut addresses: Vec<Address> = self
+            .devices
+            .keys()
+            .filter(|a| !filtered || self.selected_addresses.contains(a))
+            .cloned()
+            .collect();
+        addresses.sort_unstable_by(|a, b| sort_addresses(&self.devices[a], &self.devices[b]));
+        addresses
+    }
+
+    /// Connections we can't attribute to a device are of no interest when looking at specific
+    /// devices.
+    fn report_unknown_connections(&self) -> bool {
+        self.selected_addresses.is_empty() && !self.unknown_connections.is_empty()
     }
 
     fn get_or_allocate_device(&mut self, address: &Address) -> &mut DeviceInformation {
@@ -547,10 +647,10 @@ impl InformationalRule {
         &mut self,
         address: &Address,
         handle: ConnectionHandle,
-        ts: NaiveDateTime,
+        packet: &Packet,
     ) {
         let device = self.get_or_allocate_device(address);
-        device.report_connection_start(handle, ts);
+        device.report_connection_start(handle, packet);
         self.handles.insert(handle, *address);
     }
 
@@ -587,7 +687,7 @@ impl InformationalRule {
         self.sco_handles.insert(handle, acl_handle);
     }
 
-    fn report_connection_end(&mut self, handle: ConnectionHandle, ts: NaiveDateTime) {
+    fn report_connection_end(&mut self, handle: ConnectionHandle, packet: &Packet) {
         // This might be a SCO disconnection event, so check that first
         if self.sco_handles.contains_key(&handle) {
             let acl_handle = self.sco_handles[&handle];
@@ -595,7 +695,7 @@ impl InformationalRule {
             conn.report_profile_end(
                 ProfileType::Hfp,
                 ProfileId::OnePerConnection(ProfileType::Hfp),
-                ts,
+                packet.ts,
             );
             return;
         }
@@ -604,7 +704,7 @@ impl InformationalRule {
         if let Some(address) = self.handles.get(&handle) {
             // This device is known
             let device = self.devices.get_mut(address).unwrap();
-            device.report_connection_end(handle, ts);
+            device.report_connection_end(handle, packet);
             self.handles.remove(&handle);
 
             // remove the associated SCO handle, if any
@@ -612,15 +712,15 @@ impl InformationalRule {
         } else {
             // Unknown device.
             let conn = self.get_or_allocate_unknown_connection(&handle);
-            conn.report_end(ts);
+            conn.report_end(packet);
         }
     }
 
-    fn report_reset(&mut self, ts: NaiveDateTime) {
+    fn report_reset(&mut self, packet: &Packet) {
         // report_connection_end removes the entries from the map, so store all the keys first.
         let handles: Vec<ConnectionHandle> = self.handles.keys().cloned().collect();
         for handle in handles {
-            self.report_connection_end(handle, ts);
+            self.report_connection_end(handle, packet);
         }
         self.sco_handles.clear();
         self.pending_disconnect_due_to_host_power_off.clear();
@@ -697,19 +797,28 @@ impl InformationalRule {
 }
 
 impl Rule for InformationalRule {
+    fn name(&self) -> &'static str {
+        "InformationalRule"
+    }
+
     fn process(&mut self, packet: &Packet) {
+        if self.first_packet.is_none() {
+            self.first_packet = Some((packet.index, packet.ts));
+        }
+        self.last_packet = Some((packet.index, packet.ts));
+
         match &packet.inner {
             PacketChild::HciEvent(ev) => match ev.specialize() {
                 EventChild::ConnectionComplete(ev) => {
                     self.report_connection_start(
                         &ev.get_bd_addr(),
                         ev.get_connection_handle(),
-                        packet.ts,
+                        packet,
                     );
 
                     // If failed, assume it's the end of connection.
                     if ev.get_status() != ErrorCode::Success {
-                        self.report_connection_end(ev.get_connection_handle(), packet.ts);
+                        self.report_connection_end(ev.get_connection_handle(), packet);
                     }
                 }
 
@@ -721,7 +830,7 @@ impl Rule for InformationalRule {
                     );
                     // If failed, assume it's the end of connection.
                     if ev.get_status() != ErrorCode::Success {
-                        self.report_connection_end(ev.get_connection_handle(), packet.ts);
+                        self.report_connection_end(ev.get_connection_handle(), packet);
                     }
                 }
 
@@ -732,7 +841,7 @@ impl Rule for InformationalRule {
                         .pending_disconnect_due_to_host_power_off
                         .remove(&ev.get_connection_handle())
                     {
-                        self.report_connection_end(ev.get_connection_handle(), packet.ts);
+                        self.report_connection_end(ev.get_connection_handle(), packet);
                     }
                 }
 
@@ -764,7 +873,7 @@ impl Rule for InformationalRule {
                         self.report_connection_start(
                             &ev.get_peer_address(),
                             ev.get_connection_handle(),
-                            packet.ts,
+                            packet,
                         );
                         self.report_address_type(&ev.get_peer_address(), AddressType::LE);
                     }
@@ -779,7 +888,7 @@ impl Rule for InformationalRule {
                         self.report_connection_start(
                             &ev.get_peer_address(),
                             ev.get_connection_handle(),
-                            packet.ts,
+                            packet,
                         );
                         self.report_address_type(&ev.get_peer_address(), AddressType::LE);
                     }
@@ -810,7 +919,7 @@ impl Rule for InformationalRule {
 
             PacketChild::HciCommand(cmd) => match cmd.specialize() {
                 CommandChild::Reset(_cmd) => {
-                    self.report_reset(packet.ts);
+                    self.report_reset(packet);
                 }
 
                 CommandChild::AclCommand(cmd) => match cmd.specialize() {
@@ -836,7 +945,7 @@ impl Rule for InformationalRule {
                         {
                             self.pending_disconnect_due_to_host_power_off
                                 .insert(cmd.get_connection_handle());
-                            self.report_connection_end(cmd.get_connection_handle(), packet.ts);
+                            self.report_connection_end(cmd.get_connection_handle(), packet);
                         }
                     }
 
@@ -930,62 +1039,22 @@ impl Rule for InformationalRule {
                     // PacketChild::AclRx(rx).specialize()
                     _ => {}
                 }
-            } // packet.inner
-        }
-    }
-
-    fn report(&self, writer: &mut dyn Write) {
-        /* Sort when displaying the addresses, from the most to the least important:
-         * (1) Device with connections > Device without connections
-         * (2) Device with known name > Device with unknown name
-         * (3) BREDR > LE > Dual
-         * (4) Name, lexicographically (case sensitive)
-         * (5) Address, alphabetically
-         */
-        fn sort_addresses(a: &DeviceInformation, b: &DeviceInformation) -> Ordering {
-            let connection_order = a.acls.is_empty().cmp(&b.acls.is_empty());
-            if connection_order != Ordering::Equal {
-                return connection_order;
-            }
-
-            let known_name_order = a.names.is_empty().cmp(&b.names.is_empty());
-            if known_name_order != Ordering::Equal {
-                return known_name_order;
-            }
-
-            let address_type_order = a.address_type.cmp(&b.address_type);
-            if address_type_order != Ordering::Equal {
-                return address_type_order;
             }
 
-            let a_name = format!("{}", DeviceInformation::print_names(&a.names));
-            let b_name = format!("{}", DeviceInformation::print_names(&b.names));
-            let name_order = a_name.cmp(&b_name);
-            if name_order != Ordering::Equal {
-                return name_order;
-            }
-
-            let a_address = <[u8; 6]>::from(a.address);
-            let b_address = <[u8; 6]>::from(b.address);
-            for i in (0..6).rev() {
-                let address_order = a_address[i].cmp(&b_address[i]);
-                if address_order != Ordering::Equal {
-                    return address_order;
-                }
-            }
-            // This shouldn't be executed
-            return Ordering::Equal;
+            // packet.inner
+            _ => {}
         }
+    }
 
-        if self.devices.is_empty() && self.unknown_connections.is_empty() {
+    fn report(&self, writer: &mut dyn Write) {
+        let addresses = self.sorted_addresses();
+        let report_unknown = self.report_unknown_connections();
+        if addresses.is_empty() && !report_unknown {
             return;
         }
 
-        let mut addresses: Vec<Address> = self.devices.keys().cloned().collect();
-        addresses.sort_unstable_by(|a, b| sort_addresses(&self.devices[a], &self.devices[b]));
-
         let _ = writeln!(writer, "InformationalRule report:");
-        if !self.unknown_connections.is_empty() {
+        if report_unknown {
             let _ = writeln!(
                 writer,
                 "Connections initiated before snoop start, {} connections",
@@ -1000,15 +1069,51 @@ impl Rule for InformationalRule {
         }
     }
 
+    // The summary is reported as informational findings so it is kept by the JSON formats: one
+    // for each device followed by one for each of its connections.
+    fn report_findings(&self) -> Vec<Finding> {
+        let (first, last) = match (self.first_packet, self.last_packet) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return vec![],
+        };
+
+        let mut findings = vec![];
+        if self.report_unknown_connections() {
+            findings
+                .extend(self.unknown_connections.values().map(|acl| acl.to_finding(first, last)));
+        }
+        for address in self.sorted_addresses() {
+            let device = &self.devices[&address];
+            let acls: Vec<Finding> = device
+                .acls
+                .iter()
+                .map(|acl| acl.to_finding(first, last).with_address(address))
+                .collect();
+            let header = format!("{}", device);
+            findings.push(Finding {
+                severity: Severity::Info,
+                start_index: acls.first().map_or(first.0, |f| f.start_index),
+                start_ts: acls.first().map_or(first.1, |f| f.start_ts),
+                end_index: acls.last().map_or(last.0, |f| f.end_index),
+                end_ts: acls.last().map_or(last.1, |f| f.end_ts),
+                addresses: vec![address],
+                handles: vec![],
+                message: header.lines().next().unwrap_or_default().to_owned(),
+            });
+            findings.extend(acls);
+        }
+        findings
+    }
+
     fn report_signals(&self) -> &[Signal] {
         &[]
     }
 }
 
 /// Get a rule group with collision rules.
-pub fn get_informational_group() -> RuleGroup {
+pub fn get_informational_group(config: &RuleConfig) -> RuleGroup {
     let mut group = RuleGroup::new();
-    group.add_rule(Box::new(InformationalRule::new()));
+    group.add_rule(Box::new(InformationalRule::new(config)));
 
     group
 }
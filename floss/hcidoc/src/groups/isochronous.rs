@@ -0,0 +1,669 @@
+//! Rule group for tracking LE Audio isochronous channels.
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
+use crate::parser::{Packet, PacketChild};
+use bt_packets::hci::{
+    CommandChild, CommandCompleteChild, ErrorCode, EventChild, LeIsoCommandChild, LeMetaEventChild,
+    OpCode,
+};
+
+enum IsoSignal {
+    CisEstablishFailed, // Controller fails to create the CIS.
+    BigCreateFailed,    // Controller fails to create the BIG.
+    BigSyncFailed,      // Controller fails to synchronize to a BIG.
+    BigSyncLost,        // Controller loses the synchronization to a BIG.
+    IsoNoDataPath,      // An isochronous stream is closed without ever setting up a data path.
+    IsoNoData,          // An isochronous stream with an HCI data path never carried data.
+}
+
+impl From<IsoSignal> for SignalDefinition {
+    fn from(signal: IsoSignal) -> Self {
+        match signal {
+            IsoSignal::CisEstablishFailed => SignalDefinition {
+                tag: "CisEstablishFailed",
+                severity: Severity::Error,
+                description: "The controller failed to create a CIS.",
+                next_step: "Check the CIG parameters against what the controller and the peer \
+                    support.",
+            },
+            IsoSignal::BigCreateFailed => SignalDefinition {
+                tag: "BigCreateFailed",
+                severity: Severity::Error,
+                description: "The controller failed to create a BIG.",
+                next_step: "Check the BIG parameters against what the controller supports, and \
+                    whether advertising was set up for it.",
+            },
+            IsoSignal::BigSyncFailed => SignalDefinition {
+                tag: "BigSyncFailed",
+                severity: Severity::Error,
+                description: "The controller failed to synchronize to a BIG.",
+                next_step: "Check whether the periodic advertising sync of the broadcast source \
+                    was established and the broadcast code is correct.",
+            },
+            IsoSignal::BigSyncLost => SignalDefinition {
+                tag: "BigSyncLost",
+                severity: Severity::Warning,
+                description: "The controller lost the synchronization to a BIG.",
+                next_step: "Check whether the broadcast source stopped or moved out of range.",
+            },
+            IsoSignal::IsoNoDataPath => SignalDefinition {
+                tag: "IsoNoDataPath",
+                severity: Severity::Warning,
+                description: "An isochronous stream was closed without ever setting up a data \
+                    path.",
+                next_step: "Check whether the audio HAL or offload path started for this stream.",
+            },
+            IsoSignal::IsoNoData => SignalDefinition {
+                tag: "IsoNoData",
+                severity: Severity::Warning,
+                description: "An isochronous stream with an HCI data path was closed without ever \
+                    carrying data.",
+                next_step: "Check whether the audio source started sending data over HCI.",
+            },
+        }
+    }
+}
+
+impl From<IsoSignal> for &'static str {
+    fn from(signal: IsoSignal) -> Self {
+        SignalDefinition::from(signal).tag
+    }
+}
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+type CigId = u8;
+type BigHandle = u8;
+
+/// Data path ID of LE Setup ISO Data Path for data exchanged over HCI. Other IDs are vendor
+/// specific and usually offload the data, so it never shows up in the log.
+const ISO_DATA_PATH_HCI: u8 = 0x00;
+
+#[derive(Debug, PartialEq)]
+enum IsoStreamState {
+    /// CIS handle was allocated via LE Set CIG Parameters.
+    Configured,
+    /// LE Create CIS was sent or the peer requested the CIS.
+    Creating,
+    /// The stream can carry data.
+    Established,
+}
+
+/// A single CIS or BIS.
+struct IsoStream {
+    state: IsoStreamState,
+
+    /// Which group this stream belongs to, as a printable string.
+    group: String,
+
+    /// Packet that moved the stream to |IsoStreamState::Established|.
+    established: Option<Packet>,
+
+    /// Whether a data path was successfully set up for this stream.
+    has_data_path: bool,
+
+    /// Whether that data path goes over HCI, only then the data shows up in the log.
+    hci_data_path: bool,
+
+    /// Number of ISO data packets sent or received on this stream.
+    data_packets: usize,
+}
+
+impl IsoStream {
+    fn new(state: IsoStreamState, group: String) -> Self {
+        IsoStream {
+            state,
+            group,
+            established: None,
+            has_data_path: false,
+            hci_data_path: false,
+            data_packets: 0,
+        }
+    }
+}
+
+/// Follows the lifetime of connected (CIG/CIS) and broadcast (BIG/BIS) isochronous streams and
+/// flags streams that fail to be established or are closed without ever being used.
+struct IsoStreamsRule {
+    /// All known CIS and BIS, keyed by their connection handle.
+    streams: HashMap<ConnectionHandle, IsoStream>,
+
+    /// CIS handles allocated to each CIG.
+    cigs: HashMap<CigId, Vec<ConnectionHandle>>,
+
+    /// BIS handles allocated to each BIG, created locally or synchronized to.
+    bigs: HashMap<BigHandle, Vec<ConnectionHandle>>,
+
+    /// CIS handles from the last LE Create CIS, waiting for its command status.
+    pending_create_cis: Vec<ConnectionHandle>,
+
+    /// BIG from the last LE Create BIG, waiting for its command status.
+    pending_create_big: Option<BigHandle>,
+
+    /// BIG from the last LE BIG Create Sync, waiting for its command status.
+    pending_big_sync: Option<BigHandle>,
+
+    /// Data path IDs from LE Setup ISO Data Path, waiting for its command complete.
+    pending_data_path: HashMap<ConnectionHandle, u8>,
+
+    /// Index and timestamp of the last packet seen, to check the streams still open at the end
+    /// of the log.
+    last_packet: Option<(usize, NaiveDateTime)>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl IsoStreamsRule {
+    pub fn new() -> Self {
+        IsoStreamsRule {
+            streams: HashMap::new(),
+            cigs: HashMap::new(),
+            bigs: HashMap::new(),
+            pending_create_cis: vec![],
+            pending_create_big: None,
+            pending_big_sync: None,
+            pending_data_path: HashMap::new(),
+            last_packet: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: IsoSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn process_set_cig_params_complete(
+        &mut self,
+        status: ErrorCode,
+        cig_id: CigId,
+        handles: &Vec<ConnectionHandle>,
+        packet: &Packet,
+    ) {
+        if status != ErrorCode::Success {
+            self.reportable.push(Finding::new(
+                packet,
+                Severity::Error,
+                format!("LE Set CIG Parameters failed with {:?} for CIG {}", status, cig_id),
+            ));
+            return;
+        }
+
+        // Reconfiguring an existing CIG replaces its CIS handles.
+        if let Some(old_handles) = self.cigs.insert(cig_id, handles.clone()) {
+            for handle in old_handles {
+                self.streams.remove(&handle);
+            }
+        }
+        for handle in handles {
+            self.streams.insert(
+                *handle,
+                IsoStream::new(IsoStreamState::Configured, format!("CIG {}", cig_id)),
+            );
+        }
+    }
+
+    fn process_create_cis(&mut self, handles: Vec<ConnectionHandle>) {
+        for handle in handles.iter() {
+            self.streams
+                .entry(*handle)
+                .or_insert_with(|| IsoStream::new(IsoStreamState::Configured, "CIG ?".into()))
+                .state = IsoStreamState::Creating;
+        }
+        self.pending_create_cis = handles;
+    }
+
+    fn process_cis_request(&mut self, handle: ConnectionHandle, cig_id: CigId) {
+        self.streams
+            .insert(handle, IsoStream::new(IsoStreamState::Creating, format!("CIG {}", cig_id)));
+    }
+
+    fn process_command_status(&mut self, status: ErrorCode, opcode: OpCode, packet: &Packet) {
+        match opcode {
+            OpCode::LeCreateCis => {
+                let handles = std::mem::take(&mut self.pending_create_cis);
+                if status != ErrorCode::Success {
+                    for handle in handles {
+                        self.report_cis_failure(status, handle, packet);
+                    }
+                }
+            }
+
+            OpCode::LeCreateBig => {
+                if let Some(big_handle) = self.pending_create_big.take() {
+                    if status != ErrorCode::Success {
+                        self.report_big_failure(status, big_handle, packet);
+                    }
+                }
+            }
+
+            OpCode::LeBigCreateSync => {
+                if let Some(big_handle) = self.pending_big_sync.take() {
+                    if status != ErrorCode::Success {
+                        self.report_big_sync_failure(status, big_handle, packet);
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn report_cis_failure(&mut self, status: ErrorCode, handle: ConnectionHandle, packet: &Packet) {
+        let group = match self.streams.get_mut(&handle) {
+            Some(stream) => {
+                stream.state = IsoStreamState::Configured;
+                stream.group.clone()
+            }
+            None => "CIG ?".into(),
+        };
+
+        self.add_signal(packet, IsoSignal::CisEstablishFailed);
+        self.reportable.push(
+            Finding::new(
+                packet,
+                Severity::Error,
+                format!("CIS (handle={}) in {} failed to establish: {:?}", handle, group, status),
+            )
+            .with_handle(handle),
+        );
+    }
+
+    fn report_big_failure(&mut self, status: ErrorCode, big_handle: BigHandle, packet: &Packet) {
+        self.add_signal(packet, IsoSignal::BigCreateFailed);
+        self.reportable.push(Finding::new(
+            packet,
+            Severity::Error,
+            format!("BIG {} failed to be created: {:?}", big_handle, status),
+        ));
+    }
+
+    fn report_big_sync_failure(
+        &mut self,
+        status: ErrorCode,
+        big_handle: BigHandle,
+        packet: &Packet,
+    ) {
+        self.add_signal(packet, IsoSignal::BigSyncFailed);
+        self.reportable.push(Finding::new(
+            packet,
+            Severity::Error,
+            format!("Synchronizing to BIG {} failed: {:?}", big_handle, status),
+        ));
+    }
+
+    fn process_cis_established(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        packet: &Packet,
+    ) {
+        if status != ErrorCode::Success {
+            self.report_cis_failure(status, handle, packet);
+            return;
+        }
+
+        let stream = self
+            .streams
+            .entry(handle)
+            .or_insert_with(|| IsoStream::new(IsoStreamState::Creating, "CIG ?".into()));
+        stream.state = IsoStreamState::Established;
+        stream.established = Some(packet.clone());
+    }
+
+    fn process_create_big_complete(
+        &mut self,
+        status: ErrorCode,
+        big_handle: BigHandle,
+        handles: &Vec<ConnectionHandle>,
+        packet: &Packet,
+    ) {
+        if status != ErrorCode::Success {
+            self.report_big_failure(status, big_handle, packet);
+            return;
+        }
+
+        self.add_big(big_handle, handles, packet);
+    }
+
+    fn process_big_sync_established(
+        &mut self,
+        status: ErrorCode,
+        big_handle: BigHandle,
+        handles: &Vec<ConnectionHandle>,
+        packet: &Packet,
+    ) {
+        if status != ErrorCode::Success {
+            self.report_big_sync_failure(status, big_handle, packet);
+            return;
+        }
+
+        self.add_big(big_handle, handles, packet);
+    }
+
+    fn add_big(&mut self, big_handle: BigHandle, handles: &Vec<ConnectionHandle>, packet: &Packet) {
+        for handle in handles {
+            let mut stream =
+                IsoStream::new(IsoStreamState::Established, format!("BIG {}", big_handle));
+            stream.established = Some(packet.clone());
+            self.streams.insert(*handle, stream);
+        }
+        self.bigs.insert(big_handle, handles.clone());
+    }
+
+    fn process_setup_data_path_complete(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        packet: &Packet,
+    ) {
+        let data_path_id = self.pending_data_path.remove(&handle);
+        if status != ErrorCode::Success {
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Error,
+                    format!("Setup ISO data path failed with {:?} (handle={})", status, handle),
+                )
+                .with_handle(handle),
+            );
+            return;
+        }
+
+        if let Some(stream) = self.streams.get_mut(&handle) {
+            stream.has_data_path = true;
+            stream.hci_data_path = data_path_id == Some(ISO_DATA_PATH_HCI);
+        }
+    }
+
+    fn process_iso_data(&mut self, handle: ConnectionHandle) {
+        if let Some(stream) = self.streams.get_mut(&handle) {
+            stream.data_packets += 1;
+        }
+    }
+
+    /// Check whether an established stream was actually used between its establishment and
+    /// |end_index|. |state| describes the stream at that point, e.g. "closed".
+    fn check_stream_used(
+        handle: ConnectionHandle,
+        stream: &IsoStream,
+        end_index: usize,
+        end_ts: NaiveDateTime,
+        state: &str,
+    ) -> Option<(IsoSignal, Finding)> {
+        let established = match (&stream.state, &stream.established) {
+            (IsoStreamState::Established, Some(established)) => established,
+            _ => return None,
+        };
+
+        let (signal, unused) = if !stream.has_data_path {
+            (IsoSignal::IsoNoDataPath, "setting up a data path")
+        } else if stream.hci_data_path && stream.data_packets == 0 {
+            (IsoSignal::IsoNoData, "carrying any data")
+        } else {
+            return None;
+        };
+        let finding = Finding::new(
+            established,
+            Severity::Warning,
+            format!("Stream (handle={}) in {} {} without {}", handle, stream.group, state, unused),
+        )
+        .until(end_index, end_ts)
+        .with_handle(handle);
+        Some((signal, finding))
+    }
+
+    /// Check whether an established stream was actually used before it went away.
+    fn close_stream(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        let stream = match self.streams.remove(&handle) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        if let Some((signal, finding)) =
+            Self::check_stream_used(handle, &stream, packet.index, packet.ts, "closed")
+        {
+            self.add_signal(packet, signal);
+            self.reportable.push(finding);
+        }
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        self.close_stream(handle, packet);
+
+        // The CIS handle stays allocated to the CIG until it is removed.
+        if let Some(cig_id) =
+            self.cigs.iter().find(|(_, handles)| handles.contains(&handle)).map(|(id, _)| *id)
+        {
+            self.streams.insert(
+                handle,
+                IsoStream::new(IsoStreamState::Configured, format!("CIG {}", cig_id)),
+            );
+        }
+    }
+
+    fn process_remove_cig_complete(&mut self, status: ErrorCode, cig_id: CigId) {
+        if status != ErrorCode::Success {
+            return;
+        }
+
+        if let Some(handles) = self.cigs.remove(&cig_id) {
+            for handle in handles {
+                self.streams.remove(&handle);
+            }
+        }
+    }
+
+    fn process_terminate_big_complete(&mut self, big_handle: BigHandle, packet: &Packet) {
+        if let Some(handles) = self.bigs.remove(&big_handle) {
+            for handle in handles {
+                self.close_stream(handle, packet);
+            }
+        }
+    }
+
+    fn process_big_sync_lost(&mut self, big_handle: BigHandle, reason: ErrorCode, packet: &Packet) {
+        self.add_signal(packet, IsoSignal::BigSyncLost);
+        self.reportable.push(Finding::new(
+            packet,
+            Severity::Warning,
+            format!("Synchronization to BIG {} lost: {:?}", big_handle, reason),
+        ));
+        self.process_terminate_big_complete(big_handle, packet);
+    }
+
+    fn process_reset(&mut self) {
+        self.streams.clear();
+        self.cigs.clear();
+        self.bigs.clear();
+        self.pending_create_cis.clear();
+        self.pending_create_big = None;
+        self.pending_big_sync = None;
+        self.pending_data_path.clear();
+    }
+}
+
+impl Rule for IsoStreamsRule {
+    fn name(&self) -> &'static str {
+        "IsoStreamsRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => match cmd.specialize() {
+                CommandChild::Reset(_) => {
+                    self.process_reset();
+                }
+
+                CommandChild::LeIsoCommand(cmd) => match cmd.specialize() {
+                    LeIsoCommandChild::LeCreateCis(cmd) => {
+                        self.process_create_cis(
+                            cmd.get_cis_config().iter().map(|c| c.cis_connection_handle).collect(),
+                        );
+                    }
+                    LeIsoCommandChild::LeCreateBig(cmd) => {
+                        self.pending_create_big = Some(cmd.get_big_handle());
+                    }
+                    LeIsoCommandChild::LeBigCreateSync(cmd) => {
+                        self.pending_big_sync = Some(cmd.get_big_handle());
+                    }
+                    LeIsoCommandChild::LeSetupIsoDataPath(cmd) => {
+                        self.pending_data_path
+                            .insert(cmd.get_connection_handle(), cmd.get_data_path_id());
+                    }
+
+                    // End LeIsoCommand.specialize()
+                    _ => {}
+                },
+
+                // End HciCommand.specialize()
+                _ => {}
+            },
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::CommandStatus(cs) => {
+                    self.process_command_status(cs.get_status(), cs.get_command_op_code(), packet);
+                }
+
+                EventChild::CommandComplete(cc) => match cc.specialize() {
+                    CommandCompleteChild::LeSetCigParametersComplete(cc) => {
+                        self.process_set_cig_params_complete(
+                            cc.get_status(),
+                            cc.get_cig_id(),
+                            cc.get_connection_handle(),
+                            packet,
+                        );
+                    }
+                    CommandCompleteChild::LeRemoveCigComplete(cc) => {
+                        self.process_remove_cig_complete(cc.get_status(), cc.get_cig_id());
+                    }
+                    CommandCompleteChild::LeBigTerminateSyncComplete(cc)
+                        if cc.get_status() == ErrorCode::Success =>
+                    {
+                        self.process_terminate_big_complete(cc.get_big_handle(), packet);
+                    }
+                    CommandCompleteChild::LeSetupIsoDataPathComplete(cc) => {
+                        self.process_setup_data_path_complete(
+                            cc.get_status(),
+                            cc.get_connection_handle(),
+                            packet,
+                        );
+                    }
+
+                    // End CommandComplete.specialize()
+                    _ => {}
+                },
+
+                EventChild::DisconnectionComplete(dsc) => {
+                    self.process_disconnection(dsc.get_connection_handle(), packet);
+                }
+
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeCisRequest(ev) => {
+                        self.process_cis_request(ev.get_cis_connection_handle(), ev.get_cig_id());
+                    }
+                    LeMetaEventChild::LeCisEstablished(ev) => {
+                        self.process_cis_established(
+                            ev.get_status(),
+                            ev.get_connection_handle(),
+                            packet,
+                        );
+                    }
+                    LeMetaEventChild::LeCreateBigComplete(ev) => {
+                        self.process_create_big_complete(
+                            ev.get_status(),
+                            ev.get_big_handle(),
+                            ev.get_connection_handle(),
+                            packet,
+                        );
+                    }
+                    LeMetaEventChild::LeTerminateBigComplete(ev) => {
+                        self.process_terminate_big_complete(ev.get_big_handle(), packet);
+                    }
+                    LeMetaEventChild::LeBigSyncEstablished(ev) => {
+                        self.process_big_sync_established(
+                            ev.get_status(),
+                            ev.get_big_handle(),
+                            ev.get_connection_handle(),
+                            packet,
+                        );
+                    }
+                    LeMetaEventChild::LeBigSyncLost(ev) => {
+                        self.process_big_sync_lost(ev.get_big_handle(), ev.get_reason(), packet);
+                    }
+
+                    // End LeMetaEvent.specialize()
+                    _ => {}
+                },
+
+                // End HciEvent.specialize()
+                _ => {}
+            },
+
+            PacketChild::IsoTx(iso) | PacketChild::IsoRx(iso) => {
+                self.process_iso_data(iso.get_connection_handle());
+            }
+
+            _ => {}
+        }
+
+        self.last_packet = Some((packet.index, packet.ts));
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        // Streams still open at the end of the log.
+        if let Some((last_index, last_ts)) = self.last_packet {
+            let mut handles: Vec<&ConnectionHandle> = self.streams.keys().collect();
+            handles.sort_unstable();
+            for handle in handles {
+                if let Some((_, finding)) = Self::check_stream_used(
+                    *handle,
+                    &self.streams[handle],
+                    last_index,
+                    last_ts,
+                    "still open at the end of the log",
+                ) {
+                    findings.push(finding);
+                }
+            }
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            IsoSignal::CisEstablishFailed,
+            IsoSignal::BigCreateFailed,
+            IsoSignal::BigSyncFailed,
+            IsoSignal::BigSyncLost,
+            IsoSignal::IsoNoDataPath,
+            IsoSignal::IsoNoData,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// Get a rule group with isochronous channel rules.
+pub fn get_isochronous_group(_config: &RuleConfig) -> RuleGroup {
+    let mut group = RuleGroup::new();
+    group.add_rule(Box::new(IsoStreamsRule::new()));
+
+    group
+}
@@ -1,5 +1,11 @@
 ///! Rule groups for hcidoc.
+pub(crate) mod audio;
 pub(crate) mod collisions;
 pub(crate) mod connections;
 pub(crate) mod controllers;
+pub(crate) mod discovery;
+pub(crate) mod gatt;
 pub(crate) mod informational;
+pub(crate) mod isochronous;
+pub(crate) mod pairing;
+pub(crate) mod power;
@@ -0,0 +1,553 @@
+//! Rule group for tracking LE pairing over the Security Manager Protocol (SMP).
+use std::collections::HashMap;
+
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
+use crate::parser::{AclContent, AclReassembler, Packet, PacketChild};
+use bt_packets::hci::{
+    Address, CommandChild, ErrorCode, EventChild, LeMetaEventChild, LeSecurityCommandChild,
+};
+use hcidoc_packets::smp::{Command as SmpCommand, CommandChild as SmpCommandChild, IoCapability};
+
+enum PairingSignal {
+    PairingFailed,      // Either side sent SMP Pairing Failed.
+    PairingTimeout,     // No SMP PDU within the SMP timeout while pairing is in progress.
+    PairingInterrupted, // Link disconnected while pairing is in progress.
+    LtkMissing,         // Host has no LTK for a peer that asks to encrypt.
+    EncryptionFailed,   // Controller failed to encrypt an LE link.
+}
+
+impl From<PairingSignal> for SignalDefinition {
+    fn from(signal: PairingSignal) -> Self {
+        match signal {
+            PairingSignal::PairingFailed => SignalDefinition {
+                tag: "SmpPairingFailed",
+                severity: Severity::Error,
+                description: "Either side sent SMP Pairing Failed.",
+                next_step: "The reason in the Pairing Failed PDU tells which step failed, compare \
+                    the IO capabilities and authentication requirements.",
+            },
+            PairingSignal::PairingTimeout => SignalDefinition {
+                tag: "SmpTimeout",
+                severity: Severity::Error,
+                description: "No SMP PDU was exchanged within the SMP timeout while pairing.",
+                next_step: "Check whether the user was prompted, most timeouts are a confirmation \
+                    nobody answered.",
+            },
+            PairingSignal::PairingInterrupted => SignalDefinition {
+                tag: "SmpPairingInterrupted",
+                severity: Severity::Warning,
+                description: "The link disconnected while pairing was in progress.",
+                next_step: "Look at the disconnection reason to tell whether the peer or the host \
+                    ended the link.",
+            },
+            PairingSignal::LtkMissing => SignalDefinition {
+                tag: "LtkMissing",
+                severity: Severity::Error,
+                description: "The host had no LTK for a peer that asked to encrypt.",
+                next_step:
+                    "Check whether the bond was removed on the host only, the peer needs to \
+                    forget it too.",
+            },
+            PairingSignal::EncryptionFailed => SignalDefinition {
+                tag: "LeEncryptionFailed",
+                severity: Severity::Error,
+                description: "The controller failed to encrypt an LE link.",
+                next_step: "The status of the Encryption Change event tells whether the key was \
+                    rejected or the link dropped.",
+            },
+        }
+    }
+}
+
+impl From<PairingSignal> for &'static str {
+    fn from(signal: PairingSignal) -> Self {
+        SignalDefinition::from(signal).tag
+    }
+}
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+/// SMP transactions time out after 30 seconds without a PDU (Core spec Vol 3, Part H, 3.4).
+const SMP_TIMEOUT_MS: i64 = 30000;
+
+/// Bits of the AuthReq field.
+const AUTH_REQ_BONDING: u8 = 0x01;
+const AUTH_REQ_MITM: u8 = 0x04;
+const AUTH_REQ_SECURE_CONNECTIONS: u8 = 0x08;
+
+/// Bits of the key distribution fields.
+const KEY_DIST_ENC: u8 = 0x01;
+const KEY_DIST_ID: u8 = 0x02;
+const KEY_DIST_SIGN: u8 = 0x04;
+
+fn print_key_distribution(keys: u8) -> String {
+    let names: Vec<&str> =
+        [(KEY_DIST_ENC, "EncKey"), (KEY_DIST_ID, "IdKey"), (KEY_DIST_SIGN, "SignKey")]
+            .iter()
+            .filter(|(bit, _)| keys & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+
+    if names.is_empty() {
+        "none".into()
+    } else {
+        names.join("+")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Initiator {
+    Host,
+    Peer,
+}
+
+/// Parameters sent in Pairing Request or Pairing Response.
+#[derive(Clone, Copy)]
+struct PairingFeatures {
+    io_capability: IoCapability,
+    auth_req: u8,
+    initiator_key_distribution: u8,
+    responder_key_distribution: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PairingResult {
+    InProgress,
+    Success,
+    Failed(String),
+    Timeout,
+    Disconnected,
+}
+
+/// Everything we know about a single pairing attempt.
+struct PairingSession {
+    /// Packet that started the pairing.
+    start: Packet,
+
+    /// Last SMP PDU seen for this pairing, used to detect timeouts.
+    last_pdu: Packet,
+
+    initiator: Initiator,
+    request: Option<PairingFeatures>,
+    response: Option<PairingFeatures>,
+
+    /// Keys received in the key distribution phase.
+    keys_from_host: u8,
+    keys_from_peer: u8,
+
+    result: PairingResult,
+}
+
+impl PairingSession {
+    fn new(packet: &Packet, initiator: Initiator) -> Self {
+        PairingSession {
+            start: packet.clone(),
+            last_pdu: packet.clone(),
+            initiator,
+            request: None,
+            response: None,
+            keys_from_host: 0,
+            keys_from_peer: 0,
+            result: PairingResult::InProgress,
+        }
+    }
+
+    fn is_secure_connections(&self) -> bool {
+        match (self.request, self.response) {
+            (Some(req), Some(rsp)) => {
+                req.auth_req & rsp.auth_req & AUTH_REQ_SECURE_CONNECTIONS != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn describe(&self, handle: ConnectionHandle, address: Option<Address>) -> String {
+        let peer = address.map_or(format!("handle {}", handle), |a| format!("{}", a));
+        let mut message = format!("Pairing with {} initiated by {:?}", peer, self.initiator);
+
+        if let (Some(req), Some(rsp)) = (self.request, self.response) {
+            let auth_req = req.auth_req & rsp.auth_req;
+            let (host, peer) = match self.initiator {
+                Initiator::Host => (req, rsp),
+                Initiator::Peer => (rsp, req),
+            };
+            message += &format!(
+                ": {}, IO capability host {:?} / peer {:?}, {}{}",
+                if self.is_secure_connections() { "Secure Connections" } else { "Legacy" },
+                host.io_capability,
+                peer.io_capability,
+                if auth_req & AUTH_REQ_BONDING != 0 { "bonding" } else { "no bonding" },
+                if (req.auth_req | rsp.auth_req) & AUTH_REQ_MITM != 0 { ", MITM" } else { "" },
+            );
+            message += &format!(
+                ", keys from host {} (negotiated {}), keys from peer {} (negotiated {})",
+                print_key_distribution(self.keys_from_host),
+                print_key_distribution(match self.initiator {
+                    Initiator::Host => rsp.initiator_key_distribution,
+                    Initiator::Peer => rsp.responder_key_distribution,
+                }),
+                print_key_distribution(self.keys_from_peer),
+                print_key_distribution(match self.initiator {
+                    Initiator::Host => rsp.responder_key_distribution,
+                    Initiator::Peer => rsp.initiator_key_distribution,
+                }),
+            );
+        }
+
+        message + &format!(", result: {:?}", self.result)
+    }
+}
+
+/// Reconstructs each LE pairing from the SMP PDUs and the related HCI commands and events, and
+/// flags the usual ways it goes wrong.
+struct SmpPairingRule {
+    /// Addresses of active LE links.
+    handles: HashMap<ConnectionHandle, Address>,
+
+    /// Latest pairing on each link.
+    sessions: HashMap<ConnectionHandle, PairingSession>,
+
+    /// Pairings that are no longer the latest on their link.
+    finished: Vec<Finding>,
+
+    /// LE Long Term Key Requests waiting for the host to reply.
+    pending_ltk_request: HashMap<ConnectionHandle, Packet>,
+
+    /// SMP PDUs can be split across ACL fragments, e.g. the Public Key on a small LE buffer.
+    reassembler: AclReassembler,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl SmpPairingRule {
+    pub fn new() -> Self {
+        SmpPairingRule {
+            handles: HashMap::new(),
+            sessions: HashMap::new(),
+            finished: vec![],
+            pending_ltk_request: HashMap::new(),
+            reassembler: AclReassembler::new(),
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: PairingSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn summarize(&self, handle: ConnectionHandle, session: &PairingSession) -> Finding {
+        let severity = match session.result {
+            PairingResult::Success | PairingResult::InProgress => Severity::Info,
+            _ => Severity::Warning,
+        };
+        let address = self.handles.get(&handle).cloned();
+        let mut finding =
+            Finding::new(&session.last_pdu, severity, session.describe(handle, address))
+                .since(&session.start)
+                .with_handle(handle);
+        if let Some(address) = address {
+            finding = finding.with_address(address);
+        }
+        finding
+    }
+
+    fn start_session(&mut self, handle: ConnectionHandle, packet: &Packet, initiator: Initiator) {
+        if let Some(old) = self.sessions.insert(handle, PairingSession::new(packet, initiator)) {
+            self.finished.push(self.summarize(handle, &old));
+        }
+    }
+
+    /// End the pairings that saw no SMP PDU within the SMP timeout before |packet|.
+    fn check_timeouts(&mut self, packet: &Packet) {
+        let timed_out: Vec<ConnectionHandle> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                session.result == PairingResult::InProgress
+                    && packet.ts.signed_duration_since(session.last_pdu.ts).num_milliseconds()
+                        > SMP_TIMEOUT_MS
+            })
+            .map(|(handle, _)| *handle)
+            .collect();
+
+        for handle in timed_out {
+            self.add_signal(packet, PairingSignal::PairingTimeout);
+            if let Some(session) = self.sessions.get_mut(&handle) {
+                session.result = PairingResult::Timeout;
+            }
+        }
+    }
+
+    fn process_smp(&mut self, handle: ConnectionHandle, from_host: bool, packet: &Packet) {
+        let smp: SmpCommand = match self.reassembler.get_acl_content(packet) {
+            AclContent::Smp(smp) => smp,
+            _ => return,
+        };
+        let sender = if from_host { Initiator::Host } else { Initiator::Peer };
+
+        match smp.specialize() {
+            SmpCommandChild::PairingRequest(req) => {
+                self.start_session(handle, packet, sender);
+                if let Some(session) = self.sessions.get_mut(&handle) {
+                    session.request = Some(PairingFeatures {
+                        io_capability: req.get_io_capability(),
+                        auth_req: req.get_auth_req(),
+                        initiator_key_distribution: req.get_initiator_key_distribution(),
+                        responder_key_distribution: req.get_responder_key_distribution(),
+                    });
+                }
+            }
+
+            SmpCommandChild::PairingResponse(rsp) => {
+                if let Some(session) = self.sessions.get_mut(&handle) {
+                    session.response = Some(PairingFeatures {
+                        io_capability: rsp.get_io_capability(),
+                        auth_req: rsp.get_auth_req(),
+                        initiator_key_distribution: rsp.get_initiator_key_distribution(),
+                        responder_key_distribution: rsp.get_responder_key_distribution(),
+                    });
+                }
+            }
+
+            SmpCommandChild::PairingFailed(failed) => {
+                self.add_signal(packet, PairingSignal::PairingFailed);
+                let reason = format!("{:?} sent {:?}", sender, failed.get_reason());
+                match self.sessions.get_mut(&handle) {
+                    Some(session) => session.result = PairingResult::Failed(reason),
+                    None => {
+                        let mut session = PairingSession::new(packet, sender);
+                        session.result = PairingResult::Failed(reason);
+                        self.sessions.insert(handle, session);
+                    }
+                }
+            }
+
+            SmpCommandChild::EncryptionInformation(_) => {
+                self.add_key(handle, from_host, KEY_DIST_ENC)
+            }
+            SmpCommandChild::IdentityInformation(_) => self.add_key(handle, from_host, KEY_DIST_ID),
+            SmpCommandChild::SigningInformation(_) => {
+                self.add_key(handle, from_host, KEY_DIST_SIGN)
+            }
+
+            // Security Request only asks the central to start pairing, it doesn't start one.
+            _ => {}
+        }
+
+        if let Some(session) = self.sessions.get_mut(&handle) {
+            session.last_pdu = packet.clone();
+        }
+    }
+
+    fn add_key(&mut self, handle: ConnectionHandle, from_host: bool, key: u8) {
+        if let Some(session) = self.sessions.get_mut(&handle) {
+            if from_host {
+                session.keys_from_host |= key;
+            } else {
+                session.keys_from_peer |= key;
+            }
+        }
+    }
+
+    fn process_encryption_change(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        packet: &Packet,
+    ) {
+        // Only interested in LE links.
+        let address = match self.handles.get(&handle) {
+            Some(address) => *address,
+            None => return,
+        };
+
+        if status == ErrorCode::Success {
+            if let Some(session) = self.sessions.get_mut(&handle) {
+                if session.result == PairingResult::InProgress {
+                    session.result = PairingResult::Success;
+                }
+            }
+            return;
+        }
+
+        self.add_signal(packet, PairingSignal::EncryptionFailed);
+        self.reportable.push(
+            Finding::new(
+                packet,
+                Severity::Error,
+                format!("LE encryption with {} failed: {:?}", address, status),
+            )
+            .with_address(address)
+            .with_handle(handle),
+        );
+        if let Some(session) = self.sessions.get_mut(&handle) {
+            if session.result == PairingResult::InProgress {
+                session.result = PairingResult::Failed(format!("Encryption change {:?}", status));
+            }
+        }
+    }
+
+    fn process_ltk_negative_reply(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        let request = match self.pending_ltk_request.remove(&handle) {
+            Some(request) => request,
+            None => return,
+        };
+        let peer =
+            self.handles.get(&handle).map_or(format!("handle {}", handle), |a| a.to_string());
+
+        self.add_signal(packet, PairingSignal::LtkMissing);
+        let mut finding = Finding::new(
+            packet,
+            Severity::Warning,
+            format!("Host has no LTK for {} and rejected the encryption request", peer),
+        )
+        .since(&request)
+        .with_handle(handle);
+        if let Some(address) = self.handles.get(&handle) {
+            finding = finding.with_address(*address);
+        }
+        self.reportable.push(finding);
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        if let Some(mut session) = self.sessions.remove(&handle) {
+            if session.result == PairingResult::InProgress {
+                self.add_signal(packet, PairingSignal::PairingInterrupted);
+                session.result = PairingResult::Disconnected;
+                session.last_pdu = packet.clone();
+            }
+            self.finished.push(self.summarize(handle, &session));
+        }
+        self.pending_ltk_request.remove(&handle);
+        self.reassembler.remove_link(handle);
+        self.handles.remove(&handle);
+    }
+
+    fn process_reset(&mut self) {
+        let handles: Vec<ConnectionHandle> = self.sessions.keys().cloned().collect();
+        for handle in handles {
+            if let Some(session) = self.sessions.remove(&handle) {
+                self.finished.push(self.summarize(handle, &session));
+            }
+        }
+        self.handles.clear();
+        self.pending_ltk_request.clear();
+        self.reassembler.clear();
+    }
+}
+
+impl Rule for SmpPairingRule {
+    fn name(&self) -> &'static str {
+        "SmpPairingRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        // Checked on every packet so a pairing that stalls is caught even if nothing else
+        // happens on its link until the end of the log.
+        self.check_timeouts(packet);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) => match cmd.specialize() {
+                CommandChild::LeSecurityCommand(cmd) => match cmd.specialize() {
+                    LeSecurityCommandChild::LeLongTermKeyRequestReply(cmd) => {
+                        self.pending_ltk_request.remove(&cmd.get_connection_handle());
+                    }
+                    LeSecurityCommandChild::LeLongTermKeyRequestNegativeReply(cmd) => {
+                        self.process_ltk_negative_reply(cmd.get_connection_handle(), packet);
+                    }
+
+                    // CommandChild::LeSecurityCommand(cmd).specialize()
+                    _ => {}
+                },
+
+                CommandChild::Reset(_) => {
+                    self.process_reset();
+                }
+
+                // PacketChild::HciCommand(cmd).specialize()
+                _ => {}
+            },
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::EncryptionChange(ev) => {
+                    self.process_encryption_change(
+                        ev.get_status(),
+                        ev.get_connection_handle(),
+                        packet,
+                    );
+                }
+                EventChild::DisconnectionComplete(ev) => {
+                    self.process_disconnection(ev.get_connection_handle(), packet);
+                }
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeConnectionComplete(ev)
+                        if ev.get_status() == ErrorCode::Success =>
+                    {
+                        self.handles.insert(ev.get_connection_handle(), ev.get_peer_address());
+                    }
+                    LeMetaEventChild::LeEnhancedConnectionComplete(ev)
+                        if ev.get_status() == ErrorCode::Success =>
+                    {
+                        self.handles.insert(ev.get_connection_handle(), ev.get_peer_address());
+                    }
+                    LeMetaEventChild::LeLongTermKeyRequest(ev) => {
+                        self.pending_ltk_request.insert(ev.get_connection_handle(), packet.clone());
+                    }
+
+                    // EventChild::LeMetaEvent(ev).specialize()
+                    _ => {}
+                },
+
+                // PacketChild::HciEvent(ev).specialize()
+                _ => {}
+            },
+
+            PacketChild::AclTx(tx) => self.process_smp(tx.get_handle(), true, packet),
+            PacketChild::AclRx(rx) => self.process_smp(rx.get_handle(), false, packet),
+
+            // packet.inner
+            _ => {}
+        }
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        // Pairings still open at the end of the log are summarized as they are.
+        let mut findings = self.reportable.clone();
+        findings.extend(self.finished.iter().cloned());
+        findings.extend(self.sessions.iter().map(|(handle, s)| self.summarize(*handle, s)));
+        findings.sort_by_key(|f| f.start_index);
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [
+            PairingSignal::PairingFailed,
+            PairingSignal::PairingTimeout,
+            PairingSignal::PairingInterrupted,
+            PairingSignal::LtkMissing,
+            PairingSignal::EncryptionFailed,
+        ] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// Get a rule group with pairing rules.
+pub fn get_pairing_group(_config: &RuleConfig) -> RuleGroup {
+    let mut group = RuleGroup::new();
+    group.add_rule(Box::new(SmpPairingRule::new()));
+
+    group
+}
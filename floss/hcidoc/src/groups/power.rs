@@ -0,0 +1,731 @@
+//! Rule group for tracking link power modes, to help chase battery drain.
+use chrono::NaiveDateTime;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::engine::{
+    Finding, Rule, RuleConfig, RuleGroup, Severity, Signal, SignalDefinition, SignalRegistry,
+};
+use crate::parser::{Packet, PacketChild};
+use bt_packets::hci::{
+    AclCommandChild, Address, Command, CommandChild, ErrorCode, EventChild,
+    LeConnectionManagementCommandChild, LeMetaEventChild, LinkType, Mode, OpCode,
+};
+
+enum PowerSignal {
+    NeverSniff,              // A long lived ACL connection never entered sniff mode.
+    ModeFlapping,            // An ACL connection switched modes many times in a short while.
+    ConnParamUpdateRejected, // An LE connection parameter update failed or the peer rejected it.
+}
+
+impl From<PowerSignal> for SignalDefinition {
+    fn from(signal: PowerSignal) -> Self {
+        match signal {
+            PowerSignal::NeverSniff => SignalDefinition {
+                tag: "NeverSniff",
+                severity: Severity::Warning,
+                description: "A long lived ACL connection stayed in active mode the whole time.",
+                next_step: "Check the sniff policy of the profiles on this connection and whether \
+                    the peer rejects Sniff Mode.",
+            },
+            PowerSignal::ModeFlapping => SignalDefinition {
+                tag: "ModeFlapping",
+                severity: Severity::Warning,
+                description: "An ACL connection switched between active and sniff mode many times \
+                    in a short while.",
+                next_step:
+                    "Check which traffic wakes the link up, the sniff idle timer may be too \
+                    short for it.",
+            },
+            PowerSignal::ConnParamUpdateRejected => SignalDefinition {
+                tag: "ConnParamUpdateRejected",
+                severity: Severity::Warning,
+                description: "An LE connection parameter update failed, usually because the peer \
+                    rejected the parameters.",
+                next_step: "Compare the requested parameters with the peer's preferred connection \
+                    parameters.",
+            },
+        }
+    }
+}
+
+impl From<PowerSignal> for &'static str {
+    fn from(signal: PowerSignal) -> Self {
+        SignalDefinition::from(signal).tag
+    }
+}
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+/// ACL connections that last at least this long are expected to enter sniff mode. Can be
+/// overridden with the `never_sniff_ms` parameter of SniffModeRule.
+const DEFAULT_NEVER_SNIFF_MS: i64 = 60000;
+
+/// This many mode changes within `flapping_window_ms` are reported as flapping. Can be overridden
+/// with the `flapping_threshold` and `flapping_window_ms` parameters of SniffModeRule.
+const DEFAULT_FLAPPING_THRESHOLD: i64 = 10;
+const DEFAULT_FLAPPING_WINDOW_MS: i64 = 10000;
+
+/// Baseband slots are 0.625 ms.
+fn slots_to_ms(slots: u16) -> f64 {
+    slots as f64 * 0.625
+}
+
+/// An ACL connection and the time it spent in each mode.
+#[derive(Clone)]
+struct SniffLink {
+    address: Address,
+    connected: Packet,
+
+    mode: Mode,
+
+    /// When the link moved to |mode|.
+    mode_since: NaiveDateTime,
+
+    active_ms: i64,
+    hold_ms: i64,
+    sniff_ms: i64,
+
+    /// Sniff intervals the link used, in slots.
+    sniff_intervals: BTreeSet<u16>,
+
+    /// Maximum transmit and receive latency negotiated by sniff subrating, in slots.
+    subrating: Option<(u16, u16)>,
+
+    /// Mode changes within the flapping window, oldest first.
+    recent_changes: VecDeque<Packet>,
+
+    /// The link was already reported for staying active too long.
+    never_sniff_reported: bool,
+}
+
+impl SniffLink {
+    fn new(address: Address, packet: &Packet) -> Self {
+        SniffLink {
+            address,
+            connected: packet.clone(),
+            mode: Mode::Active,
+            mode_since: packet.ts,
+            active_ms: 0,
+            hold_ms: 0,
+            sniff_ms: 0,
+            sniff_intervals: BTreeSet::new(),
+            subrating: None,
+            recent_changes: VecDeque::new(),
+            never_sniff_reported: false,
+        }
+    }
+
+    /// Account the time spent in the current mode up to |ts|.
+    fn account(&mut self, ts: NaiveDateTime) {
+        let elapsed_ms = ts.signed_duration_since(self.mode_since).num_milliseconds();
+        match self.mode {
+            Mode::Active => self.active_ms += elapsed_ms,
+            Mode::Hold => self.hold_ms += elapsed_ms,
+            Mode::Sniff => self.sniff_ms += elapsed_ms,
+        }
+        self.mode_since = ts;
+    }
+
+    fn entered_sniff(&self) -> bool {
+        self.mode == Mode::Sniff || !self.sniff_intervals.is_empty()
+    }
+}
+
+/// Follows Mode Change and Sniff Subrating events on ACL connections, reporting how long each
+/// connection spent in each mode and connections that don't save power.
+struct SniffModeRule {
+    /// Open ACL connections, keyed by connection handle.
+    links: HashMap<ConnectionHandle, SniffLink>,
+
+    never_sniff_ms: i64,
+    flapping_threshold: i64,
+    flapping_window_ms: i64,
+
+    /// Index and timestamp of the last packet seen, to account connections still open at the end
+    /// of the log.
+    last_packet: Option<(usize, NaiveDateTime)>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl SniffModeRule {
+    pub fn new(config: &RuleConfig) -> Self {
+        SniffModeRule {
+            links: HashMap::new(),
+            never_sniff_ms: config.get_i64(
+                "SniffModeRule",
+                "never_sniff_ms",
+                DEFAULT_NEVER_SNIFF_MS,
+            ),
+            flapping_threshold: config.get_i64(
+                "SniffModeRule",
+                "flapping_threshold",
+                DEFAULT_FLAPPING_THRESHOLD,
+            ),
+            flapping_window_ms: config.get_i64(
+                "SniffModeRule",
+                "flapping_window_ms",
+                DEFAULT_FLAPPING_WINDOW_MS,
+            ),
+            last_packet: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: PowerSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn process_mode_change(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        mode: Mode,
+        interval: u16,
+        packet: &Packet,
+    ) {
+        let link = match self.links.get_mut(&handle) {
+            Some(link) => link,
+            None => return,
+        };
+
+        if status != ErrorCode::Success {
+            let address = link.address;
+            self.reportable.push(
+                Finding::new(
+                    packet,
+                    Severity::Warning,
+                    format!("Mode change on handle {} failed with {:?}", handle, status),
+                )
+                .with_address(address)
+                .with_handle(handle),
+            );
+            return;
+        }
+
+        link.account(packet.ts);
+        link.mode = mode;
+        if mode == Mode::Sniff {
+            link.sniff_intervals.insert(interval);
+        }
+
+        while let Some(oldest) = link.recent_changes.front() {
+            if packet.ts.signed_duration_since(oldest.ts).num_milliseconds()
+                <= self.flapping_window_ms
+            {
+                break;
+            }
+            link.recent_changes.pop_front();
+        }
+        link.recent_changes.push_back(packet.clone());
+        if (link.recent_changes.len() as i64) < self.flapping_threshold {
+            return;
+        }
+
+        let first = link.recent_changes.front().cloned().unwrap_or_else(|| packet.clone());
+        let message = format!(
+            "Handle {} changed mode {} times in {} ms",
+            handle,
+            link.recent_changes.len(),
+            packet.ts.signed_duration_since(first.ts).num_milliseconds()
+        );
+        let address = link.address;
+        link.recent_changes.clear();
+        self.add_signal(packet, PowerSignal::ModeFlapping);
+        self.reportable.push(
+            Finding::new(packet, Severity::Warning, message)
+                .since(&first)
+                .with_address(address)
+                .with_handle(handle),
+        );
+    }
+
+    fn process_sniff_subrating(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        max_tx_latency: u16,
+        max_rx_latency: u16,
+    ) {
+        if status != ErrorCode::Success {
+            return;
+        }
+        if let Some(link) = self.links.get_mut(&handle) {
+            link.subrating = Some((max_tx_latency, max_rx_latency));
+        }
+    }
+
+    /// Flag the links that have been open for |never_sniff_ms| without entering sniff by the time
+    /// of |packet|, so links that are still open at the end of the log are flagged too.
+    fn check_never_sniff(&mut self, packet: &Packet) {
+        let mut handles: Vec<ConnectionHandle> = self
+            .links
+            .iter()
+            .filter(|(_, link)| {
+                !link.never_sniff_reported
+                    && !link.entered_sniff()
+                    && packet.ts.signed_duration_since(link.connected.ts).num_milliseconds()
+                        >= self.never_sniff_ms
+            })
+            .map(|(handle, _)| *handle)
+            .collect();
+        handles.sort_unstable();
+
+        for handle in handles {
+            let link = self.links.get_mut(&handle).unwrap();
+            link.never_sniff_reported = true;
+            let duration_ms = packet.ts.signed_duration_since(link.connected.ts).num_milliseconds();
+            let finding = Finding::new(
+                packet,
+                Severity::Warning,
+                format!(
+                    "Handle {} stayed active for {} ms without entering sniff",
+                    handle, duration_ms
+                ),
+            )
+            .since(&link.connected)
+            .with_address(link.address)
+            .with_handle(handle);
+            self.add_signal(packet, PowerSignal::NeverSniff);
+            self.reportable.push(finding);
+        }
+    }
+
+    fn summary_finding(
+        handle: ConnectionHandle,
+        link: &SniffLink,
+        end_index: usize,
+        end_ts: NaiveDateTime,
+    ) -> Finding {
+        let duration_ms = end_ts.signed_duration_since(link.connected.ts).num_milliseconds();
+        let intervals: Vec<String> =
+            link.sniff_intervals.iter().map(|i| format!("{:.2} ms", slots_to_ms(*i))).collect();
+        let mut message = format!(
+            "Handle {}: {} ms active, {} ms sniff, {} ms hold over {} ms",
+            handle, link.active_ms, link.sniff_ms, link.hold_ms, duration_ms
+        );
+        if !intervals.is_empty() {
+            message += &format!(", sniff interval {}", intervals.join(", "));
+        }
+        if let Some((tx, rx)) = link.subrating {
+            message += &format!(
+                ", subrating max latency tx {:.2} ms rx {:.2} ms",
+                slots_to_ms(tx),
+                slots_to_ms(rx)
+            );
+        }
+
+        if !link.entered_sniff() {
+            message += ", never entered sniff";
+        }
+        Finding::new(&link.connected, Severity::Info, message)
+            .until(end_index, end_ts)
+            .with_address(link.address)
+            .with_handle(handle)
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        let mut link = match self.links.remove(&handle) {
+            Some(link) => link,
+            None => return,
+        };
+        link.account(packet.ts);
+        self.reportable.push(Self::summary_finding(handle, &link, packet.index, packet.ts));
+    }
+}
+
+impl Rule for SniffModeRule {
+    fn name(&self) -> &'static str {
+        "SniffModeRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        self.check_never_sniff(packet);
+
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) if cmd.get_op_code() == OpCode::Reset => {
+                self.links.clear();
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::ConnectionComplete(ev)
+                    if ev.get_status() == ErrorCode::Success
+                        && ev.get_link_type() == LinkType::Acl =>
+                {
+                    self.links.insert(
+                        ev.get_connection_handle(),
+                        SniffLink::new(ev.get_bd_addr(), packet),
+                    );
+                }
+                EventChild::ModeChange(ev) => {
+                    self.process_mode_change(
+                        ev.get_status(),
+                        ev.get_connection_handle(),
+                        ev.get_current_mode(),
+                        ev.get_interval(),
+                        packet,
+                    );
+                }
+                EventChild::SniffSubratingEvent(ev) => {
+                    self.process_sniff_subrating(
+                        ev.get_status(),
+                        ev.get_connection_handle(),
+                        ev.get_maximum_transmit_latency(),
+                        ev.get_maximum_receive_latency(),
+                    );
+                }
+                EventChild::DisconnectionComplete(ev) if ev.get_status() == ErrorCode::Success => {
+                    self.process_disconnection(ev.get_connection_handle(), packet);
+                }
+
+                // End HciEvent.specialize()
+                _ => {}
+            },
+
+            _ => {}
+        }
+
+        self.last_packet = Some((packet.index, packet.ts));
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        // Connections still open at the end of the log.
+        if let Some((last_index, last_ts)) = self.last_packet {
+            let mut links: Vec<(&ConnectionHandle, &SniffLink)> = self.links.iter().collect();
+            links.sort_by_key(|(_, link)| link.connected.index);
+            for (handle, link) in links {
+                let mut link_at_end = link.clone();
+                link_at_end.account(last_ts);
+                findings.push(Self::summary_finding(*handle, &link_at_end, last_index, last_ts));
+            }
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        for signal in [PowerSignal::NeverSniff, PowerSignal::ModeFlapping] {
+            registry.register(signal.into());
+        }
+    }
+}
+
+/// Connection interval, peripheral latency and supervision timeout of an LE connection, in the
+/// units HCI uses.
+#[derive(Clone, Copy, PartialEq)]
+struct ConnectionParameters {
+    interval: u16,
+    latency: u16,
+    timeout: u16,
+}
+
+impl ConnectionParameters {
+    fn describe(&self) -> String {
+        // The interval is in units of 1.25 ms and the timeout in units of 10 ms.
+        format!(
+            "interval {:.2} ms, latency {}, timeout {} ms",
+            self.interval as f64 * 1.25,
+            self.latency,
+            self.timeout as u32 * 10
+        )
+    }
+}
+
+/// An LE connection and the time it spent with each set of parameters.
+#[derive(Clone)]
+struct LeLink {
+    address: Address,
+    connected: Packet,
+
+    parameters: ConnectionParameters,
+
+    /// When |parameters| were applied.
+    parameters_since: NaiveDateTime,
+
+    /// Time spent with each set of parameters, in the order they were first used.
+    durations: Vec<(ConnectionParameters, i64)>,
+
+    /// Last LE Connection Update sent for this connection, waiting for its completion.
+    pending_update: Option<Packet>,
+}
+
+impl LeLink {
+    fn new(address: Address, parameters: ConnectionParameters, packet: &Packet) -> Self {
+        LeLink {
+            address,
+            connected: packet.clone(),
+            parameters,
+            parameters_since: packet.ts,
+            durations: vec![],
+            pending_update: None,
+        }
+    }
+
+    /// Account the time spent with the current parameters up to |ts|.
+    fn account(&mut self, ts: NaiveDateTime) {
+        let elapsed_ms = ts.signed_duration_since(self.parameters_since).num_milliseconds();
+        match self.durations.iter_mut().find(|(p, _)| *p == self.parameters) {
+            Some((_, ms)) => *ms += elapsed_ms,
+            None => self.durations.push((self.parameters, elapsed_ms)),
+        }
+        self.parameters_since = ts;
+    }
+}
+
+/// Follows the connection parameters of LE connections, reporting how long each connection spent
+/// with each set of parameters and updates that failed.
+struct LeConnectionParametersRule {
+    /// Open LE connections, keyed by connection handle.
+    links: HashMap<ConnectionHandle, LeLink>,
+
+    /// Index and timestamp of the last packet seen, to account connections still open at the end
+    /// of the log.
+    last_packet: Option<(usize, NaiveDateTime)>,
+
+    /// Pre-defined signals discovered in the logs.
+    signals: Vec<Signal>,
+
+    /// Interesting occurrences surfaced by this rule.
+    reportable: Vec<Finding>,
+}
+
+impl LeConnectionParametersRule {
+    pub fn new() -> Self {
+        LeConnectionParametersRule {
+            links: HashMap::new(),
+            last_packet: None,
+            signals: vec![],
+            reportable: vec![],
+        }
+    }
+
+    fn add_signal(&mut self, packet: &Packet, signal: PowerSignal) {
+        self.signals.push(Signal { index: packet.index, ts: packet.ts, tag: signal.into() });
+    }
+
+    fn process_connection(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        address: Address,
+        parameters: ConnectionParameters,
+        packet: &Packet,
+    ) {
+        if status == ErrorCode::Success {
+            self.links.insert(handle, LeLink::new(address, parameters, packet));
+        }
+    }
+
+    fn get_connection_update_handle(cmd: &Command) -> Option<ConnectionHandle> {
+        let acl = match cmd.specialize() {
+            CommandChild::AclCommand(acl) => acl,
+            _ => return None,
+        };
+        let le_conn = match acl.specialize() {
+            AclCommandChild::LeConnectionManagementCommand(le_conn) => le_conn,
+            _ => return None,
+        };
+        match le_conn.specialize() {
+            LeConnectionManagementCommandChild::LeConnectionUpdate(update) => {
+                Some(update.get_connection_handle())
+            }
+            _ => None,
+        }
+    }
+
+    fn process_update_complete(
+        &mut self,
+        status: ErrorCode,
+        handle: ConnectionHandle,
+        parameters: ConnectionParameters,
+        packet: &Packet,
+    ) {
+        let link = match self.links.get_mut(&handle) {
+            Some(link) => link,
+            None => return,
+        };
+        let request = link.pending_update.take();
+
+        if status == ErrorCode::Success {
+            link.account(packet.ts);
+            link.parameters = parameters;
+            return;
+        }
+
+        let mut finding = Finding::new(
+            packet,
+            Severity::Warning,
+            format!(
+                "Connection parameter update on handle {} failed with {:?}, staying at {}",
+                handle,
+                status,
+                link.parameters.describe()
+            ),
+        )
+        .with_address(link.address)
+        .with_handle(handle);
+        if let Some(request) = &request {
+            finding = finding.since(request);
+        }
+        self.add_signal(packet, PowerSignal::ConnParamUpdateRejected);
+        self.reportable.push(finding);
+    }
+
+    fn summary_finding(
+        handle: ConnectionHandle,
+        link: &LeLink,
+        end_index: usize,
+        end_ts: NaiveDateTime,
+    ) -> Finding {
+        let duration_ms = end_ts.signed_duration_since(link.connected.ts).num_milliseconds();
+        let durations: Vec<String> = link
+            .durations
+            .iter()
+            .map(|(parameters, ms)| format!("{} ms at {}", ms, parameters.describe()))
+            .collect();
+        Finding::new(
+            &link.connected,
+            Severity::Info,
+            format!(
+                "Handle {}: {} parameter sets over {} ms: {}",
+                handle,
+                link.durations.len(),
+                duration_ms,
+                durations.join("; ")
+            ),
+        )
+        .until(end_index, end_ts)
+        .with_address(link.address)
+        .with_handle(handle)
+    }
+
+    fn process_disconnection(&mut self, handle: ConnectionHandle, packet: &Packet) {
+        if let Some(mut link) = self.links.remove(&handle) {
+            link.account(packet.ts);
+            self.reportable.push(Self::summary_finding(handle, &link, packet.index, packet.ts));
+        }
+    }
+}
+
+impl Rule for LeConnectionParametersRule {
+    fn name(&self) -> &'static str {
+        "LeConnectionParametersRule"
+    }
+
+    fn process(&mut self, packet: &Packet) {
+        match &packet.inner {
+            PacketChild::HciCommand(cmd) if cmd.get_op_code() == OpCode::Reset => {
+                self.links.clear();
+            }
+            PacketChild::HciCommand(cmd) => {
+                if let Some(handle) = Self::get_connection_update_handle(cmd) {
+                    if let Some(link) = self.links.get_mut(&handle) {
+                        link.pending_update = Some(packet.clone());
+                    }
+                }
+            }
+
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::LeMetaEvent(ev) => match ev.specialize() {
+                    LeMetaEventChild::LeConnectionComplete(ev) => {
+                        self.process_connection(
+                            ev.get_status(),
+                            ev.get_connection_handle(),
+                            ev.get_peer_address(),
+                            ConnectionParameters {
+                                interval: ev.get_conn_interval(),
+                                latency: ev.get_conn_latency(),
+                                timeout: ev.get_supervision_timeout(),
+                            },
+                            packet,
+                        );
+                    }
+                    LeMetaEventChild::LeEnhancedConnectionComplete(ev) => {
+                        self.process_connection(
+                            ev.get_status(),
+                            ev.get_connection_handle(),
+                            ev.get_peer_address(),
+                            ConnectionParameters {
+                                interval: ev.get_conn_interval(),
+                                latency: ev.get_conn_latency(),
+                                timeout: ev.get_supervision_timeout(),
+                            },
+                            packet,
+                        );
+                    }
+                    LeMetaEventChild::LeConnectionUpdateComplete(ev) => {
+                        self.process_update_complete(
+                            ev.get_status(),
+                            ev.get_connection_handle(),
+                            ConnectionParameters {
+                                interval: ev.get_conn_interval(),
+                                latency: ev.get_conn_latency(),
+                                timeout: ev.get_supervision_timeout(),
+                            },
+                            packet,
+                        );
+                    }
+
+                    // End LeMetaEvent.specialize()
+                    _ => {}
+                },
+                EventChild::DisconnectionComplete(ev) if ev.get_status() == ErrorCode::Success => {
+                    self.process_disconnection(ev.get_connection_handle(), packet);
+                }
+
+                // End HciEvent.specialize()
+                _ => {}
+            },
+
+            _ => {}
+        }
+
+        self.last_packet = Some((packet.index, packet.ts));
+    }
+
+    fn report_findings(&self) -> Vec<Finding> {
+        let mut findings = self.reportable.clone();
+
+        // Connections still open at the end of the log.
+        if let Some((last_index, last_ts)) = self.last_packet {
+            let mut links: Vec<(&ConnectionHandle, &LeLink)> = self.links.iter().collect();
+            links.sort_by_key(|(_, link)| link.connected.index);
+            for (handle, link) in links {
+                let mut link_at_end = link.clone();
+                link_at_end.account(last_ts);
+                findings.push(Self::summary_finding(*handle, &link_at_end, last_index, last_ts));
+            }
+        }
+
+        findings
+    }
+
+    fn report_signals(&self) -> &[Signal] {
+        self.signals.as_slice()
+    }
+
+    fn register_signals(&self, registry: &mut SignalRegistry) {
+        registry.register(PowerSignal::ConnParamUpdateRejected.into());
+    }
+}
+
+/// Get a rule group with link power mode rules.
+pub fn get_power_group(config: &RuleConfig) -> RuleGroup {
+    let mut group = RuleGroup::new();
+    group.add_rule(Box::new(SniffModeRule::new(config)));
+    group.add_rule(Box::new(LeConnectionParametersRule::new()));
+
+    group
+}
@@ -0,0 +1,128 @@
+//! Follows dynamic L2CAP channels, so rules for protocols on top of L2CAP know which PSM the data
+//! on a channel belongs to.
+
+use std::collections::HashMap;
+
+use hcidoc_packets::l2cap::{ConnectionResponseResult, Control, ControlChild};
+
+/// Valid values are in the range 0x0000-0x0EFF.
+type ConnectionHandle = u16;
+
+pub type Cid = u16;
+pub type Psm = u16;
+
+/// Which side of the link sent a packet.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Sender {
+    Host,
+    Peer,
+}
+
+impl Sender {
+    pub fn other(self) -> Self {
+        match self {
+            Sender::Host => Sender::Peer,
+            Sender::Peer => Sender::Host,
+        }
+    }
+}
+
+/// An open dynamic channel.
+#[derive(Clone, Copy, Debug)]
+pub struct Channel {
+    pub handle: ConnectionHandle,
+    pub psm: Psm,
+    pub host_cid: Cid,
+    pub peer_cid: Cid,
+}
+
+/// Change to the set of open channels caused by a signalling packet.
+pub enum ChannelEvent {
+    Opened(Channel),
+    Closed(Channel),
+}
+
+/// Dynamic channels on all links, keyed by the CID the host uses.
+#[derive(Default)]
+pub struct L2capChannels {
+    /// Connection requests waiting for a response, keyed by the CID of the requester.
+    pending: HashMap<(ConnectionHandle, Sender, Cid), Psm>,
+
+    channels: HashMap<(ConnectionHandle, Cid), Channel>,
+}
+
+impl L2capChannels {
+    pub fn new() -> Self {
+        L2capChannels::default()
+    }
+
+    /// Update the channels from a signalling packet.
+    pub fn process_control(
+        &mut self,
+        handle: ConnectionHandle,
+        sender: Sender,
+        control: &Control,
+    ) -> Option<ChannelEvent> {
+        match control.specialize() {
+            ControlChild::ConnectionRequest(req) => {
+                self.pending.insert((handle, sender, req.get_source_cid()), req.get_psm());
+                None
+            }
+            ControlChild::ConnectionResponse(rsp) => {
+                // The response is sent by the side that accepts the channel.
+                let initiator = sender.other();
+                let key = (handle, initiator, rsp.get_source_cid());
+                match rsp.get_result() {
+                    ConnectionResponseResult::Pending => None,
+                    ConnectionResponseResult::Success => {
+                        let psm = self.pending.remove(&key)?;
+                        let (host_cid, peer_cid) = match initiator {
+                            Sender::Host => (rsp.get_source_cid(), rsp.get_destination_cid()),
+                            Sender::Peer => (rsp.get_destination_cid(), rsp.get_source_cid()),
+                        };
+                        let channel = Channel { handle, psm, host_cid, peer_cid };
+                        self.channels.insert((handle, host_cid), channel);
+                        Some(ChannelEvent::Opened(channel))
+                    }
+                    _ => {
+                        self.pending.remove(&key);
+                        None
+                    }
+                }
+            }
+            ControlChild::DisconnectionResponse(rsp) => {
+                // The destination CID belongs to the side sending the response.
+                let host_cid = match sender {
+                    Sender::Host => rsp.get_destination_cid(),
+                    Sender::Peer => rsp.get_source_cid(),
+                };
+                self.channels.remove(&(handle, host_cid)).map(ChannelEvent::Closed)
+            }
+
+            // control.specialize()
+            _ => None,
+        }
+    }
+
+    /// Find the channel a data frame belongs to. Frames sent by the host are addressed to the CID
+    /// of the peer and the other way around.
+    pub fn get(&self, handle: ConnectionHandle, sender: Sender, cid: Cid) -> Option<&Channel> {
+        match sender {
+            Sender::Host => {
+                self.channels.values().find(|c| c.handle == handle && c.peer_cid == cid)
+            }
+            Sender::Peer => self.channels.get(&(handle, cid)),
+        }
+    }
+
+    /// Forget all channels on a link that went away.
+    pub fn remove_link(&mut self, handle: ConnectionHandle) {
+        self.pending.retain(|(h, _, _), _| *h != handle);
+        self.channels.retain(|(h, _), _| *h != handle);
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.channels.clear();
+    }
+}
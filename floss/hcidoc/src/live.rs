@@ -0,0 +1,211 @@
+//! Sources of packets that keep producing data while hcidoc is running: the kernel HCI monitor
+//! channel and log files that are still being written.
+
+use std::fs::File;
+use std::io::{BufReader, Error, Read};
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags};
+use nix::sys::time::{TimeVal, TimeValLike};
+use nix::sys::uio::IoVec;
+
+use crate::parser::{unix_usecs_to_snoop_timestamp, LinuxSnoopPacket, LogParser};
+
+/// Socket protocol constant for HCI.
+const BTPROTO_HCI: libc::c_int = 1;
+
+/// Bind to all controllers at once.
+const HCI_DEV_NONE: u16 = 0xFFFF;
+
+/// HCI channel carrying a copy of all traffic, in the same layout as btmon files.
+const HCI_CHANNEL_MONITOR: u16 = 2;
+
+/// Size of the header in front of every packet read from the monitor channel: opcode, adapter
+/// index and payload length, all little-endian.
+const MONITOR_HEADER_SIZE: usize = 6;
+
+/// Large enough for any packet sent on the monitor channel.
+const MONITOR_MAX_PACKET_SIZE: usize = 4096;
+
+/// How often blocked reads wake up to check whether the user asked to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Set once the user presses Ctrl-C.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signum: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Stop reading on Ctrl-C instead of exiting, so the final report can still be written.
+pub fn stop_on_interrupt() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Reader that waits for more data at the end of the file instead of reporting end of file, like
+/// `tail -f`. It only ends once interrupted.
+pub struct FollowReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> FollowReader<R> {
+    pub fn new(inner: R) -> Self {
+        FollowReader { inner }
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.inner.read(buf)?;
+            if read > 0 || buf.is_empty() || interrupted() {
+                return Ok(read);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Open a log that is still being written. Packets keep coming until interrupted.
+pub fn follow_log(filepath: &str) -> std::io::Result<LogParser> {
+    let fd = Box::new(BufReader::new(FollowReader::new(File::open(filepath)?)));
+
+    Ok(LogParser::from_reader(fd))
+}
+
+/// Socket address for HCI sockets. Mirrors `struct sockaddr_hci` from the kernel.
+#[repr(C)]
+struct SockAddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+/// Packets read from the kernel HCI monitor channel as they are sent and received. Needs
+/// CAP_NET_RAW, just like btmon.
+pub struct MonitorSocket {
+    fd: RawFd,
+}
+
+impl MonitorSocket {
+    pub fn open() -> std::io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(libc::PF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC, BTPROTO_HCI)
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // Closes the socket if any of the steps below fail.
+        let socket = MonitorSocket { fd };
+
+        let addr = SockAddrHci {
+            // AF_BLUETOOTH can always be cast into u16
+            hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            hci_dev: HCI_DEV_NONE,
+            hci_channel: HCI_CHANNEL_MONITOR,
+        };
+        let ret = unsafe {
+            libc::bind(
+                socket.fd,
+                (&addr as *const SockAddrHci) as *const libc::sockaddr,
+                mem::size_of::<SockAddrHci>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // The monitor header has no timestamp, so ask the kernel for the time it saw each packet.
+        setsockopt(socket.fd, sockopt::ReceiveTimestamp, &true)?;
+
+        Ok(socket)
+    }
+}
+
+impl Drop for MonitorSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl Iterator for MonitorSocket {
+    type Item = LinuxSnoopPacket;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fd = self.fd;
+        let mut buf = [0u8; MONITOR_MAX_PACKET_SIZE];
+        loop {
+            if interrupted() {
+                return None;
+            }
+
+            // Wake up regularly so Ctrl-C is noticed even when there is no traffic.
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+            match poll(&mut fds, POLL_INTERVAL.as_millis() as i32) {
+                Ok(0) | Err(Errno::EINTR) => continue,
+                Ok(_) => (),
+                Err(e) => {
+                    eprintln!("Error polling monitor socket: {:?}", e);
+                    return None;
+                }
+            }
+
+            let mut cmsg = nix::cmsg_space!(TimeVal);
+            let iov = [IoVec::from_mut_slice(&mut buf)];
+            let (read, kernel_ts) = match recvmsg(fd, &iov, Some(&mut cmsg), MsgFlags::empty()) {
+                Ok(msg) => (
+                    msg.bytes,
+                    msg.cmsgs().find_map(|cmsg| match cmsg {
+                        ControlMessageOwned::ScmTimestamp(tv) => Some(tv),
+                        _ => None,
+                    }),
+                ),
+                Err(Errno::EAGAIN) | Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    eprintln!("Error reading monitor socket: {:?}", e);
+                    return None;
+                }
+            };
+
+            if read < MONITOR_HEADER_SIZE {
+                continue;
+            }
+
+            let opcode = u16::from_le_bytes([buf[0], buf[1]]);
+            let index = u16::from_le_bytes([buf[2], buf[3]]);
+            let data = buf[MONITOR_HEADER_SIZE..read].to_vec();
+            let unix_usecs = match kernel_ts {
+                Some(tv) => tv.num_microseconds(),
+                None => SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| i64::try_from(d.as_micros()).unwrap_or(0)),
+            };
+
+            return Some(LinuxSnoopPacket {
+                original_length: data.len() as u32,
+                included_length: data.len() as u32,
+                flags: (u32::from(index) << 16) | u32::from(opcode),
+                drops: 0,
+                timestamp_magic_us: unix_usecs_to_snoop_timestamp(unix_usecs),
+                data,
+            });
+        }
+    }
+}
@@ -1,26 +1,113 @@
 use clap::{Arg, ArgAction, Command};
 use std::io::Write;
 
+mod diff;
 mod engine;
+mod filter;
 mod groups;
+mod l2cap;
+mod live;
 mod parser;
+mod rfcomm;
+mod vendor;
 
-use crate::engine::RuleEngine;
-use crate::groups::{collisions, connections, controllers, informational};
-use crate::parser::{LinuxSnoopOpcodes, LogParser, LogType, Packet};
+use crate::diff::LogSummary;
+use crate::engine::{GetRuleGroup, OutputFormat, RuleConfig, RuleEngine, Severity};
+use crate::filter::{parse_address, parse_index_range, PacketFilter, TimeBound};
+use crate::groups::{
+    audio, collisions, connections, controllers, discovery, gatt, informational, isochronous,
+    pairing, power,
+};
+use crate::live::MonitorSocket;
+use crate::parser::{LinuxSnoopOpcodes, LinuxSnoopPacket, LogParser, Packet};
+
+/// All rule groups known to hcidoc. They are all enabled by default.
+const RULE_GROUPS: [(&str, GetRuleGroup); 10] = [
+    ("Audio", audio::get_audio_group),
+    ("Collisions", collisions::get_collisions_group),
+    ("Connections", connections::get_connections_group),
+    ("Controllers", controllers::get_controllers_group),
+    ("Discovery", discovery::get_discovery_group),
+    ("Gatt", gatt::get_gatt_group),
+    ("Informational", informational::get_informational_group),
+    ("Isochronous", isochronous::get_isochronous_group),
+    ("Pairing", pairing::get_pairing_group),
+    ("Power", power::get_power_group),
+];
+
+/// Create an engine running the selected rule groups. No groups selected means all groups.
+fn new_engine(config: RuleConfig, included: &[&String], excluded: &[&String]) -> RuleEngine {
+    let mut engine = RuleEngine::new(config);
+    for (name, get_group) in RULE_GROUPS.iter() {
+        let selected = included.is_empty() || included.iter().any(|g| g == name);
+        if selected && !excluded.iter().any(|g| g == name) {
+            engine.add_rule_group(name.to_string(), *get_group);
+        }
+    }
+    engine
+}
+
+/// Warn about the rules and parameters of |config| that don't exist.
+fn warn_unknown_config(config: &RuleConfig) {
+    // Creating the rules makes them look up their parameters.
+    let rule_names: Vec<&str> =
+        RULE_GROUPS.iter().flat_map(|(_, get_group)| get_group(config).rule_names()).collect();
+    config.warn_unknown(&rule_names);
+}
+
+/// Open a log, or stdin if |filename| is empty, and detect its format.
+fn open_log(filename: &str, follow: bool) -> Result<LogParser, String> {
+    let opened = if follow { live::follow_log(filename) } else { LogParser::new(filename) };
+    let mut parser = opened.map_err(|e| {
+        format!(
+            "Failed to load parser on {}: {}",
+            if filename.len() == 0 { "stdin" } else { filename },
+            e
+        )
+    })?;
+
+    parser.read_log_type().map_err(|e| format!("Parsing {} failed: {}", filename, e))?;
+    Ok(parser)
+}
+
+/// Hand every packet accepted by |filter| to |process|.
+fn process_packets(
+    packets: impl Iterator<Item = LinuxSnoopPacket>,
+    filter: &mut PacketFilter,
+    ignore_unknown_opcode: bool,
+    mut process: impl FnMut(Packet),
+) {
+    for (pos, v) in packets.enumerate() {
+        match Packet::try_from((pos, &v)) {
+            Ok(p) if !filter.accept(&p) => (),
+            Ok(p) => process(p),
+            Err(e) => {
+                if !ignore_unknown_opcode {
+                    match v.opcode() {
+                        LinuxSnoopOpcodes::Command | LinuxSnoopOpcodes::Event => {
+                            eprintln!("#{}: {}", pos, e);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
 
 fn main() {
-    let matches = Command::new("hcidoc")
+    let cli = Command::new("hcidoc")
         .version("0.1")
         .author("Abhishek Pandit-Subedi <abhishekpandit@google.com>")
-        .about("Analyzes a linux HCI snoop log for specific behaviors and errors.")
-        .arg(
-            Arg::new("filename")
-                .help("Path to the snoop log. If omitted, read from stdin instead."),
-        )
+        .about("Analyzes a linux or Android HCI snoop log for specific behaviors and errors.")
+        .arg(Arg::new("filename").help(
+            "Path to the snoop log, pcap capture or Android bugreport. If omitted, read from \
+                 stdin instead.",
+        ))
         .arg(
             Arg::new("ignore-unknown")
                 .long("ignore-unknown")
+                .global(true)
                 .action(ArgAction::SetTrue)
                 .help("Don't print warning for unknown opcodes"),
         )
@@ -37,9 +124,104 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Only print signals from active rules, don't print other events."),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .value_parser(["text", "json", "jsonl"])
+                .default_value("text")
+                .help("Output format for reports and signals."),
+        )
+        .arg(
+            Arg::new("groups")
+                .long("groups")
+                .global(true)
+                .value_delimiter(',')
+                .help("Comma separated list of rule groups to run. Defaults to all groups."),
+        )
+        .arg(
+            Arg::new("exclude-groups")
+                .long("exclude-groups")
+                .global(true)
+                .value_delimiter(',')
+                .help("Comma separated list of rule groups to skip."),
+        )
+        .arg(
+            Arg::new("list-rules")
+                .long("list-rules")
+                .action(ArgAction::SetTrue)
+                .help("List the available rule groups and their rules, then exit."),
+        )
+        .arg(
+            Arg::new("list-signals")
+                .long("list-signals")
+                .action(ArgAction::SetTrue)
+                .help("List the signals the selected rule groups can raise, then exit."),
+        )
+        .arg(
+            Arg::new("fail-on")
+                .long("fail-on")
+                .value_parser(["info", "warning", "error"])
+                .help("Exit with status 1 if a signal of at least this severity is raised."),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .global(true)
+                .help("Path to a JSON file with per-rule parameters."),
+        )
+        .arg(Arg::new("since").long("since").global(true).help(
+            "Skip packets before this time, either a timestamp like 2023-11-14T22:13:20 or an \
+             offset from the first packet like +90s.",
+        ))
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .global(true)
+                .help("Skip packets after this time, in the same format as --since."),
+        )
+        .arg(
+            Arg::new("index-range")
+                .long("index-range")
+                .global(true)
+                .help("Only analyze packets in this range of indices, like 100-200."),
+        )
+        .arg(Arg::new("address").long("address").global(true).value_delimiter(',').help(
+            "Comma separated list of device addresses to analyze. Traffic of other devices is \
+             skipped.",
+        ))
+        .arg(
+            Arg::new("live")
+                .long("live")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["filename", "follow"])
+                .help("Analyze traffic from the kernel HCI monitor channel as it happens."),
+        )
+        .arg(
+            Arg::new("follow")
+                .long("follow")
+                .action(ArgAction::SetTrue)
+                .requires("filename")
+                .help("Keep reading the log as it grows, like `tail -f`."),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about(
+                    "Runs the same rules on a good and a bad log of the same scenario and reports \
+                     how they differ.",
+                )
+                .arg(Arg::new("good").required(true).help("Path to the log where things work."))
+                .arg(Arg::new("bad").required(true).help("Path to the log with the regression.")),
+        )
         .get_matches();
 
-    let filename = match matches.get_one::<String>("filename") {
+    // Options shared with the diff mode may be given after its name.
+    let matches = match cli.subcommand() {
+        Some((_, subcommand)) => subcommand,
+        None => &cli,
+    };
+
+    let filename = match cli.get_one::<String>("filename") {
         Some(f) => f,
         None => "",
     };
@@ -49,12 +231,12 @@ fn main() {
         None => false,
     };
 
-    let mut report_signals = match matches.get_one::<bool>("signals") {
+    let mut report_signals = match cli.get_one::<bool>("signals") {
         Some(v) => *v,
         None => false,
     };
 
-    let report_only_signals = match matches.get_one::<bool>("signals-only") {
+    let report_only_signals = match cli.get_one::<bool>("signals-only") {
         Some(v) => *v,
         None => false,
     };
@@ -63,59 +245,190 @@ fn main() {
         report_signals = true;
     }
 
-    let mut parser = match LogParser::new(filename) {
-        Ok(p) => p,
-        Err(e) => {
-            println!(
-                "Failed to load parser on {}: {}",
-                if filename.len() == 0 { "stdin" } else { filename },
-                e
-            );
+    let format = match matches.get_one::<String>("format") {
+        Some(f) => OutputFormat::try_from(f.as_str()).unwrap_or(OutputFormat::Text),
+        None => OutputFormat::Text,
+    };
+
+    let mut config = match matches.get_one::<String>("config") {
+        Some(path) => match RuleConfig::from_file(path) {
+            Ok(c) => {
+                warn_unknown_config(&c);
+                c
+            }
+            Err(e) => {
+                println!("Failed to load config {}: {}", path, e);
+                return;
+            }
+        },
+        None => RuleConfig::new(),
+    };
+
+    if cli.get_flag("list-rules") {
+        for (name, get_group) in RULE_GROUPS.iter() {
+            println!("{}", name);
+            for rule in get_group(&config).rule_names() {
+                println!("  {}", rule);
+            }
+        }
+        return;
+    }
+
+    let included: Vec<&String> = matches.get_many::<String>("groups").unwrap_or_default().collect();
+    let excluded: Vec<&String> =
+        matches.get_many::<String>("exclude-groups").unwrap_or_default().collect();
+    if let Some(unknown) = included
+        .iter()
+        .chain(excluded.iter())
+        .find(|g| !RULE_GROUPS.iter().any(|(name, _)| *name == g.as_str()))
+    {
+        println!("Unknown rule group: {}. Use --list-rules to see available groups.", unknown);
+        return;
+    }
+
+    if cli.get_flag("list-signals") {
+        let engine = new_engine(config, &included, &excluded);
+        for registered in engine.signal_registry().iter() {
+            let definition = &registered.definition;
+            println!("{} ({}, {})", definition.tag, registered.group, definition.severity);
+            println!("  {}", definition.description);
+            println!("  Next step: {}", definition.next_step);
+        }
+        return;
+    }
+
+    let fail_on =
+        cli.get_one::<String>("fail-on").and_then(|s| Severity::try_from(s.as_str()).ok());
+
+    let mut filter = PacketFilter::new();
+    let parse_time =
+        |arg: &str| matches.get_one::<String>(arg).map(|t| TimeBound::try_from(t.as_str()));
+    match (parse_time("since").transpose(), parse_time("until").transpose()) {
+        (Ok(since), Ok(until)) => filter.set_time_window(since, until),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
             return;
         }
-    };
+    }
+
+    if let Some(range) = matches.get_one::<String>("index-range") {
+        match parse_index_range(range) {
+            Ok((start, end)) => filter.set_index_range(start, end),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
 
-    let log_type = match parser.read_log_type() {
-        Ok(v) => v,
+    let addresses = match matches
+        .get_many::<String>("address")
+        .unwrap_or_default()
+        .map(|a| parse_address(a))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(addresses) => addresses,
         Err(e) => {
-            println!("Parsing {} failed: {}", filename, e);
+            println!("{}", e);
             return;
         }
     };
-
-    // Create engine with default rule groups.
-    let mut engine = RuleEngine::new();
-    engine.add_rule_group("Collisions".into(), collisions::get_collisions_group());
-    engine.add_rule_group("Connections".into(), connections::get_connections_group());
-    engine.add_rule_group("Controllers".into(), controllers::get_controllers_group());
-    engine.add_rule_group("Informational".into(), informational::get_informational_group());
+    filter.set_addresses(addresses.clone());
+    config.set_addresses(addresses);
 
     // Decide where to write output.
     let mut writer: Box<dyn Write> = Box::new(std::io::stdout());
 
-    if let LogType::LinuxSnoop(_header) = log_type {
-        for (pos, v) in parser.get_snoop_iterator().expect("Not a linux snoop file").enumerate() {
-            match Packet::try_from((pos, &v)) {
-                Ok(p) => engine.process(p),
+    if let Some(("diff", diff)) = cli.subcommand() {
+        if !filename.is_empty() || cli.get_flag("live") || cli.get_flag("follow") {
+            println!(
+                "diff reads its own two logs and can't be combined with a log, --live or --follow."
+            );
+            return;
+        }
+
+        let mut summaries = vec![];
+        for arg in ["good", "bad"] {
+            let filename = diff.get_one::<String>(arg).expect("Log paths are required");
+            let mut parser = match open_log(filename, false) {
+                Ok(p) => p,
                 Err(e) => {
-                    if !ignore_unknown_opcode {
-                        match v.opcode() {
-                            LinuxSnoopOpcodes::Command | LinuxSnoopOpcodes::Event => {
-                                eprintln!("#{}: {}", pos, e);
-                            }
-                            _ => (),
-                        }
-                    }
+                    println!("{}", e);
+                    return;
                 }
+            };
+
+            // Every log starts with a fresh filter, since relative time bounds and device
+            // selection depend on the log.
+            let mut summary =
+                LogSummary::new(new_engine(config.clone(), &included, &excluded), &config);
+            process_packets(
+                parser.get_packet_iterator().expect("Unsupported log file"),
+                &mut filter.clone(),
+                ignore_unknown_opcode,
+                |p| summary.process(p),
+            );
+            summaries.push((filename.as_str(), summary));
+        }
+
+        let (good, bad) = (&summaries[0], &summaries[1]);
+        diff::report_diff(&mut writer, format, (good.0, &good.1), (bad.0, &bad.1));
+        return;
+    }
+
+    let live = cli.get_flag("live");
+    let follow = cli.get_flag("follow");
+
+    // Live sources only stop on Ctrl-C, after which the usual report is written.
+    if live || follow {
+        live::stop_on_interrupt();
+    }
+
+    let mut parser: LogParser;
+    let packets: Box<dyn Iterator<Item = LinuxSnoopPacket>> = if live {
+        match MonitorSocket::open() {
+            Ok(socket) => Box::new(socket),
+            Err(e) => {
+                println!("Failed to open the HCI monitor channel: {}", e);
+                return;
             }
         }
+    } else {
+        parser = match open_log(filename, follow) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        parser.get_packet_iterator().expect("Unsupported log file")
+    };
+
+    // Create engine with the selected rule groups.
+    let mut engine = new_engine(config, &included, &excluded);
 
-        if !report_only_signals {
-            engine.report(&mut writer);
+    process_packets(packets, &mut filter, ignore_unknown_opcode, |p| {
+        engine.process(p);
+        if live || follow {
+            engine.report_new_signals(&mut writer, format);
         }
-        if report_signals {
-            let _ = writeln!(&mut writer, "### Signals ###");
-            engine.report_signals(&mut writer);
+    });
+
+    // Signals of live sources were already written as they were raised.
+    let report_signals = report_signals && !(live || follow);
+    if !report_only_signals || report_signals {
+        engine.report_with_format(&mut writer, format, !report_only_signals, report_signals);
+    }
+
+    if let (Some(threshold), Some(worst)) = (fail_on, engine.worst_signal_severity()) {
+        if worst >= threshold {
+            let _ = writer.flush();
+            eprintln!(
+                "Raised a signal of severity {}, failing since --fail-on is {}",
+                worst, threshold
+            );
+            std::process::exit(1);
         }
     }
 }
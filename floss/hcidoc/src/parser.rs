@@ -1,19 +1,24 @@
 //! Parsing of various Bluetooth packets.
 use chrono::NaiveDateTime;
+use flate2::read::ZlibDecoder;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::cast::FromPrimitive;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::io::{BufRead, BufReader, Cursor, Error, ErrorKind, Read};
 
-use bt_packets::hci::{Acl, AclChild, Command, Event};
+use bt_packets::hci::{Acl, AclChild, Address, Command, Event, Iso, PacketBoundaryFlag, Sco};
+use hcidoc_packets::att::Att;
 use hcidoc_packets::l2cap::{
-    BasicFrame, BasicFrameChild, Control, ControlFrameChild, GroupFrameChild, LeControl,
-    LeControlFrameChild,
+    AttFrameChild, BasicFrame, BasicFrameChild, Control, ControlFrameChild, GroupFrameChild,
+    LeControl, LeControlFrameChild, SmpFrameChild,
 };
+use hcidoc_packets::smp::Command as SmpCommand;
 
 /// Linux snoop file header format. This format is used by `btmon` on Linux systems that have bluez
-/// installed.
+/// installed. Android devices (and the GD snoop logger) use the same header, but with a H1 or H4
+/// datalink type instead of the monitor type.
 #[derive(Clone, Copy, Debug)]
 pub struct LinuxSnoopHeader {
     id: [u8; 8],
@@ -24,8 +29,17 @@ pub struct LinuxSnoopHeader {
 /// Identifier for a Linux snoop file. In ASCII, this is 'btsnoop\0'.
 const LINUX_SNOOP_MAGIC: [u8; 8] = [0x62, 0x74, 0x73, 0x6e, 0x6f, 0x6f, 0x70, 0x00];
 
-/// Snoop files in monitor format will have this value in link type.
-const LINUX_SNOOP_MONITOR_TYPE: u32 = 2001;
+/// Datalink types supported in the snoop header.
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+#[repr(u32)]
+pub enum SnoopDatalinkType {
+    /// Un-encapsulated HCI. Packet type is only known through the flags.
+    H1 = 1001,
+    /// HCI UART. Every packet is preceded by the H4 packet type byte.
+    H4 = 1002,
+    /// Linux monitor format. Opcode and adapter index are encoded in the flags.
+    LinuxMonitor = 2001,
+}
 
 /// Size of snoop header. 8 bytes for magic and another 8 for additional info.
 const LINUX_SNOOP_HEADER_SIZE: usize = 16;
@@ -57,10 +71,11 @@ impl TryFrom<&[u8]> for LinuxSnoopHeader {
             return Err(format!("Version is not supported. Got {}.", header.version));
         }
 
-        if header.data_type != LINUX_SNOOP_MONITOR_TYPE {
+        if SnoopDatalinkType::from_u32(header.data_type).is_none() {
             return Err(format!(
-                "Invalid data type in snoop file. We want monitor type ({}) but got {}",
-                LINUX_SNOOP_MONITOR_TYPE, header.data_type
+                "Invalid data type in snoop file. We want H1 (1001), H4 (1002) or monitor (2001) \
+                 but got {}",
+                header.data_type
             ));
         }
 
@@ -68,6 +83,13 @@ impl TryFrom<&[u8]> for LinuxSnoopHeader {
     }
 }
 
+impl LinuxSnoopHeader {
+    pub fn datalink_type(&self) -> SnoopDatalinkType {
+        // The header can't be constructed with an unsupported data type.
+        SnoopDatalinkType::from_u32(self.data_type).unwrap()
+    }
+}
+
 /// Opcodes for Linux snoop packets.
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 #[repr(u16)]
@@ -119,8 +141,73 @@ impl LinuxSnoopPacket {
     pub fn opcode(&self) -> LinuxSnoopOpcodes {
         LinuxSnoopOpcodes::from_u32(self.flags & 0xffff).unwrap_or(LinuxSnoopOpcodes::Invalid)
     }
+
+    /// Rewrite a packet read from a H1 or H4 snoop file into the monitor layout so the rest of the
+    /// parser doesn't need to care about the datalink type: the opcode and adapter index are moved
+    /// into |flags| and the H4 packet type byte is removed from |data|.
+    fn into_monitor_format(mut self, datalink: SnoopDatalinkType) -> Self {
+        let received = self.flags & BTSNOOP_FLAG_RECEIVED != 0;
+        let opcode = match datalink {
+            SnoopDatalinkType::LinuxMonitor => return self,
+
+            SnoopDatalinkType::H1 => {
+                match (self.flags & BTSNOOP_FLAG_COMMAND_OR_EVENT != 0, received) {
+                    (true, false) => LinuxSnoopOpcodes::Command,
+                    (true, true) => LinuxSnoopOpcodes::Event,
+                    // H1 doesn't distinguish between data packets so assume ACL.
+                    (false, false) => LinuxSnoopOpcodes::AclTxPacket,
+                    (false, true) => LinuxSnoopOpcodes::AclRxPacket,
+                }
+            }
+
+            SnoopDatalinkType::H4 => {
+                if self.data.is_empty() {
+                    LinuxSnoopOpcodes::Invalid
+                } else {
+                    let packet_type = H4PacketType::from_u8(self.data.remove(0));
+                    self.included_length = self.included_length.saturating_sub(1);
+                    self.original_length = self.original_length.saturating_sub(1);
+
+                    match (packet_type, received) {
+                        (Some(H4PacketType::Command), _) => LinuxSnoopOpcodes::Command,
+                        (Some(H4PacketType::Event), _) => LinuxSnoopOpcodes::Event,
+                        (Some(H4PacketType::Acl), false) => LinuxSnoopOpcodes::AclTxPacket,
+                        (Some(H4PacketType::Acl), true) => LinuxSnoopOpcodes::AclRxPacket,
+                        (Some(H4PacketType::Sco), false) => LinuxSnoopOpcodes::ScoTxPacket,
+                        (Some(H4PacketType::Sco), true) => LinuxSnoopOpcodes::ScoRxPacket,
+                        (Some(H4PacketType::Iso), false) => LinuxSnoopOpcodes::IsoTx,
+                        (Some(H4PacketType::Iso), true) => LinuxSnoopOpcodes::IsoRx,
+                        (None, _) => LinuxSnoopOpcodes::Invalid,
+                    }
+                }
+            }
+        };
+
+        self.flags = (u32::from(BTSNOOP_ADAPTER_INDEX) << 16) | (opcode as u32);
+        self
+    }
 }
 
+/// H4 packet type byte that precedes every packet in H4 snoop files.
+#[derive(Debug, FromPrimitive)]
+#[repr(u8)]
+enum H4PacketType {
+    Command = 0x01,
+    Acl = 0x02,
+    Sco = 0x03,
+    Event = 0x04,
+    Iso = 0x05,
+}
+
+/// Set in H1/H4 snoop packet flags if the packet was received from the controller.
+const BTSNOOP_FLAG_RECEIVED: u32 = 0x01;
+
+/// Set in H1/H4 snoop packet flags if the packet is a command or an event.
+const BTSNOOP_FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+/// H1/H4 snoop files only contain a single controller so use the first adapter index.
+const BTSNOOP_ADAPTER_INDEX: u16 = 0;
+
 /// Size of packet preamble (everything except the data).
 const LINUX_SNOOP_PACKET_PREAMBLE_SIZE: usize = 24;
 
@@ -172,14 +259,16 @@ impl TryFrom<&[u8]> for LinuxSnoopPacket {
     }
 }
 
-/// Reader for Linux snoop files.
+/// Reader for Linux snoop files. Packets from H1/H4 snoop files are converted to the monitor
+/// format while reading.
 pub struct LinuxSnoopReader<'a> {
     fd: Box<dyn BufRead + 'a>,
+    datalink: SnoopDatalinkType,
 }
 
 impl<'a> LinuxSnoopReader<'a> {
-    fn new(fd: Box<dyn BufRead + 'a>) -> Self {
-        LinuxSnoopReader { fd }
+    fn new(fd: Box<dyn BufRead + 'a>, datalink: SnoopDatalinkType) -> Self {
+        LinuxSnoopReader { fd, datalink }
     }
 }
 
@@ -208,7 +297,7 @@ impl<'a> Iterator for LinuxSnoopReader<'a> {
                     match self.fd.read_exact(&mut rem_data[0..size]) {
                         Ok(()) => {
                             p.data = rem_data[0..size].to_vec();
-                            Some(p)
+                            Some(p.into_monitor_format(self.datalink))
                         }
                         Err(e) => {
                             eprintln!("Couldn't read any packet data: {}", e);
@@ -216,7 +305,7 @@ impl<'a> Iterator for LinuxSnoopReader<'a> {
                         }
                     }
                 } else {
-                    Some(p)
+                    Some(p.into_monitor_format(self.datalink))
                 }
             }
             Err(_) => None,
@@ -224,17 +313,571 @@ impl<'a> Iterator for LinuxSnoopReader<'a> {
     }
 }
 
+/// Magic number of a pcap file with microsecond timestamps, in the writer's byte order.
+const PCAP_MAGIC_USECS: u32 = 0xa1b2c3d4;
+
+/// Magic number of a pcap file with nanosecond timestamps, in the writer's byte order.
+const PCAP_MAGIC_NSECS: u32 = 0xa1b23c4d;
+
+/// Size of the pcap global header.
+const PCAP_HEADER_SIZE: usize = 24;
+
+/// Size of the pcap per-packet record header.
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+/// Block type of the pcapng Section Header Block. It reads the same in both byte orders.
+const PCAPNG_SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+
+/// Block type of the pcapng Interface Description Block.
+const PCAPNG_INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+
+/// Block type of the pcapng Simple Packet Block.
+const PCAPNG_SIMPLE_PACKET_BLOCK: u32 = 0x00000003;
+
+/// Block type of the pcapng Enhanced Packet Block.
+const PCAPNG_ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+
+/// Byte order magic found in the pcapng Section Header Block.
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+/// Interface Description Block option with the timestamp resolution.
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+
+/// Interface Description Block option with the timestamp offset in seconds.
+const PCAPNG_OPTION_IF_TSOFFSET: u16 = 14;
+
+/// Upper bound on a single pcap record or pcapng block. Anything larger is a corrupt file.
+const PCAP_MAX_RECORD_SIZE: usize = 0x40000;
+
+/// Size of the pseudo header in front of the packet data for the supported link types.
+const PCAP_PSEUDO_HEADER_SIZE: usize = 4;
+
+/// Link types (as assigned by tcpdump.org) that we can parse from pcap and pcapng files.
+#[derive(Clone, Copy, Debug, PartialEq, FromPrimitive)]
+#[repr(u32)]
+pub enum PcapLinkType {
+    /// H4 packet preceded by a 4-byte direction pseudo header.
+    BluetoothHciH4WithPhdr = 201,
+    /// Linux monitor packet preceded by a pseudo header with adapter index and opcode.
+    BluetoothLinuxMonitor = 254,
+}
+
+fn read_u16_with_order(bytes: &[u8], big_endian: bool) -> u16 {
+    let bytes: [u8; 2] = bytes[0..2].try_into().unwrap();
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn read_u32_with_order(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes: [u8; 4] = bytes[0..4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Converts a unix timestamp in microseconds to the timestamp format used in snoop packets.
+pub(crate) fn unix_usecs_to_snoop_timestamp(unix_usecs: i64) -> u64 {
+    u64::try_from(unix_usecs - LINUX_SNOOP_OFFSET_TO_UNIXTIME_SECS * USECS_TO_SECS).unwrap_or(0)
+}
+
+impl LinuxSnoopPacket {
+    /// Build a monitor format packet out of a frame captured in a pcap or pcapng file. Frames that
+    /// can't be understood are still returned (with an invalid opcode) so that packet indices
+    /// match the frame numbers shown by Wireshark.
+    fn from_pcap_frame(
+        link_type: Option<PcapLinkType>,
+        unix_usecs: i64,
+        original_length: u32,
+        frame: &[u8],
+    ) -> Self {
+        let mut packet = LinuxSnoopPacket {
+            original_length: original_length.saturating_sub(PCAP_PSEUDO_HEADER_SIZE as u32),
+            included_length: 0,
+            flags: LinuxSnoopOpcodes::Invalid as u32,
+            drops: 0,
+            timestamp_magic_us: unix_usecs_to_snoop_timestamp(unix_usecs),
+            data: vec![],
+        };
+
+        if frame.len() < PCAP_PSEUDO_HEADER_SIZE {
+            return packet;
+        }
+
+        let (pseudo_header, data) = frame.split_at(PCAP_PSEUDO_HEADER_SIZE);
+        packet.included_length = data.len().try_into().unwrap_or(0);
+        packet.data = data.to_vec();
+
+        // Pseudo headers are always in network order, regardless of the file byte order.
+        match link_type {
+            Some(PcapLinkType::BluetoothHciH4WithPhdr) => {
+                let direction = u32::from_be_bytes(pseudo_header.try_into().unwrap());
+                packet.flags = direction & BTSNOOP_FLAG_RECEIVED;
+                packet.into_monitor_format(SnoopDatalinkType::H4)
+            }
+
+            Some(PcapLinkType::BluetoothLinuxMonitor) => {
+                let adapter_index = u16::from_be_bytes(pseudo_header[0..2].try_into().unwrap());
+                let opcode = u16::from_be_bytes(pseudo_header[2..4].try_into().unwrap());
+                packet.flags = (u32::from(adapter_index) << 16) | u32::from(opcode);
+                packet
+            }
+
+            None => {
+                packet.data.clear();
+                packet.included_length = 0;
+                packet
+            }
+        }
+    }
+}
+
+/// Global header of a classic pcap file.
+#[derive(Clone, Copy, Debug)]
+pub struct PcapHeader {
+    big_endian: bool,
+    nanosecond_resolution: bool,
+    link_type: PcapLinkType,
+}
+
+impl TryFrom<&[u8]> for PcapHeader {
+    type Error = String;
+
+    fn try_from(item: &[u8]) -> Result<Self, Self::Error> {
+        if item.len() != PCAP_HEADER_SIZE {
+            return Err(format!("Invalid size for pcap header: {}", item.len()));
+        }
+
+        let magic = u32::from_le_bytes(item[0..4].try_into().unwrap());
+        let (big_endian, nanosecond_resolution) = match magic {
+            PCAP_MAGIC_USECS => (false, false),
+            PCAP_MAGIC_NSECS => (false, true),
+            _ => match magic.swap_bytes() {
+                PCAP_MAGIC_USECS => (true, false),
+                PCAP_MAGIC_NSECS => (true, true),
+                _ => return Err(format!("Not a pcap file. Got magic {:#010x}.", magic)),
+            },
+        };
+
+        let version_major = read_u16_with_order(&item[4..6], big_endian);
+        let version_minor = read_u16_with_order(&item[6..8], big_endian);
+        if version_major != 2 {
+            return Err(format!(
+                "Version is not supported. Got {}.{}.",
+                version_major, version_minor
+            ));
+        }
+
+        // The upper bits of the link type field can carry FCS information which we don't need.
+        let raw_link_type = read_u32_with_order(&item[20..24], big_endian) & 0xffff;
+        let link_type = PcapLinkType::from_u32(raw_link_type)
+            .ok_or(format!("Unsupported pcap link type: {}", raw_link_type))?;
+
+        Ok(PcapHeader { big_endian, nanosecond_resolution, link_type })
+    }
+}
+
+/// Reader for classic pcap files.
+pub struct PcapReader<'a> {
+    fd: Box<dyn BufRead + 'a>,
+    header: PcapHeader,
+}
+
+impl<'a> PcapReader<'a> {
+    fn new(fd: Box<dyn BufRead + 'a>, header: PcapHeader) -> Self {
+        PcapReader { fd, header }
+    }
+}
+
+impl<'a> Iterator for PcapReader<'a> {
+    type Item = LinuxSnoopPacket;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = [0u8; PCAP_RECORD_HEADER_SIZE];
+        if let Err(e) = self.fd.read_exact(&mut record) {
+            if e.kind() != ErrorKind::UnexpectedEof {
+                eprintln!("Error reading pcap file: {:?}", e);
+            }
+            return None;
+        }
+
+        let big_endian = self.header.big_endian;
+        let ts_secs = i64::from(read_u32_with_order(&record[0..4], big_endian));
+        let ts_frac = i64::from(read_u32_with_order(&record[4..8], big_endian));
+        let included_length = read_u32_with_order(&record[8..12], big_endian);
+        let original_length = read_u32_with_order(&record[12..16], big_endian);
+
+        let size = usize::try_from(included_length).unwrap_or(usize::MAX);
+        if size > PCAP_MAX_RECORD_SIZE {
+            eprintln!("Pcap record is too large ({} bytes), file is probably corrupt", size);
+            return None;
+        }
+
+        let mut frame = vec![0u8; size];
+        if let Err(e) = self.fd.read_exact(&mut frame) {
+            eprintln!("Couldn't read any packet data: {}", e);
+            return None;
+        }
+
+        let ts_usecs = if self.header.nanosecond_resolution { ts_frac / 1000 } else { ts_frac };
+        let unix_usecs = ts_secs * USECS_TO_SECS + ts_usecs;
+
+        Some(LinuxSnoopPacket::from_pcap_frame(
+            Some(self.header.link_type),
+            unix_usecs,
+            original_length,
+            &frame,
+        ))
+    }
+}
+
+/// Section header of a pcapng file. Only the first section header is kept here, the reader
+/// handles any further sections on its own.
+#[derive(Clone, Copy, Debug)]
+pub struct PcapNgHeader {
+    big_endian: bool,
+}
+
+/// Interface described by a pcapng Interface Description Block.
+struct PcapNgInterface {
+    link_type: Option<PcapLinkType>,
+
+    /// Number of timestamp units per second.
+    ts_units_per_sec: u64,
+
+    /// Offset in seconds to add to all timestamps on this interface.
+    ts_offset_secs: i64,
+}
+
+impl PcapNgInterface {
+    fn from_block_body(body: &[u8], big_endian: bool) -> Self {
+        let link_type = PcapLinkType::from_u16(read_u16_with_order(&body[0..2], big_endian));
+        let mut interface =
+            PcapNgInterface { link_type, ts_units_per_sec: 1_000_000, ts_offset_secs: 0 };
+
+        // Options start after link type (2), reserved (2) and snaplen (4).
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = read_u16_with_order(&options[0..2], big_endian);
+            let length = usize::from(read_u16_with_order(&options[2..4], big_endian));
+            let padded_length = (length + 3) & !3;
+            if options.len() < 4 + padded_length {
+                break;
+            }
+
+            let value = &options[4..4 + length];
+            match code {
+                PCAPNG_OPTION_IF_TSRESOL if length == 1 => {
+                    // MSB set means a power of 2, otherwise a power of 10.
+                    let exponent = u32::from(value[0] & 0x7f);
+                    let base: u64 = if value[0] & 0x80 != 0 { 2 } else { 10 };
+                    interface.ts_units_per_sec = base.checked_pow(exponent).unwrap_or(1_000_000);
+                }
+                PCAPNG_OPTION_IF_TSOFFSET if length == 8 => {
+                    let offset: [u8; 8] = value.try_into().unwrap();
+                    interface.ts_offset_secs = if big_endian {
+                        i64::from_be_bytes(offset)
+                    } else {
+                        i64::from_le_bytes(offset)
+                    };
+                }
+                _ => {}
+            }
+
+            options = &options[4 + padded_length..];
+        }
+
+        interface
+    }
+
+    fn to_unix_usecs(&self, ts: u64) -> i64 {
+        let secs = ts / self.ts_units_per_sec;
+        let frac = ts % self.ts_units_per_sec;
+        let usecs = u128::from(frac) * 1_000_000 / u128::from(self.ts_units_per_sec);
+
+        (i64::try_from(secs).unwrap_or(0) + self.ts_offset_secs) * USECS_TO_SECS
+            + i64::try_from(usecs).unwrap_or(0)
+    }
+}
+
+/// Reader for pcapng files. Every section and interface can have its own byte order and link
+/// type, so those are tracked while reading.
+pub struct PcapNgReader<'a> {
+    fd: Box<dyn BufRead + 'a>,
+    big_endian: bool,
+    interfaces: Vec<PcapNgInterface>,
+}
+
+impl<'a> PcapNgReader<'a> {
+    fn new(fd: Box<dyn BufRead + 'a>, header: PcapNgHeader) -> Self {
+        PcapNgReader { fd, big_endian: header.big_endian, interfaces: vec![] }
+    }
+
+    /// Read the body of a block (everything after block type and length) including the trailing
+    /// length, and return the body without it.
+    fn read_block_body(&mut self, total_length: u32) -> Option<Vec<u8>> {
+        let total_length = usize::try_from(total_length).unwrap_or(usize::MAX);
+        if !(12..=PCAP_MAX_RECORD_SIZE).contains(&total_length) {
+            eprintln!("Invalid pcapng block length {}, file is probably corrupt", total_length);
+            return None;
+        }
+
+        let mut body = vec![0u8; total_length - 8];
+        if let Err(e) = self.fd.read_exact(&mut body) {
+            eprintln!("Couldn't read pcapng block: {}", e);
+            return None;
+        }
+
+        body.truncate(total_length - 12);
+        Some(body)
+    }
+}
+
+impl<'a> Iterator for PcapNgReader<'a> {
+    type Item = LinuxSnoopPacket;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut block_header = [0u8; 8];
+            if let Err(e) = self.fd.read_exact(&mut block_header) {
+                if e.kind() != ErrorKind::UnexpectedEof {
+                    eprintln!("Error reading pcapng file: {:?}", e);
+                }
+                return None;
+            }
+
+            let block_type = read_u32_with_order(&block_header[0..4], self.big_endian);
+
+            // A new section can change the byte order, so peek at its byte order magic before
+            // reading the block length.
+            if block_type == PCAPNG_SECTION_HEADER_BLOCK {
+                let mut bom = [0u8; 4];
+                self.fd.read_exact(&mut bom).ok()?;
+                self.big_endian = u32::from_le_bytes(bom) != PCAPNG_BYTE_ORDER_MAGIC;
+                self.interfaces.clear();
+
+                let total_length = read_u32_with_order(&block_header[4..8], self.big_endian);
+                let remaining = usize::try_from(total_length).unwrap_or(usize::MAX);
+                if !(16..=PCAP_MAX_RECORD_SIZE).contains(&remaining) {
+                    eprintln!("Invalid pcapng section header length {}", remaining);
+                    return None;
+                }
+                let mut rest = vec![0u8; remaining - 12];
+                self.fd.read_exact(&mut rest).ok()?;
+                continue;
+            }
+
+            let total_length = read_u32_with_order(&block_header[4..8], self.big_endian);
+            let body = self.read_block_body(total_length)?;
+            let big_endian = self.big_endian;
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION_BLOCK if body.len() >= 8 => {
+                    self.interfaces.push(PcapNgInterface::from_block_body(&body, big_endian));
+                }
+
+                PCAPNG_ENHANCED_PACKET_BLOCK if body.len() >= 20 => {
+                    let interface_id = read_u32_with_order(&body[0..4], big_endian);
+                    let ts_high = u64::from(read_u32_with_order(&body[4..8], big_endian));
+                    let ts_low = u64::from(read_u32_with_order(&body[8..12], big_endian));
+                    let captured_length = read_u32_with_order(&body[12..16], big_endian);
+                    let original_length = read_u32_with_order(&body[16..20], big_endian);
+
+                    let end = (20 + captured_length as usize).min(body.len());
+                    let interface =
+                        usize::try_from(interface_id).ok().and_then(|id| self.interfaces.get(id));
+                    let link_type = interface.and_then(|i| i.link_type);
+                    let unix_usecs =
+                        interface.map_or(0, |i| i.to_unix_usecs((ts_high << 32) | ts_low));
+
+                    return Some(LinuxSnoopPacket::from_pcap_frame(
+                        link_type,
+                        unix_usecs,
+                        original_length,
+                        &body[20..end],
+                    ));
+                }
+
+                // Simple packets have no timestamp and always belong to the first interface.
+                PCAPNG_SIMPLE_PACKET_BLOCK if body.len() >= 4 => {
+                    let original_length = read_u32_with_order(&body[0..4], big_endian);
+                    let end = (4 + original_length as usize).min(body.len());
+                    let link_type = self.interfaces.first().and_then(|i| i.link_type);
+
+                    return Some(LinuxSnoopPacket::from_pcap_frame(
+                        link_type,
+                        0,
+                        original_length,
+                        &body[4..end],
+                    ));
+                }
+
+                // Skip everything else (name resolution, statistics, custom blocks, ...).
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Markers around the btsnooz section of an Android bugreport.
+const BTSNOOZ_BUGREPORT_BEGIN: &str = "--- BEGIN:BTSNOOP_LOG_SUMMARY";
+const BTSNOOZ_BUGREPORT_END: &str = "--- END:BTSNOOP_LOG_SUMMARY";
+
+/// Size of the uncompressed btsnooz preamble: version and timestamp of the last packet.
+const BTSNOOZ_PREAMBLE_SIZE: usize = 9;
+
+/// Size of the header in front of every btsnooz v1 and v2 record.
+const BTSNOOZ_V1_RECORD_HEADER_SIZE: usize = 7;
+const BTSNOOZ_V2_RECORD_HEADER_SIZE: usize = 9;
+
+/// First byte of a zlib stream with the default compression settings.
+const ZLIB_HEADER_MAGIC: u8 = 0x78;
+
+/// Map a btsnooz packet type, which comes from the stack's internal representation, to the
+/// monitor opcode.
+fn btsnooz_type_to_opcode(snooz_type: u8) -> LinuxSnoopOpcodes {
+    match snooz_type {
+        0x10 => LinuxSnoopOpcodes::Event,
+        0x11 => LinuxSnoopOpcodes::AclRxPacket,
+        0x12 => LinuxSnoopOpcodes::ScoRxPacket,
+        0x17 => LinuxSnoopOpcodes::IsoRx,
+        0x20 => LinuxSnoopOpcodes::Command,
+        0x21 => LinuxSnoopOpcodes::AclTxPacket,
+        0x22 => LinuxSnoopOpcodes::ScoTxPacket,
+        0x2d => LinuxSnoopOpcodes::IsoTx,
+        _ => LinuxSnoopOpcodes::Invalid,
+    }
+}
+
+/// Find the btsnooz payload in |contents|, which is either a bugreport, the base64 text from a
+/// bugreport or the decoded binary.
+fn extract_btsnooz(contents: &[u8]) -> Option<Vec<u8>> {
+    let is_binary = |bytes: &[u8]| {
+        bytes.len() > BTSNOOZ_PREAMBLE_SIZE
+            && (bytes[0] == 1 || bytes[0] == 2)
+            && bytes[BTSNOOZ_PREAMBLE_SIZE] == ZLIB_HEADER_MAGIC
+    };
+    if is_binary(contents) {
+        return Some(contents.to_vec());
+    }
+
+    // Bugreports are mostly text but aren't guaranteed to be valid UTF-8 everywhere.
+    let text = String::from_utf8_lossy(contents);
+    let encoded: String = match text.find(BTSNOOZ_BUGREPORT_BEGIN) {
+        Some(begin) => {
+            let section = &text[begin..];
+            let end = section.find(BTSNOOZ_BUGREPORT_END)?;
+            // Skip the rest of the begin marker line.
+            let start = section.find('\n')?;
+            section[start..end.max(start)].split_whitespace().collect()
+        }
+        None => text.split_whitespace().collect(),
+    };
+
+    base64::decode(encoded).ok().filter(|decoded| is_binary(decoded))
+}
+
+/// Decompress a btsnooz log, the compressed ring buffer of recent packets that Android devices
+/// include in bugreports, and rebuild it as a Linux snoop packet stream in the monitor format,
+/// without the file header.
+fn decode_btsnooz(snooz: &[u8]) -> std::io::Result<Vec<u8>> {
+    let version = snooz[0];
+    let record_header_size = match version {
+        1 => BTSNOOZ_V1_RECORD_HEADER_SIZE,
+        2 => BTSNOOZ_V2_RECORD_HEADER_SIZE,
+        _ => return Err(Error::other(format!("Unsupported btsnooz version {}", version))),
+    };
+
+    // Despite the names, btsnooz timestamps are unix timestamps in microseconds.
+    let last_ts = u64::from_le_bytes(snooz[1..BTSNOOZ_PREAMBLE_SIZE].try_into().unwrap());
+
+    let mut records = vec![];
+    ZlibDecoder::new(&snooz[BTSNOOZ_PREAMBLE_SIZE..]).read_to_end(&mut records)?;
+
+    // Each record has the delta from the previous one so the first pass finds the timestamp of
+    // the first packet.
+    let mut headers = vec![];
+    let mut offset = 0;
+    while offset + record_header_size <= records.len() {
+        let header = &records[offset..offset + record_header_size];
+        // Both lengths include the packet type, which isn't part of the packet data.
+        let length = usize::from(u16::from_le_bytes([header[0], header[1]]));
+        let (original_length, delta_ts, snooz_type) = match version {
+            1 => (length, u32::from_le_bytes(header[2..6].try_into().unwrap()), header[6]),
+            _ => (
+                usize::from(u16::from_le_bytes([header[2], header[3]])),
+                u32::from_le_bytes(header[4..8].try_into().unwrap()),
+                header[8],
+            ),
+        };
+
+        let data_start = offset + record_header_size;
+        let data_end = data_start + length.saturating_sub(1);
+        if data_end > records.len() {
+            break;
+        }
+        headers.push((
+            data_start..data_end,
+            original_length.saturating_sub(1),
+            delta_ts,
+            snooz_type,
+        ));
+        offset = data_end;
+    }
+
+    let total_delta: u64 = headers.iter().map(|(_, _, delta_ts, _)| u64::from(*delta_ts)).sum();
+    let mut ts = unix_usecs_to_snoop_timestamp(i64::try_from(last_ts).unwrap_or(0))
+        .saturating_sub(total_delta);
+
+    let mut stream = vec![];
+    for (data, original_length, delta_ts, snooz_type) in headers {
+        ts += u64::from(delta_ts);
+
+        // Packets are usually truncated already, keep them within what the reader accepts.
+        let data = &records[data.start..data.end.min(data.start + LINUX_SNOOP_MAX_PACKET_SIZE)];
+        let original_length = u32::try_from(original_length.max(data.len())).unwrap_or(u32::MAX);
+        stream.extend_from_slice(&original_length.to_be_bytes());
+        stream.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        stream.extend_from_slice(&(btsnooz_type_to_opcode(snooz_type) as u32).to_be_bytes());
+        stream.extend_from_slice(&0u32.to_be_bytes());
+        stream.extend_from_slice(&ts.to_be_bytes());
+        stream.extend_from_slice(data);
+    }
+
+    Ok(stream)
+}
+
 /// What kind of log file is this?
 #[derive(Clone, Debug)]
 pub enum LogType {
     /// Linux snoop file generated by something like `btmon`.
     LinuxSnoop(LinuxSnoopHeader),
+
+    /// Snoop file with H1 or H4 datalink, generated by Android devices or the GD snoop logger.
+    BtSnoop(LinuxSnoopHeader),
+
+    /// Classic pcap file generated by something like `tcpdump` or Wireshark.
+    Pcap(PcapHeader),
+
+    /// Pcapng file generated by something like Wireshark.
+    PcapNg(PcapNgHeader),
+
+    /// Compressed btsnooz log from an Android bugreport, either the whole bugreport or just the
+    /// btsnooz section.
+    BtSnooz,
 }
 
 /// Parses different Bluetooth log types.
 pub struct LogParser {
     fd: Box<dyn BufRead>,
     log_type: Option<LogType>,
+
+    /// Whether the log is still being written, so reading to its end never returns.
+    growing: bool,
 }
 
 impl<'a> LogParser {
@@ -246,23 +889,93 @@ impl<'a> LogParser {
             fd = Box::new(BufReader::new(File::open(filepath)?));
         }
 
-        Ok(Self { fd, log_type: None })
+        Ok(Self { fd, log_type: None, growing: false })
+    }
+
+    /// Parse a log that is still being written from a reader that waits for more at its end.
+    pub fn from_reader(fd: Box<dyn BufRead>) -> Self {
+        Self { fd, log_type: None, growing: true }
     }
 
     /// Check the log file type for the current log file. This advances the read pointer.
     /// For a non-intrusive query, use |get_log_type|.
     pub fn read_log_type(&mut self) -> std::io::Result<LogType> {
-        let mut buf = [0; LINUX_SNOOP_HEADER_SIZE];
+        // All supported log types have at least 4 bytes of magic at the start.
+        let mut magic = [0u8; 4];
+        self.fd.read_exact(&mut magic)?;
 
-        self.fd.read_exact(&mut buf)?;
+        let log_type = if magic == LINUX_SNOOP_MAGIC[0..4] {
+            let mut buf = [0u8; LINUX_SNOOP_HEADER_SIZE];
+            buf[0..4].copy_from_slice(&magic);
+            self.fd.read_exact(&mut buf[4..])?;
+
+            let header = LinuxSnoopHeader::try_from(&buf[0..LINUX_SNOOP_HEADER_SIZE])
+                .map_err(Error::other)?;
+            match header.datalink_type() {
+                SnoopDatalinkType::LinuxMonitor => LogType::LinuxSnoop(header),
+                SnoopDatalinkType::H1 | SnoopDatalinkType::H4 => LogType::BtSnoop(header),
+            }
+        } else if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER_BLOCK {
+            LogType::PcapNg(self.read_pcapng_section_header()?)
+        } else if [u32::from_le_bytes(magic), u32::from_be_bytes(magic)]
+            .iter()
+            .any(|m| *m == PCAP_MAGIC_USECS || *m == PCAP_MAGIC_NSECS)
+        {
+            let mut buf = [0u8; PCAP_HEADER_SIZE];
+            buf[0..4].copy_from_slice(&magic);
+            self.fd.read_exact(&mut buf[4..])?;
 
-        if let Ok(header) = LinuxSnoopHeader::try_from(&buf[0..LINUX_SNOOP_HEADER_SIZE]) {
-            let log_type = LogType::LinuxSnoop(header);
-            self.log_type = Some(log_type.clone());
-            Ok(log_type)
+            let header = PcapHeader::try_from(&buf[0..PCAP_HEADER_SIZE]).map_err(Error::other)?;
+            LogType::Pcap(header)
+        } else if self.growing {
+            // A bugreport is only decoded once it's complete, which never happens while following.
+            return Err(Error::other(
+                "Unsupported log file type, only snoop and pcap logs can be followed",
+            ));
         } else {
-            Err(Error::new(ErrorKind::Other, "Unsupported log file type"))
+            // Anything else could be a bugreport with a btsnooz section. It needs to be decoded as
+            // a whole, so replace the input with the rebuilt packet stream.
+            let mut contents = magic.to_vec();
+            self.fd.read_to_end(&mut contents)?;
+            let snooz = extract_btsnooz(&contents)
+                .ok_or_else(|| Error::other("Unsupported log file type"))?;
+            self.fd = Box::new(Cursor::new(decode_btsnooz(&snooz)?));
+            LogType::BtSnooz
+        };
+
+        self.log_type = Some(log_type.clone());
+        Ok(log_type)
+    }
+
+    /// Read the remainder of the first pcapng Section Header Block (after the block type).
+    fn read_pcapng_section_header(&mut self) -> std::io::Result<PcapNgHeader> {
+        let mut buf = [0u8; 12];
+        self.fd.read_exact(&mut buf)?;
+
+        let big_endian = match u32::from_le_bytes(buf[4..8].try_into().unwrap()) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            _ if u32::from_be_bytes(buf[4..8].try_into().unwrap()) == PCAPNG_BYTE_ORDER_MAGIC => {
+                true
+            }
+            _ => return Err(Error::other("Invalid pcapng byte order magic")),
+        };
+
+        let total_length =
+            usize::try_from(read_u32_with_order(&buf[0..4], big_endian)).unwrap_or(usize::MAX);
+        if !(28..=PCAP_MAX_RECORD_SIZE).contains(&total_length) {
+            return Err(Error::other("Invalid pcapng section header length"));
         }
+
+        // Skip the section length and options, we don't need them.
+        let mut rest = vec![0u8; total_length - 16];
+        self.fd.read_exact(&mut rest)?;
+
+        let version_major = read_u16_with_order(&buf[8..10], big_endian);
+        if version_major != 1 {
+            return Err(Error::other(format!("Unsupported pcapng version {}", version_major)));
+        }
+
+        Ok(PcapNgHeader { big_endian })
     }
 
     /// Get cached log type. To initially read the log type, use |read_log_type|.
@@ -270,13 +983,94 @@ impl<'a> LogParser {
         self.log_type.clone()
     }
 
-    pub fn get_snoop_iterator(&mut self) -> Option<LinuxSnoopReader> {
-        // Limit to LinuxSnoop files.
-        if !matches!(self.get_log_type()?, LogType::LinuxSnoop(_)) {
-            return None;
+    /// Get an iterator over all packets in the log, regardless of the log type. Packets are
+    /// always provided in the Linux snoop monitor format.
+    pub fn get_packet_iterator(
+        &mut self,
+    ) -> Option<Box<dyn Iterator<Item = LinuxSnoopPacket> + '_>> {
+        let log_type = self.get_log_type()?;
+        let fd = Box::new(BufReader::new(&mut self.fd));
+        match log_type {
+            LogType::LinuxSnoop(header) | LogType::BtSnoop(header) => {
+                Some(Box::new(LinuxSnoopReader::new(fd, header.datalink_type())))
+            }
+            LogType::Pcap(header) => Some(Box::new(PcapReader::new(fd, header))),
+            LogType::PcapNg(header) => Some(Box::new(PcapNgReader::new(fd, header))),
+            LogType::BtSnooz => {
+                Some(Box::new(LinuxSnoopReader::new(fd, SnoopDatalinkType::LinuxMonitor)))
+            }
         }
+    }
+}
+
+/// Size of the payload of the monitor NewIndex opcode: type, bus, address and name.
+const MONITOR_NEW_INDEX_SIZE: usize = 16;
 
-        Some(LinuxSnoopReader::new(Box::new(BufReader::new(&mut self.fd))))
+/// Size of the payload of the monitor IndexInfo opcode: address and manufacturer.
+const MONITOR_INDEX_INFO_SIZE: usize = 8;
+
+/// A controller was added. Sent by the monitor before any traffic on that adapter index.
+#[derive(Debug, Clone)]
+pub struct NewIndex {
+    pub address: Address,
+}
+
+impl TryFrom<&[u8]> for NewIndex {
+    type Error = String;
+
+    fn try_from(item: &[u8]) -> Result<Self, Self::Error> {
+        if item.len() < MONITOR_NEW_INDEX_SIZE {
+            return Err(format!("NewIndex too short: {} bytes", item.len()));
+        }
+
+        // Skip over the controller type and bus. The controller name follows the address.
+        Ok(NewIndex { address: Address::from(&<[u8; 6]>::try_from(&item[2..8]).unwrap()) })
+    }
+}
+
+/// Additional information about a controller, usually sent once its address is known.
+#[derive(Debug, Clone)]
+pub struct IndexInfo {
+    pub address: Address,
+}
+
+impl TryFrom<&[u8]> for IndexInfo {
+    type Error = String;
+
+    fn try_from(item: &[u8]) -> Result<Self, Self::Error> {
+        if item.len() < MONITOR_INDEX_INFO_SIZE {
+            return Err(format!("IndexInfo too short: {} bytes", item.len()));
+        }
+
+        // The manufacturer follows the address.
+        Ok(IndexInfo { address: Address::from(&<[u8; 6]>::try_from(&item[0..6]).unwrap()) })
+    }
+}
+
+/// Opcode group of vendor specific commands.
+const VENDOR_OGF: u16 = 0x3F;
+
+const EVENT_COMMAND_COMPLETE: u8 = 0x0E;
+const EVENT_COMMAND_STATUS: u8 = 0x0F;
+const EVENT_VENDOR_SPECIFIC: u8 = 0xFF;
+
+pub fn is_vendor_opcode(opcode: u16) -> bool {
+    opcode >> 10 == VENDOR_OGF
+}
+
+/// Whether an HCI command is vendor specific.
+fn is_vendor_command(data: &[u8]) -> bool {
+    data.len() >= 3 && is_vendor_opcode(u16::from_le_bytes([data[0], data[1]]))
+}
+
+/// Whether an HCI event is about a vendor specific command.
+fn is_vendor_command_event(data: &[u8]) -> bool {
+    let opcode_at =
+        |offset: usize| data.get(offset..offset + 2).map(|o| u16::from_le_bytes([o[0], o[1]]));
+    match data.first() {
+        Some(&EVENT_COMMAND_COMPLETE) => opcode_at(3).is_some_and(is_vendor_opcode),
+        Some(&EVENT_COMMAND_STATUS) => opcode_at(4).is_some_and(is_vendor_opcode),
+        _ => false,
     }
 }
 
@@ -287,6 +1081,21 @@ pub enum PacketChild {
     HciEvent(Event),
     AclTx(Acl),
     AclRx(Acl),
+    ScoTx(Sco),
+    ScoRx(Sco),
+    IsoTx(Iso),
+    IsoRx(Iso),
+
+    /// Vendor specific command the HCI packet definitions can't parse, as sent.
+    RawVendorCommand(Vec<u8>),
+
+    /// Vendor specific event, or event about a vendor specific command the HCI packet definitions
+    /// can't parse, as received.
+    RawVendorEvent(Vec<u8>),
+
+    NewIndex(NewIndex),
+    DeleteIndex,
+    IndexInfo(IndexInfo),
 }
 
 impl<'a> TryFrom<&'a LinuxSnoopPacket> for PacketChild {
@@ -296,11 +1105,23 @@ impl<'a> TryFrom<&'a LinuxSnoopPacket> for PacketChild {
         match item.opcode() {
             LinuxSnoopOpcodes::Command => match Command::parse(item.data.as_slice()) {
                 Ok(command) => Ok(PacketChild::HciCommand(command)),
+                // Vendor specific opcodes are mostly unknown, leave them to the vendor decoders.
+                Err(_) if is_vendor_command(&item.data) => {
+                    Ok(PacketChild::RawVendorCommand(item.data.clone()))
+                }
                 Err(e) => Err(format!("Couldn't parse command: {:?}", e)),
             },
 
+            // Vendors reuse the subevent codes the HCI packet definitions assign to Android events,
+            // so those definitions would misparse them.
+            LinuxSnoopOpcodes::Event if item.data.first() == Some(&EVENT_VENDOR_SPECIFIC) => {
+                Ok(PacketChild::RawVendorEvent(item.data.clone()))
+            }
             LinuxSnoopOpcodes::Event => match Event::parse(item.data.as_slice()) {
                 Ok(event) => Ok(PacketChild::HciEvent(event)),
+                Err(_) if is_vendor_command_event(&item.data) => {
+                    Ok(PacketChild::RawVendorEvent(item.data.clone()))
+                }
                 Err(e) => Err(format!("Couldn't parse event: {:?}", e)),
             },
 
@@ -314,6 +1135,36 @@ impl<'a> TryFrom<&'a LinuxSnoopPacket> for PacketChild {
                 Err(e) => Err(format!("Couldn't parse acl rx: {:?}", e)),
             },
 
+            LinuxSnoopOpcodes::ScoTxPacket => match Sco::parse(item.data.as_slice()) {
+                Ok(data) => Ok(PacketChild::ScoTx(data)),
+                Err(e) => Err(format!("Couldn't parse sco tx: {:?}", e)),
+            },
+
+            LinuxSnoopOpcodes::ScoRxPacket => match Sco::parse(item.data.as_slice()) {
+                Ok(data) => Ok(PacketChild::ScoRx(data)),
+                Err(e) => Err(format!("Couldn't parse sco rx: {:?}", e)),
+            },
+
+            LinuxSnoopOpcodes::IsoTx => match Iso::parse(item.data.as_slice()) {
+                Ok(data) => Ok(PacketChild::IsoTx(data)),
+                Err(e) => Err(format!("Couldn't parse iso tx: {:?}", e)),
+            },
+
+            LinuxSnoopOpcodes::IsoRx => match Iso::parse(item.data.as_slice()) {
+                Ok(data) => Ok(PacketChild::IsoRx(data)),
+                Err(e) => Err(format!("Couldn't parse iso rx: {:?}", e)),
+            },
+
+            LinuxSnoopOpcodes::NewIndex => {
+                NewIndex::try_from(item.data.as_slice()).map(PacketChild::NewIndex)
+            }
+
+            LinuxSnoopOpcodes::DeleteIndex => Ok(PacketChild::DeleteIndex),
+
+            LinuxSnoopOpcodes::IndexInfo => {
+                IndexInfo::try_from(item.data.as_slice()).map(PacketChild::IndexInfo)
+            }
+
             // TODO(b/262928525) - Add packet handlers for more packet types.
             _ => Err(format!("Unhandled packet opcode: {:?}", item.opcode())),
         }
@@ -363,40 +1214,130 @@ impl<'a> TryFrom<(usize, &'a LinuxSnoopPacket)> for Packet {
 pub enum AclContent {
     Control(Control),
     LeControl(LeControl),
+    Att(Att),
+    Smp(SmpCommand),
     ConnectionlessData(u16, Vec<u8>),
-    StandardData(Vec<u8>),
+    StandardData(u16, Vec<u8>),
     None,
 }
 
 pub fn get_acl_content(acl: &Acl) -> AclContent {
     match acl.specialize() {
-        AclChild::Payload(bytes) => match BasicFrame::parse(bytes.as_ref()) {
-            Ok(bf) => match bf.specialize() {
-                BasicFrameChild::ControlFrame(cf) => match cf.specialize() {
-                    ControlFrameChild::Payload(p) => match Control::parse(p.as_ref()) {
-                        Ok(control) => AclContent::Control(control),
-                        Err(_) => AclContent::None,
-                    },
-                    _ => AclContent::None,
+        AclChild::Payload(bytes) => get_basic_frame_content(bytes.as_ref()),
+        _ => AclContent::None,
+    }
+}
+
+fn get_basic_frame_content(bytes: &[u8]) -> AclContent {
+    match BasicFrame::parse(bytes) {
+        Ok(bf) => match bf.specialize() {
+            BasicFrameChild::ControlFrame(cf) => match cf.specialize() {
+                ControlFrameChild::Payload(p) => match Control::parse(p.as_ref()) {
+                    Ok(control) => AclContent::Control(control),
+                    Err(_) => AclContent::None,
+                },
+                _ => AclContent::None,
+            },
+            BasicFrameChild::LeControlFrame(lcf) => match lcf.specialize() {
+                LeControlFrameChild::Payload(p) => match LeControl::parse(p.as_ref()) {
+                    Ok(le_control) => AclContent::LeControl(le_control),
+                    Err(_) => AclContent::None,
                 },
-                BasicFrameChild::LeControlFrame(lcf) => match lcf.specialize() {
-                    LeControlFrameChild::Payload(p) => match LeControl::parse(p.as_ref()) {
-                        Ok(le_control) => AclContent::LeControl(le_control),
-                        Err(_) => AclContent::None,
-                    },
-                    _ => AclContent::None,
+                _ => AclContent::None,
+            },
+            BasicFrameChild::AttFrame(af) => match af.specialize() {
+                AttFrameChild::Payload(p) => match Att::parse(p.as_ref()) {
+                    Ok(att) => AclContent::Att(att),
+                    Err(_) => AclContent::None,
                 },
-                BasicFrameChild::GroupFrame(gf) => match gf.specialize() {
-                    GroupFrameChild::Payload(p) => {
-                        AclContent::ConnectionlessData(gf.get_psm(), p.to_vec())
-                    }
-                    _ => AclContent::None,
+                _ => AclContent::None,
+            },
+            BasicFrameChild::SmpFrame(sf) => match sf.specialize() {
+                SmpFrameChild::Payload(p) => match SmpCommand::parse(p.as_ref()) {
+                    Ok(smp) => AclContent::Smp(smp),
+                    Err(_) => AclContent::None,
                 },
-                BasicFrameChild::Payload(p) => AclContent::StandardData(p.to_vec()),
                 _ => AclContent::None,
             },
-            Err(_) => AclContent::None,
+            BasicFrameChild::GroupFrame(gf) => match gf.specialize() {
+                GroupFrameChild::Payload(p) => {
+                    AclContent::ConnectionlessData(gf.get_psm(), p.to_vec())
+                }
+                _ => AclContent::None,
+            },
+            BasicFrameChild::Payload(p) => {
+                AclContent::StandardData(bf.get_channel_id(), p.to_vec())
+            }
+            _ => AclContent::None,
         },
-        _ => AclContent::None,
+        Err(_) => AclContent::None,
+    }
+}
+
+/// Size of the basic L2CAP header: payload length and channel id.
+const L2CAP_BASIC_HEADER_SIZE: usize = 4;
+
+/// Reassembles L2CAP frames split across several ACL packets, so that PDUs longer than a single
+/// ACL packet can still be parsed.
+#[derive(Default)]
+pub struct AclReassembler {
+    /// Start of frames still waiting for more fragments, keyed by connection handle and whether
+    /// the host sent them.
+    fragments: HashMap<(u16, bool), Vec<u8>>,
+}
+
+impl AclReassembler {
+    pub fn new() -> Self {
+        AclReassembler::default()
+    }
+
+    /// Same as `get_acl_content`, but for the whole frame an ACL packet completes. Returns
+    /// `AclContent::None` while more fragments are expected, and for fragments whose start
+    /// wasn't seen.
+    pub fn get_acl_content(&mut self, packet: &Packet) -> AclContent {
+        let (acl, from_host) = match &packet.inner {
+            PacketChild::AclTx(acl) => (acl, true),
+            PacketChild::AclRx(acl) => (acl, false),
+            _ => return AclContent::None,
+        };
+        let payload = match acl.specialize() {
+            AclChild::Payload(bytes) => bytes,
+            _ => return AclContent::None,
+        };
+
+        let key = (acl.get_handle(), from_host);
+        match acl.get_packet_boundary_flag() {
+            PacketBoundaryFlag::ContinuingFragment => match self.fragments.get_mut(&key) {
+                Some(frame) => frame.extend_from_slice(payload.as_ref()),
+                None => return AclContent::None,
+            },
+            // A new start drops any frame that was never completed.
+            _ => {
+                self.fragments.insert(key, payload.to_vec());
+            }
+        }
+
+        let frame = &self.fragments[&key];
+        if frame.len() < L2CAP_BASIC_HEADER_SIZE {
+            return AclContent::None;
+        }
+        let length = usize::from(u16::from_le_bytes([frame[0], frame[1]]));
+        if frame.len() < L2CAP_BASIC_HEADER_SIZE + length {
+            return AclContent::None;
+        }
+
+        match self.fragments.remove(&key) {
+            Some(frame) => get_basic_frame_content(&frame),
+            None => AclContent::None,
+        }
+    }
+
+    /// Drop incomplete frames of a link that went away.
+    pub fn remove_link(&mut self, handle: u16) {
+        self.fragments.retain(|(h, _), _| *h != handle);
+    }
+
+    pub fn clear(&mut self) {
+        self.fragments.clear();
     }
 }
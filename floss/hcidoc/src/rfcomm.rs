@@ -0,0 +1,80 @@
+//! Decodes RFCOMM frames (TS 07.10 as profiled by the Bluetooth RFCOMM spec) carried on L2CAP
+//! channels with the RFCOMM PSM.
+
+use std::convert::TryFrom;
+
+use crate::l2cap::Psm;
+
+pub const RFCOMM_PSM: Psm = 0x0003;
+
+/// Data link connection identifier. DLCI 0 is the multiplexer control channel.
+pub type Dlci = u8;
+
+/// Mask of the poll/final bit in the control field.
+const POLL_FINAL: u8 = 0x10;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RfcommFrameType {
+    /// Set Asynchronous Balanced Mode, opens a DLC.
+    Sabm,
+    /// Unnumbered Acknowledgement.
+    Ua,
+    /// Disconnected Mode, refuses a DLC.
+    Dm,
+    /// Disconnect, closes a DLC.
+    Disc,
+    /// Unnumbered Information with Header check, carries data.
+    Uih,
+}
+
+impl TryFrom<u8> for RfcommFrameType {
+    type Error = String;
+
+    fn try_from(control: u8) -> Result<Self, Self::Error> {
+        match control & !POLL_FINAL {
+            0x2F => Ok(RfcommFrameType::Sabm),
+            0x63 => Ok(RfcommFrameType::Ua),
+            0x0F => Ok(RfcommFrameType::Dm),
+            0x43 => Ok(RfcommFrameType::Disc),
+            0xEF => Ok(RfcommFrameType::Uih),
+            _ => Err(format!("Unknown RFCOMM control field 0x{:02x}", control)),
+        }
+    }
+}
+
+pub struct RfcommFrame {
+    pub dlci: Dlci,
+    pub frame_type: RfcommFrameType,
+    pub payload: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for RfcommFrame {
+    type Error = String;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < 4 {
+            return Err(format!("RFCOMM frame too short: {} bytes", data.len()));
+        }
+        let dlci = data[0] >> 2;
+        let frame_type = RfcommFrameType::try_from(data[1])?;
+
+        // The length takes a second byte when its EA bit is not set.
+        let (length, mut offset) = match data[2] & 0x01 {
+            1 => (usize::from(data[2] >> 1), 3),
+            _ => (usize::from(data[2] >> 1) | (usize::from(data[3]) << 7), 4),
+        };
+
+        // A UIH frame with the poll bit set on a data channel starts with a byte of credits for
+        // the other side.
+        if frame_type == RfcommFrameType::Uih && data[1] & POLL_FINAL != 0 && dlci != 0 {
+            offset += 1;
+        }
+
+        // The payload is followed by the frame check sequence.
+        let payload = data
+            .get(offset..offset + length)
+            .ok_or(format!("RFCOMM payload of {} bytes doesn't fit in the frame", length))?;
+
+        Ok(RfcommFrame { dlci, frame_type, payload: payload.to_vec() })
+    }
+}
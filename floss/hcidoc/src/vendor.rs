@@ -0,0 +1,384 @@
+//! Decodes vendor specific commands and events. HCI extensions and chip vendors each get their own
+//! decoder, and chip specific decoders only run on controllers from their manufacturer.
+
+use pdl_runtime::Packet as _;
+use std::convert::TryFrom;
+
+use crate::parser::{is_vendor_opcode, Packet, PacketChild};
+use bt_packets::hci::{Address, CommandCompleteChild, ErrorCode, EventChild};
+
+/// Company identifiers from the Bluetooth assigned numbers.
+const MANUFACTURER_INTEL: u16 = 0x0002;
+const MANUFACTURER_REALTEK: u16 = 0x005D;
+
+const EVENT_COMMAND_COMPLETE: u8 = 0x0E;
+const EVENT_VENDOR_SPECIFIC: u8 = 0xFF;
+
+/// Opcodes the MSFT extension is known to use, since it has no fixed opcode.
+const MSFT_OPCODES: [u16; 4] = [
+    0xFC1E, // Intel
+    0xFCF0, // Realtek
+    0xFD30, // MediaTek
+    0xFD70, // Qualcomm
+];
+
+const MSFT_READ_SUPPORTED_FEATURES: u8 = 0x00;
+const MSFT_LE_MONITOR_ADV: u8 = 0x03;
+const MSFT_LE_CANCEL_MONITOR_ADV: u8 = 0x04;
+const MSFT_LE_MONITOR_DEVICE_EVENT: u8 = 0x02;
+
+const AOSP_LE_GET_VENDOR_CAPABILITIES: u16 = 0xFD53;
+const AOSP_BQR_EVENT: u8 = 0x58;
+
+/// Report id of the periodic Bluetooth Quality Reports, the others are sent when a link degrades.
+pub const BQR_ID_MONITOR_MODE: u8 = 0x01;
+
+/// Intel diagnostics events start with this, followed by TLVs.
+const INTEL_DIAGNOSTICS_HEADER: [u8; 3] = [0x87, 0x80, 0x03];
+const INTEL_TLV_TYPE_ID: u8 = 0x01;
+
+const REALTEK_COREDUMP_EVENT: u8 = 0x34;
+
+/// Name of an HCI status code, or its value if it isn't known.
+pub fn get_status_name(status: u8) -> String {
+    match ErrorCode::try_from(status) {
+        Ok(code) => format!("{:?}", code),
+        Err(_) => format!("status 0x{:02x}", status),
+    }
+}
+
+/// Bytes of a vendor specific packet, split up the way all vendors share.
+pub enum VendorData {
+    /// Vendor specific command with its parameters.
+    Command { opcode: u16, params: Vec<u8> },
+
+    /// Command Complete of a vendor specific command with its return parameters.
+    CommandComplete { opcode: u16, params: Vec<u8> },
+
+    /// Parameters of a Vendor Specific event.
+    Event { params: Vec<u8> },
+}
+
+impl VendorData {
+    fn from_packet(packet: &Packet) -> Option<Self> {
+        let (bytes, is_command) = match &packet.inner {
+            PacketChild::HciCommand(cmd) if is_vendor_opcode(u16::from(cmd.get_op_code())) => {
+                (cmd.clone().to_vec(), true)
+            }
+            PacketChild::HciEvent(ev) => match ev.specialize() {
+                EventChild::CommandComplete(cc)
+                    if is_vendor_opcode(u16::from(cc.get_command_op_code())) =>
+                {
+                    (ev.clone().to_vec(), false)
+                }
+                _ => return None,
+            },
+            PacketChild::RawVendorCommand(bytes) => (bytes.clone(), true),
+            PacketChild::RawVendorEvent(bytes) => (bytes.clone(), false),
+            _ => return None,
+        };
+
+        if is_command {
+            let opcode = u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]);
+            return Some(VendorData::Command { opcode, params: bytes.get(3..)?.to_vec() });
+        }
+
+        match *bytes.first()? {
+            EVENT_VENDOR_SPECIFIC => Some(VendorData::Event { params: bytes.get(2..)?.to_vec() }),
+            EVENT_COMMAND_COMPLETE => {
+                let opcode = u16::from_le_bytes([*bytes.get(3)?, *bytes.get(4)?]);
+                Some(VendorData::CommandComplete { opcode, params: bytes.get(5..)?.to_vec() })
+            }
+            // Command Status only tells whether the command was accepted.
+            _ => None,
+        }
+    }
+}
+
+/// What a decoder understood from a vendor specific packet.
+pub enum VendorReport {
+    /// The MSFT extension is available on |opcode|, and its events start with |event_prefix|.
+    MsftFeatures { opcode: u16, features: u64, event_prefix: Vec<u8> },
+
+    /// Completion of MSFT LE Monitor Advertisement. The handle is only valid on success.
+    MsftMonitorAdded { status: u8, handle: Option<u8> },
+
+    /// Completion of MSFT LE Cancel Monitor Advertisement.
+    MsftMonitorCancelled { status: u8, handle: u8 },
+
+    /// A monitor started or stopped tracking a device.
+    MsftMonitorDevice { handle: u8, address: Address, tracking: bool },
+
+    /// Android vendor capabilities, see LeGetVendorCapabilitiesComplete.
+    AospCapabilities {
+        version: Option<u16>,
+        max_advertisers: u8,
+        max_filters: u8,
+        quality_reports: Option<bool>,
+    },
+
+    /// Bluetooth Quality Report about the link quality of a connection.
+    BqrLinkQuality {
+        report_id: u8,
+        reason: &'static str,
+        handle: u16,
+        rssi: i8,
+        snr: i8,
+        retransmissions: u32,
+        no_rx: u32,
+        naks: u32,
+    },
+
+    /// Bluetooth Quality Report about a fatal controller issue.
+    BqrRootInflammation { error_code: u8, vendor_error_code: u8 },
+
+    /// Bluetooth Quality Report carrying a vendor log dump.
+    BqrLogDump { reason: &'static str },
+
+    /// The controller firmware crashed or dumped its state.
+    FirmwareException { vendor: &'static str, description: String },
+
+    /// Vendor debug or telemetry data hcidoc doesn't interpret further.
+    Telemetry { vendor: &'static str },
+}
+
+/// Decodes the vendor specific packets of one HCI extension or chip vendor. To support a new one,
+/// implement this and add it to |VendorDecoders::new|.
+pub trait VendorDecoder {
+    /// Manufacturer whose controllers use these packets. Extensions that any controller may
+    /// implement return None.
+    fn manufacturer(&self) -> Option<u16> {
+        None
+    }
+
+    /// Decode a packet, or return None if it doesn't belong to this decoder.
+    fn decode(&mut self, data: &VendorData) -> Option<VendorReport>;
+}
+
+/// Microsoft defined HCI commands and events.
+#[derive(Default)]
+struct MsftDecoder {
+    /// Learnt from MSFT Read Supported Features, MSFT events can't be told apart without it.
+    event_prefix: Option<Vec<u8>>,
+
+    /// Handle of the monitor being cancelled, since the completion doesn't repeat it.
+    cancelling: Option<u8>,
+}
+
+impl VendorDecoder for MsftDecoder {
+    fn decode(&mut self, data: &VendorData) -> Option<VendorReport> {
+        match data {
+            VendorData::Command { opcode, params } if MSFT_OPCODES.contains(opcode) => {
+                if params.first() == Some(&MSFT_LE_CANCEL_MONITOR_ADV) {
+                    self.cancelling = params.get(1).copied();
+                }
+                None
+            }
+
+            VendorData::CommandComplete { opcode, params } if MSFT_OPCODES.contains(opcode) => {
+                let status = *params.first()?;
+                match *params.get(1)? {
+                    MSFT_READ_SUPPORTED_FEATURES if status == 0 => {
+                        let features = u64::from_le_bytes(params.get(2..10)?.try_into().ok()?);
+                        let prefix_len = usize::from(*params.get(10)?);
+                        let event_prefix = params.get(11..11 + prefix_len)?.to_vec();
+                        self.event_prefix = Some(event_prefix.clone());
+                        Some(VendorReport::MsftFeatures { opcode: *opcode, features, event_prefix })
+                    }
+                    MSFT_LE_MONITOR_ADV => Some(VendorReport::MsftMonitorAdded {
+                        status,
+                        handle: params.get(2).copied().filter(|_| status == 0),
+                    }),
+                    MSFT_LE_CANCEL_MONITOR_ADV => Some(VendorReport::MsftMonitorCancelled {
+                        status,
+                        handle: self.cancelling.take()?,
+                    }),
+                    _ => None,
+                }
+            }
+
+            VendorData::Event { params } => {
+                let prefix = self.event_prefix.as_ref()?;
+                let event = params.strip_prefix(prefix.as_slice())?;
+                // Event code, address type, address, monitor handle and monitor state.
+                if event.len() < 10 || event[0] != MSFT_LE_MONITOR_DEVICE_EVENT {
+                    return None;
+                }
+                Some(VendorReport::MsftMonitorDevice {
+                    handle: event[8],
+                    address: Address::from(&<[u8; 6]>::try_from(&event[2..8]).ok()?),
+                    tracking: event[9] == 1,
+                })
+            }
+
+            _ => None,
+        }
+    }
+}
+
+/// Android vendor commands and events, see the vendor section of hci_packets.pdl.
+struct AospDecoder;
+
+impl AospDecoder {
+    fn get_bqr_reason(report_id: u8) -> Option<&'static str> {
+        match report_id {
+            BQR_ID_MONITOR_MODE => Some("monitor mode"),
+            0x02 => Some("approaching link supervision timeout"),
+            0x03 => Some("A2DP audio choppy"),
+            0x04 => Some("SCO voice choppy"),
+            0x11 => Some("LMP/LL message trace"),
+            0x12 => Some("scheduling trace"),
+            0x13 => Some("controller debug info"),
+            _ => None,
+        }
+    }
+}
+
+impl VendorDecoder for AospDecoder {
+    fn decode(&mut self, data: &VendorData) -> Option<VendorReport> {
+        let u32_at = |params: &[u8], offset: usize| {
+            params.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        match data {
+            VendorData::CommandComplete { opcode: AOSP_LE_GET_VENDOR_CAPABILITIES, params } => {
+                if *params.first()? != 0 {
+                    return None;
+                }
+                // Later versions only append to the base capabilities.
+                Some(VendorReport::AospCapabilities {
+                    version: params.get(9..11).map(|v| u16::from_le_bytes([v[0], v[1]])),
+                    max_advertisers: *params.get(1)?,
+                    max_filters: *params.get(7)?,
+                    quality_reports: params.get(20).map(|b| *b != 0),
+                })
+            }
+
+            VendorData::Event { params } if params.first() == Some(&AOSP_BQR_EVENT) => {
+                let report_id = *params.get(1)?;
+                match report_id {
+                    // Link quality reports start with the packet type, handle and role.
+                    0x01..=0x04 => Some(VendorReport::BqrLinkQuality {
+                        report_id,
+                        reason: Self::get_bqr_reason(report_id)?,
+                        handle: u16::from_le_bytes([*params.get(3)?, *params.get(4)?]) & 0x0FFF,
+                        rssi: *params.get(7)? as i8,
+                        snr: *params.get(8)? as i8,
+                        retransmissions: u32_at(params, 17)?,
+                        no_rx: u32_at(params, 21)?,
+                        naks: u32_at(params, 25)?,
+                    }),
+                    0x05 => Some(VendorReport::BqrRootInflammation {
+                        error_code: *params.get(2)?,
+                        vendor_error_code: *params.get(3)?,
+                    }),
+                    0x11..=0x13 => {
+                        Some(VendorReport::BqrLogDump { reason: Self::get_bqr_reason(report_id)? })
+                    }
+                    _ => None,
+                }
+            }
+
+            _ => None,
+        }
+    }
+}
+
+/// Intel diagnostics events, which carry firmware exceptions and telemetry.
+struct IntelDecoder;
+
+impl VendorDecoder for IntelDecoder {
+    fn manufacturer(&self) -> Option<u16> {
+        Some(MANUFACTURER_INTEL)
+    }
+
+    fn decode(&mut self, data: &VendorData) -> Option<VendorReport> {
+        let params = match data {
+            VendorData::Event { params } => params.strip_prefix(&INTEL_DIAGNOSTICS_HEADER)?,
+            _ => return None,
+        };
+
+        // The first TLV tells what kind of diagnostics follow.
+        let description = match (params.first(), params.get(2)) {
+            (Some(&INTEL_TLV_TYPE_ID), Some(0x00)) => "system exception",
+            (Some(&INTEL_TLV_TYPE_ID), Some(0x01)) => "fatal exception",
+            (Some(&INTEL_TLV_TYPE_ID), Some(0x02)) => "debug exception",
+            (Some(&INTEL_TLV_TYPE_ID), Some(0xDE)) => "test exception",
+            _ => return Some(VendorReport::Telemetry { vendor: "Intel" }),
+        };
+        Some(VendorReport::FirmwareException {
+            vendor: "Intel",
+            description: description.to_string(),
+        })
+    }
+}
+
+/// Realtek vendor events.
+struct RealtekDecoder;
+
+impl VendorDecoder for RealtekDecoder {
+    fn manufacturer(&self) -> Option<u16> {
+        Some(MANUFACTURER_REALTEK)
+    }
+
+    fn decode(&mut self, data: &VendorData) -> Option<VendorReport> {
+        match data {
+            VendorData::Event { params } if params.first() == Some(&REALTEK_COREDUMP_EVENT) => {
+                Some(VendorReport::FirmwareException {
+                    vendor: "Realtek",
+                    description: "firmware core dump".to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Runs the vendor decoders that apply to a controller.
+pub struct VendorDecoders {
+    decoders: Vec<Box<dyn VendorDecoder>>,
+
+    /// Manufacturer of the controller, once Read Local Version Information was answered. Until
+    /// then only the extension decoders are tried, chip vendors reuse the same event codes.
+    manufacturer: Option<u16>,
+}
+
+impl VendorDecoders {
+    pub fn new() -> Self {
+        VendorDecoders {
+            decoders: vec![
+                Box::new(MsftDecoder::default()),
+                Box::new(AospDecoder),
+                Box::new(IntelDecoder),
+                Box::new(RealtekDecoder),
+            ],
+            manufacturer: None,
+        }
+    }
+
+    /// Decode |packet| if it is vendor specific and one of the decoders understands it.
+    pub fn decode(&mut self, packet: &Packet) -> Option<VendorReport> {
+        if let PacketChild::HciEvent(ev) = &packet.inner {
+            if let EventChild::CommandComplete(cc) = ev.specialize() {
+                if let CommandCompleteChild::ReadLocalVersionInformationComplete(rsp) =
+                    cc.specialize()
+                {
+                    if rsp.get_status() == ErrorCode::Success {
+                        self.manufacturer =
+                            Some(rsp.get_local_version_information().manufacturer_name);
+                    }
+                }
+            }
+        }
+
+        let data = VendorData::from_packet(packet)?;
+        let manufacturer = self.manufacturer;
+        self.decoders
+            .iter_mut()
+            .filter(|decoder| match decoder.manufacturer() {
+                Some(expected) => manufacturer == Some(expected),
+                None => true,
+            })
+            .find_map(|decoder| decoder.decode(&data))
+    }
+}
@@ -16,7 +16,7 @@ use super::{
     ids::{AdvertiserId, TransportIndex},
     mtu::MtuEvent,
     opcode_types::{classify_opcode, OperationType},
-    server::isolation_manager::IsolationManager,
+    server::{isolation_manager::IsolationManager, AttLinkSecurity},
 };
 
 static ARBITER: RwLock<Option<Arc<Mutex<IsolationManager>>>> = RwLock::new(None);
@@ -35,6 +35,8 @@ pub fn initialize_arbiter() -> Arc<Mutex<IsolationManager>> {
         |tcb_idx| on_mtu_event(TransportIndex(tcb_idx), MtuEvent::OutgoingRequest),
         |tcb_idx, mtu| on_mtu_event(TransportIndex(tcb_idx), MtuEvent::IncomingResponse(mtu)),
         |tcb_idx, mtu| on_mtu_event(TransportIndex(tcb_idx), MtuEvent::IncomingRequest(mtu)),
+        |tcb_idx, congested| on_congestion_change(TransportIndex(tcb_idx), congested),
+        on_security_change,
     );
 
     arbiter
@@ -154,6 +156,45 @@ fn on_mtu_event(tcb_idx: TransportIndex, event: MtuEvent) {
     }
 }
 
+fn on_congestion_change(tcb_idx: TransportIndex, congested: bool) {
+    if with_arbiter(|arbiter| arbiter.is_connection_isolated(tcb_idx)) {
+        do_in_rust_thread(move |modules| {
+            let Some(bearer) = modules.gatt_module.get_bearer(tcb_idx) else {
+                error!("Bearer for {tcb_idx:?} not found");
+                return;
+            };
+            bearer.handle_congestion_event(congested);
+        });
+    }
+}
+
+fn on_security_change(
+    tcb_idx: u8,
+    encrypted: bool,
+    authenticated: bool,
+    key_size: u8,
+    link_key_known: bool,
+) {
+    let tcb_idx = TransportIndex(tcb_idx);
+    if with_arbiter(|arbiter| arbiter.is_connection_isolated(tcb_idx)) {
+        do_in_rust_thread(move |modules| {
+            let Some(bearer) = modules.gatt_module.get_bearer(tcb_idx) else {
+                error!("Bearer for {tcb_idx:?} not found");
+                return;
+            };
+            // authorization is granted by the upper layer, not the link, so keep it
+            let authorized = bearer.link_security().authorized;
+            bearer.set_link_security(AttLinkSecurity {
+                encrypted,
+                authenticated,
+                key_size,
+                link_key_known,
+                authorized,
+            });
+        });
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -14,7 +14,7 @@ use crate::packets::{AttAttributeDataChild, AttAttributeDataView, AttErrorCode};
 use super::{
     ffi::AttributeBackingType,
     ids::{AttHandle, ConnectionId, TransactionId, TransportIndex},
-    server::IndicationError,
+    server::{IndicationError, NotificationError},
 };
 
 /// These callbacks are expected to be made available to the GattModule from
@@ -52,6 +52,10 @@ pub trait GattCallbacks {
         result: Result<(), IndicationError>,
     );
 
+    /// Invoked when a handle value notification has been sent (or has failed
+    /// to send)
+    fn on_notification_sent(&self, conn_id: ConnectionId, result: Result<(), NotificationError>);
+
     /// Execute or cancel any prepared writes
     fn on_execute(
         &self,
@@ -0,0 +1,134 @@
+//! The L2CAP channel underlying an ATT bearer becomes congested if we send
+//! packets faster than the controller can drain them. While it is congested,
+//! notifications must be held back, since (unlike indications) nothing else
+//! paces them.
+
+use std::{cell::Cell, future::Future};
+
+use log::{info, warn};
+use tokio::sync::OwnedMutexGuard;
+
+use crate::core::shared_mutex::SharedMutex;
+
+/// The congestion state of the channel underlying an ATT bearer
+pub struct AttCongestion {
+    /// Held by pending_congestion while the channel is congested
+    uncongested: SharedMutex<()>,
+    /// Lock guard held while the channel is congested
+    pending_congestion: Cell<Option<OwnedMutexGuard<()>>>,
+}
+
+impl AttCongestion {
+    /// Constructor
+    pub fn new() -> Self {
+        Self { uncongested: SharedMutex::new(()), pending_congestion: Cell::new(None) }
+    }
+
+    /// Wait until the channel is no longer congested. Resolves to None if this
+    /// object is dropped (i.e. the connection goes away) while waiting.
+    pub fn wait_until_uncongested(&self) -> impl Future<Output = Option<()>> {
+        let pending_uncongested = self.uncongested.lock();
+        async move { pending_uncongested.await.map(drop) }
+    }
+
+    /// Handle a congestion event from the underlying channel
+    pub fn handle_event(&self, congested: bool) {
+        if !congested {
+            if self.pending_congestion.take().is_some() {
+                info!("Channel is no longer congested, resuming notifications");
+            }
+            return;
+        }
+        // waiters only hold the lock momentarily, so if we cannot take it, the
+        // channel must already be marked as congested
+        match self.uncongested.try_lock() {
+            Ok(guard) => {
+                info!("Channel is congested, pausing notifications");
+                self.pending_congestion.replace(Some(guard));
+            }
+            Err(_) => warn!("Channel is already congested"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::task::{block_on_locally, try_await};
+
+    use super::*;
+
+    #[test]
+    fn test_initially_uncongested() {
+        block_on_locally(async move {
+            let congestion = AttCongestion::new();
+
+            let uncongested = try_await(congestion.wait_until_uncongested()).await;
+
+            assert!(matches!(uncongested, Ok(Some(()))));
+        });
+    }
+
+    #[test]
+    fn test_wait_while_congested() {
+        block_on_locally(async move {
+            // arrange
+            let congestion = AttCongestion::new();
+
+            // act: mark as congested, then try to wait
+            congestion.handle_event(true);
+            let pending = try_await(congestion.wait_until_uncongested()).await;
+
+            // assert: we are blocked
+            assert!(pending.is_err());
+        });
+    }
+
+    #[test]
+    fn test_resume_when_uncongested() {
+        block_on_locally(async move {
+            // arrange: a waiter blocked on congestion
+            let congestion = AttCongestion::new();
+            congestion.handle_event(true);
+            let pending = try_await(congestion.wait_until_uncongested()).await.unwrap_err();
+
+            // act: clear the congestion
+            congestion.handle_event(false);
+
+            // assert: the waiter is unblocked
+            assert_eq!(pending.await, Some(()));
+        });
+    }
+
+    #[test]
+    fn test_repeated_congestion_events() {
+        block_on_locally(async move {
+            // arrange
+            let congestion = AttCongestion::new();
+
+            // act: mark as congested twice, then clear it once
+            congestion.handle_event(true);
+            congestion.handle_event(true);
+            congestion.handle_event(false);
+
+            // assert: we are no longer congested
+            let uncongested = try_await(congestion.wait_until_uncongested()).await;
+            assert!(matches!(uncongested, Ok(Some(()))));
+        });
+    }
+
+    #[test]
+    fn test_unblock_on_drop() {
+        block_on_locally(async move {
+            // arrange: a waiter blocked on congestion
+            let congestion = AttCongestion::new();
+            congestion.handle_event(true);
+            let pending = try_await(congestion.wait_until_uncongested()).await.unwrap_err();
+
+            // act: drop the congestion state (as would happen upon a disconnection)
+            drop(congestion);
+
+            // assert: the waiter is unblocked
+            assert_eq!(pending.await, None);
+        });
+    }
+}
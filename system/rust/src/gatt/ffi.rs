@@ -30,7 +30,7 @@ use super::{
             AttPermissions, GattCharacteristicWithHandle, GattDescriptorWithHandle,
             GattServiceWithHandle,
         },
-        IndicationError,
+        IndicationError, NotificationError,
     },
     GattCallbacks,
 };
@@ -107,6 +107,11 @@ mod inner {
         /// peer device has confirmed it, or if some error occurred.
         #[cxx_name = "OnIndicationSentConfirmation"]
         fn on_indication_sent_confirmation(self: &GattServerCallbacks, conn_id: u16, status: i32);
+
+        /// This callback is invoked when a notification has been sent, or if
+        /// some error occurred.
+        #[cxx_name = "OnNotificationSent"]
+        fn on_notification_sent(self: &GattServerCallbacks, conn_id: u16, status: i32);
     }
 
     /// What action the arbiter should take in response to an incoming packet
@@ -158,6 +163,14 @@ mod inner {
             on_outgoing_mtu_req: fn(tcb_idx: u8),
             on_incoming_mtu_resp: fn(tcb_idx: u8, mtu: usize),
             on_incoming_mtu_req: fn(tcb_idx: u8, mtu: usize),
+            on_congestion_change: fn(tcb_idx: u8, congested: bool),
+            on_security_change: fn(
+                tcb_idx: u8,
+                encrypted: bool,
+                authenticated: bool,
+                key_size: u8,
+                link_key_known: bool,
+            ),
         );
 
         /// Send an outgoing packet on the specified tcb_idx
@@ -175,6 +188,7 @@ mod inner {
         // att operations
         fn send_response(server_id: u8, conn_id: u16, trans_id: u32, status: u8, value: &[u8]);
         fn send_indication(_server_id: u8, handle: u16, conn_id: u16, value: &[u8]);
+        fn send_notification(_server_id: u8, handle: u16, conn_id: u16, value: &[u8]);
 
         // connection
         fn is_connection_isolated(conn_id: u16) -> bool;
@@ -250,6 +264,17 @@ impl GattCallbacks for GattCallbacksImpl {
         )
     }
 
+    fn on_notification_sent(&self, conn_id: ConnectionId, result: Result<(), NotificationError>) {
+        trace!("on_notification_sent ({conn_id:?}, {result:?}");
+        self.0.as_ref().unwrap().on_notification_sent(
+            conn_id.0,
+            match result {
+                Ok(()) => 0, // GATT_SUCCESS
+                _ => 133,    // GATT_ERROR
+            },
+        )
+    }
+
     fn on_execute(
         &self,
         conn_id: ConnectionId,
@@ -323,9 +348,9 @@ fn consume_descriptors<'a>(
     while let Some(GattRecord { uuid, attribute_handle, permissions, .. }) =
         records.next_if(|record| record.record_type == GattRecordType::Descriptor)
     {
-        let mut att_permissions = AttPermissions::empty();
-        att_permissions.set(AttPermissions::READABLE, permissions & 0x01 != 0);
-        att_permissions.set(AttPermissions::WRITABLE_WITH_RESPONSE, permissions & 0x10 != 0);
+        let mut att_permissions = security_requirements(*permissions);
+        att_permissions.set(AttPermissions::READABLE, permissions & 0x07 != 0);
+        att_permissions.set(AttPermissions::WRITABLE_WITH_RESPONSE, permissions & 0x70 != 0);
 
         out.push(GattDescriptorWithHandle {
             handle: AttHandle(*attribute_handle),
@@ -336,6 +361,23 @@ fn consume_descriptors<'a>(
     out
 }
 
+/// Convert the security bits of a legacy tGATT_PERM into the corresponding
+/// AttPermissions (the access bits themselves are handled by the caller)
+fn security_requirements(permissions: u16) -> AttPermissions {
+    let mut att_permissions = AttPermissions::empty();
+    // GATT_PERM_READ_ENCRYPTED and GATT_PERM_READ_ENC_MITM
+    att_permissions.set(AttPermissions::READ_ENCRYPTED, permissions & 0x02 != 0);
+    att_permissions.set(AttPermissions::READ_AUTHENTICATED, permissions & 0x04 != 0);
+    // GATT_PERM_WRITE_ENCRYPTED and GATT_PERM_WRITE_ENC_MITM
+    att_permissions.set(AttPermissions::WRITE_ENCRYPTED, permissions & 0x20 != 0);
+    att_permissions.set(AttPermissions::WRITE_AUTHENTICATED, permissions & 0x40 != 0);
+    // GATT_ENCRYPT_KEY_SIZE_MASK holds the key size minus 6, if set
+    match (permissions & 0xF000) >> 12 {
+        0 => att_permissions,
+        key_size => att_permissions.with_min_key_size(key_size as u8 + 6),
+    }
+}
+
 fn records_to_service(service_records: &[GattRecord]) -> Result<GattServiceWithHandle> {
     let mut characteristics = vec![];
     let mut service_handle_uuid = None;
@@ -354,7 +396,8 @@ fn records_to_service(service_records: &[GattRecord]) -> Result<GattServiceWithH
                 characteristics.push(GattCharacteristicWithHandle {
                     handle: AttHandle(record.attribute_handle),
                     type_: record.uuid,
-                    permissions: AttPermissions::from_bits_truncate(record.properties),
+                    permissions: AttPermissions::from_bits_truncate(record.properties.into())
+                        | security_requirements(record.permissions),
                     descriptors: consume_descriptors(&mut service_records),
                 });
             }
@@ -487,6 +530,30 @@ fn send_indication(_server_id: u8, handle: u16, conn_id: u16, value: &[u8]) {
     })
 }
 
+fn send_notification(_server_id: u8, handle: u16, conn_id: u16, value: &[u8]) {
+    if !rust_event_loop_is_enabled() {
+        return;
+    }
+
+    let handle = AttHandle(handle);
+    let conn_id = ConnectionId(conn_id);
+    let value = AttAttributeDataChild::RawData(value.into());
+
+    trace!("send_notification {handle:?}, {conn_id:?}");
+
+    do_in_rust_thread(move |modules| {
+        let Some(bearer) = modules.gatt_module.get_bearer(conn_id.get_tcb_idx()) else {
+            error!("connection {conn_id:?} does not exist");
+            return;
+        };
+        let pending_notification = bearer.send_notification(handle, value);
+        let gatt_outgoing_callbacks = modules.gatt_outgoing_callbacks.clone();
+        spawn_local(async move {
+            gatt_outgoing_callbacks.on_notification_sent(conn_id, pending_notification.await);
+        });
+    })
+}
+
 fn associate_server_with_advertiser(server_id: u8, advertiser_id: u8) {
     if !rust_event_loop_is_enabled() {
         return;
@@ -700,6 +767,53 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_characteristic_security_permissions() {
+        let service = records_to_service(&[
+            make_service_record(SERVICE_UUID, SERVICE_HANDLE),
+            GattRecord {
+                // GATT_PERM_READ_ENCRYPTED | GATT_PERM_WRITE_ENC_MITM, with a 16 byte key
+                permissions: 0x02 | 0x40 | 0xA000,
+                ..make_characteristic_record(
+                    CHARACTERISTIC_UUID,
+                    CHARACTERISTIC_HANDLE,
+                    0x02 | 0x08,
+                )
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            service.characteristics[0].permissions,
+            (AttPermissions::READABLE
+                | AttPermissions::WRITABLE_WITH_RESPONSE
+                | AttPermissions::READ_ENCRYPTED
+                | AttPermissions::WRITE_AUTHENTICATED)
+                .with_min_key_size(16)
+        );
+    }
+
+    #[test]
+    fn test_descriptor_security_permissions() {
+        let service = records_to_service(&[
+            make_service_record(SERVICE_UUID, AttHandle(1)),
+            make_characteristic_record(CHARACTERISTIC_UUID, AttHandle(2), 0),
+            make_descriptor_record(DESCRIPTOR_UUID, AttHandle(3), 0x04),
+            make_descriptor_record(DESCRIPTOR_UUID, AttHandle(4), 0x20 | 0x1000),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            service.characteristics[0].descriptors[0].permissions,
+            AttPermissions::READABLE | AttPermissions::READ_AUTHENTICATED
+        );
+        assert_eq!(
+            service.characteristics[0].descriptors[1].permissions,
+            (AttPermissions::WRITABLE_WITH_RESPONSE | AttPermissions::WRITE_ENCRYPTED)
+                .with_min_key_size(7)
+        );
+    }
+
     #[test]
     fn test_descriptors_multiple_characteristics() {
         let service = records_to_service(&[
@@ -123,6 +123,14 @@ void GattServerCallbacks::OnIndicationSentConfirmation(uint16_t conn_id,
       FROM_HERE, base::BindOnce(callbacks.indication_sent_cb, conn_id, status));
 }
 
+void GattServerCallbacks::OnNotificationSent(uint16_t conn_id,
+                                             int status) const {
+  // notifications are reported to the upper layer through the same callback
+  // as indications
+  do_in_jni_thread(
+      FROM_HERE, base::BindOnce(callbacks.indication_sent_cb, conn_id, status));
+}
+
 void GattServerCallbacks::OnExecute(uint16_t conn_id, uint32_t trans_id,
                                     bool execute) const {
   auto addr = AddressOfConnection(conn_id);
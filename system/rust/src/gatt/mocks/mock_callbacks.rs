@@ -5,7 +5,7 @@ use crate::{
         callbacks::{GattWriteType, TransactionDecision},
         ffi::AttributeBackingType,
         ids::{AttHandle, ConnectionId, TransactionId},
-        server::IndicationError,
+        server::{IndicationError, NotificationError},
         GattCallbacks,
     },
     packets::{AttAttributeDataView, OwnedAttAttributeDataView, Packet},
@@ -39,6 +39,8 @@ pub enum MockCallbackEvents {
     ),
     /// GattCallbacks#on_indication_sent_confirmation invoked
     OnIndicationSentConfirmation(ConnectionId, Result<(), IndicationError>),
+    /// GattCallbacks#on_notification_sent invoked
+    OnNotificationSent(ConnectionId, Result<(), NotificationError>),
     /// GattCallbacks#on_execute invoked
     OnExecute(ConnectionId, TransactionId, TransactionDecision),
 }
@@ -86,6 +88,10 @@ impl GattCallbacks for MockCallbacks {
         self.0.send(MockCallbackEvents::OnIndicationSentConfirmation(conn_id, result)).unwrap();
     }
 
+    fn on_notification_sent(&self, conn_id: ConnectionId, result: Result<(), NotificationError>) {
+        self.0.send(MockCallbackEvents::OnNotificationSent(conn_id, result)).unwrap();
+    }
+
     fn on_execute(
         &self,
         conn_id: ConnectionId,
@@ -5,6 +5,7 @@ mod att_database;
 pub mod att_server_bearer;
 pub mod gatt_database;
 mod indication_handler;
+mod notification_handler;
 mod request_handler;
 pub mod services;
 mod transactions;
@@ -42,7 +43,9 @@ use anyhow::{anyhow, bail, Result};
 use bt_common::init_flags::always_use_private_gatt_for_debugging_is_enabled;
 use log::info;
 
+pub use att_database::AttLinkSecurity;
 pub use indication_handler::IndicationError;
+pub use notification_handler::NotificationError;
 
 #[allow(missing_docs)]
 pub struct GattModule {
@@ -6,6 +6,7 @@ use crate::{
     gatt::ids::AttHandle,
     packets::{
         AttAttributeDataChild, AttAttributeDataView, AttErrorCode, AttHandleBuilder, AttHandleView,
+        OwnedAttAttributeDataView, Serializable,
     },
 };
 
@@ -32,18 +33,40 @@ bitflags! {
     /// The attribute properties supported by the current GATT server implementation
     /// Unimplemented properties will default to false.
     ///
-    /// These values are from Core Spec 5.3 Vol 3G 3.3.1.1 Characteristic Properties,
-    /// and also match what Android uses in JNI.
+    /// The low byte holds the values from Core Spec 5.3 Vol 3G 3.3.1.1
+    /// Characteristic Properties, which also match what Android uses in JNI.
+    /// The remaining bits describe the security the link must have before the
+    /// attribute value can be accessed (Core Spec 5.3 Vol 3F 3.2.5 Attribute
+    /// Permissions).
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-    pub struct AttPermissions : u8 {
+    pub struct AttPermissions : u32 {
         /// Attribute can be read using READ_REQ
         const READABLE = 0x02;
         /// Attribute can be written to using WRITE_CMD
         const WRITABLE_WITHOUT_RESPONSE = 0x04;
         /// Attribute can be written to using WRITE_REQ
         const WRITABLE_WITH_RESPONSE = 0x08;
+        /// Attribute value may be sent using notifications
+        const NOTIFY = 0x10;
         /// Attribute value may be sent using indications
         const INDICATE = 0x20;
+        /// Reading the attribute requires an encrypted link
+        const READ_ENCRYPTED = 0x0100;
+        /// Reading the attribute requires an encrypted link, using a key
+        /// generated with MITM protection
+        const READ_AUTHENTICATED = 0x0200;
+        /// Reading the attribute requires the peer to be authorized
+        const READ_AUTHORIZED = 0x0400;
+        /// Writing the attribute requires an encrypted link
+        const WRITE_ENCRYPTED = 0x0800;
+        /// Writing the attribute requires an encrypted link, using a key
+        /// generated with MITM protection
+        const WRITE_AUTHENTICATED = 0x1000;
+        /// Writing the attribute requires the peer to be authorized
+        const WRITE_AUTHORIZED = 0x2000;
+        /// The minimum encryption key size (in bytes) needed to access the
+        /// attribute, see min_key_size()
+        const MIN_KEY_SIZE = 0x1F_0000;
     }
 }
 
@@ -60,18 +83,137 @@ impl AttPermissions {
     pub fn writable_without_response(&self) -> bool {
         self.contains(AttPermissions::WRITABLE_WITHOUT_RESPONSE)
     }
+    /// Attribute value may be sent using notifications
+    pub fn notify(&self) -> bool {
+        self.contains(AttPermissions::NOTIFY)
+    }
     /// Attribute value may be sent using indications
     pub fn indicate(&self) -> bool {
         self.contains(AttPermissions::INDICATE)
     }
+
+    /// The minimum encryption key size (in bytes) needed to access the
+    /// attribute, or 0 if there is no such requirement
+    pub fn min_key_size(&self) -> u8 {
+        ((*self & AttPermissions::MIN_KEY_SIZE).bits() >> 16) as u8
+    }
+
+    /// Require the link to be encrypted with a key of at least the given size
+    /// (in bytes) before the attribute can be accessed. Valid key sizes are
+    /// 7 to 16 bytes (Core Spec 5.3 Vol 3H 2.3.4).
+    pub fn with_min_key_size(self, key_size: u8) -> Self {
+        self.difference(AttPermissions::MIN_KEY_SIZE)
+            | AttPermissions::from_bits_truncate((key_size as u32) << 16)
+    }
+
+    /// Check that a link with the given security state may read this attribute
+    pub fn check_read_security(&self, security: &AttLinkSecurity) -> Result<(), AttErrorCode> {
+        self.check_security(
+            security,
+            AttPermissions::READ_ENCRYPTED,
+            AttPermissions::READ_AUTHENTICATED,
+            AttPermissions::READ_AUTHORIZED,
+        )
+    }
+
+    /// Check that a link with the given security state may write this attribute
+    pub fn check_write_security(&self, security: &AttLinkSecurity) -> Result<(), AttErrorCode> {
+        self.check_security(
+            security,
+            AttPermissions::WRITE_ENCRYPTED,
+            AttPermissions::WRITE_AUTHENTICATED,
+            AttPermissions::WRITE_AUTHORIZED,
+        )
+    }
+
+    fn check_security(
+        &self,
+        security: &AttLinkSecurity,
+        encrypted: AttPermissions,
+        authenticated: AttPermissions,
+        authorized: AttPermissions,
+    ) -> Result<(), AttErrorCode> {
+        let needs_authentication = self.contains(authenticated);
+        let needs_encryption =
+            needs_authentication || self.contains(encrypted) || self.min_key_size() != 0;
+
+        if needs_encryption && !security.encrypted {
+            // as per 5.3 3C 10.3.2, a peer we already share a key with only needs to
+            // encrypt the link, while any other peer needs to pair first
+            return Err(if security.link_key_known {
+                AttErrorCode::INSUFFICIENT_ENCRYPTION
+            } else {
+                AttErrorCode::INSUFFICIENT_AUTHENTICATION
+            });
+        }
+        if needs_authentication && !security.authenticated {
+            return Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION);
+        }
+        if needs_encryption && security.key_size < self.min_key_size() {
+            return Err(AttErrorCode::INSUFFICIENT_ENCRYPTION_KEY_SIZE);
+        }
+        if self.contains(authorized) && !security.authorized {
+            return Err(AttErrorCode::INSUFFICIENT_AUTHORIZATION);
+        }
+        Ok(())
+    }
+}
+
+/// The security state of the link underlying an ATT bearer, against which
+/// attribute security requirements are checked
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttLinkSecurity {
+    /// The link is encrypted
+    pub encrypted: bool,
+    /// The link is encrypted with a key generated with MITM protection
+    pub authenticated: bool,
+    /// The size (in bytes) of the key encrypting the link
+    pub key_size: u8,
+    /// We share a key with the peer (i.e. it is bonded), so it can encrypt the
+    /// link without pairing again
+    pub link_key_known: bool,
+    /// The upper layer has authorized the peer to access attributes that
+    /// require authorization
+    pub authorized: bool,
+}
+
+/// Skip the first `offset` bytes of an attribute value, for databases that hold
+/// the whole value and need to serve a read starting partway through it.
+///
+/// As per 5.3 3F 3.4.4.5 ATT_READ_BLOB_REQ, an offset past the end of the value
+/// is rejected with INVALID_OFFSET, while an offset equal to its length yields
+/// an empty value.
+pub fn offset_att_data(
+    data: AttAttributeDataChild,
+    offset: u32,
+) -> Result<AttAttributeDataChild, AttErrorCode> {
+    if offset == 0 {
+        return Ok(data);
+    }
+    let bytes = data.to_vec().map_err(|_| AttErrorCode::UNLIKELY_ERROR)?;
+    match bytes.get(offset as usize..) {
+        Some(rest) => Ok(AttAttributeDataChild::RawData(rest.into())),
+        None => Err(AttErrorCode::INVALID_OFFSET),
+    }
+}
+
+/// A write queued by ATT_PREPARE_WRITE_REQ, to be committed (together with
+/// all the other writes in the queue) by ATT_EXECUTE_WRITE_REQ
+#[derive(Debug)]
+pub struct PreparedWrite {
+    pub handle: AttHandle,
+    pub offset: u32,
+    pub value: OwnedAttAttributeDataView,
 }
 
 #[async_trait(?Send)]
 pub trait AttDatabase {
-    /// Read an attribute by handle
+    /// Read an attribute by handle, starting at the given byte offset into
+    /// its value (nonzero only for ATT_READ_BLOB_REQ)
     async fn read_attribute(
         &self,
         handle: AttHandle,
+        offset: u32,
     ) -> Result<AttAttributeDataChild, AttErrorCode>;
 
     /// Write to an attribute by handle
@@ -84,6 +226,14 @@ pub trait AttDatabase {
     /// Write to an attribute by handle
     fn write_no_response_attribute(&self, handle: AttHandle, data: AttAttributeDataView<'_>);
 
+    /// Commit a queue of prepared writes, in order. Either all of them take
+    /// effect, or none do, in which case the handle of the write that failed
+    /// is returned alongside the error.
+    async fn execute_prepared_writes(
+        &self,
+        writes: &[PreparedWrite],
+    ) -> Result<(), (AttHandle, AttErrorCode)>;
+
     /// List all the attributes in this database.
     ///
     /// Expected to return them in sorted order.
@@ -120,8 +270,9 @@ impl AttDatabase for SnapshottedAttDatabase<'_> {
     async fn read_attribute(
         &self,
         handle: AttHandle,
+        offset: u32,
     ) -> Result<AttAttributeDataChild, AttErrorCode> {
-        self.backing.read_attribute(handle).await
+        self.backing.read_attribute(handle, offset).await
     }
 
     async fn write_attribute(
@@ -136,9 +287,135 @@ impl AttDatabase for SnapshottedAttDatabase<'_> {
         self.backing.write_no_response_attribute(handle, data);
     }
 
+    async fn execute_prepared_writes(
+        &self,
+        writes: &[PreparedWrite],
+    ) -> Result<(), (AttHandle, AttErrorCode)> {
+        self.backing.execute_prepared_writes(writes).await
+    }
+
     fn list_attributes(&self) -> Vec<AttAttribute> {
         self.attributes.clone()
     }
 }
 
 impl StableAttDatabase for SnapshottedAttDatabase<'_> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UNENCRYPTED: AttLinkSecurity = AttLinkSecurity {
+        encrypted: false,
+        authenticated: false,
+        key_size: 0,
+        link_key_known: false,
+        authorized: false,
+    };
+    const ENCRYPTED: AttLinkSecurity = AttLinkSecurity {
+        encrypted: true,
+        authenticated: false,
+        key_size: 16,
+        link_key_known: true,
+        authorized: false,
+    };
+    const AUTHENTICATED: AttLinkSecurity = AttLinkSecurity { authenticated: true, ..ENCRYPTED };
+
+    #[test]
+    fn test_no_requirements() {
+        let permissions = AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE;
+
+        assert_eq!(permissions.check_read_security(&UNENCRYPTED), Ok(()));
+        assert_eq!(permissions.check_write_security(&UNENCRYPTED), Ok(()));
+    }
+
+    #[test]
+    fn test_encryption_required_without_key() {
+        let permissions = AttPermissions::READ_ENCRYPTED;
+
+        // assert: the peer must pair before it can encrypt
+        assert_eq!(
+            permissions.check_read_security(&UNENCRYPTED),
+            Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+        );
+    }
+
+    #[test]
+    fn test_encryption_required_with_key() {
+        let permissions = AttPermissions::READ_ENCRYPTED;
+        let security = AttLinkSecurity { link_key_known: true, ..UNENCRYPTED };
+
+        // assert: the peer only needs to encrypt with its existing key
+        assert_eq!(
+            permissions.check_read_security(&security),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+        assert_eq!(permissions.check_read_security(&ENCRYPTED), Ok(()));
+    }
+
+    #[test]
+    fn test_authentication_required() {
+        let permissions = AttPermissions::WRITE_AUTHENTICATED;
+
+        assert_eq!(
+            permissions.check_write_security(&ENCRYPTED),
+            Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+        );
+        assert_eq!(permissions.check_write_security(&AUTHENTICATED), Ok(()));
+    }
+
+    #[test]
+    fn test_key_size_required() {
+        let permissions = AttPermissions::READ_ENCRYPTED.with_min_key_size(16);
+        let security = AttLinkSecurity { key_size: 7, ..ENCRYPTED };
+
+        assert_eq!(permissions.min_key_size(), 16);
+        assert_eq!(
+            permissions.check_read_security(&security),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION_KEY_SIZE)
+        );
+        assert_eq!(permissions.check_read_security(&ENCRYPTED), Ok(()));
+    }
+
+    #[test]
+    fn test_key_size_implies_encryption() {
+        let permissions = AttPermissions::READABLE.with_min_key_size(7);
+        let security = AttLinkSecurity { link_key_known: true, ..UNENCRYPTED };
+
+        assert_eq!(
+            permissions.check_read_security(&security),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+    }
+
+    #[test]
+    fn test_authorization_required() {
+        let permissions = AttPermissions::READ_AUTHORIZED;
+        let security = AttLinkSecurity { authorized: true, ..UNENCRYPTED };
+
+        assert_eq!(
+            permissions.check_read_security(&UNENCRYPTED),
+            Err(AttErrorCode::INSUFFICIENT_AUTHORIZATION)
+        );
+        assert_eq!(permissions.check_read_security(&security), Ok(()));
+    }
+
+    #[test]
+    fn test_read_and_write_requirements_independent() {
+        let permissions = AttPermissions::WRITE_ENCRYPTED;
+
+        assert_eq!(permissions.check_read_security(&UNENCRYPTED), Ok(()));
+        assert_eq!(
+            permissions.check_write_security(&UNENCRYPTED),
+            Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+        );
+    }
+
+    #[test]
+    fn test_replace_min_key_size() {
+        let permissions = AttPermissions::READABLE.with_min_key_size(16).with_min_key_size(10);
+
+        assert_eq!(permissions.min_key_size(), 10);
+        assert!(permissions.readable());
+    }
+}
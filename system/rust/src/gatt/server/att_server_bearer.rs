@@ -14,6 +14,7 @@ use crate::{
         shared_mutex::SharedMutex,
     },
     gatt::{
+        congestion::AttCongestion,
         ids::AttHandle,
         mtu::{AttMtu, MtuEvent},
         opcode_types::{classify_opcode, OperationType},
@@ -26,9 +27,10 @@ use crate::{
 };
 
 use super::{
-    att_database::AttDatabase,
+    att_database::{AttDatabase, AttLinkSecurity},
     command_handler::AttCommandHandler,
     indication_handler::{ConfirmationWatcher, IndicationError, IndicationHandler},
+    notification_handler::{NotificationError, NotificationHandler},
     request_handler::AttRequestHandler,
 };
 
@@ -53,6 +55,7 @@ pub struct AttServerBearer<T: AttDatabase> {
     // general
     send_packet: Box<dyn Fn(AttBuilder) -> Result<(), SerializeError>>,
     mtu: AttMtu,
+    link_security: Cell<AttLinkSecurity>,
 
     // request state
     curr_request: Cell<AttRequestState<T>>,
@@ -61,6 +64,11 @@ pub struct AttServerBearer<T: AttDatabase> {
     indication_handler: SharedMutex<IndicationHandler<T>>,
     pending_confirmation: ConfirmationWatcher,
 
+    // notification state
+    notification_handler: SharedMutex<NotificationHandler<T>>,
+    congestion: AttCongestion,
+    multiple_notifications_supported: Cell<bool>,
+
     // command handler (across all bearers)
     command_handler: AttCommandHandler<T>,
 }
@@ -76,12 +84,17 @@ impl<T: AttDatabase + Clone + 'static> AttServerBearer<T> {
         Self {
             send_packet: Box::new(send_packet),
             mtu: AttMtu::new(),
+            link_security: Cell::new(AttLinkSecurity::default()),
 
             curr_request: AttRequestState::Idle(AttRequestHandler::new(db.clone())).into(),
 
             indication_handler: SharedMutex::new(indication_handler),
             pending_confirmation,
 
+            notification_handler: SharedMutex::new(NotificationHandler::new(db.clone())),
+            congestion: AttCongestion::new(),
+            multiple_notifications_supported: Cell::new(false),
+
             command_handler: AttCommandHandler::new(db),
         }
     }
@@ -91,6 +104,24 @@ impl<T: AttDatabase + Clone + 'static> AttServerBearer<T> {
         let packet = AttBuilder { opcode: HACK_child_to_opcode(&child), _child_: child };
         (self.send_packet)(packet)
     }
+
+    /// Record whether the peer has advertised support for
+    /// ATT_MULTIPLE_HANDLE_VALUE_NTF (in its Client Supported Features)
+    pub fn set_multiple_notifications_supported(&self, supported: bool) {
+        self.multiple_notifications_supported.set(supported);
+    }
+
+    /// The current security state of the underlying link
+    pub fn link_security(&self) -> AttLinkSecurity {
+        self.link_security.get()
+    }
+
+    /// Update the security state of the underlying link (e.g. once it is
+    /// encrypted), against which the security requirements of attributes are
+    /// checked. Requests that are already in progress are not affected.
+    pub fn set_link_security(&self, security: AttLinkSecurity) {
+        self.link_security.set(security);
+    }
 }
 
 impl<T: AttDatabase + Clone + 'static> WeakBoxRef<'_, AttServerBearer<T>> {
@@ -99,7 +130,7 @@ impl<T: AttDatabase + Clone + 'static> WeakBoxRef<'_, AttServerBearer<T>> {
     pub fn handle_packet(&self, packet: AttView<'_>) {
         match classify_opcode(packet.get_opcode()) {
             OperationType::Command => {
-                self.command_handler.process_packet(packet);
+                self.command_handler.process_packet(packet, &self.link_security.get());
             }
             OperationType::Request => {
                 self.handle_request(packet);
@@ -146,12 +177,72 @@ impl<T: AttDatabase + Clone + 'static> WeakBoxRef<'_, AttServerBearer<T>> {
         }
     }
 
+    /// Send a notification. The returned future resolves once the
+    /// notification has been sent, which may be delayed if MTU negotiation is
+    /// taking place or if the channel is congested. If multiple calls are
+    /// outstanding, they are executed in FIFO order.
+    pub fn send_notification(
+        &self,
+        handle: AttHandle,
+        data: AttAttributeDataChild,
+    ) -> impl Future<Output = Result<(), NotificationError>> {
+        self.send_multiple_notifications(vec![(handle, data)])
+    }
+
+    /// Send several notifications, grouping them into
+    /// ATT_MULTIPLE_HANDLE_VALUE_NTFs if the peer supports them. Otherwise,
+    /// behaves like repeated calls to send_notification().
+    pub fn send_multiple_notifications(
+        &self,
+        notifications: Vec<(AttHandle, AttAttributeDataChild)>,
+    ) -> impl Future<Output = Result<(), NotificationError>> {
+        trace!("sending {} notification(s)", notifications.len());
+
+        let locked_notification_handler = self.notification_handler.lock();
+        let pending_mtu = self.mtu.snapshot();
+        let pending_uncongested = self.congestion.wait_until_uncongested();
+        let multiple_notifications_supported = self.multiple_notifications_supported.get();
+        let this = self.downgrade();
+
+        async move {
+            let connection_dropped = |stage| {
+                warn!("notification cancelled while {stage} since the connection dropped");
+                NotificationError::SendError(SendError::ConnectionDropped)
+            };
+            // first wait until we are at the head of the queue
+            let notification_handler =
+                locked_notification_handler.await.ok_or_else(|| connection_dropped("queued"))?;
+            // then, if MTU negotiation is taking place, wait for it to complete
+            let mtu = pending_mtu
+                .await
+                .ok_or_else(|| connection_dropped("waiting for MTU exchange to complete"))?;
+            // then wait for any congestion to clear, rather than piling more packets
+            // onto the channel
+            pending_uncongested
+                .await
+                .ok_or_else(|| connection_dropped("waiting for congestion to clear"))?;
+            // finally, send
+            notification_handler.send(
+                notifications,
+                mtu,
+                multiple_notifications_supported,
+                |packet| this.try_send_packet(packet),
+            )
+        }
+    }
+
     /// Handle a snooped MTU event, to update the MTU we use for our various
     /// operations
     pub fn handle_mtu_event(&self, mtu_event: MtuEvent) -> Result<()> {
         self.mtu.handle_event(mtu_event)
     }
 
+    /// Handle a change in the congestion state of the underlying channel, to
+    /// pause or resume outgoing notifications
+    pub fn handle_congestion_event(&self, congested: bool) {
+        self.congestion.handle_event(congested)
+    }
+
     fn handle_request(&self, packet: AttView<'_>) {
         let curr_request = self.curr_request.replace(AttRequestState::Pending(None));
         self.curr_request.replace(match curr_request {
@@ -159,11 +250,12 @@ impl<T: AttDatabase + Clone + 'static> WeakBoxRef<'_, AttServerBearer<T>> {
                 // even if the MTU is updated afterwards, 5.3 3F 3.4.2.2 states that the
                 // request-time MTU should be used
                 let mtu = self.mtu.snapshot_or_default();
+                let security = self.link_security.get();
                 let packet = packet.to_owned_packet();
                 let this = self.downgrade();
                 let task = spawn_local(async move {
                     trace!("starting ATT transaction");
-                    let reply = request_handler.process_packet(packet.view(), mtu).await;
+                    let reply = request_handler.process_packet(packet.view(), mtu, &security).await;
                     this.with(|this| {
                         this.map(|this| {
                             match this.send_packet(reply) {
@@ -246,6 +338,7 @@ mod test {
     const VALID_HANDLE: AttHandle = AttHandle(3);
     const INVALID_HANDLE: AttHandle = AttHandle(4);
     const ANOTHER_VALID_HANDLE: AttHandle = AttHandle(10);
+    const ENCRYPTED_HANDLE: AttHandle = AttHandle(11);
 
     const TCB_IDX: TransportIndex = TransportIndex(1);
 
@@ -256,7 +349,9 @@ mod test {
                 AttAttribute {
                     handle: VALID_HANDLE,
                     type_: Uuid::new(0x1234),
-                    permissions: AttPermissions::READABLE | AttPermissions::INDICATE,
+                    permissions: AttPermissions::READABLE
+                        | AttPermissions::NOTIFY
+                        | AttPermissions::INDICATE,
                 },
                 vec![5, 6],
             ),
@@ -264,10 +359,20 @@ mod test {
                 AttAttribute {
                     handle: ANOTHER_VALID_HANDLE,
                     type_: Uuid::new(0x5678),
-                    permissions: AttPermissions::READABLE | AttPermissions::INDICATE,
+                    permissions: AttPermissions::READABLE
+                        | AttPermissions::NOTIFY
+                        | AttPermissions::INDICATE,
                 },
                 vec![5, 6],
             ),
+            (
+                AttAttribute {
+                    handle: ENCRYPTED_HANDLE,
+                    type_: Uuid::new(0x9ABC),
+                    permissions: AttPermissions::READABLE | AttPermissions::READ_ENCRYPTED,
+                },
+                vec![7, 8],
+            ),
         ]);
         let (tx, rx) = unbounded_channel();
         let conn = AttServerBearer::new(db, move |packet| {
@@ -317,6 +422,52 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_request_checked_against_link_security() {
+        block_on_locally(async {
+            // arrange
+            let (conn, mut rx) = open_connection();
+            let read_encrypted_handle = || {
+                conn.as_ref().handle_packet(
+                    build_att_view_or_crash(AttReadRequestBuilder {
+                        attribute_handle: ENCRYPTED_HANDLE.into(),
+                    })
+                    .view(),
+                )
+            };
+
+            // act: read an attribute that requires encryption, before and after the
+            // link is encrypted
+            read_encrypted_handle();
+            let before_encryption = rx.recv().await.unwrap();
+            conn.as_ref().set_link_security(AttLinkSecurity {
+                encrypted: true,
+                authenticated: false,
+                key_size: 16,
+                link_key_known: true,
+                authorized: false,
+            });
+            read_encrypted_handle();
+            let after_encryption = rx.recv().await.unwrap();
+
+            // assert: the read only succeeds once the link is encrypted
+            assert_eq!(
+                before_encryption._child_,
+                AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                    opcode_in_error: AttOpcode::READ_REQUEST,
+                    handle_in_error: ENCRYPTED_HANDLE.into(),
+                    error_code: AttErrorCode::INSUFFICIENT_AUTHENTICATION,
+                })
+            );
+            assert_eq!(
+                after_encryption._child_,
+                AttChild::AttReadResponse(AttReadResponseBuilder {
+                    value: build_att_data(AttAttributeDataChild::RawData([7, 8].into())),
+                })
+            );
+        });
+    }
+
     #[test]
     fn test_concurrent_transaction_failure() {
         // arrange: AttServerBearer linked to a backing datastore and packet queue, with
@@ -675,4 +826,162 @@ mod test {
             assert_eq!(rx.recv().await.unwrap().opcode, AttOpcode::HANDLE_VALUE_INDICATION);
         });
     }
+
+    #[test]
+    fn test_notification_sent() {
+        block_on_locally(async {
+            // arrange
+            let (conn, mut rx) = open_connection();
+
+            // act: send a notification
+            let res = conn
+                .as_ref()
+                .send_notification(VALID_HANDLE, AttAttributeDataChild::RawData([1, 2, 3].into()))
+                .await;
+
+            // assert: the notification was sent without waiting for any response
+            assert!(matches!(res, Ok(())));
+            assert_eq!(rx.recv().await.unwrap().opcode, AttOpcode::HANDLE_VALUE_NOTIFICATION);
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        });
+    }
+
+    #[test]
+    fn test_notification_pending_mtu() {
+        block_on_locally(async {
+            // arrange: pending MTU negotiation
+            let (conn, mut rx) = open_connection();
+            conn.as_ref().handle_mtu_event(MtuEvent::OutgoingRequest).unwrap();
+
+            // act: try to send a notification with a large payload size
+            let pending_send = try_await(conn.as_ref().send_notification(
+                VALID_HANDLE,
+                AttAttributeDataChild::RawData((1..50).collect()),
+            ))
+            .await
+            .unwrap_err();
+            // assert: nothing is sent while the MTU exchange is outstanding
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            // then resolve the MTU negotiation with a large MTU
+            conn.as_ref().handle_mtu_event(MtuEvent::IncomingResponse(100)).unwrap();
+
+            // assert: the notification was sent
+            assert!(matches!(pending_send.await, Ok(())));
+            assert_eq!(rx.recv().await.unwrap().opcode, AttOpcode::HANDLE_VALUE_NOTIFICATION);
+        });
+    }
+
+    #[test]
+    fn test_notifications_paused_while_congested() {
+        block_on_locally(async {
+            // arrange: a congested channel
+            let (conn, mut rx) = open_connection();
+            conn.as_ref().handle_congestion_event(true);
+
+            // act: send two notifications
+            let pending_send1 = try_await(
+                conn.as_ref()
+                    .send_notification(VALID_HANDLE, AttAttributeDataChild::RawData([1].into())),
+            )
+            .await
+            .unwrap_err();
+            let pending_send2 = try_await(conn.as_ref().send_notification(
+                ANOTHER_VALID_HANDLE,
+                AttAttributeDataChild::RawData([2].into()),
+            ))
+            .await
+            .unwrap_err();
+            // assert: neither is sent while the channel is congested
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            // then clear the congestion
+            conn.as_ref().handle_congestion_event(false);
+
+            // assert: both were sent, in order
+            assert!(matches!(pending_send1.await, Ok(())));
+            assert!(matches!(pending_send2.await, Ok(())));
+            let AttChild::AttHandleValueNotification(sent1) = rx.recv().await.unwrap()._child_ else {
+                unreachable!()
+            };
+            let AttChild::AttHandleValueNotification(sent2) = rx.recv().await.unwrap()._child_ else {
+                unreachable!()
+            };
+            assert_eq!(sent1.handle, VALID_HANDLE.into());
+            assert_eq!(sent2.handle, ANOTHER_VALID_HANDLE.into());
+        });
+    }
+
+    #[test]
+    fn test_notification_connection_drop_while_congested() {
+        block_on_locally(async {
+            // arrange: a notification pending on a congested channel
+            let (conn, _rx) = open_connection();
+            conn.as_ref().handle_congestion_event(true);
+            let pending_send =
+                try_await(conn.as_ref().send_notification(
+                    VALID_HANDLE,
+                    AttAttributeDataChild::RawData([1, 2, 3].into()),
+                ))
+                .await
+                .unwrap_err();
+
+            // act: drop the connection
+            drop(conn);
+
+            // assert: the pending notification fails with the appropriate error
+            assert!(matches!(
+                pending_send.await,
+                Err(NotificationError::SendError(SendError::ConnectionDropped))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_multiple_notifications_unsupported() {
+        block_on_locally(async {
+            // arrange
+            let (conn, mut rx) = open_connection();
+
+            // act: send two notifications at once
+            let res = conn
+                .as_ref()
+                .send_multiple_notifications(vec![
+                    (VALID_HANDLE, AttAttributeDataChild::RawData([1].into())),
+                    (ANOTHER_VALID_HANDLE, AttAttributeDataChild::RawData([2].into())),
+                ])
+                .await;
+
+            // assert: they were sent separately, since the peer has not advertised support
+            // for ATT_MULTIPLE_HANDLE_VALUE_NTF
+            assert!(matches!(res, Ok(())));
+            assert_eq!(rx.recv().await.unwrap().opcode, AttOpcode::HANDLE_VALUE_NOTIFICATION);
+            assert_eq!(rx.recv().await.unwrap().opcode, AttOpcode::HANDLE_VALUE_NOTIFICATION);
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        });
+    }
+
+    #[test]
+    fn test_multiple_notifications_supported() {
+        block_on_locally(async {
+            // arrange: a peer that supports ATT_MULTIPLE_HANDLE_VALUE_NTF
+            let (conn, mut rx) = open_connection();
+            conn.as_ref().set_multiple_notifications_supported(true);
+
+            // act: send two notifications at once
+            let res = conn
+                .as_ref()
+                .send_multiple_notifications(vec![
+                    (VALID_HANDLE, AttAttributeDataChild::RawData([1].into())),
+                    (ANOTHER_VALID_HANDLE, AttAttributeDataChild::RawData([2].into())),
+                ])
+                .await;
+
+            // assert: they were sent together
+            assert!(matches!(res, Ok(())));
+            assert_eq!(
+                rx.recv().await.unwrap().opcode,
+                AttOpcode::MULTIPLE_HANDLE_VALUE_NOTIFICATION
+            );
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        });
+    }
 }
@@ -2,7 +2,7 @@ use log::warn;
 
 use crate::packets::{AttOpcode, AttView, AttWriteCommandView, Packet};
 
-use super::att_database::AttDatabase;
+use super::att_database::{AttDatabase, AttLinkSecurity, StableAttDatabase};
 
 /// This struct handles all ATT commands.
 pub struct AttCommandHandler<Db: AttDatabase> {
@@ -14,7 +14,7 @@ impl<Db: AttDatabase> AttCommandHandler<Db> {
         Self { db }
     }
 
-    pub fn process_packet(&self, packet: AttView<'_>) {
+    pub fn process_packet(&self, packet: AttView<'_>, security: &AttLinkSecurity) {
         let snapshotted_db = self.db.snapshot();
         match packet.get_opcode() {
             AttOpcode::WRITE_COMMAND => {
@@ -22,8 +22,16 @@ impl<Db: AttDatabase> AttCommandHandler<Db> {
                   warn!("failed to parse WRITE_COMMAND packet");
                   return;
                 };
-                snapshotted_db
-                    .write_no_response_attribute(packet.get_handle().into(), packet.get_value());
+                let handle = packet.get_handle().into();
+                // commands have no response, so if the link is not secure enough we
+                // can only drop the write
+                if let Some(attr) = snapshotted_db.find_attribute(handle) {
+                    if let Err(err) = attr.permissions.check_write_security(security) {
+                        warn!("dropping WRITE_COMMAND to {handle:?} ({err:?})");
+                        return;
+                    }
+                }
+                snapshotted_db.write_no_response_attribute(handle, packet.get_value());
             }
             _ => {
                 warn!("Dropping unsupported opcode {:?}", packet.get_opcode());
@@ -39,7 +47,7 @@ mod test {
         gatt::{
             ids::AttHandle,
             server::{
-                att_database::{AttAttribute, AttDatabase},
+                att_database::{AttAttribute, AttDatabase, AttLinkSecurity},
                 command_handler::AttCommandHandler,
                 gatt_database::AttPermissions,
                 test::test_att_db::TestAttDatabase,
@@ -74,10 +82,39 @@ mod test {
             handle: AttHandle(3).into(),
             value: build_att_data(data.clone()),
         });
-        handler.process_packet(att_view.view());
+        handler.process_packet(att_view.view(), &AttLinkSecurity::default());
 
         // assert: the db has been updated
-        assert_eq!(block_on_locally(db.read_attribute(AttHandle(3))).unwrap(), data);
+        assert_eq!(block_on_locally(db.read_attribute(AttHandle(3), 0)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_write_command_insufficient_encryption() {
+        // arrange: an attribute that requires encryption to write
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(3),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::READABLE
+                    | AttPermissions::WRITABLE_WITHOUT_RESPONSE
+                    | AttPermissions::WRITE_ENCRYPTED,
+            },
+            vec![1, 2, 3],
+        )]);
+        let handler = AttCommandHandler { db: db.clone() };
+
+        // act: send write command over an unencrypted link
+        let att_view = build_att_view_or_crash(AttWriteCommandBuilder {
+            handle: AttHandle(3).into(),
+            value: build_att_data(AttAttributeDataChild::RawData([1, 2].into())),
+        });
+        handler.process_packet(att_view.view(), &AttLinkSecurity::default());
+
+        // assert: the write was dropped
+        assert_eq!(
+            block_on_locally(db.read_attribute(AttHandle(3), 0)).unwrap(),
+            AttAttributeDataChild::RawData([1, 2, 3].into())
+        );
     }
 
     #[test]
@@ -92,7 +129,7 @@ mod test {
             handle_in_error: AttHandle(1).into(),
             error_code: AttErrorCode::UNLIKELY_ERROR,
         });
-        handler.process_packet(att_view.view());
+        handler.process_packet(att_view.view(), &AttLinkSecurity::default());
 
         // assert: nothing happens (we crash if anything is unhandled within a mock)
     }
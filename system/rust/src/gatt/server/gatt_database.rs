@@ -14,7 +14,7 @@ use crate::{
         uuid::Uuid,
     },
     gatt::{
-        callbacks::{GattWriteRequestType, RawGattDatastore},
+        callbacks::{GattWriteRequestType, RawGattDatastore, TransactionDecision},
         ffi::AttributeBackingType,
         ids::{AttHandle, TransportIndex},
     },
@@ -26,7 +26,7 @@ use crate::{
 };
 
 use super::{
-    att_database::{AttAttribute, AttDatabase},
+    att_database::{offset_att_data, AttAttribute, AttDatabase, PreparedWrite},
     att_server_bearer::AttServerBearer,
 };
 
@@ -216,7 +216,7 @@ impl GattDatabase {
                                 .writable_without_response()
                                 .into(),
                             write: characteristic.permissions.writable_with_response().into(),
-                            notify: 0,
+                            notify: characteristic.permissions.notify().into(),
                             indicate: characteristic.permissions.indicate().into(),
                             authenticated_signed_writes: 0,
                             extended_properties: 0,
@@ -344,6 +344,7 @@ impl AttDatabase for AttDatabaseImpl {
     async fn read_attribute(
         &self,
         handle: AttHandle,
+        offset: u32,
     ) -> Result<AttAttributeDataChild, AttErrorCode> {
         let value = self.gatt_db.with(|gatt_db| {
             let Some(gatt_db) = gatt_db else {
@@ -361,26 +362,14 @@ impl AttDatabase for AttDatabaseImpl {
         })?;
 
         match value {
-            AttAttributeBackingValue::Static(val) => return Ok(val),
+            AttAttributeBackingValue::Static(val) => offset_att_data(val, offset),
             AttAttributeBackingValue::DynamicCharacteristic(datastore) => {
                 datastore
-                    .read(
-                        self.tcb_idx,
-                        handle,
-                        /* offset */ 0,
-                        AttributeBackingType::Characteristic,
-                    )
+                    .read(self.tcb_idx, handle, offset, AttributeBackingType::Characteristic)
                     .await
             }
             AttAttributeBackingValue::DynamicDescriptor(datastore) => {
-                datastore
-                    .read(
-                        self.tcb_idx,
-                        handle,
-                        /* offset */ 0,
-                        AttributeBackingType::Descriptor,
-                    )
-                    .await
+                datastore.read(self.tcb_idx, handle, offset, AttributeBackingType::Descriptor).await
             }
         }
     }
@@ -480,6 +469,78 @@ impl AttDatabase for AttDatabaseImpl {
         };
     }
 
+    async fn execute_prepared_writes(
+        &self,
+        writes: &[PreparedWrite],
+    ) -> Result<(), (AttHandle, AttErrorCode)> {
+        // first, check that every write is permitted, so we can reject the whole
+        // queue before any of it reaches the upper layer
+        let mut targets = vec![];
+        for write in writes {
+            let handle = write.handle;
+            let value = self.gatt_db.with(|gatt_db| {
+                let Some(gatt_db) = gatt_db else {
+                    // db must have been closed
+                    return Err(AttErrorCode::INVALID_HANDLE);
+                };
+                let services = gatt_db.schema.borrow();
+                let Some(attr) = services.attributes.get(&handle) else {
+                    return Err(AttErrorCode::INVALID_HANDLE);
+                };
+                if !attr.attribute.permissions.writable_with_response() {
+                    return Err(AttErrorCode::WRITE_NOT_PERMITTED);
+                }
+                Ok(attr.value.clone())
+            });
+            let target = match value.map_err(|err| (handle, err))? {
+                AttAttributeBackingValue::Static(val) => {
+                    error!("A static attribute {val:?} is marked as writable - ignoring it and rejecting the write...");
+                    return Err((handle, AttErrorCode::WRITE_NOT_PERMITTED));
+                }
+                AttAttributeBackingValue::DynamicCharacteristic(datastore) => {
+                    (datastore, AttributeBackingType::Characteristic)
+                }
+                AttAttributeBackingValue::DynamicDescriptor(datastore) => {
+                    (datastore, AttributeBackingType::Descriptor)
+                }
+            };
+            targets.push(target);
+        }
+
+        // then, hand each write to its datastore, keeping track of which datastores
+        // (and the first handle queued on each) will need to execute or cancel
+        let mut pending: Vec<(AttHandle, Rc<dyn RawGattDatastore>)> = vec![];
+        for (write, (datastore, attr_type)) in writes.iter().zip(targets) {
+            if !pending.iter().any(|(_, other)| Rc::ptr_eq(other, &datastore)) {
+                pending.push((write.handle, datastore.clone()));
+            }
+            let result = datastore
+                .write(
+                    self.tcb_idx,
+                    write.handle,
+                    attr_type,
+                    GattWriteRequestType::Prepare { offset: write.offset },
+                    write.value.view(),
+                )
+                .await;
+            if let Err(err) = result {
+                self.cancel_prepared_writes(&pending).await;
+                return Err((write.handle, err));
+            }
+        }
+
+        // finally, commit. If a later datastore fails, the earlier ones cannot be
+        // rolled back, but we can at least cancel the ones that have not yet committed
+        for (i, (handle, datastore)) in pending.iter().enumerate() {
+            if let Err(err) = datastore.execute(self.tcb_idx, TransactionDecision::Execute).await {
+                self.cancel_prepared_writes(&pending[i + 1..]).await;
+                return Err((*handle, err));
+            }
+        }
+
+        Ok(())
+    }
+
     fn list_attributes(&self) -> Vec<AttAttribute> {
         self.gatt_db.with(|db| {
             db.map(|db| db.schema.borrow().attributes.values().map(|attr| attr.attribute).collect())
@@ -495,6 +556,14 @@ impl Clone for AttDatabaseImpl {
 }
 
 impl AttDatabaseImpl {
+    async fn cancel_prepared_writes(&self, pending: &[(AttHandle, Rc<dyn RawGattDatastore>)]) {
+        for (handle, datastore) in pending {
+            if let Err(err) = datastore.execute(self.tcb_idx, TransactionDecision::Cancel).await {
+                warn!("failed to cancel prepared writes starting at {handle:?} ({err:?})");
+            }
+        }
+    }
+
     /// When the bearer owning this AttDatabase is invalidated,
     /// we must notify the listeners tied to our GattDatabase.
     ///
@@ -514,7 +583,11 @@ impl AttDatabaseImpl {
 
 #[cfg(test)]
 mod test {
-    use tokio::{join, sync::mpsc::error::TryRecvError, task::spawn_local};
+    use tokio::{
+        join,
+        sync::mpsc::{error::TryRecvError, UnboundedReceiver},
+        task::spawn_local,
+    };
 
     use crate::{
         gatt::mocks::{
@@ -522,7 +595,7 @@ mod test {
             mock_datastore::{MockDatastore, MockDatastoreEvents},
             mock_raw_datastore::{MockRawDatastore, MockRawDatastoreEvents},
         },
-        packets::Packet,
+        packets::{Packet, Serializable},
         utils::{
             packet::{build_att_data, build_view_or_crash},
             task::block_on_locally,
@@ -548,7 +621,7 @@ mod test {
         let gatt_db = SharedBox::new(GattDatabase::new());
         let att_db = gatt_db.get_att_database(TCB_IDX);
 
-        let resp = tokio_test::block_on(att_db.read_attribute(AttHandle(1)));
+        let resp = tokio_test::block_on(att_db.read_attribute(AttHandle(1), 0));
 
         assert_eq!(resp, Err(AttErrorCode::INVALID_HANDLE))
     }
@@ -570,7 +643,7 @@ mod test {
         let att_db = gatt_db.get_att_database(TCB_IDX);
 
         let attrs = att_db.list_attributes();
-        let service_value = tokio_test::block_on(att_db.read_attribute(SERVICE_HANDLE));
+        let service_value = tokio_test::block_on(att_db.read_attribute(SERVICE_HANDLE, 0));
 
         assert_eq!(
             attrs,
@@ -694,7 +767,7 @@ mod test {
 
         let attrs = att_db.list_attributes();
         let characteristic_decl =
-            tokio_test::block_on(att_db.read_attribute(CHARACTERISTIC_DECLARATION_HANDLE));
+            tokio_test::block_on(att_db.read_attribute(CHARACTERISTIC_DECLARATION_HANDLE, 0));
 
         assert_eq!(attrs.len(), 3, "{attrs:?}");
         assert_eq!(attrs[0].type_, PRIMARY_SERVICE_DECLARATION_UUID);
@@ -764,7 +837,7 @@ mod test {
 
         // assert: the characteristic declaration has all the bits we support set
         let characteristic_decl =
-            tokio_test::block_on(att_db.read_attribute(CHARACTERISTIC_DECLARATION_HANDLE));
+            tokio_test::block_on(att_db.read_attribute(CHARACTERISTIC_DECLARATION_HANDLE, 0));
         assert_eq!(
             characteristic_decl,
             Ok(AttAttributeDataChild::GattCharacteristicDeclarationValue(
@@ -774,7 +847,7 @@ mod test {
                         broadcast: 0,
                         write_without_response: 1,
                         write: 1,
-                        notify: 0,
+                        notify: 1,
                         indicate: 1,
                         authenticated_signed_writes: 0,
                         extended_properties: 0,
@@ -823,7 +896,7 @@ mod test {
                 };
                     reply.send(Ok(data.clone())).unwrap();
                 },
-                att_db.read_attribute(CHARACTERISTIC_VALUE_HANDLE)
+                att_db.read_attribute(CHARACTERISTIC_VALUE_HANDLE, 0)
             )
             .1
         });
@@ -832,6 +905,79 @@ mod test {
         assert_eq!(characteristic_value, Ok(data));
     }
 
+    #[test]
+    fn test_read_characteristic_value_with_offset() {
+        // arrange: create a database with a single characteristic backed by a raw datastore
+        let (gatt_datastore, mut data_evts) = MockRawDatastore::new();
+        let gatt_db = SharedBox::new(GattDatabase::new());
+        gatt_db
+            .add_service_with_handles(
+                GattServiceWithHandle {
+                    handle: SERVICE_HANDLE,
+                    type_: SERVICE_TYPE,
+                    characteristics: vec![GattCharacteristicWithHandle {
+                        handle: CHARACTERISTIC_VALUE_HANDLE,
+                        type_: CHARACTERISTIC_TYPE,
+                        permissions: AttPermissions::READABLE,
+                        descriptors: vec![],
+                    }],
+                },
+                Rc::new(gatt_datastore),
+            )
+            .unwrap();
+        let att_db = gatt_db.get_att_database(TCB_IDX);
+        let data = AttAttributeDataChild::RawData(Box::new([3, 4]));
+
+        // act: read from the database at an offset
+        let characteristic_value = tokio_test::block_on(async {
+            join!(
+                async {
+                    let MockRawDatastoreEvents::Read(
+                    TCB_IDX,
+                    CHARACTERISTIC_VALUE_HANDLE,
+                    AttributeBackingType::Characteristic,
+                    5,
+                    reply,
+                ) = data_evts.recv().await.unwrap() else {
+                    unreachable!()
+                };
+                    reply.send(Ok(data.clone())).unwrap();
+                },
+                att_db.read_attribute(CHARACTERISTIC_VALUE_HANDLE, 5)
+            )
+            .1
+        });
+
+        // assert: the offset was forwarded, and the datastore's value returned as-is
+        assert_eq!(characteristic_value, Ok(data));
+    }
+
+    #[test]
+    fn test_read_static_value_with_offset() {
+        let (gatt_datastore, _) = MockDatastore::new();
+        let gatt_db = SharedBox::new(GattDatabase::new());
+        gatt_db
+            .add_service_with_handles(
+                GattServiceWithHandle {
+                    handle: SERVICE_HANDLE,
+                    type_: SERVICE_TYPE,
+                    characteristics: vec![],
+                },
+                Rc::new(gatt_datastore),
+            )
+            .unwrap();
+        let att_db = gatt_db.get_att_database(TCB_IDX);
+
+        let full_value = tokio_test::block_on(att_db.read_attribute(SERVICE_HANDLE, 0));
+        let offset_value = tokio_test::block_on(att_db.read_attribute(SERVICE_HANDLE, 4));
+        let invalid_value = tokio_test::block_on(att_db.read_attribute(SERVICE_HANDLE, 17));
+
+        // the service declaration holds a 128-bit UUID, so offsets up to 16 are valid
+        let full_value = full_value.unwrap().to_vec().unwrap();
+        assert_eq!(offset_value, Ok(AttAttributeDataChild::RawData(full_value[4..].into())));
+        assert_eq!(invalid_value, Err(AttErrorCode::INVALID_OFFSET));
+    }
+
     #[test]
     fn test_unreadable_characteristic() {
         let (gatt_datastore, _) = MockDatastore::new();
@@ -853,7 +999,7 @@ mod test {
             .unwrap();
 
         let characteristic_value = tokio_test::block_on(
-            gatt_db.get_att_database(TCB_IDX).read_attribute(CHARACTERISTIC_VALUE_HANDLE),
+            gatt_db.get_att_database(TCB_IDX).read_attribute(CHARACTERISTIC_VALUE_HANDLE, 0),
         );
 
         assert_eq!(characteristic_value, Err(AttErrorCode::READ_NOT_PERMITTED));
@@ -1066,7 +1212,9 @@ mod test {
         let descriptor_value = block_on_locally(async {
             // start write task
             let pending_read =
-                spawn_local(async move { att_db.read_attribute(DESCRIPTOR_HANDLE).await.unwrap() });
+                spawn_local(
+                    async move { att_db.read_attribute(DESCRIPTOR_HANDLE, 0).await.unwrap() },
+                );
 
             let MockDatastoreEvents::Read(
                 TCB_IDX,
@@ -1261,7 +1409,7 @@ mod test {
                 };
                     reply.send(Ok(data.clone())).unwrap();
                 },
-                att_db.read_attribute(AttHandle(6))
+                att_db.read_attribute(AttHandle(6), 0)
             )
             .1
         });
@@ -1551,4 +1699,153 @@ mod test {
         // assert: no callback was sent
         assert_eq!(data_events.try_recv().unwrap_err(), TryRecvError::Empty);
     }
+
+    fn make_prepared_write(handle: AttHandle, offset: u32, value: &[u8]) -> PreparedWrite {
+        PreparedWrite {
+            handle,
+            offset,
+            value: build_view_or_crash(build_att_data(AttAttributeDataChild::RawData(
+                value.into(),
+            ))),
+        }
+    }
+
+    fn make_db_with_writable_characteristic_and_descriptor(
+    ) -> (SharedBox<GattDatabase>, UnboundedReceiver<MockRawDatastoreEvents>) {
+        let (gatt_datastore, data_evts) = MockRawDatastore::new();
+        let gatt_db = SharedBox::new(GattDatabase::new());
+        gatt_db
+            .add_service_with_handles(
+                GattServiceWithHandle {
+                    handle: SERVICE_HANDLE,
+                    type_: SERVICE_TYPE,
+                    characteristics: vec![GattCharacteristicWithHandle {
+                        handle: CHARACTERISTIC_VALUE_HANDLE,
+                        type_: CHARACTERISTIC_TYPE,
+                        permissions: AttPermissions::WRITABLE_WITH_RESPONSE,
+                        descriptors: vec![GattDescriptorWithHandle {
+                            handle: DESCRIPTOR_HANDLE,
+                            type_: DESCRIPTOR_TYPE,
+                            permissions: AttPermissions::WRITABLE_WITH_RESPONSE,
+                        }],
+                    }],
+                },
+                Rc::new(gatt_datastore),
+            )
+            .unwrap();
+        (gatt_db, data_evts)
+    }
+
+    #[test]
+    fn test_execute_prepared_writes() {
+        // arrange
+        let (gatt_db, mut data_evts) = make_db_with_writable_characteristic_and_descriptor();
+        let att_db = gatt_db.get_att_database(TCB_IDX);
+        let writes = vec![
+            make_prepared_write(CHARACTERISTIC_VALUE_HANDLE, 0, &[1, 2]),
+            make_prepared_write(DESCRIPTOR_HANDLE, 3, &[3]),
+        ];
+
+        // act: execute, and reply to each callback
+        let res = block_on_locally(async {
+            let pending = spawn_local(async move { att_db.execute_prepared_writes(&writes).await });
+
+            let MockRawDatastoreEvents::Write(
+                TCB_IDX,
+                CHARACTERISTIC_VALUE_HANDLE,
+                AttributeBackingType::Characteristic,
+                GattWriteRequestType::Prepare { offset: 0 },
+                data,
+                reply,
+            ) = data_evts.recv().await.unwrap() else {
+                unreachable!();
+            };
+            assert_eq!(data.view().get_raw_payload().collect::<Vec<_>>(), vec![1, 2]);
+            reply.send(Ok(())).unwrap();
+
+            let MockRawDatastoreEvents::Write(
+                TCB_IDX,
+                DESCRIPTOR_HANDLE,
+                AttributeBackingType::Descriptor,
+                GattWriteRequestType::Prepare { offset: 3 },
+                _,
+                reply,
+            ) = data_evts.recv().await.unwrap() else {
+                unreachable!();
+            };
+            reply.send(Ok(())).unwrap();
+
+            let MockRawDatastoreEvents::Execute(TCB_IDX, TransactionDecision::Execute, reply) =
+                data_evts.recv().await.unwrap() else {
+                unreachable!();
+            };
+            reply.send(Ok(())).unwrap();
+
+            pending.await.unwrap()
+        });
+
+        // assert: success, and the shared datastore was only executed once
+        assert_eq!(res, Ok(()));
+        assert_eq!(data_evts.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
+
+    #[test]
+    fn test_execute_prepared_writes_cancelled_on_failure() {
+        // arrange
+        let (gatt_db, mut data_evts) = make_db_with_writable_characteristic_and_descriptor();
+        let att_db = gatt_db.get_att_database(TCB_IDX);
+        let writes = vec![
+            make_prepared_write(CHARACTERISTIC_VALUE_HANDLE, 0, &[1, 2]),
+            make_prepared_write(DESCRIPTOR_HANDLE, 0, &[3]),
+        ];
+
+        // act: accept the first write, but reject the second
+        let res = block_on_locally(async {
+            let pending = spawn_local(async move { att_db.execute_prepared_writes(&writes).await });
+
+            let MockRawDatastoreEvents::Write(_, _, _, _, _, reply) =
+                data_evts.recv().await.unwrap() else {
+                unreachable!();
+            };
+            reply.send(Ok(())).unwrap();
+            let MockRawDatastoreEvents::Write(_, _, _, _, _, reply) =
+                data_evts.recv().await.unwrap() else {
+                unreachable!();
+            };
+            reply.send(Err(AttErrorCode::INVALID_OFFSET)).unwrap();
+
+            // assert: the datastore is told to discard what it was given
+            let MockRawDatastoreEvents::Execute(TCB_IDX, TransactionDecision::Cancel, reply) =
+                data_evts.recv().await.unwrap() else {
+                unreachable!();
+            };
+            reply.send(Ok(())).unwrap();
+
+            pending.await.unwrap()
+        });
+
+        // assert: the failing handle is reported
+        assert_eq!(res, Err((DESCRIPTOR_HANDLE, AttErrorCode::INVALID_OFFSET)));
+    }
+
+    #[test]
+    fn test_execute_prepared_writes_checks_permissions_first() {
+        // arrange: a queue where the second write targets a read-only attribute
+        let (gatt_db, mut data_evts) = make_db_with_writable_characteristic_and_descriptor();
+        let att_db = gatt_db.get_att_database(TCB_IDX);
+        let writes = vec![
+            make_prepared_write(CHARACTERISTIC_VALUE_HANDLE, 0, &[1, 2]),
+            make_prepared_write(CHARACTERISTIC_DECLARATION_HANDLE, 0, &[3]),
+        ];
+
+        // act
+        let res = block_on_locally(att_db.execute_prepared_writes(&writes));
+
+        // assert: rejected without the datastore seeing anything
+        assert_eq!(
+            res,
+            Err((CHARACTERISTIC_DECLARATION_HANDLE, AttErrorCode::WRITE_NOT_PERMITTED))
+        );
+        assert_eq!(data_evts.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
 }
@@ -0,0 +1,333 @@
+use log::warn;
+
+use crate::{
+    gatt::ids::AttHandle,
+    packets::{
+        AttAttributeDataBuilder, AttAttributeDataChild, AttChild,
+        AttHandleValueNotificationBuilder, AttMultipleHandleValueNotificationBuilder, Serializable,
+    },
+};
+
+use super::{
+    att_database::{AttDatabase, StableAttDatabase},
+    att_server_bearer::SendError,
+};
+
+#[derive(Debug)]
+/// Errors that can occur while sending a notification
+pub enum NotificationError {
+    /// The provided data exceeds the MTU limitations
+    DataExceedsMtu {
+        /// The actual max payload size permitted
+        /// (ATT_MTU - 3, since 3 bytes are needed for the header)
+        mtu: usize,
+    },
+    /// The notified attribute handle does not exist
+    AttributeNotFound,
+    /// The notified attribute does not support notifications
+    NotificationsNotSupported,
+    /// Failed to send the outgoing notification packet
+    SendError(SendError),
+}
+
+pub struct NotificationHandler<T> {
+    db: T,
+}
+
+impl<T: AttDatabase> NotificationHandler<T> {
+    pub fn new(db: T) -> Self {
+        Self { db }
+    }
+
+    /// Send the supplied notifications in order. If the peer supports
+    /// ATT_MULTIPLE_HANDLE_VALUE_NTF, as many as fit in the MTU are grouped
+    /// into each packet, otherwise each is sent in its own
+    /// ATT_HANDLE_VALUE_NTF.
+    ///
+    /// All the notifications are validated before any are sent.
+    pub fn send(
+        &self,
+        notifications: Vec<(AttHandle, AttAttributeDataChild)>,
+        mtu: usize,
+        multiple_notifications_supported: bool,
+        mut send_packet: impl FnMut(AttChild) -> Result<(), SendError>,
+    ) -> Result<(), NotificationError> {
+        let db = self.db.snapshot();
+        let mut values = vec![];
+        for (handle, data) in notifications {
+            let value = data
+                .to_vec()
+                .map_err(SendError::SerializeError)
+                .map_err(NotificationError::SendError)?;
+            // As per Core Spec 5.3 Vol 3F 3.4.7.1, the notified value must be at most
+            // ATT_MTU-3
+            if value.len() > mtu - 3 {
+                return Err(NotificationError::DataExceedsMtu { mtu: mtu - 3 });
+            }
+            if !db
+                .find_attribute(handle)
+                .ok_or(NotificationError::AttributeNotFound)?
+                .permissions
+                .notify()
+            {
+                warn!("cannot send notification for {handle:?} since it does not support notifications");
+                return Err(NotificationError::NotificationsNotSupported);
+            }
+            values.push((handle, value));
+        }
+
+        let groups = if multiple_notifications_supported {
+            group_notifications(values, mtu)
+        } else {
+            values.into_iter().map(|value| vec![value]).collect()
+        };
+
+        for mut group in groups {
+            let packet = if group.len() == 1 {
+                let (handle, value) = group.remove(0);
+                AttHandleValueNotificationBuilder { handle: handle.into(), value: raw_data(value) }
+                    .into()
+            } else {
+                // as per 5.3 3F 3.4.7.4 ATT_MULTIPLE_HANDLE_VALUE_NTF, each value is
+                // preceded by its handle and its length
+                let mut tuples = vec![];
+                for (handle, value) in group {
+                    tuples.extend(handle.0.to_le_bytes());
+                    tuples.extend((value.len() as u16).to_le_bytes());
+                    tuples.extend(value);
+                }
+                AttMultipleHandleValueNotificationBuilder {
+                    handle_length_value_tuple_list: raw_data(tuples),
+                }
+                .into()
+            };
+            send_packet(packet).map_err(NotificationError::SendError)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Greedily pack consecutive notifications into groups whose handle-length-value
+/// tuples fit in a single ATT_MULTIPLE_HANDLE_VALUE_NTF (i.e. in ATT_MTU-1 bytes).
+/// A notification too large to share a packet ends up in a group by itself.
+fn group_notifications(
+    values: Vec<(AttHandle, Vec<u8>)>,
+    mtu: usize,
+) -> Vec<Vec<(AttHandle, Vec<u8>)>> {
+    // the tuples follow the 1-byte opcode
+    let max_group_size = mtu - 1;
+    let mut groups: Vec<Vec<(AttHandle, Vec<u8>)>> = vec![];
+    let mut group_size = 0;
+    for (handle, value) in values {
+        let tuple_size = 4 + value.len();
+        match groups.last_mut() {
+            Some(group) if group_size + tuple_size <= max_group_size => {
+                group_size += tuple_size;
+                group.push((handle, value));
+            }
+            _ => {
+                group_size = tuple_size;
+                groups.push(vec![(handle, value)]);
+            }
+        }
+    }
+    groups
+}
+
+fn raw_data(value: Vec<u8>) -> AttAttributeDataBuilder {
+    AttAttributeDataBuilder { _child_: AttAttributeDataChild::RawData(value.into_boxed_slice()) }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        core::uuid::Uuid,
+        gatt::server::{
+            att_database::AttAttribute, gatt_database::AttPermissions,
+            test::test_att_db::TestAttDatabase,
+        },
+        utils::packet::build_att_data,
+    };
+
+    use super::*;
+
+    const HANDLE: AttHandle = AttHandle(1);
+    const ANOTHER_HANDLE: AttHandle = AttHandle(2);
+    const NONEXISTENT_HANDLE: AttHandle = AttHandle(3);
+    const NON_NOTIFY_HANDLE: AttHandle = AttHandle(4);
+    const MTU: usize = 32;
+
+    fn get_data() -> AttAttributeDataChild {
+        AttAttributeDataChild::RawData([1, 2, 3].into())
+    }
+
+    fn get_notification_handler() -> NotificationHandler<TestAttDatabase> {
+        NotificationHandler::new(TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: HANDLE,
+                    type_: Uuid::new(123),
+                    permissions: AttPermissions::NOTIFY,
+                },
+                vec![],
+            ),
+            (
+                AttAttribute {
+                    handle: ANOTHER_HANDLE,
+                    type_: Uuid::new(123),
+                    permissions: AttPermissions::NOTIFY,
+                },
+                vec![],
+            ),
+            (
+                AttAttribute {
+                    handle: NON_NOTIFY_HANDLE,
+                    type_: Uuid::new(123),
+                    permissions: AttPermissions::READABLE | AttPermissions::INDICATE,
+                },
+                vec![],
+            ),
+        ]))
+    }
+
+    fn send(
+        notifications: Vec<(AttHandle, AttAttributeDataChild)>,
+        mtu: usize,
+        multiple_notifications_supported: bool,
+    ) -> (Result<(), NotificationError>, Vec<AttChild>) {
+        let mut sent = vec![];
+        let res = get_notification_handler().send(
+            notifications,
+            mtu,
+            multiple_notifications_supported,
+            |packet| {
+                sent.push(packet);
+                Ok(())
+            },
+        );
+        (res, sent)
+    }
+
+    #[test]
+    fn test_notification_sent() {
+        // act: send a notification
+        let (res, sent) = send(vec![(HANDLE, get_data())], MTU, false);
+
+        // assert: that an AttHandleValueNotification was sent
+        assert!(matches!(res, Ok(())));
+        assert_eq!(
+            sent,
+            vec![AttChild::AttHandleValueNotification(AttHandleValueNotificationBuilder {
+                handle: HANDLE.into(),
+                value: build_att_data(get_data()),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        // act: send a notification on a nonexistent handle
+        let (res, sent) = send(vec![(NONEXISTENT_HANDLE, get_data())], MTU, false);
+
+        // assert: that we failed with NotificationError::AttributeNotFound
+        assert!(matches!(res, Err(NotificationError::AttributeNotFound)));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_permission() {
+        // act: send a notification on an attribute that only supports indications
+        let (res, sent) = send(vec![(NON_NOTIFY_HANDLE, get_data())], MTU, false);
+
+        // assert: that we failed with NotificationError::NotificationsNotSupported
+        assert!(matches!(res, Err(NotificationError::NotificationsNotSupported)));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_mtu_exceeds() {
+        // act: send a notification with an ATT_MTU of 4 and data length of 3
+        let (res, sent) = send(vec![(HANDLE, get_data())], 4, false);
+
+        // assert: that we got the expected error, indicating the max data size (not the
+        // ATT_MTU, but ATT_MTU-3)
+        assert!(matches!(res, Err(NotificationError::DataExceedsMtu { mtu: 1 })));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_nothing_sent_if_any_invalid() {
+        // act: send a valid notification, followed by an invalid one
+        let (res, sent) =
+            send(vec![(HANDLE, get_data()), (NON_NOTIFY_HANDLE, get_data())], MTU, true);
+
+        // assert: neither was sent
+        assert!(matches!(res, Err(NotificationError::NotificationsNotSupported)));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_separate_notifications_if_multiple_unsupported() {
+        // act: send two notifications to a peer that does not support
+        // ATT_MULTIPLE_HANDLE_VALUE_NTF
+        let (res, sent) =
+            send(vec![(HANDLE, get_data()), (ANOTHER_HANDLE, get_data())], MTU, false);
+
+        // assert: each was sent separately
+        assert!(matches!(res, Ok(())));
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(sent[0], AttChild::AttHandleValueNotification(_)));
+        assert!(matches!(sent[1], AttChild::AttHandleValueNotification(_)));
+    }
+
+    #[test]
+    fn test_multiple_handle_value_notification() {
+        // act: send two notifications to a peer that supports
+        // ATT_MULTIPLE_HANDLE_VALUE_NTF
+        let (res, sent) = send(
+            vec![
+                (HANDLE, get_data()),
+                (ANOTHER_HANDLE, AttAttributeDataChild::RawData([4, 5].into())),
+            ],
+            MTU,
+            true,
+        );
+
+        // assert: they were sent together as handle-length-value tuples
+        assert!(matches!(res, Ok(())));
+        assert_eq!(
+            sent,
+            vec![AttChild::AttMultipleHandleValueNotification(
+                AttMultipleHandleValueNotificationBuilder {
+                    handle_length_value_tuple_list: build_att_data(AttAttributeDataChild::RawData(
+                        [1, 0, 3, 0, 1, 2, 3, 2, 0, 2, 0, 4, 5].into()
+                    )),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_multiple_handle_value_notification_split_by_mtu() {
+        // act: send three notifications, where only two tuples fit in the MTU
+        // (4 + 3 bytes each, with ATT_MTU-1 = 15)
+        let (res, sent) = send(
+            vec![(HANDLE, get_data()), (ANOTHER_HANDLE, get_data()), (HANDLE, get_data())],
+            16,
+            true,
+        );
+
+        // assert: the first two were grouped, and the last was sent on its own
+        assert!(matches!(res, Ok(())));
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(sent[0], AttChild::AttMultipleHandleValueNotification(_)));
+        assert_eq!(
+            sent[1],
+            AttChild::AttHandleValueNotification(AttHandleValueNotificationBuilder {
+                handle: HANDLE.into(),
+                value: build_att_data(get_data()),
+            })
+        );
+    }
+}
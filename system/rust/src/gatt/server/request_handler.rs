@@ -3,40 +3,55 @@ use log::warn;
 use crate::{
     gatt::ids::AttHandle,
     packets::{
-        AttChild, AttErrorCode, AttErrorResponseBuilder, AttFindByTypeValueRequestView,
-        AttFindInformationRequestView, AttOpcode, AttReadByGroupTypeRequestView,
-        AttReadByTypeRequestView, AttReadRequestView, AttView, AttWriteRequestView, Packet,
-        ParseError,
+        AttChild, AttErrorCode, AttErrorResponseBuilder, AttExecuteWriteRequestView,
+        AttFindByTypeValueRequestView, AttFindInformationRequestView, AttOpcode,
+        AttPrepareWriteRequestView, AttReadBlobRequestView, AttReadByGroupTypeRequestView,
+        AttReadByTypeRequestView, AttReadMultipleRequestView, AttReadMultipleVariableRequestView,
+        AttReadRequestView, AttView, AttWriteRequestView, Packet, ParseError,
     },
 };
 
 use super::{
-    att_database::AttDatabase,
+    att_database::{AttDatabase, AttLinkSecurity},
     transactions::{
+        execute_write_request::handle_execute_write_request,
         find_by_type_value::handle_find_by_type_value_request,
         find_information_request::handle_find_information_request,
+        prepare_write_request::{handle_prepare_write_request, PrepareWriteQueue},
+        read_blob_request::handle_read_blob_request,
         read_by_group_type_request::handle_read_by_group_type_request,
-        read_by_type_request::handle_read_by_type_request, read_request::handle_read_request,
+        read_by_type_request::handle_read_by_type_request,
+        read_multiple_request::handle_read_multiple_request,
+        read_multiple_variable_request::handle_read_multiple_variable_request,
+        read_request::handle_read_request,
         write_request::handle_write_request,
     },
 };
 
 /// This struct handles all requests needing ACKs. Only ONE should exist per
-/// bearer per database, to ensure serialization.
+/// bearer per database, to ensure serialization. It also owns the bearer's
+/// prepare queue, so queued writes are discarded when the bearer is dropped.
 pub struct AttRequestHandler<Db: AttDatabase> {
     db: Db,
+    prepare_write_queue: PrepareWriteQueue,
 }
 
 impl<Db: AttDatabase> AttRequestHandler<Db> {
     pub fn new(db: Db) -> Self {
-        Self { db }
+        Self { db, prepare_write_queue: PrepareWriteQueue::new() }
     }
 
     // Runs a task to process an incoming packet. Takes an exclusive reference to
     // ensure that only one request is outstanding at a time (notifications +
-    // commands should take a different path)
-    pub async fn process_packet(&mut self, packet: AttView<'_>, mtu: usize) -> AttChild {
-        match self.try_parse_and_process_packet(packet, mtu).await {
+    // commands should take a different path). The security of the link is
+    // checked against the requirements of every attribute accessed.
+    pub async fn process_packet(
+        &mut self,
+        packet: AttView<'_>,
+        mtu: usize,
+        security: &AttLinkSecurity,
+    ) -> AttChild {
+        match self.try_parse_and_process_packet(packet, mtu, security).await {
             Ok(result) => result,
             Err(_) => {
                 // parse error, assume it's an unsupported request
@@ -55,16 +70,43 @@ impl<Db: AttDatabase> AttRequestHandler<Db> {
         &mut self,
         packet: AttView<'_>,
         mtu: usize,
+        security: &AttLinkSecurity,
     ) -> Result<AttChild, ParseError> {
         let snapshotted_db = self.db.snapshot();
         match packet.get_opcode() {
-            AttOpcode::READ_REQUEST => {
-                Ok(handle_read_request(AttReadRequestView::try_parse(packet)?, mtu, &self.db).await)
-            }
+            AttOpcode::READ_REQUEST => Ok(handle_read_request(
+                AttReadRequestView::try_parse(packet)?,
+                mtu,
+                security,
+                &snapshotted_db,
+            )
+            .await),
+            AttOpcode::READ_BLOB_REQUEST => Ok(handle_read_blob_request(
+                AttReadBlobRequestView::try_parse(packet)?,
+                mtu,
+                security,
+                &snapshotted_db,
+            )
+            .await),
+            AttOpcode::READ_MULTIPLE_REQUEST => Ok(handle_read_multiple_request(
+                AttReadMultipleRequestView::try_parse(packet)?,
+                mtu,
+                security,
+                &snapshotted_db,
+            )
+            .await),
+            AttOpcode::READ_MULTIPLE_VARIABLE_REQUEST => Ok(handle_read_multiple_variable_request(
+                AttReadMultipleVariableRequestView::try_parse(packet)?,
+                mtu,
+                security,
+                &snapshotted_db,
+            )
+            .await),
             AttOpcode::READ_BY_GROUP_TYPE_REQUEST => {
                 handle_read_by_group_type_request(
                     AttReadByGroupTypeRequestView::try_parse(packet)?,
                     mtu,
+                    security,
                     &snapshotted_db,
                 )
                 .await
@@ -73,6 +115,7 @@ impl<Db: AttDatabase> AttRequestHandler<Db> {
                 handle_read_by_type_request(
                     AttReadByTypeRequestView::try_parse(packet)?,
                     mtu,
+                    security,
                     &snapshotted_db,
                 )
                 .await
@@ -85,12 +128,28 @@ impl<Db: AttDatabase> AttRequestHandler<Db> {
             AttOpcode::FIND_BY_TYPE_VALUE_REQUEST => Ok(handle_find_by_type_value_request(
                 AttFindByTypeValueRequestView::try_parse(packet)?,
                 mtu,
+                security,
                 &snapshotted_db,
             )
             .await),
-            AttOpcode::WRITE_REQUEST => {
-                Ok(handle_write_request(AttWriteRequestView::try_parse(packet)?, &self.db).await)
-            }
+            AttOpcode::WRITE_REQUEST => Ok(handle_write_request(
+                AttWriteRequestView::try_parse(packet)?,
+                security,
+                &snapshotted_db,
+            )
+            .await),
+            AttOpcode::PREPARE_WRITE_REQUEST => Ok(handle_prepare_write_request(
+                AttPrepareWriteRequestView::try_parse(packet)?,
+                &mut self.prepare_write_queue,
+                security,
+                &snapshotted_db,
+            )),
+            AttOpcode::EXECUTE_WRITE_REQUEST => Ok(handle_execute_write_request(
+                AttExecuteWriteRequestView::try_parse(packet)?,
+                &mut self.prepare_write_queue,
+                &self.db,
+            )
+            .await),
             _ => {
                 warn!("Dropping unsupported opcode {:?}", packet.get_opcode());
                 Err(ParseError::InvalidEnumValue)
@@ -111,8 +170,11 @@ mod test {
             test::test_att_db::TestAttDatabase,
         },
         packets::{
-            AttAttributeDataChild, AttReadRequestBuilder, AttReadResponseBuilder,
-            AttWriteResponseBuilder,
+            AttAttributeDataChild, AttExecuteWriteFlags, AttExecuteWriteRequestBuilder,
+            AttExecuteWriteResponseBuilder, AttPrepareWriteRequestBuilder,
+            AttReadBlobRequestBuilder, AttReadBlobResponseBuilder,
+            AttReadMultipleVariableRequestBuilder, AttReadMultipleVariableResponseBuilder,
+            AttReadRequestBuilder, AttReadResponseBuilder, AttWriteResponseBuilder,
         },
         utils::packet::{build_att_data, build_att_view_or_crash},
     };
@@ -128,13 +190,17 @@ mod test {
             },
             vec![1, 2, 3],
         )]);
-        let mut handler = AttRequestHandler { db };
+        let mut handler = AttRequestHandler::new(db);
         let att_view = build_att_view_or_crash(AttReadRequestBuilder {
             attribute_handle: AttHandle(3).into(),
         });
 
         // act
-        let response = tokio_test::block_on(handler.process_packet(att_view.view(), 31));
+        let response = tokio_test::block_on(handler.process_packet(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
 
         // assert
         assert_eq!(
@@ -145,6 +211,131 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_read_blob_request() {
+        // arrange
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(3),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::READABLE,
+            },
+            vec![1, 2, 3],
+        )]);
+        let mut handler = AttRequestHandler::new(db);
+        let att_view = build_att_view_or_crash(AttReadBlobRequestBuilder {
+            attribute_handle: AttHandle(3).into(),
+            value_offset: 1,
+        });
+
+        // act
+        let response = tokio_test::block_on(handler.process_packet(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttReadBlobResponse(AttReadBlobResponseBuilder {
+                value: build_att_data(AttAttributeDataChild::RawData([2, 3].into()))
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_multiple_variable_request() {
+        // arrange
+        let db = TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(3),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![1, 2, 3],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(4),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![4],
+            ),
+        ]);
+        let mut handler = AttRequestHandler::new(db);
+        let att_view = build_att_view_or_crash(AttReadMultipleVariableRequestBuilder {
+            set_of_handles: [AttHandle(4).into(), AttHandle(3).into()].into(),
+        });
+
+        // act
+        let response = tokio_test::block_on(handler.process_packet(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttReadMultipleVariableResponse(AttReadMultipleVariableResponseBuilder {
+                length_value_tuple_list: build_att_data(AttAttributeDataChild::RawData(
+                    [1, 0, 4, 3, 0, 1, 2, 3].into()
+                ))
+            })
+        );
+    }
+
+    #[test]
+    fn test_prepared_writes_are_queued_until_executed() {
+        // arrange
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(3),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE,
+            },
+            vec![1, 2, 3],
+        )]);
+        let mut handler = AttRequestHandler::new(db.clone());
+        let prepare = |offset, value: [u8; 2]| {
+            build_att_view_or_crash(AttPrepareWriteRequestBuilder {
+                handle: AttHandle(3).into(),
+                offset,
+                value: build_att_data(AttAttributeDataChild::RawData(value.into())),
+            })
+        };
+        let execute = build_att_view_or_crash(AttExecuteWriteRequestBuilder {
+            flags: AttExecuteWriteFlags::EXECUTE,
+        });
+
+        // act: queue two writes in separate transactions
+        tokio_test::block_on(handler.process_packet(
+            prepare(0, [4, 5]).view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
+        tokio_test::block_on(handler.process_packet(
+            prepare(2, [6, 7]).view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
+        let before_execute = tokio_test::block_on(db.read_attribute(AttHandle(3), 0));
+        let response = tokio_test::block_on(handler.process_packet(
+            execute.view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
+        let after_execute = tokio_test::block_on(db.read_attribute(AttHandle(3), 0));
+
+        // assert: the value only changed once the queue was executed
+        assert_eq!(before_execute, Ok(AttAttributeDataChild::RawData([1, 2, 3].into())));
+        assert_eq!(response, AttChild::AttExecuteWriteResponse(AttExecuteWriteResponseBuilder {}));
+        assert_eq!(after_execute, Ok(AttAttributeDataChild::RawData([4, 5, 6, 7].into())));
+    }
+
     #[test]
     fn test_unsupported_request() {
         // arrange
@@ -156,11 +347,15 @@ mod test {
             },
             vec![1, 2, 3],
         )]);
-        let mut handler = AttRequestHandler { db };
+        let mut handler = AttRequestHandler::new(db);
         let att_view = build_att_view_or_crash(AttWriteResponseBuilder {});
 
         // act
-        let response = tokio_test::block_on(handler.process_packet(att_view.view(), 31));
+        let response = tokio_test::block_on(handler.process_packet(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+        ));
 
         // assert
         assert_eq!(
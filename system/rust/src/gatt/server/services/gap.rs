@@ -42,9 +42,10 @@ impl GattDatastore for GapService {
     ) -> Result<AttAttributeDataChild, AttErrorCode> {
         match handle {
             DEVICE_NAME_HANDLE => {
-                // for non-bonded peers, don't let them read the device name
-                // TODO(aryarahul): support discoverability, when we make this the main GATT server
-                Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+                // only authenticated peers get this far (see the permissions below)
+                // TODO(aryarahul): expose the real device name (and support discoverability),
+                // when we make this the main GATT server
+                Ok(AttAttributeDataChild::RawData([].into()))
             }
             // 0x0000 from AssignedNumbers => "Unknown"
             DEVICE_APPEARANCE_HANDLE => Ok(AttAttributeDataChild::RawData([0x00, 0x00].into())),
@@ -72,10 +73,11 @@ pub fn register_gap_service(database: &mut GattDatabase) -> Result<()> {
             type_: GAP_SERVICE_UUID,
             // Device Name
             characteristics: vec![
+                // only peers that paired with MITM protection may read the device name
                 GattCharacteristicWithHandle {
                     handle: DEVICE_NAME_HANDLE,
                     type_: DEVICE_NAME_UUID,
-                    permissions: AttPermissions::READABLE,
+                    permissions: AttPermissions::READABLE | AttPermissions::READ_AUTHENTICATED,
                     descriptors: vec![],
                 },
                 // Appearance
@@ -98,7 +100,7 @@ mod test {
     use crate::{
         core::shared_box::SharedBox,
         gatt::server::{
-            att_database::AttDatabase,
+            att_database::{AttDatabase, AttLinkSecurity},
             gatt_database::{GattDatabase, CHARACTERISTIC_UUID, PRIMARY_SERVICE_DECLARATION_UUID},
         },
         utils::task::block_on_locally,
@@ -135,20 +137,24 @@ mod test {
         assert_eq!(attrs[3].type_, CHARACTERISTIC_UUID);
         assert_eq!(attrs[4].type_, DEVICE_APPEARANCE_UUID);
         // assert: permissions of value attrs are correct
-        assert_eq!(attrs[2].permissions, AttPermissions::READABLE);
+        assert_eq!(
+            attrs[2].permissions,
+            AttPermissions::READABLE | AttPermissions::READ_AUTHENTICATED
+        );
         assert_eq!(attrs[4].permissions, AttPermissions::READABLE);
     }
 
     #[test]
-    fn test_read_device_name_not_discoverable() {
+    fn test_device_name_not_discoverable() {
         // arrange
         let (_gatt_db, att_db) = init_dbs();
+        let attrs = att_db.list_attributes();
 
-        // act: try to read the device name
-        let name = block_on_locally(att_db.read_attribute(DEVICE_NAME_HANDLE));
+        // act: check whether an unencrypted link can read the device name
+        let result = attrs[2].permissions.check_read_security(&AttLinkSecurity::default());
 
-        // assert: the name is not readable
-        assert_eq!(name, Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION));
+        // assert: the peer must pair first
+        assert_eq!(result, Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION));
     }
 
     #[test]
@@ -157,7 +163,7 @@ mod test {
         let (_gatt_db, att_db) = init_dbs();
 
         // act: try to read the device name
-        let name = block_on_locally(att_db.read_attribute(DEVICE_APPEARANCE_HANDLE));
+        let name = block_on_locally(att_db.read_attribute(DEVICE_APPEARANCE_HANDLE, 0));
 
         // assert: the name is not readable
         assert_eq!(name, Ok(AttAttributeDataChild::RawData([0x00, 0x00].into())));
@@ -40,12 +40,18 @@ struct GattService {
 struct ClientState {
     bearer: WeakBox<AttServerBearer<AttDatabaseImpl>>,
     registered_for_service_change: bool,
+    client_supported_features: u8,
 }
 
 // Must lie in the range specified by GATT_GATT_START_HANDLE from legacy stack
 const GATT_SERVICE_HANDLE: AttHandle = AttHandle(1);
 const SERVICE_CHANGE_HANDLE: AttHandle = AttHandle(3);
 const SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE: AttHandle = AttHandle(4);
+const CLIENT_SUPPORTED_FEATURES_HANDLE: AttHandle = AttHandle(6);
+
+/// Bit set in the Client Supported Features by clients that can receive
+/// ATT_MULTIPLE_HANDLE_VALUE_NTF (Core Spec 5.3 Vol 3G 7.2)
+const MULTIPLE_HANDLE_VALUE_NOTIFICATIONS_SUPPORTED: u8 = 0x04;
 
 /// The UUID used for the GATT service (Assigned Numbers 3.4.1 Services by Name)
 pub const GATT_SERVICE_UUID: Uuid = Uuid::new(0x1801);
@@ -53,6 +59,8 @@ pub const GATT_SERVICE_UUID: Uuid = Uuid::new(0x1801);
 pub const SERVICE_CHANGE_UUID: Uuid = Uuid::new(0x2A05);
 /// The UUID used for the Client Characteristic Configuration descriptor (Assigned Numbers 3.7 Descriptors)
 pub const CLIENT_CHARACTERISTIC_CONFIGURATION_UUID: Uuid = Uuid::new(0x2902);
+/// The UUID used for the Client Supported Features characteristic (Assigned Numbers 3.8.1 Characteristics by Name)
+pub const CLIENT_SUPPORTED_FEATURES_UUID: Uuid = Uuid::new(0x2B29);
 
 #[async_trait(?Send)]
 impl GattDatastore for GattService {
@@ -74,6 +82,16 @@ impl GattDatastore for GattService {
                     .into(),
             }
             .into())
+        } else if handle == CLIENT_SUPPORTED_FEATURES_HANDLE {
+            Ok(AttAttributeDataChild::RawData(
+                [self
+                    .clients
+                    .borrow()
+                    .get(&tcb_idx)
+                    .map(|state| state.client_supported_features)
+                    .unwrap_or(0)]
+                .into(),
+            ))
         } else {
             unreachable!()
         }
@@ -100,6 +118,28 @@ impl GattDatastore for GattService {
             };
             state.registered_for_service_change = ccc.get_indication() != 0;
             Ok(())
+        } else if handle == CLIENT_SUPPORTED_FEATURES_HANDLE {
+            // only the first octet holds bits defined as of 5.3, so the rest are ignored
+            let features = data.get_raw_payload().next().unwrap_or(0);
+            let mut clients = self.clients.borrow_mut();
+            let Some(state) = clients.get_mut(&tcb_idx) else {
+                error!("Received write request from disconnected client...");
+                return Err(AttErrorCode::UNLIKELY_ERROR);
+            };
+            // as per 5.3 3G 7.2, a client may not clear a bit it has previously set
+            if state.client_supported_features & !features != 0 {
+                warn!("client tried to clear its supported features, rejecting");
+                return Err(AttErrorCode::VALUE_NOT_ALLOWED);
+            }
+            state.client_supported_features = features;
+            state.bearer.with(|bearer| {
+                if let Some(bearer) = bearer {
+                    bearer.set_multiple_notifications_supported(
+                        features & MULTIPLE_HANDLE_VALUE_NOTIFICATIONS_SUPPORTED != 0,
+                    )
+                }
+            });
+            Ok(())
         } else {
             unreachable!()
         }
@@ -115,7 +155,11 @@ impl GattDatabaseCallbacks for GattService {
         // TODO(aryarahul): registered_for_service_change may not be false for bonded devices
         self.clients.borrow_mut().insert(
             tcb_idx,
-            ClientState { bearer: bearer.downgrade(), registered_for_service_change: false },
+            ClientState {
+                bearer: bearer.downgrade(),
+                registered_for_service_change: false,
+                client_supported_features: 0,
+            },
         );
     }
 
@@ -156,17 +200,27 @@ pub fn register_gatt_service(database: &mut GattDatabase) -> Result<()> {
         GattServiceWithHandle {
             handle: GATT_SERVICE_HANDLE,
             type_: GATT_SERVICE_UUID,
-            // Service Changed Characteristic
-            characteristics: vec![GattCharacteristicWithHandle {
-                handle: SERVICE_CHANGE_HANDLE,
-                type_: SERVICE_CHANGE_UUID,
-                permissions: AttPermissions::INDICATE,
-                descriptors: vec![GattDescriptorWithHandle {
-                    handle: SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE,
-                    type_: CLIENT_CHARACTERISTIC_CONFIGURATION_UUID,
+            characteristics: vec![
+                // Service Changed Characteristic
+                GattCharacteristicWithHandle {
+                    handle: SERVICE_CHANGE_HANDLE,
+                    type_: SERVICE_CHANGE_UUID,
+                    permissions: AttPermissions::INDICATE,
+                    descriptors: vec![GattDescriptorWithHandle {
+                        handle: SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE,
+                        type_: CLIENT_CHARACTERISTIC_CONFIGURATION_UUID,
+                        permissions: AttPermissions::READABLE
+                            | AttPermissions::WRITABLE_WITH_RESPONSE,
+                    }],
+                },
+                // Client Supported Features Characteristic
+                GattCharacteristicWithHandle {
+                    handle: CLIENT_SUPPORTED_FEATURES_HANDLE,
+                    type_: CLIENT_SUPPORTED_FEATURES_UUID,
                     permissions: AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE,
-                }],
-            }],
+                    descriptors: vec![],
+                },
+            ],
         },
         this.clone(),
     )?;
@@ -232,22 +286,102 @@ mod test {
         // act: discover all services
         let attrs = att_db.list_attributes();
 
-        // assert: 1 service + 1 char decl + 1 char value + 1 char descriptor = 4 attrs
-        assert_eq!(attrs.len(), 4);
+        // assert: 1 service + 2 char decls + 2 char values + 1 char descriptor = 6 attrs
+        assert_eq!(attrs.len(), 6);
         // assert: value handles are correct
         assert_eq!(attrs[0].handle, GATT_SERVICE_HANDLE);
         assert_eq!(attrs[2].handle, SERVICE_CHANGE_HANDLE);
+        assert_eq!(attrs[5].handle, CLIENT_SUPPORTED_FEATURES_HANDLE);
         // assert: types are correct
         assert_eq!(attrs[0].type_, PRIMARY_SERVICE_DECLARATION_UUID);
         assert_eq!(attrs[1].type_, CHARACTERISTIC_UUID);
         assert_eq!(attrs[2].type_, SERVICE_CHANGE_UUID);
         assert_eq!(attrs[3].type_, CLIENT_CHARACTERISTIC_CONFIGURATION_UUID);
+        assert_eq!(attrs[4].type_, CHARACTERISTIC_UUID);
+        assert_eq!(attrs[5].type_, CLIENT_SUPPORTED_FEATURES_UUID);
         // assert: permissions of value attrs are correct
         assert_eq!(attrs[2].permissions, AttPermissions::INDICATE);
         assert_eq!(
             attrs[3].permissions,
             AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE
         );
+        assert_eq!(
+            attrs[5].permissions,
+            AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE
+        );
+    }
+
+    async fn write_client_supported_features(
+        att_db: &impl AttDatabase,
+        features: u8,
+    ) -> Result<(), AttErrorCode> {
+        att_db
+            .write_attribute(
+                CLIENT_SUPPORTED_FEATURES_HANDLE,
+                build_view_or_crash(build_att_data(AttAttributeDataChild::RawData(
+                    [features].into(),
+                )))
+                .view(),
+            )
+            .await
+    }
+
+    #[test]
+    fn test_default_client_supported_features() {
+        // arrange
+        let gatt_db = init_gatt_db();
+        let (att_db, _, _) = add_connection(&gatt_db, TCB_IDX);
+
+        // act: read the client supported features
+        let resp = block_on_locally(att_db.read_attribute(CLIENT_SUPPORTED_FEATURES_HANDLE, 0));
+
+        // assert: no features are enabled
+        assert_eq!(resp, Ok(AttAttributeDataChild::RawData([0].into())));
+    }
+
+    #[test]
+    fn test_write_client_supported_features() {
+        // arrange
+        let gatt_db = init_gatt_db();
+        let (att_db, _, _) = add_connection(&gatt_db, TCB_IDX);
+
+        // act: enable multiple handle value notifications
+        block_on_locally(write_client_supported_features(&att_db, 0x04)).unwrap();
+        let resp = block_on_locally(att_db.read_attribute(CLIENT_SUPPORTED_FEATURES_HANDLE, 0));
+
+        // assert: the feature is enabled
+        assert_eq!(resp, Ok(AttAttributeDataChild::RawData([0x04].into())));
+    }
+
+    #[test]
+    fn test_client_supported_features_cannot_be_cleared() {
+        // arrange
+        let gatt_db = init_gatt_db();
+        let (att_db, _, _) = add_connection(&gatt_db, TCB_IDX);
+        block_on_locally(write_client_supported_features(&att_db, 0x04)).unwrap();
+
+        // act: try to clear the feature
+        let res = block_on_locally(write_client_supported_features(&att_db, 0x00));
+
+        // assert: the write is rejected, and the feature is still enabled
+        assert_eq!(res, Err(AttErrorCode::VALUE_NOT_ALLOWED));
+        let resp = block_on_locally(att_db.read_attribute(CLIENT_SUPPORTED_FEATURES_HANDLE, 0));
+        assert_eq!(resp, Ok(AttAttributeDataChild::RawData([0x04].into())));
+    }
+
+    #[test]
+    fn test_client_supported_features_are_per_connection() {
+        // arrange
+        let gatt_db = init_gatt_db();
+        let (att_db_1, _, _) = add_connection(&gatt_db, TCB_IDX);
+        let (att_db_2, _, _) = add_connection(&gatt_db, ANOTHER_TCB_IDX);
+
+        // act: enable a feature on only the first connection
+        block_on_locally(write_client_supported_features(&att_db_1, 0x04)).unwrap();
+
+        // assert: the second connection is unaffected
+        let resp = block_on_locally(att_db_2.read_attribute(CLIENT_SUPPORTED_FEATURES_HANDLE, 0));
+        assert_eq!(resp, Ok(AttAttributeDataChild::RawData([0].into())));
     }
 
     #[test]
@@ -257,8 +391,8 @@ mod test {
         let (att_db, _, _) = add_connection(&gatt_db, TCB_IDX);
 
         // act: try to read the CCC descriptor
-        let resp =
-            block_on_locally(att_db.read_attribute(SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE)).unwrap();
+        let resp = block_on_locally(att_db.read_attribute(SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE, 0))
+            .unwrap();
 
         // assert: we are not registered for either indications/notifications
         let AttAttributeDataChild::GattClientCharacteristicConfiguration(configuration) = resp else {
@@ -296,8 +430,8 @@ mod test {
         block_on_locally(register_for_indication(&att_db, SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE))
             .unwrap();
         // read our registration status
-        let resp =
-            block_on_locally(att_db.read_attribute(SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE)).unwrap();
+        let resp = block_on_locally(att_db.read_attribute(SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE, 0))
+            .unwrap();
 
         // assert: we are registered for indications
         let AttAttributeDataChild::GattClientCharacteristicConfiguration(configuration) = resp else {
@@ -340,8 +474,8 @@ mod test {
         )
         .unwrap();
         // read our registration status
-        let resp =
-            block_on_locally(att_db.read_attribute(SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE)).unwrap();
+        let resp = block_on_locally(att_db.read_attribute(SERVICE_CHANGE_CCC_DESCRIPTOR_HANDLE, 0))
+            .unwrap();
 
         // assert: we are not registered for indications
         let AttAttributeDataChild::GattClientCharacteristicConfiguration(configuration) = resp else {
@@ -511,4 +645,54 @@ mod test {
             assert!(rx2.recv().await.is_none());
         });
     }
+
+    #[test]
+    fn test_multiple_notifications_once_client_supports_them() {
+        block_on_locally(async {
+            // arrange: a service with two characteristics that support notifications
+            let gatt_db = init_gatt_db();
+            let (att_db, bearer, mut rx) = add_connection(&gatt_db, TCB_IDX);
+            let (gatt_datastore, _) = MockDatastore::new();
+            gatt_db
+                .add_service_with_handles(
+                    GattServiceWithHandle {
+                        handle: AttHandle(15),
+                        type_: SERVICE_TYPE,
+                        characteristics: vec![
+                            GattCharacteristicWithHandle {
+                                handle: AttHandle(17),
+                                type_: CHARACTERISTIC_TYPE,
+                                permissions: AttPermissions::NOTIFY,
+                                descriptors: vec![],
+                            },
+                            GattCharacteristicWithHandle {
+                                handle: AttHandle(19),
+                                type_: CHARACTERISTIC_TYPE,
+                                permissions: AttPermissions::NOTIFY,
+                                descriptors: vec![],
+                            },
+                        ],
+                    },
+                    Rc::new(gatt_datastore),
+                )
+                .unwrap();
+
+            // act: advertise support for multiple handle value notifications, then
+            // send two notifications
+            write_client_supported_features(&att_db, 0x04).await.unwrap();
+            bearer
+                .as_ref()
+                .send_multiple_notifications(vec![
+                    (AttHandle(17), AttAttributeDataChild::RawData([1].into())),
+                    (AttHandle(19), AttAttributeDataChild::RawData([2].into())),
+                ])
+                .await
+                .unwrap();
+
+            // assert: they were sent together
+            let resp = rx.recv().await.unwrap();
+            assert!(matches!(resp._child_, AttChild::AttMultipleHandleValueNotification(_)));
+            assert!(rx.try_recv().is_err());
+        });
+    }
 }
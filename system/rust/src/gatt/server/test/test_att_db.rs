@@ -1,14 +1,20 @@
 use crate::{
     gatt::{
         ids::AttHandle,
-        server::att_database::{AttAttribute, AttDatabase, StableAttDatabase},
+        server::att_database::{
+            offset_att_data, AttAttribute, AttDatabase, PreparedWrite, StableAttDatabase,
+        },
     },
     packets::{AttAttributeDataChild, AttAttributeDataView, AttErrorCode},
 };
 
 use async_trait::async_trait;
 use log::{info, warn};
-use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{btree_map::Entry, BTreeMap},
+    rc::Rc,
+};
 
 #[derive(Clone, Debug)]
 pub struct TestAttDatabase {
@@ -41,17 +47,19 @@ impl AttDatabase for TestAttDatabase {
     async fn read_attribute(
         &self,
         handle: AttHandle,
+        offset: u32,
     ) -> Result<AttAttributeDataChild, AttErrorCode> {
-        info!("reading {handle:?}");
+        info!("reading {handle:?} at offset {offset}");
         match self.attributes.get(&handle) {
             Some(TestAttributeWithData { attribute: AttAttribute { permissions, .. }, .. })
                 if !permissions.readable() =>
             {
                 Err(AttErrorCode::READ_NOT_PERMITTED)
             }
-            Some(TestAttributeWithData { data, .. }) => {
-                Ok(AttAttributeDataChild::RawData(data.borrow().clone().into_boxed_slice()))
-            }
+            Some(TestAttributeWithData { data, .. }) => offset_att_data(
+                AttAttributeDataChild::RawData(data.borrow().clone().into_boxed_slice()),
+                offset,
+            ),
             None => Err(AttErrorCode::INVALID_HANDLE),
         }
     }
@@ -86,6 +94,37 @@ impl AttDatabase for TestAttDatabase {
             }
         }
     }
+    async fn execute_prepared_writes(
+        &self,
+        writes: &[PreparedWrite],
+    ) -> Result<(), (AttHandle, AttErrorCode)> {
+        // apply the writes to copies of the values, and only commit if all succeed
+        let mut new_values = BTreeMap::new();
+        for PreparedWrite { handle, offset, value } in writes {
+            let attr = match self.attributes.get(handle) {
+                Some(attr) if !attr.attribute.permissions.writable_with_response() => {
+                    return Err((*handle, AttErrorCode::WRITE_NOT_PERMITTED))
+                }
+                Some(attr) => attr,
+                None => return Err((*handle, AttErrorCode::INVALID_HANDLE)),
+            };
+            let data = match new_values.entry(*handle) {
+                Entry::Vacant(entry) => entry.insert(attr.data.borrow().clone()),
+                Entry::Occupied(entry) => entry.into_mut(),
+            };
+            let offset = *offset as usize;
+            if offset > data.len() {
+                return Err((*handle, AttErrorCode::INVALID_OFFSET));
+            }
+            data.truncate(offset);
+            data.extend(value.view().get_raw_payload());
+        }
+        for (handle, data) in new_values {
+            info!("committing prepared write to {handle:?}");
+            self.attributes[&handle].data.replace(data);
+        }
+        Ok(())
+    }
     fn list_attributes(&self) -> Vec<AttAttribute> {
         self.attributes.values().map(|attr| attr.attribute).collect()
     }
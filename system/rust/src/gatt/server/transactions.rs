@@ -1,7 +1,12 @@
+pub mod execute_write_request;
 pub mod find_by_type_value;
 pub mod find_information_request;
 mod helpers;
+pub mod prepare_write_request;
+pub mod read_blob_request;
 pub mod read_by_group_type_request;
 pub mod read_by_type_request;
+pub mod read_multiple_request;
+pub mod read_multiple_variable_request;
 pub mod read_request;
 pub mod write_request;
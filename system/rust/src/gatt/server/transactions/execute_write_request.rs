@@ -0,0 +1,235 @@
+use log::{info, warn};
+
+use crate::{
+    gatt::server::att_database::AttDatabase,
+    packets::{
+        AttChild, AttErrorCode, AttErrorResponseBuilder, AttExecuteWriteFlags,
+        AttExecuteWriteRequestView, AttExecuteWriteResponseBuilder, AttOpcode,
+    },
+};
+
+use super::prepare_write_request::PrepareWriteQueue;
+
+/// The longest an attribute value can be (5.3 3F 3.2.9)
+const MAX_ATTRIBUTE_VALUE_LENGTH: usize = 512;
+
+pub async fn handle_execute_write_request<T: AttDatabase>(
+    request: AttExecuteWriteRequestView<'_>,
+    queue: &mut PrepareWriteQueue,
+    db: &T,
+) -> AttChild {
+    // whatever the outcome, the queue is emptied
+    let writes = queue.take();
+
+    if request.get_flags() == AttExecuteWriteFlags::CANCEL {
+        info!("cancelling {} prepared writes", writes.len());
+        return AttExecuteWriteResponseBuilder {}.into();
+    }
+
+    // check every write before committing any of them
+    let oversized = writes.iter().find(|write| {
+        write.offset as usize + write.value.view().get_raw_payload().count()
+            > MAX_ATTRIBUTE_VALUE_LENGTH
+    });
+    let result = match oversized {
+        Some(write) => Err((write.handle, AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)),
+        None if writes.is_empty() => Ok(()),
+        None => db.execute_prepared_writes(&writes).await,
+    };
+
+    match result {
+        Ok(()) => AttExecuteWriteResponseBuilder {}.into(),
+        Err((handle, error_code)) => {
+            warn!("failed to execute prepared writes at {handle:?} ({error_code:?})");
+            AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::EXECUTE_WRITE_REQUEST,
+                handle_in_error: handle.into(),
+                error_code,
+            }
+            .into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::{
+            ids::AttHandle,
+            server::{
+                att_database::{AttAttribute, AttLinkSecurity, AttPermissions},
+                test::test_att_db::TestAttDatabase,
+                transactions::prepare_write_request::handle_prepare_write_request,
+            },
+        },
+        packets::{
+            AttAttributeDataChild, AttExecuteWriteRequestBuilder, AttPrepareWriteRequestBuilder,
+        },
+        utils::packet::{build_att_data, build_view_or_crash},
+    };
+
+    fn make_db() -> TestAttDatabase {
+        TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(1),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE,
+                },
+                vec![1, 2, 3],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(2),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE,
+                },
+                vec![4, 5, 6],
+            ),
+        ])
+    }
+
+    fn prepare_write(
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+        queue: &mut PrepareWriteQueue,
+        db: &TestAttDatabase,
+    ) {
+        let att_view = build_view_or_crash(AttPrepareWriteRequestBuilder {
+            handle: AttHandle(handle).into(),
+            offset,
+            value: build_att_data(AttAttributeDataChild::RawData(value.into())),
+        });
+        let response =
+            handle_prepare_write_request(att_view.view(), queue, &AttLinkSecurity::default(), db);
+        assert!(matches!(response, AttChild::AttPrepareWriteResponse(_)), "{response:?}");
+    }
+
+    fn do_execute_write_request(
+        flags: AttExecuteWriteFlags,
+        queue: &mut PrepareWriteQueue,
+        db: &TestAttDatabase,
+    ) -> AttChild {
+        let att_view = build_view_or_crash(AttExecuteWriteRequestBuilder { flags });
+        tokio_test::block_on(handle_execute_write_request(att_view.view(), queue, db))
+    }
+
+    fn read(db: &TestAttDatabase, handle: u16) -> AttAttributeDataChild {
+        tokio_test::block_on(db.read_attribute(AttHandle(handle), 0)).unwrap()
+    }
+
+    #[test]
+    fn test_long_write() {
+        // arrange: queue a value in parts
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        prepare_write(1, 0, &[7, 8], &mut queue, &db);
+        prepare_write(1, 2, &[9, 10], &mut queue, &db);
+
+        // act
+        let response = do_execute_write_request(AttExecuteWriteFlags::EXECUTE, &mut queue, &db);
+
+        // assert: the parts were assembled
+        assert_eq!(response, AttChild::AttExecuteWriteResponse(AttExecuteWriteResponseBuilder {}));
+        assert_eq!(read(&db, 1), AttAttributeDataChild::RawData([7, 8, 9, 10].into()));
+    }
+
+    #[test]
+    fn test_reliable_write_across_handles() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        prepare_write(1, 0, &[7], &mut queue, &db);
+        prepare_write(2, 0, &[8], &mut queue, &db);
+
+        // act
+        let response = do_execute_write_request(AttExecuteWriteFlags::EXECUTE, &mut queue, &db);
+
+        // assert: both handles were written
+        assert_eq!(response, AttChild::AttExecuteWriteResponse(AttExecuteWriteResponseBuilder {}));
+        assert_eq!(read(&db, 1), AttAttributeDataChild::RawData([7].into()));
+        assert_eq!(read(&db, 2), AttAttributeDataChild::RawData([8].into()));
+    }
+
+    #[test]
+    fn test_cancel() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        prepare_write(1, 0, &[7], &mut queue, &db);
+
+        // act
+        let response = do_execute_write_request(AttExecuteWriteFlags::CANCEL, &mut queue, &db);
+
+        // assert: nothing was written, and the queue is empty
+        assert_eq!(response, AttChild::AttExecuteWriteResponse(AttExecuteWriteResponseBuilder {}));
+        assert_eq!(read(&db, 1), AttAttributeDataChild::RawData([1, 2, 3].into()));
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn test_execute_empty_queue() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+
+        // act
+        let response = do_execute_write_request(AttExecuteWriteFlags::EXECUTE, &mut queue, &db);
+
+        // assert
+        assert_eq!(response, AttChild::AttExecuteWriteResponse(AttExecuteWriteResponseBuilder {}));
+    }
+
+    #[test]
+    fn test_failed_write_is_atomic() {
+        // arrange: a valid write to one handle, then an invalid offset on another
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        prepare_write(1, 0, &[7], &mut queue, &db);
+        prepare_write(2, 4, &[8], &mut queue, &db);
+
+        // act
+        let response = do_execute_write_request(AttExecuteWriteFlags::EXECUTE, &mut queue, &db);
+
+        // assert: the error names the failing handle
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::EXECUTE_WRITE_REQUEST,
+                handle_in_error: AttHandle(2).into(),
+                error_code: AttErrorCode::INVALID_OFFSET,
+            })
+        );
+        // assert: neither write took effect, and the queue is empty
+        assert_eq!(read(&db, 1), AttAttributeDataChild::RawData([1, 2, 3].into()));
+        assert_eq!(read(&db, 2), AttAttributeDataChild::RawData([4, 5, 6].into()));
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn test_oversized_value() {
+        // arrange: a write that would extend the value past the maximum length
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        prepare_write(1, 0, &[7], &mut queue, &db);
+        prepare_write(2, 500, &[0; 13], &mut queue, &db);
+
+        // act
+        let response = do_execute_write_request(AttExecuteWriteFlags::EXECUTE, &mut queue, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::EXECUTE_WRITE_REQUEST,
+                handle_in_error: AttHandle(2).into(),
+                error_code: AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH,
+            })
+        );
+        assert_eq!(read(&db, 1), AttAttributeDataChild::RawData([1, 2, 3].into()));
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     core::uuid::Uuid,
     gatt::{
         ids::AttHandle,
-        server::att_database::{AttAttribute, StableAttDatabase},
+        server::att_database::{AttAttribute, AttLinkSecurity, StableAttDatabase},
     },
     packets::{
         AttChild, AttErrorCode, AttErrorResponseBuilder, AttFindByTypeValueRequestView,
@@ -14,12 +14,13 @@ use crate::{
 
 use super::helpers::{
     att_grouping::find_group_end, att_range_filter::filter_to_range,
-    payload_accumulator::PayloadAccumulator,
+    check_access::check_read_access, payload_accumulator::PayloadAccumulator,
 };
 
 pub async fn handle_find_by_type_value_request(
     request: AttFindByTypeValueRequestView<'_>,
     mtu: usize,
+    security: &AttLinkSecurity,
     db: &impl StableAttDatabase,
 ) -> AttChild {
     let Some(attrs) = filter_to_range(
@@ -42,7 +43,11 @@ pub async fn handle_find_by_type_value_request(
         if Uuid::from(request.get_attribute_type()) != type_ {
             continue;
         }
-        if let Ok(value) = db.read_attribute(handle).await {
+        if let Err(err) = check_read_access(db, handle, security) {
+            warn!("skipping {handle:?} in FindByTypeRequest since it cannot be read ({err:?})");
+            continue;
+        }
+        if let Ok(value) = db.read_attribute(handle, /* offset */ 0).await {
             if let Ok(data) = value.to_vec() {
                 if data == request.get_attribute_value().get_raw_payload().collect::<Vec<_>>() {
                     // match found
@@ -135,8 +140,12 @@ mod test {
             attribute_type: UUID.try_into().unwrap(),
             attribute_value: build_att_data(AttAttributeDataChild::RawData(VALUE.into())),
         });
-        let response =
-            tokio_test::block_on(handle_find_by_type_value_request(att_view.view(), 128, &db));
+        let response = tokio_test::block_on(handle_find_by_type_value_request(
+            att_view.view(),
+            128,
+            &AttLinkSecurity::default(),
+            &db,
+        ));
 
         // assert: we only matched the ones with the correct UUID
         let AttChild::AttFindByTypeValueResponse(response) = response else {
@@ -197,8 +206,12 @@ mod test {
             attribute_type: UUID.try_into().unwrap(),
             attribute_value: build_att_data(AttAttributeDataChild::RawData(VALUE.into())),
         });
-        let response =
-            tokio_test::block_on(handle_find_by_type_value_request(att_view.view(), 128, &db));
+        let response = tokio_test::block_on(handle_find_by_type_value_request(
+            att_view.view(),
+            128,
+            &AttLinkSecurity::default(),
+            &db,
+        ));
 
         // assert
         let AttChild::AttFindByTypeValueResponse(response) = response else {
@@ -234,8 +247,12 @@ mod test {
             attribute_type: UUID.try_into().unwrap(),
             attribute_value: build_att_data(AttAttributeDataChild::RawData(VALUE.into())),
         });
-        let response =
-            tokio_test::block_on(handle_find_by_type_value_request(att_view.view(), 128, &db));
+        let response = tokio_test::block_on(handle_find_by_type_value_request(
+            att_view.view(),
+            128,
+            &AttLinkSecurity::default(),
+            &db,
+        ));
 
         // assert
         let AttChild::AttErrorResponse(response) = response else {
@@ -270,8 +287,12 @@ mod test {
             attribute_type: UUID.try_into().unwrap(),
             attribute_value: build_att_data(AttAttributeDataChild::RawData(VALUE.into())),
         });
-        let response =
-            tokio_test::block_on(handle_find_by_type_value_request(att_view.view(), 128, &db));
+        let response = tokio_test::block_on(handle_find_by_type_value_request(
+            att_view.view(),
+            128,
+            &AttLinkSecurity::default(),
+            &db,
+        ));
 
         // assert: got ATTRIBUTE_NOT_FOUND erro
         let AttChild::AttErrorResponse(response) = response else {
@@ -324,8 +345,12 @@ mod test {
             attribute_type: CHARACTERISTIC_UUID.try_into().unwrap(),
             attribute_value: build_att_data(AttAttributeDataChild::RawData(VALUE.into())),
         });
-        let response =
-            tokio_test::block_on(handle_find_by_type_value_request(att_view.view(), 128, &db));
+        let response = tokio_test::block_on(handle_find_by_type_value_request(
+            att_view.view(),
+            128,
+            &AttLinkSecurity::default(),
+            &db,
+        ));
 
         // assert
         let AttChild::AttFindByTypeValueResponse(response) = response else {
@@ -372,8 +397,12 @@ mod test {
             attribute_type: UUID.try_into().unwrap(),
             attribute_value: build_att_data(AttAttributeDataChild::RawData(VALUE.into())),
         });
-        let response =
-            tokio_test::block_on(handle_find_by_type_value_request(att_view.view(), 5, &db));
+        let response = tokio_test::block_on(handle_find_by_type_value_request(
+            att_view.view(),
+            5,
+            &AttLinkSecurity::default(),
+            &db,
+        ));
 
         // assert: only one of the two matches produced
         let AttChild::AttFindByTypeValueResponse(response) = response else {
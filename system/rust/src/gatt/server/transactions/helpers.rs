@@ -1,5 +1,7 @@
 pub mod att_filter_by_size_type;
 pub mod att_grouping;
 pub mod att_range_filter;
+pub mod check_access;
 pub mod payload_accumulator;
+pub mod read_multiple_attributes;
 pub mod truncate_att_data;
@@ -3,11 +3,11 @@
 
 use crate::{
     core::uuid::Uuid,
-    gatt::server::att_database::{AttAttribute, StableAttDatabase},
+    gatt::server::att_database::{AttAttribute, AttLinkSecurity, StableAttDatabase},
     packets::{AttAttributeDataChild, AttErrorCode, Serializable},
 };
 
-use super::truncate_att_data::truncate_att_data;
+use super::{check_access::check_read_access, truncate_att_data::truncate_att_data};
 
 /// An attribute and the value
 #[derive(Debug, PartialEq, Eq)]
@@ -17,20 +17,22 @@ pub struct AttributeWithValue {
     pub value: AttAttributeDataChild,
 }
 
-/// Takes a StableAttDatabase, a range of handles, a target type, and a
-/// size limit.
+/// Takes a StableAttDatabase, a range of handles, a target type, a size
+/// limit, and the security of the link.
 ///
 /// Returns an iterator of attributes in the range and matching the type,
 /// with the max number of elements such that each attribute has the same
 /// size.
 ///
 /// Attributes are truncated to the attr_size limit before size comparison.
-/// If an error occurs while reading, do not output further attributes.
+/// If an error occurs while reading (including if the link is not secure
+/// enough to read an attribute), do not output further attributes.
 pub async fn filter_read_attributes_by_size_type(
     db: &impl StableAttDatabase,
     attrs: impl Iterator<Item = AttAttribute>,
     target: Uuid,
     size_limit: usize,
+    security: &AttLinkSecurity,
 ) -> Result<impl Iterator<Item = AttributeWithValue>, AttErrorCode> {
     let target_attrs = attrs.filter(|attr| attr.type_ == target);
 
@@ -38,7 +40,11 @@ pub async fn filter_read_attributes_by_size_type(
     let mut curr_elem_size = None;
 
     for attr @ AttAttribute { handle, .. } in target_attrs {
-        match db.read_attribute(handle).await {
+        let value = match check_read_access(db, handle, security) {
+            Ok(()) => db.read_attribute(handle, /* offset */ 0).await,
+            Err(err) => Err(err),
+        };
+        match value {
             Ok(value) => {
                 let value = truncate_att_data(value, size_limit);
                 let value_size = value.size_in_bits().unwrap_or(0);
@@ -103,6 +109,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
@@ -152,6 +159,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
@@ -207,6 +215,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
@@ -238,6 +247,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             2,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
@@ -262,6 +272,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
@@ -287,6 +298,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ));
 
         // assert: got READ_NOT_PERMITTED
@@ -330,6 +342,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
@@ -381,6 +394,7 @@ mod test {
             db.list_attributes().into_iter(),
             UUID,
             31,
+            &AttLinkSecurity::default(),
         ))
         .unwrap();
 
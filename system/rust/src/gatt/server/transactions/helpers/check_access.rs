@@ -0,0 +1,150 @@
+//! This module extracts the common logic in checking that an attribute can be
+//! accessed, given the security of the link the request arrived on
+
+use crate::{
+    gatt::{
+        ids::AttHandle,
+        server::att_database::{AttLinkSecurity, StableAttDatabase},
+    },
+    packets::AttErrorCode,
+};
+
+/// Check that the attribute with the given handle can be read over a link
+/// with the given security.
+///
+/// A missing or unreadable attribute takes precedence over any security
+/// requirement, so that the peer is not asked to pair just to find that out.
+pub fn check_read_access(
+    db: &impl StableAttDatabase,
+    handle: AttHandle,
+    security: &AttLinkSecurity,
+) -> Result<(), AttErrorCode> {
+    match db.find_attribute(handle) {
+        None => Err(AttErrorCode::INVALID_HANDLE),
+        Some(attr) if !attr.permissions.readable() => Err(AttErrorCode::READ_NOT_PERMITTED),
+        Some(attr) => attr.permissions.check_read_security(security),
+    }
+}
+
+/// Check that the attribute with the given handle can be written (using
+/// WRITE_REQ) over a link with the given security.
+///
+/// A missing or unwritable attribute takes precedence over any security
+/// requirement, so that the peer is not asked to pair just to find that out.
+pub fn check_write_access(
+    db: &impl StableAttDatabase,
+    handle: AttHandle,
+    security: &AttLinkSecurity,
+) -> Result<(), AttErrorCode> {
+    match db.find_attribute(handle) {
+        None => Err(AttErrorCode::INVALID_HANDLE),
+        Some(attr) if !attr.permissions.writable_with_response() => {
+            Err(AttErrorCode::WRITE_NOT_PERMITTED)
+        }
+        Some(attr) => attr.permissions.check_write_security(security),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::server::{
+            att_database::{AttAttribute, AttPermissions},
+            test::test_att_db::TestAttDatabase,
+        },
+    };
+
+    const ENCRYPTED: AttLinkSecurity = AttLinkSecurity {
+        encrypted: true,
+        authenticated: false,
+        key_size: 16,
+        link_key_known: true,
+        authorized: false,
+    };
+
+    fn make_db() -> TestAttDatabase {
+        TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(1),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE
+                        | AttPermissions::WRITABLE_WITH_RESPONSE
+                        | AttPermissions::READ_ENCRYPTED
+                        | AttPermissions::WRITE_ENCRYPTED,
+                },
+                vec![],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(2),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READ_ENCRYPTED | AttPermissions::WRITE_ENCRYPTED,
+                },
+                vec![],
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_access_allowed() {
+        let db = make_db();
+
+        assert_eq!(check_read_access(&db, AttHandle(1), &ENCRYPTED), Ok(()));
+        assert_eq!(check_write_access(&db, AttHandle(1), &ENCRYPTED), Ok(()));
+    }
+
+    #[test]
+    fn test_insufficient_security() {
+        let db = make_db();
+
+        // act: access an encrypted attribute over an unencrypted (but bonded) link
+        let security = AttLinkSecurity { link_key_known: true, ..Default::default() };
+
+        // assert
+        assert_eq!(
+            check_read_access(&db, AttHandle(1), &security),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+        assert_eq!(
+            check_write_access(&db, AttHandle(1), &security),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        let db = make_db();
+
+        assert_eq!(
+            check_read_access(&db, AttHandle(3), &ENCRYPTED),
+            Err(AttErrorCode::INVALID_HANDLE)
+        );
+        assert_eq!(
+            check_write_access(&db, AttHandle(3), &ENCRYPTED),
+            Err(AttErrorCode::INVALID_HANDLE)
+        );
+    }
+
+    #[test]
+    fn test_permission_checked_before_security() {
+        let db = make_db();
+
+        // act: access an attribute that can be neither read nor written, over an
+        // unencrypted link
+        let security = AttLinkSecurity::default();
+
+        // assert: we are told that it is not permitted, rather than to encrypt
+        assert_eq!(
+            check_read_access(&db, AttHandle(2), &security),
+            Err(AttErrorCode::READ_NOT_PERMITTED)
+        );
+        assert_eq!(
+            check_write_access(&db, AttHandle(2), &security),
+            Err(AttErrorCode::WRITE_NOT_PERMITTED)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! This module extracts the common logic in reading a set of attributes by
+//! handle, used in READ_MULTIPLE_REQ and READ_MULTIPLE_VARIABLE_REQ
+
+use crate::{
+    gatt::{
+        ids::AttHandle,
+        server::att_database::{AttLinkSecurity, StableAttDatabase},
+    },
+    packets::{AttErrorCode, Serializable},
+};
+
+use super::check_access::check_read_access;
+
+/// Takes a StableAttDatabase, a set of handles, and the security of the link,
+/// and reads the value of each attribute in order.
+///
+/// Returns the serialized values, or the first handle that could not be
+/// read, alongside the error. Permissions are checked for every handle before
+/// any attribute is read, so that the upper layer only sees reads for requests
+/// that can succeed.
+pub async fn read_multiple_attributes(
+    db: &impl StableAttDatabase,
+    handles: &[AttHandle],
+    security: &AttLinkSecurity,
+) -> Result<Vec<Vec<u8>>, (AttHandle, AttErrorCode)> {
+    // 5.3 3F 3.4.4.7 and 3.4.4.11 both require at least two handles
+    if handles.len() < 2 {
+        return Err((handles.first().copied().unwrap_or(AttHandle(0)), AttErrorCode::INVALID_PDU));
+    }
+
+    for &handle in handles {
+        check_read_access(db, handle, security).map_err(|err| (handle, err))?;
+    }
+
+    let mut values = vec![];
+    for &handle in handles {
+        let value = db.read_attribute(handle, /* offset */ 0).await.map_err(|err| (handle, err))?;
+        values.push(value.to_vec().map_err(|_| (handle, AttErrorCode::UNLIKELY_ERROR))?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::server::{
+            att_database::{AttAttribute, AttPermissions},
+            test::test_att_db::TestAttDatabase,
+        },
+    };
+
+    fn make_db() -> TestAttDatabase {
+        TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(1),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![1, 2],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(2),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![3],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(3),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::empty(),
+                },
+                vec![4],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(5),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE.with_min_key_size(16),
+                },
+                vec![5],
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_read_in_order() {
+        let db = make_db();
+
+        let values = tokio_test::block_on(read_multiple_attributes(
+            &db,
+            &[AttHandle(2), AttHandle(1)],
+            &AttLinkSecurity::default(),
+        ));
+
+        assert_eq!(values, Ok(vec![vec![3], vec![1, 2]]));
+    }
+
+    #[test]
+    fn test_repeated_handle() {
+        let db = make_db();
+
+        let values = tokio_test::block_on(read_multiple_attributes(
+            &db,
+            &[AttHandle(1), AttHandle(1)],
+            &AttLinkSecurity::default(),
+        ));
+
+        assert_eq!(values, Ok(vec![vec![1, 2], vec![1, 2]]));
+    }
+
+    #[test]
+    fn test_single_handle() {
+        let db = make_db();
+
+        let values = tokio_test::block_on(read_multiple_attributes(
+            &db,
+            &[AttHandle(1)],
+            &AttLinkSecurity::default(),
+        ));
+
+        assert_eq!(values, Err((AttHandle(1), AttErrorCode::INVALID_PDU)));
+    }
+
+    #[test]
+    fn test_first_failure_reported() {
+        let db = make_db();
+
+        let values = tokio_test::block_on(read_multiple_attributes(
+            &db,
+            &[AttHandle(1), AttHandle(4), AttHandle(3)],
+            &AttLinkSecurity::default(),
+        ));
+
+        assert_eq!(values, Err((AttHandle(4), AttErrorCode::INVALID_HANDLE)));
+    }
+
+    #[test]
+    fn test_not_readable() {
+        let db = make_db();
+
+        let values = tokio_test::block_on(read_multiple_attributes(
+            &db,
+            &[AttHandle(1), AttHandle(3)],
+            &AttLinkSecurity::default(),
+        ));
+
+        assert_eq!(values, Err((AttHandle(3), AttErrorCode::READ_NOT_PERMITTED)));
+    }
+
+    #[test]
+    fn test_insufficient_key_size() {
+        let db = make_db();
+        let security = AttLinkSecurity {
+            encrypted: true,
+            key_size: 7,
+            link_key_known: true,
+            ..Default::default()
+        };
+
+        let values = tokio_test::block_on(read_multiple_attributes(
+            &db,
+            &[AttHandle(1), AttHandle(5)],
+            &security,
+        ));
+
+        assert_eq!(values, Err((AttHandle(5), AttErrorCode::INSUFFICIENT_ENCRYPTION_KEY_SIZE)));
+    }
+}
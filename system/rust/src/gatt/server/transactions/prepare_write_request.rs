@@ -0,0 +1,331 @@
+use log::warn;
+
+use crate::{
+    gatt::server::att_database::{AttLinkSecurity, PreparedWrite, StableAttDatabase},
+    packets::{
+        AttAttributeDataBuilder, AttAttributeDataChild, AttChild, AttErrorCode,
+        AttErrorResponseBuilder, AttOpcode, AttPrepareWriteRequestView,
+        AttPrepareWriteResponseBuilder, Packet,
+    },
+};
+
+use super::helpers::check_access::check_write_access;
+
+/// The most writes a client can queue before it must execute or cancel them
+const MAX_QUEUED_WRITES: usize = 128;
+/// The most bytes of attribute value (across all writes) a client can queue
+const MAX_QUEUED_BYTES: usize = 4096;
+
+/// The prepare queue of a single bearer (5.3 3F 3.4.6.1). Writes stay here
+/// until the client executes them, so if it cancels (or disconnects) they are
+/// discarded without the upper layer ever seeing them.
+#[derive(Default)]
+pub struct PrepareWriteQueue {
+    writes: Vec<PreparedWrite>,
+    queued_bytes: usize,
+}
+
+impl PrepareWriteQueue {
+    /// Constructor
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn push(&mut self, write: PreparedWrite) -> Result<(), AttErrorCode> {
+        let len = write.value.view().get_raw_payload().count();
+        if self.writes.len() >= MAX_QUEUED_WRITES || self.queued_bytes + len > MAX_QUEUED_BYTES {
+            return Err(AttErrorCode::PREPARE_QUEUE_FULL);
+        }
+        self.queued_bytes += len;
+        self.writes.push(write);
+        Ok(())
+    }
+
+    /// Remove and return all the queued writes, in the order they were prepared
+    pub fn take(&mut self) -> Vec<PreparedWrite> {
+        self.queued_bytes = 0;
+        std::mem::take(&mut self.writes)
+    }
+}
+
+pub fn handle_prepare_write_request(
+    request: AttPrepareWriteRequestView<'_>,
+    queue: &mut PrepareWriteQueue,
+    security: &AttLinkSecurity,
+    db: &impl StableAttDatabase,
+) -> AttChild {
+    let handle = request.get_handle().into();
+    let offset = request.get_offset();
+
+    // permissions (and security requirements) are checked when the write is
+    // queued, but the offset and length can only be validated once the writes
+    // are executed (5.3 3F 3.4.6.1)
+    let result = check_write_access(db, handle, security).and_then(|()| {
+        queue.push(PreparedWrite {
+            handle,
+            offset: offset.into(),
+            value: request.get_value().to_owned_packet(),
+        })
+    });
+
+    match result {
+        // the response echoes the request, so the client can check what was queued
+        Ok(()) => AttPrepareWriteResponseBuilder {
+            handle: handle.into(),
+            offset,
+            value: AttAttributeDataBuilder {
+                _child_: AttAttributeDataChild::RawData(
+                    request.get_value().get_raw_payload().collect(),
+                ),
+            },
+        }
+        .into(),
+        Err(error_code) => {
+            warn!("rejecting prepared write to {handle:?} ({error_code:?})");
+            AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::PREPARE_WRITE_REQUEST,
+                handle_in_error: handle.into(),
+                error_code,
+            }
+            .into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::{
+            ids::AttHandle,
+            server::{
+                att_database::{AttAttribute, AttDatabase, AttPermissions},
+                test::test_att_db::TestAttDatabase,
+            },
+        },
+        packets::{AttPrepareWriteRequestBuilder, Serializable},
+        utils::packet::{build_att_data, build_view_or_crash},
+    };
+
+    fn make_db() -> TestAttDatabase {
+        TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(1),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE | AttPermissions::WRITABLE_WITH_RESPONSE,
+                },
+                vec![],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(2),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![],
+            ),
+        ])
+    }
+
+    fn do_prepare_write_request(
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+        queue: &mut PrepareWriteQueue,
+        db: &TestAttDatabase,
+    ) -> AttChild {
+        let att_view = build_view_or_crash(AttPrepareWriteRequestBuilder {
+            handle: AttHandle(handle).into(),
+            offset,
+            value: build_att_data(AttAttributeDataChild::RawData(value.into())),
+        });
+        handle_prepare_write_request(att_view.view(), queue, &AttLinkSecurity::default(), db)
+    }
+
+    #[test]
+    fn test_queued_write_is_echoed() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+
+        // act
+        let response = do_prepare_write_request(1, 3, &[1, 2], &mut queue, &db);
+
+        // assert: the response echoes the request
+        response.to_vec().unwrap(); // check it serializes
+        assert_eq!(
+            response,
+            AttChild::AttPrepareWriteResponse(AttPrepareWriteResponseBuilder {
+                handle: AttHandle(1).into(),
+                offset: 3,
+                value: build_att_data(AttAttributeDataChild::RawData([1, 2].into())),
+            })
+        );
+        // assert: the write was queued
+        let writes = queue.take();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].handle, AttHandle(1));
+        assert_eq!(writes[0].offset, 3);
+        assert_eq!(writes[0].value.view().get_raw_payload().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_write_is_not_applied_until_executed() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+
+        // act
+        do_prepare_write_request(1, 0, &[1, 2], &mut queue, &db);
+
+        // assert: the database is untouched
+        assert_eq!(
+            tokio_test::block_on(db.read_attribute(AttHandle(1), 0)),
+            Ok(AttAttributeDataChild::RawData([].into()))
+        );
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+
+        // act
+        let response = do_prepare_write_request(3, 0, &[1, 2], &mut queue, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::PREPARE_WRITE_REQUEST,
+                handle_in_error: AttHandle(3).into(),
+                error_code: AttErrorCode::INVALID_HANDLE,
+            })
+        );
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn test_not_writable() {
+        // arrange
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+
+        // act
+        let response = do_prepare_write_request(2, 0, &[1, 2], &mut queue, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::PREPARE_WRITE_REQUEST,
+                handle_in_error: AttHandle(2).into(),
+                error_code: AttErrorCode::WRITE_NOT_PERMITTED,
+            })
+        );
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn test_queue_full_by_count() {
+        // arrange: fill the queue with empty writes
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        for _ in 0..MAX_QUEUED_WRITES {
+            do_prepare_write_request(1, 0, &[], &mut queue, &db);
+        }
+
+        // act
+        let response = do_prepare_write_request(1, 0, &[], &mut queue, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::PREPARE_WRITE_REQUEST,
+                handle_in_error: AttHandle(1).into(),
+                error_code: AttErrorCode::PREPARE_QUEUE_FULL,
+            })
+        );
+        assert_eq!(queue.take().len(), MAX_QUEUED_WRITES);
+    }
+
+    #[test]
+    fn test_queue_full_by_size() {
+        // arrange: queue all but one byte of the limit
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        do_prepare_write_request(1, 0, &vec![0; MAX_QUEUED_BYTES - 1], &mut queue, &db);
+
+        // act: try to queue two more bytes
+        let response = do_prepare_write_request(1, 0, &[1, 2], &mut queue, &db);
+
+        // assert: rejected
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::PREPARE_WRITE_REQUEST,
+                handle_in_error: AttHandle(1).into(),
+                error_code: AttErrorCode::PREPARE_QUEUE_FULL,
+            })
+        );
+    }
+
+    #[test]
+    fn test_queue_space_freed_after_take() {
+        // arrange: fill the queue
+        let db = make_db();
+        let mut queue = PrepareWriteQueue::new();
+        do_prepare_write_request(1, 0, &vec![0; MAX_QUEUED_BYTES], &mut queue, &db);
+
+        // act: drain it, then queue another write
+        queue.take();
+        let response = do_prepare_write_request(1, 0, &[1, 2], &mut queue, &db);
+
+        // assert: accepted
+        assert!(matches!(response, AttChild::AttPrepareWriteResponse(_)));
+    }
+
+    #[test]
+    fn test_insufficient_authorization() {
+        // arrange: an attribute that requires authorization to write
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(1),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::WRITABLE_WITH_RESPONSE
+                    | AttPermissions::WRITE_AUTHORIZED,
+            },
+            vec![],
+        )]);
+        let mut queue = PrepareWriteQueue::new();
+
+        // act: try to queue a write from an unauthorized peer
+        let att_view = build_view_or_crash(AttPrepareWriteRequestBuilder {
+            handle: AttHandle(1).into(),
+            offset: 0,
+            value: build_att_data(AttAttributeDataChild::RawData([1, 2].into())),
+        });
+        let response = handle_prepare_write_request(
+            att_view.view(),
+            &mut queue,
+            &AttLinkSecurity::default(),
+            &db,
+        );
+
+        // assert: rejected, and nothing was queued
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::PREPARE_WRITE_REQUEST,
+                handle_in_error: AttHandle(1).into(),
+                error_code: AttErrorCode::INSUFFICIENT_AUTHORIZATION,
+            })
+        );
+        assert!(queue.take().is_empty());
+    }
+}
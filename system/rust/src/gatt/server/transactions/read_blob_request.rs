@@ -0,0 +1,211 @@
+use crate::{
+    gatt::server::att_database::{AttLinkSecurity, StableAttDatabase},
+    packets::{
+        AttAttributeDataBuilder, AttChild, AttErrorResponseBuilder, AttOpcode,
+        AttReadBlobRequestView, AttReadBlobResponseBuilder,
+    },
+};
+
+use super::helpers::{check_access::check_read_access, truncate_att_data::truncate_att_data};
+
+pub async fn handle_read_blob_request(
+    request: AttReadBlobRequestView<'_>,
+    mtu: usize,
+    security: &AttLinkSecurity,
+    db: &impl StableAttDatabase,
+) -> AttChild {
+    let handle = request.get_attribute_handle().into();
+    let offset = request.get_value_offset().into();
+
+    // the database decides whether the offset is valid, since only it knows the
+    // full length of the value (and whether it supports long reads at all)
+    let value = match check_read_access(db, handle, security) {
+        Ok(()) => db.read_attribute(handle, offset).await,
+        Err(error_code) => Err(error_code),
+    };
+    match value {
+        Ok(data) => AttReadBlobResponseBuilder {
+            // as per 5.3 3F 3.4.4.6 ATT_READ_BLOB_RSP, we truncate to MTU - 1
+            value: AttAttributeDataBuilder { _child_: truncate_att_data(data, mtu - 1) },
+        }
+        .into(),
+        Err(error_code) => AttErrorResponseBuilder {
+            opcode_in_error: AttOpcode::READ_BLOB_REQUEST,
+            handle_in_error: handle.into(),
+            error_code,
+        }
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::{
+            ids::AttHandle,
+            server::{
+                att_database::{AttAttribute, AttPermissions},
+                test::test_att_db::TestAttDatabase,
+            },
+        },
+        packets::{AttAttributeDataChild, AttErrorCode, AttReadBlobRequestBuilder, Serializable},
+        utils::packet::{build_att_data, build_view_or_crash},
+    };
+
+    fn make_db_with_handle_and_value(handle: u16, value: Vec<u8>) -> TestAttDatabase {
+        TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(handle),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::READABLE,
+            },
+            value,
+        )])
+    }
+
+    fn do_read_blob_request(
+        handle: u16,
+        offset: u16,
+        mtu: usize,
+        db: &TestAttDatabase,
+    ) -> AttChild {
+        let att_view = build_view_or_crash(AttReadBlobRequestBuilder {
+            attribute_handle: AttHandle(handle).into(),
+            value_offset: offset,
+        });
+        tokio_test::block_on(handle_read_blob_request(
+            att_view.view(),
+            mtu,
+            &AttLinkSecurity::default(),
+            db,
+        ))
+    }
+
+    #[test]
+    fn test_read_from_offset() {
+        let db = make_db_with_handle_and_value(3, vec![1, 2, 3, 4, 5]);
+
+        // act
+        let response = do_read_blob_request(3, 2, 31, &db);
+
+        response.to_vec().unwrap(); // check it serializes
+        assert_eq!(
+            response,
+            AttChild::AttReadBlobResponse(AttReadBlobResponseBuilder {
+                value: build_att_data(AttAttributeDataChild::RawData([3, 4, 5].into()))
+            })
+        )
+    }
+
+    #[test]
+    fn test_truncated_read_from_offset() {
+        let db = make_db_with_handle_and_value(3, vec![1, 2, 3, 4, 5]);
+
+        // act
+        let response = do_read_blob_request(3, 1, 3, &db);
+
+        // assert: only MTU - 1 bytes starting at the offset are returned
+        assert_eq!(response.to_vec().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_read_long_value_in_parts() {
+        let value: Vec<u8> = (0..50).collect();
+        let db = make_db_with_handle_and_value(3, value.clone());
+        let mtu = 23;
+
+        // act: read the way a client would, advancing the offset by what it got
+        let mut read: Vec<u8> = vec![];
+        loop {
+            let response = do_read_blob_request(3, read.len() as u16, mtu, &db);
+            let part = response.to_vec().unwrap();
+            read.extend(part.iter());
+            if part.len() < mtu - 1 {
+                break;
+            }
+        }
+
+        // assert: the whole value was read
+        assert_eq!(read, value);
+    }
+
+    #[test]
+    fn test_read_at_end_of_value() {
+        let db = make_db_with_handle_and_value(3, vec![1, 2, 3]);
+
+        // act
+        let response = do_read_blob_request(3, 3, 31, &db);
+
+        // assert: an offset equal to the length is valid, and returns no data
+        assert_eq!(
+            response,
+            AttChild::AttReadBlobResponse(AttReadBlobResponseBuilder {
+                value: build_att_data(AttAttributeDataChild::RawData([].into()))
+            })
+        )
+    }
+
+    #[test]
+    fn test_invalid_offset() {
+        let db = make_db_with_handle_and_value(3, vec![1, 2, 3]);
+
+        // act
+        let response = do_read_blob_request(3, 4, 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_BLOB_REQUEST,
+                handle_in_error: AttHandle(3).into(),
+                error_code: AttErrorCode::INVALID_OFFSET,
+            })
+        );
+    }
+
+    #[test]
+    fn test_missed_read() {
+        let db = make_db_with_handle_and_value(3, vec![4, 5]);
+
+        // act
+        let response = do_read_blob_request(4, 0, 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_BLOB_REQUEST,
+                handle_in_error: AttHandle(4).into(),
+                error_code: AttErrorCode::INVALID_HANDLE,
+            })
+        );
+    }
+
+    #[test]
+    fn test_not_readable() {
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(3),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::empty(),
+            },
+            vec![1, 2, 3],
+        )]);
+
+        // act
+        let response = do_read_blob_request(3, 1, 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_BLOB_REQUEST,
+                handle_in_error: AttHandle(3).into(),
+                error_code: AttErrorCode::READ_NOT_PERMITTED,
+            })
+        );
+    }
+}
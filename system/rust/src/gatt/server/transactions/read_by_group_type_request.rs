@@ -3,7 +3,7 @@ use crate::{
     gatt::{
         ids::AttHandle,
         server::{
-            att_database::StableAttDatabase,
+            att_database::{AttLinkSecurity, StableAttDatabase},
             gatt_database::{PRIMARY_SERVICE_DECLARATION_UUID, SECONDARY_SERVICE_DECLARATION_UUID},
         },
     },
@@ -24,6 +24,7 @@ use super::helpers::{
 pub async fn handle_read_by_group_type_request(
     request: AttReadByGroupTypeRequestView<'_>,
     mtu: usize,
+    security: &AttLinkSecurity,
     db: &impl StableAttDatabase,
 ) -> Result<AttChild, ParseError> {
     let group_type: Uuid = request.get_attribute_group_type().try_into()?;
@@ -65,7 +66,7 @@ pub async fn handle_read_by_group_type_request(
     let mut matches = PayloadAccumulator::new(mtu - 2);
 
     // MTU-6 limit comes from Core Spec 5.3 Vol 3F 3.4.4.9
-    match filter_read_attributes_by_size_type(db, attrs, group_type, mtu - 6).await {
+    match filter_read_attributes_by_size_type(db, attrs, group_type, mtu - 6, security).await {
         Ok(attrs) => {
             for AttributeWithValue { attr, value } in attrs {
                 if !matches.push(AttReadByGroupTypeDataElementBuilder {
@@ -146,9 +147,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_group_type: PRIMARY_SERVICE_DECLARATION_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 31, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we identified both service groups
         let AttChild::AttReadByGroupTypeResponse(response) = response else {
@@ -185,9 +190,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_group_type: CHARACTERISTIC_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 31, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: got UNSUPPORTED_GROUP_TYPE
         let AttChild::AttErrorResponse(response) = response else {
@@ -214,9 +223,13 @@ mod test {
             ending_handle: AttHandle(2).into(),
             attribute_group_type: PRIMARY_SERVICE_DECLARATION_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 31, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we return an INVALID_HANDLE error
         let AttChild::AttErrorResponse(response) = response else {
@@ -251,9 +264,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_group_type: PRIMARY_SERVICE_DECLARATION_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 7, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            7,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we identified both service groups
         let AttChild::AttReadByGroupTypeResponse(response) = response else {
@@ -300,9 +317,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_group_type: PRIMARY_SERVICE_DECLARATION_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 9, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            9,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we return only the first attribute
         let AttChild::AttReadByGroupTypeResponse(response) = response else {
@@ -349,9 +370,13 @@ mod test {
             ending_handle: AttHandle(3).into(),
             attribute_group_type: PRIMARY_SERVICE_DECLARATION_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 31, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: the end_group_handle is correct, even though it exceeds the query
         // interval
@@ -399,9 +424,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_group_type: PRIMARY_SERVICE_DECLARATION_UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_group_type_request(att_view.view(), 31, &db))
-                .unwrap();
+        let response = tokio_test::block_on(handle_read_by_group_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we return ATTRIBUTE_NOT_FOUND
         let AttChild::AttErrorResponse(response) = response else {
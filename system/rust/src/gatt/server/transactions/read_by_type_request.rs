@@ -1,6 +1,9 @@
 use crate::{
     core::uuid::Uuid,
-    gatt::{ids::AttHandle, server::att_database::StableAttDatabase},
+    gatt::{
+        ids::AttHandle,
+        server::att_database::{AttLinkSecurity, StableAttDatabase},
+    },
     packets::{
         AttAttributeDataBuilder, AttChild, AttErrorCode, AttErrorResponseBuilder, AttOpcode,
         AttReadByTypeDataElementBuilder, AttReadByTypeRequestView, AttReadByTypeResponseBuilder,
@@ -17,6 +20,7 @@ use super::helpers::{
 pub async fn handle_read_by_type_request(
     request: AttReadByTypeRequestView<'_>,
     mtu: usize,
+    security: &AttLinkSecurity,
     db: &impl StableAttDatabase,
 ) -> Result<AttChild, ParseError> {
     let request_type: Uuid = request.get_attribute_type().try_into()?;
@@ -49,7 +53,7 @@ pub async fn handle_read_by_type_request(
     let mut out = PayloadAccumulator::new(mtu - 2);
 
     // MTU-4 limit comes from Core Spec 5.3 Vol 3F 3.4.4.1
-    match filter_read_attributes_by_size_type(db, attrs, request_type, mtu - 4).await {
+    match filter_read_attributes_by_size_type(db, attrs, request_type, mtu - 4, security).await {
         Ok(attrs) => {
             for AttributeWithValue { attr, value } in attrs {
                 if !out.push(AttReadByTypeDataElementBuilder {
@@ -111,8 +115,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_type: UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_type_request(att_view.view(), 31, &db)).unwrap();
+        let response = tokio_test::block_on(handle_read_by_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert
         let AttChild::AttReadByTypeResponse(response) = response else {
@@ -166,8 +175,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_type: UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_type_request(att_view.view(), 31, &db)).unwrap();
+        let response = tokio_test::block_on(handle_read_by_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we correctly filtered by type (so we are using the filter_by_type
         // utility)
@@ -220,8 +234,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_type: UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_type_request(att_view.view(), 8, &db)).unwrap();
+        let response = tokio_test::block_on(handle_read_by_type_request(
+            att_view.view(),
+            8,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we return only the first attribute
         let AttChild::AttReadByTypeResponse(response) = response else {
@@ -267,8 +286,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_type: UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_type_request(att_view.view(), 31, &db)).unwrap();
+        let response = tokio_test::block_on(handle_read_by_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we return ATTRIBUTE_NOT_FOUND
         let AttChild::AttErrorResponse(response) = response else {
@@ -295,8 +319,13 @@ mod test {
             ending_handle: AttHandle(6).into(),
             attribute_type: UUID.into(),
         });
-        let response =
-            tokio_test::block_on(handle_read_by_type_request(att_view.view(), 31, &db)).unwrap();
+        let response = tokio_test::block_on(handle_read_by_type_request(
+            att_view.view(),
+            31,
+            &AttLinkSecurity::default(),
+            &db,
+        ))
+        .unwrap();
 
         // assert: we return an INVALID_HANDLE error
         let AttChild::AttErrorResponse(response) = response else {
@@ -311,4 +340,61 @@ mod test {
             }
         )
     }
+
+    #[test]
+    fn test_insufficient_security() {
+        // arrange: a readable attribute, followed by one that requires encryption
+        let db = TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(3),
+                    type_: UUID,
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![1],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(4),
+                    type_: UUID,
+                    permissions: AttPermissions::READABLE | AttPermissions::READ_ENCRYPTED,
+                },
+                vec![2],
+            ),
+        ]);
+
+        // act: read them over an unencrypted link, starting from each one
+        let read_from = |handle| {
+            let att_view = build_view_or_crash(AttReadByTypeRequestBuilder {
+                starting_handle: AttHandle(handle).into(),
+                ending_handle: AttHandle(6).into(),
+                attribute_type: UUID.into(),
+            });
+            tokio_test::block_on(handle_read_by_type_request(
+                att_view.view(),
+                31,
+                &AttLinkSecurity::default(),
+                &db,
+            ))
+            .unwrap()
+        };
+        let first_response = read_from(3);
+        let second_response = read_from(4);
+
+        // assert: the first response stops before the encrypted attribute
+        let AttChild::AttReadByTypeResponse(first_response) = first_response else {
+            unreachable!("{:?}", first_response)
+        };
+        assert_eq!(first_response.data.len(), 1);
+        assert_eq!(first_response.data[0].handle, AttHandle(3).into());
+        // assert: the second response tells the peer to pair
+        assert_eq!(
+            second_response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                handle_in_error: AttHandle(4).into(),
+                opcode_in_error: AttOpcode::READ_BY_TYPE_REQUEST,
+                error_code: AttErrorCode::INSUFFICIENT_AUTHENTICATION,
+            })
+        );
+    }
 }
@@ -0,0 +1,182 @@
+use crate::{
+    gatt::{
+        ids::AttHandle,
+        server::att_database::{AttLinkSecurity, StableAttDatabase},
+    },
+    packets::{
+        AttAttributeDataBuilder, AttAttributeDataChild, AttChild, AttErrorResponseBuilder,
+        AttOpcode, AttReadMultipleRequestView, AttReadMultipleResponseBuilder,
+    },
+};
+
+use super::helpers::read_multiple_attributes::read_multiple_attributes;
+
+pub async fn handle_read_multiple_request(
+    request: AttReadMultipleRequestView<'_>,
+    mtu: usize,
+    security: &AttLinkSecurity,
+    db: &impl StableAttDatabase,
+) -> AttChild {
+    let handles = request.get_set_of_handles_iter().map(AttHandle::from).collect::<Vec<_>>();
+
+    match read_multiple_attributes(db, &handles, security).await {
+        Ok(values) => {
+            // as per 5.3 3F 3.4.4.8 ATT_READ_MULTIPLE_RSP, the values are simply
+            // concatenated, and we truncate to MTU - 1
+            let mut set_of_values = values.concat();
+            set_of_values.truncate(mtu - 1);
+            AttReadMultipleResponseBuilder {
+                set_of_values: AttAttributeDataBuilder {
+                    _child_: AttAttributeDataChild::RawData(set_of_values.into_boxed_slice()),
+                },
+            }
+            .into()
+        }
+        Err((handle, error_code)) => AttErrorResponseBuilder {
+            opcode_in_error: AttOpcode::READ_MULTIPLE_REQUEST,
+            handle_in_error: handle.into(),
+            error_code,
+        }
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::server::{
+            att_database::{AttAttribute, AttPermissions},
+            test::test_att_db::TestAttDatabase,
+        },
+        packets::{AttErrorCode, AttReadMultipleRequestBuilder, Serializable},
+        utils::packet::{build_att_data, build_view_or_crash},
+    };
+
+    fn make_db() -> TestAttDatabase {
+        TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(3),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![1, 2, 3],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(4),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![4, 5],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(5),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::WRITABLE_WITH_RESPONSE,
+                },
+                vec![6],
+            ),
+        ])
+    }
+
+    fn do_read_multiple_request(handles: &[u16], mtu: usize, db: &TestAttDatabase) -> AttChild {
+        let att_view = build_view_or_crash(AttReadMultipleRequestBuilder {
+            set_of_handles: handles.iter().map(|&handle| AttHandle(handle).into()).collect(),
+        });
+        tokio_test::block_on(handle_read_multiple_request(
+            att_view.view(),
+            mtu,
+            &AttLinkSecurity::default(),
+            db,
+        ))
+    }
+
+    #[test]
+    fn test_read_multiple() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_request(&[4, 3], 31, &db);
+
+        // assert: the values are concatenated in the order requested
+        response.to_vec().unwrap(); // check it serializes
+        assert_eq!(
+            response,
+            AttChild::AttReadMultipleResponse(AttReadMultipleResponseBuilder {
+                set_of_values: build_att_data(AttAttributeDataChild::RawData(
+                    [4, 5, 1, 2, 3].into()
+                ))
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_to_mtu() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_request(&[3, 4], 5, &db);
+
+        // assert: only MTU - 1 bytes are returned, cutting the last value short
+        assert_eq!(response.to_vec().unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_request(&[3, 6], 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_MULTIPLE_REQUEST,
+                handle_in_error: AttHandle(6).into(),
+                error_code: AttErrorCode::INVALID_HANDLE,
+            })
+        );
+    }
+
+    #[test]
+    fn test_not_readable() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_request(&[3, 5, 4], 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_MULTIPLE_REQUEST,
+                handle_in_error: AttHandle(5).into(),
+                error_code: AttErrorCode::READ_NOT_PERMITTED,
+            })
+        );
+    }
+
+    #[test]
+    fn test_too_few_handles() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_request(&[3], 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_MULTIPLE_REQUEST,
+                handle_in_error: AttHandle(3).into(),
+                error_code: AttErrorCode::INVALID_PDU,
+            })
+        );
+    }
+}
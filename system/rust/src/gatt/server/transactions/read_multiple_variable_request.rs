@@ -0,0 +1,173 @@
+use crate::{
+    gatt::{
+        ids::AttHandle,
+        server::att_database::{AttLinkSecurity, StableAttDatabase},
+    },
+    packets::{
+        AttAttributeDataBuilder, AttAttributeDataChild, AttChild, AttErrorResponseBuilder,
+        AttOpcode, AttReadMultipleVariableRequestView, AttReadMultipleVariableResponseBuilder,
+    },
+};
+
+use super::helpers::read_multiple_attributes::read_multiple_attributes;
+
+pub async fn handle_read_multiple_variable_request(
+    request: AttReadMultipleVariableRequestView<'_>,
+    mtu: usize,
+    security: &AttLinkSecurity,
+    db: &impl StableAttDatabase,
+) -> AttChild {
+    let handles = request.get_set_of_handles_iter().map(AttHandle::from).collect::<Vec<_>>();
+
+    match read_multiple_attributes(db, &handles, security).await {
+        Ok(values) => {
+            // as per 5.3 3F 3.4.4.12 ATT_READ_MULTIPLE_VARIABLE_RSP, each value is
+            // prefixed by its full length, and the whole list is truncated to MTU - 1,
+            // so the client can tell if the last value was cut short
+            let mut tuples = vec![];
+            for value in values {
+                tuples.extend(u16::try_from(value.len()).unwrap_or(u16::MAX).to_le_bytes());
+                tuples.extend(value);
+            }
+            tuples.truncate(mtu - 1);
+            AttReadMultipleVariableResponseBuilder {
+                length_value_tuple_list: AttAttributeDataBuilder {
+                    _child_: AttAttributeDataChild::RawData(tuples.into_boxed_slice()),
+                },
+            }
+            .into()
+        }
+        Err((handle, error_code)) => AttErrorResponseBuilder {
+            opcode_in_error: AttOpcode::READ_MULTIPLE_VARIABLE_REQUEST,
+            handle_in_error: handle.into(),
+            error_code,
+        }
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        core::uuid::Uuid,
+        gatt::server::{
+            att_database::{AttAttribute, AttPermissions},
+            test::test_att_db::TestAttDatabase,
+        },
+        packets::{AttErrorCode, AttReadMultipleVariableRequestBuilder, Serializable},
+        utils::packet::{build_att_data, build_view_or_crash},
+    };
+
+    fn make_db() -> TestAttDatabase {
+        TestAttDatabase::new(vec![
+            (
+                AttAttribute {
+                    handle: AttHandle(3),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![1, 2, 3],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(4),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::READABLE,
+                },
+                vec![],
+            ),
+            (
+                AttAttribute {
+                    handle: AttHandle(5),
+                    type_: Uuid::new(0x1234),
+                    permissions: AttPermissions::WRITABLE_WITH_RESPONSE,
+                },
+                vec![6],
+            ),
+        ])
+    }
+
+    fn do_read_multiple_variable_request(
+        handles: &[u16],
+        mtu: usize,
+        db: &TestAttDatabase,
+    ) -> AttChild {
+        let att_view = build_view_or_crash(AttReadMultipleVariableRequestBuilder {
+            set_of_handles: handles.iter().map(|&handle| AttHandle(handle).into()).collect(),
+        });
+        tokio_test::block_on(handle_read_multiple_variable_request(
+            att_view.view(),
+            mtu,
+            &AttLinkSecurity::default(),
+            db,
+        ))
+    }
+
+    #[test]
+    fn test_read_multiple_variable() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_variable_request(&[3, 4], 31, &db);
+
+        // assert: each value (even an empty one) is prefixed by its length
+        response.to_vec().unwrap(); // check it serializes
+        assert_eq!(
+            response,
+            AttChild::AttReadMultipleVariableResponse(AttReadMultipleVariableResponseBuilder {
+                length_value_tuple_list: build_att_data(AttAttributeDataChild::RawData(
+                    [3, 0, 1, 2, 3, 0, 0].into()
+                ))
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_to_mtu() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_variable_request(&[4, 3], 6, &db);
+
+        // assert: only MTU - 1 bytes are returned, but the last length is not changed
+        assert_eq!(response.to_vec().unwrap(), vec![0, 0, 3, 0, 1]);
+    }
+
+    #[test]
+    fn test_not_readable() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_variable_request(&[3, 5], 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_MULTIPLE_VARIABLE_REQUEST,
+                handle_in_error: AttHandle(5).into(),
+                error_code: AttErrorCode::READ_NOT_PERMITTED,
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        let db = make_db();
+
+        // act
+        let response = do_read_multiple_variable_request(&[7, 3], 31, &db);
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_MULTIPLE_VARIABLE_REQUEST,
+                handle_in_error: AttHandle(7).into(),
+                error_code: AttErrorCode::INVALID_HANDLE,
+            })
+        );
+    }
+}
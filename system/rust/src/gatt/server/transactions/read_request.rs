@@ -1,21 +1,26 @@
 use crate::{
-    gatt::server::att_database::AttDatabase,
+    gatt::server::att_database::{AttLinkSecurity, StableAttDatabase},
     packets::{
         AttAttributeDataBuilder, AttChild, AttErrorResponseBuilder, AttOpcode, AttReadRequestView,
         AttReadResponseBuilder,
     },
 };
 
-use super::helpers::truncate_att_data::truncate_att_data;
+use super::helpers::{check_access::check_read_access, truncate_att_data::truncate_att_data};
 
-pub async fn handle_read_request<T: AttDatabase>(
+pub async fn handle_read_request(
     request: AttReadRequestView<'_>,
     mtu: usize,
-    db: &T,
+    security: &AttLinkSecurity,
+    db: &impl StableAttDatabase,
 ) -> AttChild {
     let handle = request.get_attribute_handle().into();
 
-    match db.read_attribute(handle).await {
+    let value = match check_read_access(db, handle, security) {
+        Ok(()) => db.read_attribute(handle, /* offset */ 0).await,
+        Err(error_code) => Err(error_code),
+    };
+    match value {
         Ok(data) => AttReadResponseBuilder {
             // as per 5.3 3F 3.4.4.4 ATT_READ_RSP, we truncate to MTU - 1
             value: AttAttributeDataBuilder { _child_: truncate_att_data(data, mtu - 1) },
@@ -66,7 +71,12 @@ mod test {
         let att_view = build_view_or_crash(AttReadRequestBuilder {
             attribute_handle: AttHandle(handle).into(),
         });
-        tokio_test::block_on(handle_read_request(att_view.view(), mtu, db))
+        tokio_test::block_on(handle_read_request(
+            att_view.view(),
+            mtu,
+            &AttLinkSecurity::default(),
+            db,
+        ))
     }
 
     #[test]
@@ -141,4 +151,34 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn test_insufficient_encryption() {
+        // arrange: an attribute that requires encryption
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(3),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::READABLE | AttPermissions::READ_ENCRYPTED,
+            },
+            vec![4, 5],
+        )]);
+        let security = AttLinkSecurity { link_key_known: true, ..Default::default() };
+
+        // act: read it over an unencrypted link
+        let att_view =
+            build_view_or_crash(AttReadRequestBuilder { attribute_handle: AttHandle(3).into() });
+        let response =
+            tokio_test::block_on(handle_read_request(att_view.view(), 31, &security, &db));
+
+        // assert
+        assert_eq!(
+            response,
+            AttChild::AttErrorResponse(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::READ_REQUEST,
+                handle_in_error: AttHandle(3).into(),
+                error_code: AttErrorCode::INSUFFICIENT_ENCRYPTION,
+            })
+        );
+    }
 }
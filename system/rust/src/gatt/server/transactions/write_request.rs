@@ -1,16 +1,23 @@
 use crate::{
-    gatt::server::att_database::AttDatabase,
+    gatt::server::att_database::{AttLinkSecurity, StableAttDatabase},
     packets::{
         AttChild, AttErrorResponseBuilder, AttOpcode, AttWriteRequestView, AttWriteResponseBuilder,
     },
 };
 
-pub async fn handle_write_request<T: AttDatabase>(
+use super::helpers::check_access::check_write_access;
+
+pub async fn handle_write_request(
     request: AttWriteRequestView<'_>,
-    db: &T,
+    security: &AttLinkSecurity,
+    db: &impl StableAttDatabase,
 ) -> AttChild {
     let handle = request.get_handle().into();
-    match db.write_attribute(handle, request.get_value()).await {
+    let result = match check_write_access(db, handle, security) {
+        Ok(()) => db.write_attribute(handle, request.get_value()).await,
+        Err(error_code) => Err(error_code),
+    };
+    match result {
         Ok(()) => AttWriteResponseBuilder {}.into(),
         Err(error_code) => AttErrorResponseBuilder {
             opcode_in_error: AttOpcode::WRITE_REQUEST,
@@ -62,11 +69,12 @@ mod test {
             handle: AttHandle(1).into(),
             value: build_att_data(data.clone()),
         });
-        let resp = block_on(handle_write_request(att_view.view(), &db));
+        let resp =
+            block_on(handle_write_request(att_view.view(), &AttLinkSecurity::default(), &db));
 
         // assert: that the write succeeded
         assert_eq!(resp, AttChild::from(AttWriteResponseBuilder {}));
-        assert_eq!(block_on(db.read_attribute(AttHandle(1))).unwrap(), data);
+        assert_eq!(block_on(db.read_attribute(AttHandle(1), 0)).unwrap(), data);
     }
 
     #[test]
@@ -85,7 +93,8 @@ mod test {
             handle: AttHandle(1).into(),
             value: build_att_data(AttAttributeDataChild::RawData([1, 2].into())),
         });
-        let resp = block_on(handle_write_request(att_view.view(), &db));
+        let resp =
+            block_on(handle_write_request(att_view.view(), &AttLinkSecurity::default(), &db));
 
         // assert: that the write failed
         assert_eq!(
@@ -97,4 +106,46 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn test_insufficient_authentication() {
+        // arrange: db with one attribute that requires an authenticated link to write
+        let db = TestAttDatabase::new(vec![(
+            AttAttribute {
+                handle: AttHandle(1),
+                type_: Uuid::new(0x1234),
+                permissions: AttPermissions::READABLE
+                    | AttPermissions::WRITABLE_WITH_RESPONSE
+                    | AttPermissions::WRITE_AUTHENTICATED,
+            },
+            vec![],
+        )]);
+        let security = AttLinkSecurity {
+            encrypted: true,
+            key_size: 16,
+            link_key_known: true,
+            ..Default::default()
+        };
+
+        // act: write to the attribute over an encrypted, but unauthenticated, link
+        let att_view = build_view_or_crash(AttWriteRequestBuilder {
+            handle: AttHandle(1).into(),
+            value: build_att_data(AttAttributeDataChild::RawData([1, 2].into())),
+        });
+        let resp = block_on(handle_write_request(att_view.view(), &security, &db));
+
+        // assert: that the write failed, and the value was not updated
+        assert_eq!(
+            resp,
+            AttChild::from(AttErrorResponseBuilder {
+                opcode_in_error: AttOpcode::WRITE_REQUEST,
+                handle_in_error: AttHandle(1).into(),
+                error_code: AttErrorCode::INSUFFICIENT_AUTHENTICATION
+            })
+        );
+        assert_eq!(
+            block_on(db.read_attribute(AttHandle(1), 0)).unwrap(),
+            AttAttributeDataChild::RawData([].into())
+        );
+    }
 }
@@ -30,6 +30,12 @@ pub fn HACK_child_to_opcode(child: &AttChild) -> AttOpcode {
         AttChild::AttReadByTypeRequest(_) => AttOpcode::READ_BY_TYPE_REQUEST,
         AttChild::AttReadRequest(_) => AttOpcode::READ_REQUEST,
         AttChild::AttReadResponse(_) => AttOpcode::READ_RESPONSE,
+        AttChild::AttReadBlobRequest(_) => AttOpcode::READ_BLOB_REQUEST,
+        AttChild::AttReadBlobResponse(_) => AttOpcode::READ_BLOB_RESPONSE,
+        AttChild::AttReadMultipleRequest(_) => AttOpcode::READ_MULTIPLE_REQUEST,
+        AttChild::AttReadMultipleResponse(_) => AttOpcode::READ_MULTIPLE_RESPONSE,
+        AttChild::AttReadMultipleVariableRequest(_) => AttOpcode::READ_MULTIPLE_VARIABLE_REQUEST,
+        AttChild::AttReadMultipleVariableResponse(_) => AttOpcode::READ_MULTIPLE_VARIABLE_RESPONSE,
         AttChild::AttErrorResponse(_) => AttOpcode::ERROR_RESPONSE,
         AttChild::AttReadByGroupTypeResponse(_) => AttOpcode::READ_BY_GROUP_TYPE_RESPONSE,
         AttChild::AttReadByTypeResponse(_) => AttOpcode::READ_BY_TYPE_RESPONSE,
@@ -38,6 +44,14 @@ pub fn HACK_child_to_opcode(child: &AttChild) -> AttOpcode {
         AttChild::AttFindByTypeValueResponse(_) => AttOpcode::FIND_BY_TYPE_VALUE_RESPONSE,
         AttChild::AttWriteRequest(_) => AttOpcode::WRITE_REQUEST,
         AttChild::AttWriteResponse(_) => AttOpcode::WRITE_RESPONSE,
+        AttChild::AttPrepareWriteRequest(_) => AttOpcode::PREPARE_WRITE_REQUEST,
+        AttChild::AttPrepareWriteResponse(_) => AttOpcode::PREPARE_WRITE_RESPONSE,
+        AttChild::AttExecuteWriteRequest(_) => AttOpcode::EXECUTE_WRITE_REQUEST,
+        AttChild::AttExecuteWriteResponse(_) => AttOpcode::EXECUTE_WRITE_RESPONSE,
+        AttChild::AttHandleValueNotification(_) => AttOpcode::HANDLE_VALUE_NOTIFICATION,
+        AttChild::AttMultipleHandleValueNotification(_) => {
+            AttOpcode::MULTIPLE_HANDLE_VALUE_NOTIFICATION
+        }
         AttChild::AttHandleValueIndication(_) => AttOpcode::HANDLE_VALUE_INDICATION,
         AttChild::AttHandleValueConfirmation(_) => AttOpcode::HANDLE_VALUE_CONFIRMATION,
         AttChild::AttExchangeMtuRequest(_) => AttOpcode::EXCHANGE_MTU_REQUEST,
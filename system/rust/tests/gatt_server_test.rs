@@ -26,7 +26,7 @@ use bluetooth_core::{
                     SERVICE_CHANGE_UUID,
                 },
             },
-            GattModule, IndicationError,
+            AttLinkSecurity, GattModule, IndicationError,
         },
     },
     packets::{
@@ -58,9 +58,9 @@ const ANOTHER_TCB_IDX: TransportIndex = TransportIndex(2);
 const ANOTHER_SERVER_ID: ServerId = ServerId(3);
 const ANOTHER_ADVERTISER_ID: AdvertiserId = AdvertiserId(4);
 
-const SERVICE_HANDLE: AttHandle = AttHandle(6);
-const CHARACTERISTIC_HANDLE: AttHandle = AttHandle(8);
-const DESCRIPTOR_HANDLE: AttHandle = AttHandle(9);
+const SERVICE_HANDLE: AttHandle = AttHandle(10);
+const CHARACTERISTIC_HANDLE: AttHandle = AttHandle(12);
+const DESCRIPTOR_HANDLE: AttHandle = AttHandle(13);
 
 const SERVICE_TYPE: Uuid = Uuid::new(0x0102);
 const CHARACTERISTIC_TYPE: Uuid = Uuid::new(0x0103);
@@ -481,6 +481,37 @@ fn test_read_device_name() {
     });
 }
 
+#[test]
+fn test_read_device_name_authenticated() {
+    start_test(async move {
+        // arrange: an authenticated link
+        let (mut gatt, mut transport_rx) = start_gatt_module();
+        create_server_and_open_connection(&mut gatt);
+        gatt.get_bearer(TCB_IDX).unwrap().set_link_security(AttLinkSecurity {
+            encrypted: true,
+            authenticated: true,
+            key_size: 16,
+            link_key_known: true,
+            authorized: false,
+        });
+
+        // act: try to read the device name
+        gatt.get_bearer(TCB_IDX).unwrap().handle_packet(
+            build_att_view_or_crash(AttReadByTypeRequestBuilder {
+                starting_handle: AttHandle(1).into(),
+                ending_handle: AttHandle(0xFFFF).into(),
+                attribute_type: DEVICE_NAME_UUID.into(),
+            })
+            .view(),
+        );
+        let (tcb_idx, resp) = transport_rx.recv().await.unwrap();
+
+        // assert: the name is now readable
+        assert_eq!(tcb_idx, TCB_IDX);
+        assert!(matches!(resp._child_, AttChild::AttReadByTypeResponse(_)));
+    });
+}
+
 #[test]
 fn test_ignored_service_change_indication() {
     start_test(async move {
@@ -57,6 +57,16 @@ class PassthroughAclArbiter : public AclArbiter {
     // no-op
   }
 
+  virtual void OnCongestionChange(uint8_t tcb_idx, bool congested) {
+    // no-op
+  }
+
+  virtual void OnSecurityChange(uint8_t tcb_idx, bool encrypted,
+                                bool authenticated, uint8_t key_size,
+                                bool link_key_known) {
+    // no-op
+  }
+
   static PassthroughAclArbiter& Get() {
     static auto singleton = PassthroughAclArbiter();
     return singleton;
@@ -72,6 +82,10 @@ struct RustArbiterCallbacks {
   ::rust::Fn<void(uint8_t tcb_idx)> on_outgoing_mtu_req;
   ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_resp;
   ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_req;
+  ::rust::Fn<void(uint8_t tcb_idx, bool congested)> on_congestion_change;
+  ::rust::Fn<void(uint8_t tcb_idx, bool encrypted, bool authenticated,
+                  uint8_t key_size, bool link_key_known)>
+      on_security_change;
 };
 
 RustArbiterCallbacks callbacks_{};
@@ -116,6 +130,19 @@ class RustGattAclArbiter : public AclArbiter {
     callbacks_.on_incoming_mtu_req(tcb_idx, mtu);
   }
 
+  virtual void OnCongestionChange(uint8_t tcb_idx, bool congested) {
+    LOG_DEBUG("Notifying Rust of congestion change %d", congested);
+    callbacks_.on_congestion_change(tcb_idx, congested);
+  }
+
+  virtual void OnSecurityChange(uint8_t tcb_idx, bool encrypted,
+                                bool authenticated, uint8_t key_size,
+                                bool link_key_known) {
+    LOG_DEBUG("Notifying Rust of security change (encrypted=%d)", encrypted);
+    callbacks_.on_security_change(tcb_idx, encrypted, authenticated, key_size,
+                                  link_key_known);
+  }
+
   void SendPacketToPeer(uint8_t tcb_idx, ::rust::Vec<uint8_t> buffer) {
     tGATT_TCB* p_tcb = gatt_get_tcb_by_idx(tcb_idx);
     if (p_tcb != nullptr) {
@@ -147,10 +174,16 @@ void StoreCallbacksFromRust(
         intercept_packet,
     ::rust::Fn<void(uint8_t tcb_idx)> on_outgoing_mtu_req,
     ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_resp,
-    ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_req) {
+    ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_req,
+    ::rust::Fn<void(uint8_t tcb_idx, bool congested)> on_congestion_change,
+    ::rust::Fn<void(uint8_t tcb_idx, bool encrypted, bool authenticated,
+                    uint8_t key_size, bool link_key_known)>
+        on_security_change) {
   LOG_INFO("Received callbacks from Rust, registering in Arbiter");
-  callbacks_ = {on_le_connect,       on_le_disconnect,     intercept_packet,
-                on_outgoing_mtu_req, on_incoming_mtu_resp, on_incoming_mtu_req};
+  callbacks_ = {on_le_connect,        on_le_disconnect,
+                intercept_packet,     on_outgoing_mtu_req,
+                on_incoming_mtu_resp, on_incoming_mtu_req,
+                on_congestion_change, on_security_change};
 }
 
 void SendPacketToPeer(uint8_t tcb_idx, ::rust::Vec<uint8_t> buffer) {
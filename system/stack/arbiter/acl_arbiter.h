@@ -48,6 +48,12 @@ class AclArbiter {
   virtual void OnIncomingMtuResp(uint8_t tcb_idx, size_t mtu) = 0;
   virtual void OnIncomingMtuReq(uint8_t tcb_idx, size_t mtu) = 0;
 
+  virtual void OnCongestionChange(uint8_t tcb_idx, bool congested) = 0;
+
+  virtual void OnSecurityChange(uint8_t tcb_idx, bool encrypted,
+                                bool authenticated, uint8_t key_size,
+                                bool link_key_known) = 0;
+
   AclArbiter() = default;
   AclArbiter(AclArbiter&& other) = default;
   AclArbiter& operator=(AclArbiter&& other) = default;
@@ -61,7 +67,11 @@ void StoreCallbacksFromRust(
         intercept_packet,
     ::rust::Fn<void(uint8_t tcb_idx)> on_outgoing_mtu_req,
     ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_resp,
-    ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_req);
+    ::rust::Fn<void(uint8_t tcb_idx, size_t mtu)> on_incoming_mtu_req,
+    ::rust::Fn<void(uint8_t tcb_idx, bool congested)> on_congestion_change,
+    ::rust::Fn<void(uint8_t tcb_idx, bool encrypted, bool authenticated,
+                    uint8_t key_size, bool link_key_known)>
+        on_security_change);
 
 void SendPacketToPeer(uint8_t tcb_idx, ::rust::Vec<uint8_t> buffer);
 
@@ -29,6 +29,7 @@
 #include "gatt_int.h"
 #include "osi/include/allocator.h"
 #include "osi/include/osi.h"
+#include "stack/arbiter/acl_arbiter.h"
 #include "stack/btm/btm_ble_sec.h"
 #include "stack/btm/btm_sec.h"
 #include "stack/include/bt_hdr.h"
@@ -219,6 +220,8 @@ void gatt_notify_enc_cmpl(const RawAddress& bd_addr) {
     return;
   }
 
+  gatt_notify_arbiter_of_security(*p_tcb);
+
   for (uint8_t i = 0; i < GATT_MAX_APPS; i++) {
     if (gatt_cb.cl_rcb[i].in_use && gatt_cb.cl_rcb[i].app_cb.p_enc_cmpl_cb) {
       (*gatt_cb.cl_rcb[i].app_cb.p_enc_cmpl_cb)(gatt_cb.cl_rcb[i].gatt_if,
@@ -239,6 +242,26 @@ void gatt_notify_enc_cmpl(const RawAddress& bd_addr) {
     p_tcb->pending_enc_clcb = new_pending_clcbs;
   }
 }
+/*******************************************************************************
+ *
+ * Function         gatt_notify_arbiter_of_security
+ *
+ * Description      Report the current security of the link to the arbiter, so
+ *                  that an isolated GATT server can check it against the
+ *                  security requirements of its attributes.
+ *
+ * Returns          none
+ *
+ ******************************************************************************/
+void gatt_notify_arbiter_of_security(const tGATT_TCB& tcb) {
+  tGATT_SEC_FLAG sec_flag;
+  uint8_t key_size;
+  gatt_sr_get_sec_info(tcb.peer_bda, tcb.transport, &sec_flag, &key_size);
+
+  bluetooth::shim::arbiter::GetArbiter().OnSecurityChange(
+      tcb.tcb_idx, sec_flag.is_encrypted, sec_flag.is_link_key_authed,
+      key_size, sec_flag.is_link_key_known);
+}
 /*******************************************************************************
  *
  * Function         gatt_set_sec_act
@@ -653,6 +653,7 @@ void gatt_verify_signature(tGATT_TCB& tcb, uint16_t cid, BT_HDR* p_buf);
 tGATT_STATUS gatt_get_link_encrypt_status(tGATT_TCB& tcb);
 tGATT_SEC_ACTION gatt_get_sec_act(tGATT_TCB* p_tcb);
 void gatt_set_sec_act(tGATT_TCB* p_tcb, tGATT_SEC_ACTION sec_act);
+void gatt_notify_arbiter_of_security(const tGATT_TCB& tcb);
 
 /* gatt_db.cc */
 void gatts_init_service_db(tGATT_SVC_DB& db, const bluetooth::Uuid& service,
@@ -541,6 +541,8 @@ static void gatt_le_connect_cback(uint16_t chan, const RawAddress& bd_addr,
   if (advertising_set.has_value()) {
     bluetooth::shim::arbiter::GetArbiter().OnLeConnect(p_tcb->tcb_idx,
                                                        advertising_set.value());
+    // a bonded peer may already have a key, even before the link is encrypted
+    gatt_notify_arbiter_of_security(*p_tcb);
   }
 
   if (is_device_le_audio_capable(bd_addr)) {
@@ -702,6 +704,9 @@ static void gatt_le_cong_cback(const RawAddress& remote_bda, bool congested) {
 
   /* if uncongested, check to see if there is any more pending data */
     gatt_channel_congestion(p_tcb, congested);
+
+  bluetooth::shim::arbiter::GetArbiter().OnCongestionChange(p_tcb->tcb_idx,
+                                                            congested);
 }
 
 /*******************************************************************************
@@ -38,6 +38,14 @@ class MockAclArbiter : public AclArbiter {
 
   virtual void OnIncomingMtuReq(uint8_t /* tcb_idx */, size_t /* mtu */) {}
 
+  virtual void OnCongestionChange(uint8_t /* tcb_idx */,
+                                  bool /* congested */) {}
+
+  virtual void OnSecurityChange(uint8_t /* tcb_idx */, bool /* encrypted */,
+                                bool /* authenticated */,
+                                uint8_t /* key_size */,
+                                bool /* link_key_known */) {}
+
   static MockAclArbiter& Get() {
     static auto singleton = MockAclArbiter();
     return singleton;
@@ -48,6 +48,9 @@ void gatt_notify_enc_cmpl(const RawAddress& /* bd_addr */) {
 void gatt_set_sec_act(tGATT_TCB* /* p_tcb */, tGATT_SEC_ACTION /* sec_act */) {
   inc_func_call_count(__func__);
 }
+void gatt_notify_arbiter_of_security(const tGATT_TCB& /* tcb */) {
+  inc_func_call_count(__func__);
+}
 void gatt_verify_signature(tGATT_TCB& /* tcb */, uint16_t /* cid */,
                            BT_HDR* /* p_buf */) {
   inc_func_call_count(__func__);